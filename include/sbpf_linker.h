@@ -0,0 +1,43 @@
+/*
+ * C interface of the sbpf-linker cdylib, built with `--features capi`.
+ *
+ * The link functions return 0 on success, handing the program over in
+ * `*program` and `*program_len`, to be freed with sbpf_program_free. On
+ * failure they return -1, and sbpf_last_error and sbpf_last_error_code say
+ * why until the next call on the same thread.
+ */
+
+#ifndef SBPF_LINKER_H
+#define SBPF_LINKER_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Relinks one upstream BPF object into an SBPF program. */
+int sbpf_link_program(const uint8_t *source, size_t source_len,
+                      uint8_t **program, size_t *program_len);
+
+/* Relinks `count` objects into one program, resolving the references
+ * between them. */
+int sbpf_link_objects(const uint8_t *const *sources,
+                      const size_t *source_lens, size_t count,
+                      uint8_t **program, size_t *program_len);
+
+/* Frees a program returned by one of the link functions. */
+void sbpf_program_free(uint8_t *program, size_t program_len);
+
+/* The message of the last failure on this thread, or NULL. */
+const char *sbpf_last_error(void);
+
+/* Its code, e.g. "undefined_symbol", or NULL. */
+const char *sbpf_last_error_code(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* SBPF_LINKER_H */
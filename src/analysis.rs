@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+
+use object::{
+    File, Object as _, ObjectSection as _, ObjectSymbol as _, RelocationTarget,
+};
+use sbpf_assembler::syscall::SYSCALLS;
+use sbpf_common::opcode::Opcode;
+
+use crate::{
+    SbpfLinkerError, byteparser::section_data, inspect::function_starts,
+};
+
+/// One instruction of an object's .text, by its raw fields.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct RawInstruction {
+    pub(crate) offset: u64,
+    pub(crate) opcode: u8,
+    pub(crate) dst: u8,
+    pub(crate) src: u8,
+    pub(crate) off: i64,
+    pub(crate) imm: i64,
+}
+
+impl RawInstruction {
+    pub(crate) fn is(&self, opcode: Opcode) -> bool {
+        self.opcode == opcode.to_bytecode()
+    }
+
+    pub(crate) fn size(&self) -> u64 {
+        if self.is(Opcode::Lddw) { 16 } else { 8 }
+    }
+
+    /// Target of a relative jump.
+    pub(crate) fn jump_target(&self) -> u64 {
+        self.offset.wrapping_add_signed((self.off + 1) * 8)
+    }
+}
+
+/// The instructions of the .text contents `data`. A truncated trailing
+/// instruction is left out.
+pub(crate) fn decode(data: &[u8]) -> Vec<RawInstruction> {
+    let mut instructions = Vec::new();
+    let mut at = 0;
+    while let Some(bytes) = data.get(at..at + 8) {
+        let instruction = RawInstruction {
+            offset: at as u64,
+            opcode: bytes[0],
+            dst: bytes[1] & 0x0f,
+            src: bytes[1] >> 4,
+            off: i64::from(i16::from_le_bytes([bytes[2], bytes[3]])),
+            imm: i64::from(i32::from_le_bytes([
+                bytes[4], bytes[5], bytes[6], bytes[7],
+            ])),
+        };
+        at += instruction.size() as usize;
+        instructions.push(instruction);
+    }
+    instructions
+}
+
+/// What a call instruction calls.
+pub(crate) enum Callee {
+    /// The function of [`Text::functions`] at this index.
+    Function(usize),
+    Syscall(String),
+    /// A `callx`, or a call whose target can't be resolved.
+    Unknown,
+}
+
+/// The functions and instructions of an object's .text, for the analyses
+/// that look at the code before it's linked.
+pub(crate) struct Text {
+    /// Start and name of every function, in address order.
+    pub(crate) functions: Vec<(u64, String)>,
+    pub(crate) instructions: Vec<RawInstruction>,
+    /// Symbols relocations in .text refer to, by instruction offset: their
+    /// address if they're defined in .text, and their name.
+    targets: HashMap<u64, (Option<u64>, String)>,
+}
+
+impl Text {
+    /// `None` if `obj` has no .text.
+    pub(crate) fn parse(obj: &File) -> Result<Option<Self>, SbpfLinkerError> {
+        let Some(text) = obj.section_by_name(".text") else {
+            return Ok(None);
+        };
+        let data = section_data(&text)?;
+        let functions = function_starts(obj, &text)?
+            .into_iter()
+            .map(|(start, name)| (start, name.to_owned()))
+            .collect();
+
+        let mut targets = HashMap::new();
+        for (offset, rel) in text.relocations() {
+            if let RelocationTarget::Symbol(index) = rel.target() {
+                let symbol = obj.symbol_by_index(index)?;
+                let address = (symbol.section_index() == Some(text.index()))
+                    .then(|| symbol.address());
+                targets.insert(
+                    offset,
+                    (address, symbol.name().unwrap_or_default().to_owned()),
+                );
+            }
+        }
+
+        let instructions = decode(&data);
+
+        Ok(Some(Self { functions, instructions, targets }))
+    }
+
+    /// Index of the function `address` is in.
+    pub(crate) fn function_at(&self, address: u64) -> Option<usize> {
+        self.functions
+            .partition_point(|(start, _)| *start <= address)
+            .checked_sub(1)
+    }
+
+    /// What the call `instruction` calls.
+    pub(crate) fn callee(&self, instruction: &RawInstruction) -> Callee {
+        let relative = (instruction.imm + 1) * 8;
+        let target = match self.targets.get(&instruction.offset) {
+            Some((None, name)) => return Callee::Syscall(name.clone()),
+            Some((Some(address), _)) => address.wrapping_add_signed(relative),
+            // without a relocation, only pc-relative calls stay in the
+            // program; others are syscalls by hash
+            None if instruction.src == 1 => {
+                instruction.offset.wrapping_add_signed(relative)
+            }
+            None => {
+                return SYSCALLS
+                    .get(&(instruction.imm as u32))
+                    .map_or(Callee::Unknown, |name| {
+                        Callee::Syscall((*name).to_owned())
+                    });
+            }
+        };
+        self.function_at(target).map_or(Callee::Unknown, Callee::Function)
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::HashSet;
+
+use object::read::archive::ArchiveFile;
+use object::{File, Object as _, ObjectSymbol as _, SymbolKind};
+
+use crate::{LinkOptions, SbpfLinkerError, bitcode::is_bitcode};
+
+/// Magic bytes at the start of an ELF file.
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+
+/// Extension of the crate metadata rustc stores in every `.rlib`.
+const RLIB_METADATA_EXTENSION: &str = ".rmeta";
+
+/// Magic bytes at the start of every `ar` archive.
+pub const ARCHIVE_MAGIC: &[u8] = b"!<arch>\n";
+
+/// A file stored in an archive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArchiveMember<'data> {
+    pub name: String,
+    pub data: &'data [u8],
+}
+
+pub fn is_archive(bytes: &[u8]) -> bool {
+    bytes.starts_with(ARCHIVE_MAGIC)
+}
+
+/// Reads the members of an `ar` archive in a deterministic order: sorted by
+/// name, ties broken by contents. First-definition-wins symbol resolution and
+/// output layout follow the order members are processed in, so this keeps
+/// links reproducible however the archive was built.
+pub fn archive_members(
+    bytes: &[u8],
+) -> Result<Vec<ArchiveMember<'_>>, SbpfLinkerError> {
+    let archive = ArchiveFile::parse(bytes)?;
+
+    let mut members = archive
+        .members()
+        .map(|member| {
+            let member = member?;
+            Ok(ArchiveMember {
+                name: String::from_utf8_lossy(member.name()).into_owned(),
+                data: member.data(bytes)?,
+            })
+        })
+        .collect::<Result<Vec<_>, SbpfLinkerError>>()?;
+    members.sort_by(|a, b| (&a.name, a.data).cmp(&(&b.name, b.data)));
+
+    Ok(members)
+}
+
+/// Picks the archive `members` a link of `objects` needs, the way GNU ld
+/// searches archives: a member is pulled in if it defines a global symbol
+/// that is referenced but not defined so far, or one of the symbols the
+/// program needs anyway, like its entry point. Members are searched until
+/// none is needed anymore, so members may refer to each other in any
+/// order, as with ld's `--start-group`.
+///
+/// Rust `.rlib`s are archives too: their crate metadata (`lib.rmeta`) and
+/// other members that aren't ELF objects are skipped. LLVM bitcode members
+/// fail with [`SbpfLinkerError::BitcodeArchiveMember`], they need LLVM.
+///
+/// The needed members are returned in the order of `members`.
+pub fn needed_members<'data>(
+    objects: &[&[u8]],
+    members: &[ArchiveMember<'data>],
+    options: &LinkOptions,
+) -> Result<Vec<ArchiveMember<'data>>, SbpfLinkerError> {
+    let mut defined = HashSet::new();
+    let mut referenced: HashSet<String> =
+        options.roots().into_iter().map(str::to_owned).collect();
+    for object in objects {
+        add_symbols(&File::parse(*object)?, &mut defined, &mut referenced);
+    }
+
+    // each member with its object, which is taken once it's pulled in
+    let mut candidates = Vec::new();
+    for member in members {
+        if member.name.ends_with(RLIB_METADATA_EXTENSION) {
+            continue;
+        }
+        if is_bitcode(member.data) {
+            return Err(SbpfLinkerError::BitcodeArchiveMember(
+                member.name.clone(),
+            ));
+        }
+        if member.data.starts_with(ELF_MAGIC) {
+            candidates.push((member, Some(File::parse(member.data)?)));
+        }
+    }
+    loop {
+        let needed = candidates.iter().position(|(_, obj)| {
+            obj.as_ref().is_some_and(|obj| {
+                obj.symbols().any(|symbol| {
+                    is_definition(&symbol)
+                        && symbol.name().is_ok_and(|name| {
+                            referenced.contains(name)
+                                && !defined.contains(name)
+                        })
+                })
+            })
+        });
+        let Some(needed) = needed else { break };
+        if let Some(obj) = candidates[needed].1.take() {
+            add_symbols(&obj, &mut defined, &mut referenced);
+        }
+    }
+
+    Ok(candidates
+        .into_iter()
+        .filter(|(_, obj)| obj.is_none())
+        .map(|(member, _)| member.clone())
+        .collect())
+}
+
+/// Records the global symbols `obj` defines and the ones it refers to
+/// without defining them. Weak references don't pull members in.
+fn add_symbols(
+    obj: &File,
+    defined: &mut HashSet<String>,
+    referenced: &mut HashSet<String>,
+) {
+    for symbol in obj.symbols() {
+        let Ok(name) = symbol.name() else { continue };
+        if name.is_empty() {
+            continue;
+        }
+        if is_definition(&symbol) {
+            defined.insert(name.to_owned());
+        } else if symbol.is_undefined() && !symbol.is_weak() {
+            referenced.insert(name.to_owned());
+        }
+    }
+}
+
+fn is_definition(symbol: &object::Symbol) -> bool {
+    symbol.is_global()
+        && !symbol.is_undefined()
+        && !matches!(symbol.kind(), SymbolKind::Section | SymbolKind::File)
+}
+
+#[cfg(test)]
+mod tests {
+    use object::SectionKind;
+    use object::elf::R_BPF_64_32;
+
+    use super::*;
+    use crate::link_objects;
+    use crate::testing::{EXIT, TestObject, call, mov64};
+
+    /// An `ar` archive of `members`, in the order given.
+    fn archive(members: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut bytes = ARCHIVE_MAGIC.to_vec();
+        for (name, data) in members {
+            let header = format!(
+                "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+                format!("{name}/"),
+                0,
+                0,
+                0,
+                644,
+                data.len()
+            );
+            bytes.extend_from_slice(header.as_bytes());
+            bytes.extend_from_slice(data);
+            if data.len() % 2 == 1 {
+                bytes.push(b'\n');
+            }
+        }
+        bytes
+    }
+
+    /// Links the members of `archive` the program needs.
+    fn link_archive(archive: &[u8]) -> Vec<u8> {
+        let members = archive_members(archive).unwrap();
+        let needed =
+            needed_members(&[], &members, &LinkOptions::default()).unwrap();
+        let objects: Vec<_> =
+            needed.iter().map(|member| member.data).collect();
+        link_objects(&objects).unwrap()
+    }
+
+    #[test]
+    fn member_order_doesnt_change_output() {
+        let mut object = TestObject::new();
+        let text = [call(), EXIT].concat();
+        let text_id = object.section(".text", SectionKind::Text, &text);
+        object.symbol("entrypoint", text_id, 0, 16);
+        let helper = object.undefined("helper");
+        object.relocation(text_id, 0, helper, R_BPF_64_32);
+        let entry = object.write();
+
+        let mut object = TestObject::new();
+        let text = [mov64(0, 0), EXIT].concat();
+        let text_id = object.section(".text", SectionKind::Text, &text);
+        object.symbol("helper", text_id, 0, 16);
+        let helper = object.write();
+
+        let forward = archive(&[("entry.o", &entry), ("helper.o", &helper)]);
+        let reversed = archive(&[("helper.o", &helper), ("entry.o", &entry)]);
+        assert_eq!(link_archive(&forward), link_archive(&reversed));
+    }
+}
@@ -0,0 +1,271 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    str,
+};
+
+use object::write::{
+    Object as OutputObject, Relocation as OutputRelocation,
+    Symbol as OutputSymbol, SymbolId, SymbolSection,
+};
+use object::{
+    Architecture, BinaryFormat, Endianness, RelocationFlags, SectionKind,
+    SymbolFlags, SymbolKind, SymbolScope,
+};
+use sbpf_assembler::{
+    astnode::ASTNode,
+    dynsym::RelocationType,
+    lexer::{ImmediateValue, Op, Token},
+    parse_tokens,
+    section::Section as _,
+    tokenize,
+};
+use sbpf_common::opcode::Opcode;
+
+use crate::{SbpfLinkerError, analysis::decode};
+
+/// Extension of sbpf assembly source files.
+pub const ASSEMBLY_EXTENSION: &str = "s";
+
+/// Whether `path` names sbpf assembly, by its extension.
+pub fn is_assembly(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == ASSEMBLY_EXTENSION)
+}
+
+/// Assembles the sbpf assembly `source`, read from `path`, into a
+/// relocatable BPF object that links like the objects LLVM emits.
+///
+/// Jumps and calls to labels of the file are resolved by the assembler.
+/// Calls to anything else, syscalls or functions of other inputs, become
+/// relocations against undefined symbols, and `lddw` of a label a
+/// relocation against it, carrying the `N` of `lddw rX, label+N`.
+/// `.globl` labels are global symbols; other labels are only kept as local
+/// symbols if they're called or loaded, so jump targets don't split the
+/// functions they're in.
+pub fn assemble_object(
+    path: &Path,
+    source: &[u8],
+) -> Result<Vec<u8>, SbpfLinkerError> {
+    let invalid = |detail: String| SbpfLinkerError::InvalidAssembly {
+        path: path.to_path_buf(),
+        detail,
+    };
+    let source =
+        str::from_utf8(source).map_err(|error| invalid(error.to_string()))?;
+    let mut tokens =
+        tokenize(source).map_err(|errors| invalid(format!("{errors:?}")))?;
+    let addends = take_addends(&mut tokens);
+    let parsed = parse_tokens(&tokens)
+        .map_err(|errors| invalid(format!("{errors:?}")))?;
+
+    let mut text = parsed.code_section.bytecode();
+    let rodata = parsed.data_section.bytecode();
+    let relocations = parsed.relocation_data.get_rel_dyns();
+
+    let mut globals = HashSet::new();
+    let mut labels = Vec::new();
+    // the addends of the loads, by their .text offset
+    let mut load_addends = HashMap::new();
+    for node in parsed.code_section.get_nodes() {
+        match node {
+            ASTNode::GlobalDecl { global_decl } => {
+                globals.insert(global_decl.get_entry_label());
+            }
+            ASTNode::Label { label, offset } => {
+                labels.push((label.name.clone(), *offset));
+            }
+            ASTNode::Instruction { instruction, offset }
+                if instruction.opcode == Opcode::Lddw =>
+            {
+                if let Some(&addend) = instruction
+                    .operands
+                    .get(1)
+                    .and_then(label_start)
+                    .and_then(|start| addends.get(&start))
+                {
+                    load_addends.insert(*offset, addend);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // the labels that need a symbol besides the globals: those loaded,
+    // and those called from within the file
+    let external: HashSet<u64> = relocations
+        .iter()
+        .filter(|(_, r_type, _)| *r_type == RelocationType::RSbfSyscall)
+        .map(|(offset, _, _)| *offset)
+        .collect();
+    let mut referenced: HashSet<&str> = relocations
+        .iter()
+        .filter(|(_, r_type, _)| *r_type == RelocationType::RSbf64Relative)
+        .map(|(_, _, name)| name.as_str())
+        .collect();
+    let called: HashSet<u64> = decode(&text)
+        .iter()
+        .filter(|instruction| {
+            instruction.is(Opcode::Call)
+                && instruction.src == 1
+                && !external.contains(&instruction.offset)
+        })
+        .map(|instruction| {
+            instruction.offset.wrapping_add_signed((instruction.imm + 1) * 8)
+        })
+        .collect();
+    for (name, offset) in &labels {
+        if called.contains(offset) {
+            referenced.insert(name);
+        }
+    }
+
+    let mut output = OutputObject::new(
+        BinaryFormat::Elf,
+        Architecture::Bpf,
+        Endianness::Little,
+    );
+    let text_id =
+        output.add_section(Vec::new(), b".text".to_vec(), SectionKind::Text);
+
+    let scope = |name: &str| {
+        if globals.contains(name) {
+            SymbolScope::Linkage
+        } else {
+            SymbolScope::Compilation
+        }
+    };
+    let mut symbols: HashMap<String, SymbolId> = HashMap::new();
+    for (name, offset) in &labels {
+        if !globals.contains(name) && !referenced.contains(name.as_str()) {
+            continue;
+        }
+        let id = output.add_symbol(OutputSymbol {
+            name: name.as_bytes().to_vec(),
+            value: *offset,
+            size: 0,
+            kind: SymbolKind::Text,
+            scope: scope(name),
+            weak: false,
+            section: SymbolSection::Section(text_id),
+            flags: SymbolFlags::None,
+        });
+        symbols.insert(name.clone(), id);
+    }
+    if !rodata.is_empty() {
+        let rodata_id = output.add_section(
+            Vec::new(),
+            b".rodata".to_vec(),
+            SectionKind::ReadOnlyData,
+        );
+        output.section_mut(rodata_id).append_data(&rodata, 8);
+        for node in parsed.data_section.get_nodes() {
+            let ASTNode::ROData { rodata, offset } = node else {
+                continue;
+            };
+            let id = output.add_symbol(OutputSymbol {
+                name: rodata.name.as_bytes().to_vec(),
+                value: *offset,
+                size: rodata.get_size(),
+                kind: SymbolKind::Data,
+                scope: scope(&rodata.name),
+                weak: false,
+                section: SymbolSection::Section(rodata_id),
+                flags: SymbolFlags::None,
+            });
+            symbols.insert(rodata.name.clone(), id);
+        }
+    }
+
+    let mut undefined: HashMap<String, SymbolId> = HashMap::new();
+    let mut text_relocations = Vec::with_capacity(relocations.len());
+    for (offset, r_type, name) in relocations {
+        let (symbol, r_type) = match r_type {
+            RelocationType::RSbfSyscall => {
+                let symbol =
+                    *undefined.entry(name.clone()).or_insert_with(|| {
+                        output.add_symbol(OutputSymbol {
+                            name: name.as_bytes().to_vec(),
+                            value: 0,
+                            size: 0,
+                            kind: SymbolKind::Unknown,
+                            scope: SymbolScope::Unknown,
+                            weak: false,
+                            section: SymbolSection::Undefined,
+                            flags: SymbolFlags::None,
+                        })
+                    });
+                (symbol, object::elf::R_BPF_64_32)
+            }
+            RelocationType::RSbf64Relative => {
+                let Some(&symbol) = symbols.get(&name) else {
+                    continue;
+                };
+                // the assembler put in the label's address in its own
+                // layout; the relocation carries it instead, and the
+                // immediate the offset into it
+                let at = offset as usize;
+                let addend = load_addends.get(&offset).copied().unwrap_or(0);
+                if let Some(imm) = text.get_mut(at + 4..at + 8) {
+                    imm.copy_from_slice(&(addend as u32).to_le_bytes());
+                }
+                if let Some(imm) = text.get_mut(at + 12..at + 16) {
+                    imm.copy_from_slice(
+                        &((addend >> 32) as u32).to_le_bytes(),
+                    );
+                }
+                (symbol, object::elf::R_BPF_64_64)
+            }
+        };
+        text_relocations.push(OutputRelocation {
+            offset,
+            symbol,
+            addend: 0,
+            flags: RelocationFlags::Elf { r_type },
+        });
+    }
+    output.section_mut(text_id).set_data(text, 8);
+    for relocation in text_relocations {
+        output.add_relocation(text_id, relocation)?;
+    }
+
+    Ok(output.write()?)
+}
+
+/// Takes the `+ N` or `- N` after the label of each `lddw rX, label`
+/// out of `tokens`, which the parser would skip, returning each `N` by
+/// where its label starts in the source.
+fn take_addends(tokens: &mut Vec<Token>) -> HashMap<usize, i64> {
+    let mut addends = HashMap::new();
+    let mut at = 0;
+    while at + 6 <= tokens.len() {
+        if let [
+            Token::Opcode(Opcode::Lddw, _),
+            Token::Register(..),
+            Token::Comma(_),
+            Token::Identifier(_, label),
+            Token::BinaryOp(op @ (Op::Add | Op::Sub), _),
+            Token::ImmediateValue(
+                ImmediateValue::Int(value) | ImmediateValue::Addr(value),
+                _,
+            ),
+        ] = &tokens[at..at + 6]
+        {
+            let addend = if matches!(op, Op::Sub) { -value } else { *value };
+            addends.insert(label.start, addend);
+            tokens.drain(at + 4..at + 6);
+        }
+        at += 1;
+    }
+    addends
+}
+
+/// Where in the source the label `operand` of a load, or the address the
+/// parser resolved it to, starts.
+fn label_start(operand: &Token) -> Option<usize> {
+    match operand {
+        Token::Identifier(_, span) | Token::ImmediateValue(_, span) => {
+            Some(span.start)
+        }
+        _ => None,
+    }
+}
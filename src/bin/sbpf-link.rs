@@ -0,0 +1,1446 @@
+use std::{
+    env, fmt, fs,
+    io::{self, Read as _, Write as _},
+    path::{Path, PathBuf},
+    process,
+};
+
+#[cfg(any(
+    feature = "rust-llvm-19",
+    feature = "rust-llvm-20",
+    feature = "rust-llvm-21"
+))]
+use aya_rustc_llvm_proxy as _;
+use clap::{CommandFactory as _, Parser, Subcommand, ValueEnum};
+use flate2::{Compression, read::MultiGzDecoder, write::GzEncoder};
+#[cfg(feature = "llvm")]
+use sbpf_linker::bitcode::compile_bitcode;
+use sbpf_linker::{
+    LinkOptions, SbpfLinkerError,
+    archive::{archive_members, is_archive, needed_members},
+    assembly::{assemble_object, is_assembly},
+    bitcode::{is_bitcode, is_llvm_ir},
+    btf::add_btf,
+    cache::{CacheKey, LinkCache},
+    compute::{ComputeEstimate, compute_units},
+    config::with_defaults,
+    debug::{Strip, add_symbols, split_debug},
+    defsym::Defsym,
+    diagnostic::{
+        ErrorFormat, Location, WarningOptions, init_logging, json_diagnostic,
+    },
+    diff::diff_programs,
+    icf::Icf,
+    inspect::{
+        DisassembledInstruction, RodataInfo, SymbolReferences,
+        cross_references, disassemble, dump_elf as elf_dump, extract_rodata,
+        function_sizes, inspect_relocations, inspect_sections,
+        inspect_symbols,
+    },
+    layout::SectionStart,
+    lines::line_map,
+    link_objects_verbose, link_objects_with_map, link_program_asm,
+    link_program_with_options,
+    loader::LoaderVersion,
+    merge_objects,
+    output::{STDOUT, TempPath, write_atomic},
+    program_hash, program_vm_version,
+    rename::parse_rename_map,
+    response::expand_response_files,
+    script::parse_linker_script,
+    size::{SizeReport, size_report},
+    splice::InstructionSequence,
+    stack::{StackUsage, stack_usage},
+    timings::{collect_timings, stage},
+    version::SbpfVersion,
+};
+
+/// The input path that stands for stdin.
+const STDIN: &str = "-";
+
+/// Every gzip member starts with these two bytes (RFC 1952, section 2.3.1).
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+#[derive(Debug, thiserror::Error)]
+#[allow(clippy::enum_variant_names)]
+enum CliError {
+    #[error("SBPF Linker Error. Error detail: ({0}).")]
+    SbpfLinkerError(#[from] SbpfLinkerError),
+    #[error("Program Read Error. Error detail: ({msg}).")]
+    ProgramReadError { msg: String },
+    #[error("Program Write Error. Error detail: ({msg}).")]
+    ProgramWriteError { msg: String },
+}
+
+impl CliError {
+    /// The error as a rustc style JSON diagnostic, pointing into `file` if
+    /// it's about a section of the input.
+    fn to_json(&self, file: Option<&Path>) -> serde_json::Value {
+        let (code, message, mut location) = match self {
+            Self::SbpfLinkerError(error) => {
+                (error.code(), error.to_string(), error.location())
+            }
+            Self::ProgramReadError { msg } => {
+                ("program_read_error", msg.clone(), Location::default())
+            }
+            Self::ProgramWriteError { msg } => {
+                ("program_write_error", msg.clone(), Location::default())
+            }
+        };
+        if location.section.is_some() && location.file.is_none() {
+            location.file = file.map(Path::to_path_buf);
+        }
+        json_diagnostic("error", Some(code), &message, &location)
+    }
+}
+
+/// Relink upstream BPF object files into an SBPF program without going
+/// through LLVM.
+#[derive(Debug, Parser)]
+#[command(version, args_override_self = true, subcommand_negates_reqs = true)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Input object files. Several are merged into one program, with
+    /// references between them resolved. Members of `ar` archives are
+    /// linked in if they define a symbol the program needs. LLVM bitcode
+    /// (`.bc`) and IR (`.ll`) are compiled with LLVM first, and sbpf
+    /// assembly (`.s`) is assembled. Gzip-compressed inputs are decompressed
+    /// first. `-` reads an object or bitcode from stdin
+    #[clap(required = true)]
+    inputs: Vec<PathBuf>,
+
+    /// Write the program to `path` instead of next to the first input, or
+    /// to stdout if `path` is `-`
+    #[clap(short, long, value_name = "path")]
+    output: Option<PathBuf>,
+
+    /// Fail instead of overwriting an existing output file
+    #[clap(long)]
+    no_clobber: bool,
+
+    /// Write the program gzip-compressed to `<stem>.so.gz`
+    #[clap(long)]
+    compress: bool,
+
+    /// Print the program's hash as verified build tooling computes it: the
+    /// SHA-256 of the uncompressed `.so`, without trailing zero bytes
+    #[clap(long)]
+    print_hash: bool,
+
+    /// Reuse the program linked before from the same inputs and options,
+    /// stored in `dir`, instead of compiling and linking again, and store
+    /// it there otherwise. Only links of just the program are cached, not
+    /// those also writing a map, line map, debug info, BTF, size report or
+    /// disassembly, and none that raised warnings
+    #[clap(long, value_name = "dir")]
+    cache_dir: Option<PathBuf>,
+
+    /// What to write: the program (`so`), its disassembly (`asm`, to a `.s`
+    /// file next to the program) or both, e.g. `--emit so,asm`
+    #[clap(long, value_enum, value_delimiter = ',', default_value = "so")]
+    emit: Vec<Emit>,
+
+    /// Stamp a minimum VM version (0-3) into the program and encode its
+    /// instructions for it, e.g. `lddw` as `mov32` and `hor64` from v2.
+    /// Fails if the program uses instructions that version can't execute
+    #[clap(long, value_name = "n")]
+    require_vm_version: Option<SbpfVersion>,
+
+    /// Target SBPF version (v0-v3), the same as `--require-vm-version`:
+    /// stamps the version into the program and rewrites the instructions it
+    /// has removed, e.g. `lddw` from v2 on. `--no-deprecated` fails on them
+    /// instead
+    #[clap(
+        long,
+        value_name = "version",
+        conflicts_with = "require_vm_version"
+    )]
+    sbpf_version: Option<SbpfVersion>,
+
+    /// Define `symbol` as an alias of another symbol or as an absolute
+    /// constant, e.g. `--defsym alias=existing` or `--defsym base=0x1000`.
+    /// An alias of a function can be the `--entry` or an export
+    #[clap(long, value_name = "symbol=expression")]
+    defsym: Vec<Defsym>,
+
+    /// Fail if the program uses instructions deprecated in the VM version
+    /// given by `--require-vm-version` or `--sbpf-version`
+    #[clap(long)]
+    no_deprecated: bool,
+
+    /// Report every decision the linker makes about `symbol`. Can be
+    /// repeated
+    #[clap(long, value_name = "symbol")]
+    trace_symbol: Vec<String>,
+
+    /// Keep the first of several definitions of a symbol, with a warning,
+    /// instead of failing
+    #[clap(long)]
+    allow_multiple_definition: bool,
+
+    /// Fail if the program would embed any read-only data, e.g. string
+    /// literals
+    #[clap(long)]
+    no_rodata: bool,
+
+    /// Lay out read-only constants with the same bytes once, e.g. string
+    /// literals repeated across objects. Distinct constants may then share
+    /// an address
+    #[clap(long)]
+    dedup_rodata: bool,
+
+    /// Rename symbols as listed in the file `path`, one `old_name new_name`
+    /// pair per line
+    #[clap(long, value_name = "path")]
+    rename_symbols: Option<PathBuf>,
+
+    /// Instructions to run on entry, as sbpf assembly (separated by `;`) or
+    /// hex-encoded bytecode
+    #[clap(long, value_name = "asm-or-bytes")]
+    prologue: Option<InstructionSequence>,
+
+    /// Instructions to run before every `exit`, as sbpf assembly (separated
+    /// by `;`) or hex-encoded bytecode
+    #[clap(long, value_name = "asm-or-bytes")]
+    epilogue: Option<InstructionSequence>,
+
+    /// Start execution at the function `symbol` instead of at the start of
+    /// .text
+    #[clap(long, value_name = "symbol")]
+    entry: Option<String>,
+
+    /// Comma separated list of functions to export in the program's
+    /// .dynsym. A glob pattern, e.g. `*process_instruction*`, exports every
+    /// function it matches, and fails if none does. See also
+    /// `--export-symbols`
+    #[clap(long, value_name = "symbols", use_value_delimiter = true, action = clap::ArgAction::Append)]
+    export: Vec<String>,
+
+    /// Export the functions listed in the file `path`, one per line. Can be
+    /// repeated to combine several files
+    #[clap(long, value_name = "path")]
+    export_symbols: Vec<PathBuf>,
+
+    /// Warn about exports that aren't functions in .text, e.g. a typo in
+    /// `--export-symbols`, instead of failing the link
+    #[clap(long)]
+    allow_undefined_exports: bool,
+
+    /// Decode the linked program again and fail unless it holds exactly
+    /// the instructions that were assembled
+    #[clap(long)]
+    verify: bool,
+
+    /// Skip the checks the loader's verifier makes over the linked program,
+    /// for programs it rejects that the loader doesn't
+    #[clap(long)]
+    no_verifier: bool,
+
+    /// Fail if a function uses more than the 4 KiB of stack its frame holds
+    #[clap(long)]
+    check_stack: bool,
+
+    /// Fail if a function uses more than `bytes` of stack, at most the
+    /// 4096 of its frame
+    #[clap(long, value_name = "bytes")]
+    stack_size: Option<u64>,
+
+    /// Bytes of heap the program expects, from 32 KiB to 256 KiB in
+    /// multiples of 1 KiB. Transactions request it, so this only checks the
+    /// size and, above the 32 KiB they get anyway, warns that they have to
+    #[clap(long, value_name = "bytes")]
+    heap_size: Option<u64>,
+
+    /// Drop functions and constants unreachable from the entry point and
+    /// the exports
+    #[clap(long)]
+    gc_sections: bool,
+
+    /// Fold functions with identical code into one: `safe` only those
+    /// whose address nothing takes, `all` any
+    #[clap(long, value_enum, value_name = "mode", default_value = "none")]
+    icf: IcfMode,
+
+    /// Place input sections as the GNU ld style linker script `path`
+    /// says, e.g. the platform tools' `bpf.ld`
+    #[clap(short = 'T', long, value_name = "path")]
+    script: Option<PathBuf>,
+
+    /// Lay out the sections defining the symbols listed in the file `path`,
+    /// one per line, first and in that order, as lld does. Orders functions
+    /// one by one if they're built with `-ffunction-sections`
+    #[clap(long, value_name = "path")]
+    symbol_ordering_file: Option<PathBuf>,
+
+    /// Load `.text` or `.rodata` at `address` instead of at its offset in
+    /// the file, e.g. `--section-start .text=0x0`. Can be repeated. Takes
+    /// `--require-vm-version v1` or later
+    #[clap(long, value_name = "section=address")]
+    section_start: Vec<SectionStart>,
+
+    /// Keep `.rodata` right after `.text` when `--section-start` moves
+    /// `.text`, instead of where it is
+    #[clap(long)]
+    rodata_after_text: bool,
+
+    /// Emit the stricter layout SBPF v3 loaders expect: `.text` alone at
+    /// 0x0, `.rodata` alone at 0x100000000 and nothing else loaded. Fails
+    /// if the program can't have it. Takes `--require-vm-version v3`
+    #[clap(long)]
+    strict_elf: bool,
+
+    /// Write a linker map to `path`: the address, size and input of every
+    /// function and rodata symbol of the program
+    #[clap(long, value_name = "path")]
+    map: Option<PathBuf>,
+
+    /// Write the source line of each instruction of the program to `path`,
+    /// from the DWARF line tables of the inputs, one `address file:line`
+    /// per line
+    #[clap(long, value_name = "path")]
+    line_map: Option<PathBuf>,
+
+    /// Add a build ID to the program and write its symbols and line tables
+    /// to a `.debug.so` companion next to it, for debuggers to find by the
+    /// build ID
+    #[clap(long, conflicts_with_all = ["strip_debug", "no_strip"])]
+    split_debug: bool,
+
+    /// Keep only the dynamic symbols of the exports, which the loader
+    /// needs. This is the default
+    #[clap(long, conflicts_with_all = ["strip_debug", "no_strip"])]
+    strip_all: bool,
+
+    /// Keep a symbol table of every function and rodata symbol, but no
+    /// debug info
+    #[clap(long, conflicts_with = "no_strip")]
+    strip_debug: bool,
+
+    /// Keep the symbol table and the DWARF line tables of the inputs
+    #[clap(long)]
+    no_strip: bool,
+
+    /// Keep the BTF of the input in the program as `.BTF` and `.BTF.ext`,
+    /// moved to where its functions ended up
+    #[clap(long)]
+    btf: bool,
+
+    /// Make the output byte-identical wherever it's linked, by writing
+    /// source paths relative to the current directory
+    #[clap(long)]
+    reproducible: bool,
+
+    /// Print the size of each section and function of the linked program,
+    /// as text or with `--size-report=json` as JSON, instead of the usual
+    /// summary
+    #[clap(
+        long,
+        value_enum,
+        value_name = "format",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "text"
+    )]
+    size_report: Option<Format>,
+
+    /// Show how each size of `--size-report` changed since the JSON report
+    /// `path`
+    #[clap(long, value_name = "path", requires = "size_report")]
+    size_diff: Option<PathBuf>,
+
+    /// Fail if the program won't load on the given loader (v1-v4)
+    #[clap(long, value_name = "loader")]
+    verify_loader: Option<LoaderVersion>,
+
+    /// Print the minimum VM version declared by an already linked program
+    /// instead of linking
+    #[clap(long)]
+    vm_version: bool,
+
+    /// Print the ELF header, program headers, section headers and dynamic
+    /// entries of the program once it's written, like `readelf -hlSd`
+    #[clap(long)]
+    dump_elf: bool,
+
+    /// Print the section layout of the input instead of linking
+    #[clap(long)]
+    list_sections: bool,
+
+    /// Print every rodata symbol of the input with a hexdump of its bytes
+    /// instead of linking
+    #[clap(long)]
+    print_rodata: bool,
+
+    /// Print, for each symbol of the input, the instructions and data that
+    /// refer to it instead of linking
+    #[clap(long)]
+    xref: bool,
+
+    /// Print the size of each function of the input, largest first, instead
+    /// of linking
+    #[clap(long = "function-sizes")]
+    list_function_sizes: bool,
+
+    /// Print the stack frame size and call depth of each function of the
+    /// input instead of linking
+    #[clap(long)]
+    stack_report: bool,
+
+    /// Print an upper bound on the compute units each function of the input
+    /// consumes instead of linking
+    #[clap(long)]
+    cu_report: bool,
+
+    /// Output format of `--print-rodata`, `--xref`, `--function-sizes`,
+    /// `--stack-report` and `--cu-report`
+    #[clap(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    /// Enable a warning (`-W<name>`, e.g. `-Wunreachable-code`), disable it
+    /// (`-Wno-<name>`), enable all of them (`-Wall`) or turn them into
+    /// errors (`-Werror`). Can be repeated
+    #[clap(short = 'W', value_name = "warning")]
+    warning_flags: Vec<String>,
+
+    /// How to print errors and warnings: as text, or as rustc style JSON
+    /// diagnostics, one per line
+    #[clap(long, value_enum, default_value_t = ErrorFormat::Human)]
+    error_format: ErrorFormat,
+
+    /// Log what the linker does: each step with `-v`, and every section,
+    /// relocation and function it handles with `-vv`
+    #[clap(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Write the `-v` log to `path` instead of stderr
+    #[clap(long, value_name = "path")]
+    log_file: Option<PathBuf>,
+
+    /// Print to stderr how long each stage of the link took, and the peak
+    /// memory by its end
+    #[clap(long)]
+    timings: bool,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Print the sections, symbols and relocations of an object or a
+    /// linked program, and a disassembly of its .text, like `objdump -dhrt`
+    Inspect {
+        /// The object or program, `-` for stdin
+        file: PathBuf,
+    },
+    /// Compare the functions, instruction by instruction, and the rodata of
+    /// two programs or objects, and how the size of each section changed.
+    /// Exits with status 1 if any function or rodata differs
+    Diff {
+        /// The program compared against, `-` for stdin
+        before: PathBuf,
+        /// The program compared
+        after: PathBuf,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Emit {
+    So,
+    Asm,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Format {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum IcfMode {
+    None,
+    Safe,
+    All,
+}
+
+fn main() -> Result<(), CliError> {
+    let args = match expand_response_files(env::args())
+        .and_then(|args| with_defaults("sbpf-link", &Args::command(), args))
+    {
+        Ok(args) => Args::parse_from(args),
+        Err(err) => {
+            eprintln!("error: {err}");
+            return Err(err.into());
+        }
+    };
+    // section offsets only point into the input if there is just one
+    let file = match &args.inputs[..] {
+        [input] => Some(input.clone()),
+        _ => None,
+    };
+    let error_format = args.error_format;
+    let log_file = match &args.log_file {
+        Some(path) => match fs::File::create(path) {
+            Ok(log_file) => Some(log_file),
+            Err(err) => {
+                eprintln!("error: can't create {}: {err}", path.display());
+                return Err(CliError::ProgramWriteError {
+                    msg: err.to_string(),
+                });
+            }
+        },
+        None => None,
+    };
+    init_logging(error_format, file.clone(), args.verbose, log_file);
+
+    let result = if args.timings {
+        let (result, timings) = collect_timings(|| link(args));
+        eprintln!("{timings}");
+        result
+    } else {
+        link(args)
+    };
+    if let (Err(error), ErrorFormat::Json) = (&result, error_format) {
+        eprintln!("{}", error.to_json(file.as_deref()));
+        process::exit(1);
+    }
+    result
+}
+
+fn link(args: Args) -> Result<(), CliError> {
+    match &args.command {
+        Some(Command::Inspect { file }) => return inspect(&read_input(file)?),
+        Some(Command::Diff { before, after }) => {
+            let diff =
+                diff_programs(&read_input(before)?, &read_input(after)?)?;
+            print!("{}", diff.render());
+            if !diff.is_empty() {
+                process::exit(1);
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+    let Args {
+        command: _,
+        inputs,
+        output,
+        no_clobber,
+        compress,
+        print_hash,
+        cache_dir,
+        emit,
+        require_vm_version,
+        sbpf_version,
+        defsym,
+        no_deprecated,
+        trace_symbol,
+        allow_multiple_definition,
+        no_rodata,
+        dedup_rodata,
+        rename_symbols,
+        prologue,
+        epilogue,
+        entry,
+        export,
+        export_symbols,
+        allow_undefined_exports,
+        verify_loader,
+        verify,
+        no_verifier,
+        check_stack,
+        stack_size,
+        heap_size,
+        gc_sections,
+        icf,
+        script,
+        symbol_ordering_file,
+        section_start,
+        rodata_after_text,
+        strict_elf,
+        map,
+        line_map: line_map_path,
+        split_debug: split,
+        strip_all: _,
+        strip_debug,
+        no_strip,
+        btf,
+        reproducible,
+        size_report: size_format,
+        size_diff,
+        vm_version,
+        dump_elf,
+        list_sections,
+        print_rodata,
+        xref,
+        list_function_sizes,
+        stack_report,
+        cu_report,
+        format,
+        warning_flags,
+        error_format: _,
+        verbose: _,
+        log_file: _,
+        timings: _,
+    } = args;
+
+    let linker_script = match script {
+        Some(path) => {
+            Some(parse_linker_script(&fs::read_to_string(path).map_err(
+                |e| CliError::ProgramReadError { msg: e.to_string() },
+            )?)?)
+        }
+        None => None,
+    };
+
+    let symbol_ordering = match symbol_ordering_file {
+        Some(path) => fs::read_to_string(path)
+            .map_err(|e| CliError::ProgramReadError { msg: e.to_string() })?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_owned)
+            .collect(),
+        None => Vec::new(),
+    };
+
+    let renames = match rename_symbols {
+        Some(path) => {
+            parse_rename_map(&fs::read_to_string(path).map_err(|e| {
+                CliError::ProgramReadError { msg: e.to_string() }
+            })?)?
+        }
+        None => Vec::new(),
+    };
+
+    let mut warnings = WarningOptions::default();
+    for flag in &warning_flags {
+        warnings.apply(flag)?;
+    }
+
+    // a symbol listed more than once, in --export-symbols files or by
+    // --export, is exported once, where it's first listed
+    let mut listed = Vec::new();
+    for path in export_symbols {
+        let symbols = fs::read_to_string(path)
+            .map_err(|e| CliError::ProgramReadError { msg: e.to_string() })?;
+        listed.extend(symbols.lines().map(str::to_owned));
+    }
+    let mut exports: Vec<String> = Vec::new();
+    for symbol in listed.into_iter().chain(export) {
+        if !exports.contains(&symbol) {
+            exports.push(symbol);
+        }
+    }
+
+    let options = LinkOptions {
+        require_vm_version: require_vm_version.or(sbpf_version),
+        defsyms: defsym,
+        reject_deprecated: no_deprecated,
+        trace_symbols: trace_symbol,
+        allow_multiple_definition,
+        forbid_rodata: no_rodata,
+        dedup_rodata,
+        renames,
+        prologue,
+        epilogue,
+        entry,
+        exports,
+        allow_undefined_exports,
+        verify_loader,
+        verify_round_trip: verify,
+        skip_verifier: no_verifier,
+        check_stack,
+        stack_size,
+        heap_size,
+        gc_sections,
+        icf: match icf {
+            IcfMode::None => Icf::None,
+            IcfMode::Safe => Icf::Safe,
+            IcfMode::All => Icf::All,
+        },
+        linker_script,
+        symbol_ordering,
+        section_starts: section_start,
+        rodata_after_text,
+        strict_elf,
+        warnings,
+        reproducible,
+    };
+
+    // named after the first input unless given, and to stdout if that's
+    // stdin too
+    let output_path = output.unwrap_or_else(|| {
+        if is_stdin(&inputs[0]) {
+            PathBuf::from(STDOUT)
+        } else {
+            output_path(&inputs[0], compress)
+        }
+    });
+    // with `-o -`, the files named after the program are named after the
+    // first input instead, and status lines go to stderr
+    let to_stdout = output_path == Path::new(STDOUT);
+    let named_after = if to_stdout {
+        self::output_path(&inputs[0], compress)
+    } else {
+        output_path.clone()
+    };
+    let check_output = |path: &Path| -> Result<(), CliError> {
+        if path == Path::new(STDOUT) {
+            return Ok(());
+        }
+        // e.g. `foo.so` holding an object relinks to `foo.so`
+        if let Ok(output) = fs::canonicalize(path)
+            && inputs.iter().any(|input| {
+                !is_stdin(input)
+                    && fs::canonicalize(input).is_ok_and(|i| i == output)
+            })
+        {
+            return Err(SbpfLinkerError::OutputWouldOverwriteInput(
+                path.to_path_buf(),
+            )
+            .into());
+        }
+        if no_clobber && path.exists() {
+            return Err(
+                SbpfLinkerError::OutputExists(path.to_path_buf()).into()
+            );
+        }
+        Ok(())
+    };
+
+    let strip = if no_strip {
+        Strip::Nothing
+    } else if strip_debug {
+        Strip::Debug
+    } else {
+        Strip::All
+    };
+    // keyed by the inputs as read, so that a hit skips LLVM too
+    let mut cache = cache_dir
+        .filter(|_| {
+            emit == [Emit::So]
+                && !(vm_version
+                    || list_sections
+                    || print_rodata
+                    || xref
+                    || list_function_sizes
+                    || stack_report
+                    || cu_report
+                    || split)
+                && map.is_none()
+                && line_map_path.is_none()
+                && strip == Strip::All
+                && !btf
+                && size_format.is_none()
+                && options.trace_symbols.is_empty()
+        })
+        .map(|dir| (LinkCache::new(dir), CacheKey::new(&options)));
+
+    // each object with the input it's named after in the map
+    let mut objects = Vec::new();
+    let mut labels = Vec::new();
+    let mut archives = Vec::new();
+    let mut bitcode = Vec::new();
+    let mut bitcode_labels = Vec::new();
+    // LLVM reads bitcode from files, so bitcode from stdin goes to one
+    let mut stdin_bitcode = None;
+    if inputs.iter().filter(|input| is_stdin(input)).count() > 1 {
+        return Err(CliError::ProgramReadError {
+            msg: "stdin (`-`) can only be read once".to_owned(),
+        });
+    }
+    for input in &inputs {
+        let source = read_input(input)?;
+        tracing::trace!(
+            "read {} ({} bytes)",
+            input_label(input),
+            source.len()
+        );
+        if let Some((_, key)) = &mut cache {
+            // which also says how the input is read, e.g. as assembly
+            key.add(input.extension().unwrap_or_default().as_encoded_bytes());
+            key.add(&source);
+        }
+        if is_stdin(input) && is_bitcode(&source) {
+            let temp = TempPath::next_to(&env::temp_dir().join("stdin.bc"));
+            fs::write(temp.path(), &source).map_err(|e| {
+                CliError::ProgramReadError { msg: e.to_string() }
+            })?;
+            bitcode.push(temp.path().to_path_buf());
+            bitcode_labels.push(input_label(input));
+            stdin_bitcode = Some(temp);
+        } else if is_bitcode(&source) || is_llvm_ir(input) {
+            bitcode.push(input.clone());
+            bitcode_labels.push(input_label(input));
+        } else if is_archive(&source) {
+            archives.push((input, source));
+        } else {
+            objects.push(if is_assembly(input) {
+                assemble_object(input, &source)?
+            } else {
+                source
+            });
+            labels.push(input_label(input));
+        }
+    }
+    if let Some((cache, key)) = &cache
+        && let Some(bytecode) = cache.get(key)
+    {
+        tracing::debug!("using the program cached as {key}");
+        check_output(&output_path)?;
+        let output = if compress {
+            self::compress(&bytecode).map_err(|e| {
+                CliError::ProgramWriteError { msg: e.to_string() }
+            })?
+        } else {
+            bytecode.clone()
+        };
+        write_output(&output_path, &output)?;
+        status(to_stdout, linked(bytecode.len(), to_stdout, &output_path));
+        if dump_elf {
+            status(to_stdout, elf_dump(&bytecode)?.trim_end_matches('\n'));
+        }
+        if print_hash {
+            status(
+                to_stdout,
+                format_args!(
+                    "{}  {}",
+                    program_hash(&bytecode),
+                    output_path.display()
+                ),
+            );
+        }
+        return Ok(());
+    }
+    // bitcode goes through LLVM first, into one more object
+    if !bitcode.is_empty() {
+        tracing::debug!(
+            "compiling {} bitcode input(s) with LLVM",
+            bitcode.len()
+        );
+        objects.push(compile(&bitcode, &bitcode_labels, &options)?);
+        drop(stdin_bitcode);
+        labels.push(bitcode_labels.join(", "));
+    }
+    // archive members are only linked in if the program needs them
+    let mut members = Vec::new();
+    let mut member_labels = Vec::new();
+    for (archive, bytes) in &archives {
+        for member in archive_members(bytes)? {
+            member_labels.push(format!(
+                "{}({})",
+                input_label(archive),
+                member.name
+            ));
+            members.push(member);
+        }
+    }
+    let objects: Vec<&[u8]> = objects.iter().map(Vec::as_slice).collect();
+    let needed = needed_members(&objects, &members, &options)?;
+    if !members.is_empty() {
+        tracing::debug!(
+            "linking in {} of {} archive member(s)",
+            needed.len(),
+            members.len()
+        );
+    }
+    for member in &needed {
+        let at = members
+            .iter()
+            .position(|m| std::ptr::eq(m.data, member.data))
+            .unwrap_or_default();
+        labels.push(member_labels[at].clone());
+    }
+    let sources: Vec<&[u8]> = objects
+        .iter()
+        .copied()
+        .chain(needed.iter().map(|member| member.data))
+        .collect();
+    let source = match sources[..] {
+        [] => return Err(SbpfLinkerError::NoInputObjects.into()),
+        [source] => source.to_vec(),
+        _ => merge_objects(&sources, &options)?,
+    };
+
+    if vm_version {
+        println!("{}", program_vm_version(&source)?);
+        return Ok(());
+    }
+
+    if list_sections {
+        print_sections(&source)?;
+        return Ok(());
+    }
+
+    if print_rodata {
+        let rodata = extract_rodata(&source)?;
+        match format {
+            Format::Text => rodata.iter().for_each(print_rodata_text),
+            Format::Json => println!("{}", rodata_json(&rodata)),
+        }
+        return Ok(());
+    }
+
+    if xref {
+        let xrefs = cross_references(&source)?;
+        match format {
+            Format::Text => xrefs.iter().for_each(print_xref_text),
+            Format::Json => println!("{}", xref_json(&xrefs)),
+        }
+        return Ok(());
+    }
+
+    if list_function_sizes {
+        let functions = function_sizes(&source)?;
+        let total: u64 = functions.iter().map(|function| function.size).sum();
+        match format {
+            Format::Text => {
+                for function in &functions {
+                    println!(
+                        "{:>8} {:>6.2}%  {}",
+                        function.size,
+                        function.size as f64 * 100.0 / total as f64,
+                        function.name
+                    );
+                }
+                println!("{total:>8} total");
+            }
+            Format::Json => println!(
+                "{}",
+                functions
+                    .iter()
+                    .map(|function| serde_json::json!({
+                        "name": function.name,
+                        "offset": function.offset,
+                        "size": function.size,
+                    }))
+                    .collect::<serde_json::Value>()
+            ),
+        }
+        return Ok(());
+    }
+
+    if stack_report {
+        let functions = stack_usage(&source)?;
+        match format {
+            Format::Text => {
+                println!("{:>8} {:>9}  function", "frame", "depth");
+                functions.iter().for_each(print_stack_text);
+            }
+            Format::Json => println!("{}", stack_json(&functions)),
+        }
+        return Ok(());
+    }
+
+    if cu_report {
+        let functions = compute_units(&source)?;
+        match format {
+            Format::Text => {
+                println!("{:>10}  function", "units");
+                functions.iter().for_each(print_compute_text);
+            }
+            Format::Json => println!("{}", compute_json(&functions)),
+        }
+        return Ok(());
+    }
+
+    if emit.contains(&Emit::Asm) {
+        // on stdout unless the program is
+        let asm_path = match (to_stdout, emit.contains(&Emit::So)) {
+            (true, true) => asm_path(&named_after),
+            (true, false) => output_path.clone(),
+            (false, _) => asm_path(&output_path),
+        };
+        check_output(&asm_path)?;
+        let asm = link_program_asm(&source, &options)?;
+        write_output(&asm_path, asm.as_bytes())?;
+        if asm_path != Path::new(STDOUT) {
+            status(
+                to_stdout,
+                format_args!(
+                    "Wrote the disassembly to {}.",
+                    asm_path.display()
+                ),
+            );
+        }
+    }
+    if !emit.contains(&Emit::So) {
+        return Ok(());
+    }
+
+    let previous = match size_diff {
+        Some(path) => {
+            Some(SizeReport::from_json(&fs::read_to_string(path).map_err(
+                |e| CliError::ProgramReadError { msg: e.to_string() },
+            )?)?)
+        }
+        None => None,
+    };
+    let (mut bytecode, link_map) = if map.is_some()
+        || line_map_path.is_some()
+        || split
+        || strip != Strip::All
+        || btf
+        || size_format.is_some()
+    {
+        let (bytecode, link_map) = link_objects_with_map(&sources, &options)?;
+        (bytecode, Some(link_map))
+    } else if let Some((cache, key)) = &cache {
+        let linked = link_objects_verbose(&sources, &options)?;
+        if linked.diagnostics.is_empty() {
+            cache.store(key, &linked.program);
+        }
+        (linked.program, None)
+    } else {
+        (link_program_with_options(&source, &options)?, None)
+    };
+    if let (Some(map_path), Some(link_map)) = (&map, &link_map) {
+        check_output(map_path)?;
+        write_atomic(map_path, link_map.render(&labels))
+            .map_err(|e| CliError::ProgramWriteError { msg: e.to_string() })?;
+    }
+    let lines = match &link_map {
+        Some(link_map)
+            if line_map_path.is_some() || split || strip == Strip::Nothing =>
+        {
+            Some(line_map(&sources, link_map, &options)?)
+        }
+        _ => None,
+    };
+    if let (Some(path), Some(lines)) = (&line_map_path, &lines) {
+        check_output(path)?;
+        write_atomic(path, lines.render())
+            .map_err(|e| CliError::ProgramWriteError { msg: e.to_string() })?;
+    }
+    if let Some(link_map) = &link_map {
+        match strip {
+            Strip::All => {}
+            Strip::Debug => add_symbols(&mut bytecode, link_map, None)?,
+            Strip::Nothing => {
+                add_symbols(&mut bytecode, link_map, lines.as_ref())?;
+            }
+        }
+        if btf {
+            add_btf(&mut bytecode, &sources, link_map, &options)?;
+        }
+    }
+    let report = match (size_format, &link_map) {
+        (Some(format), Some(link_map)) => {
+            Some((format, size_report(&bytecode, link_map)?))
+        }
+        _ => None,
+    };
+    if split && let (Some(link_map), Some(lines)) = (&link_map, &lines) {
+        let split = split_debug(&bytecode, link_map, lines)?;
+        let debug_path = debug_path(&named_after);
+        check_output(&debug_path)?;
+        write_atomic(&debug_path, &split.debug)
+            .map_err(|e| CliError::ProgramWriteError { msg: e.to_string() })?;
+        status(
+            to_stdout,
+            format_args!("Wrote the debug info to {}.", debug_path.display()),
+        );
+        bytecode = split.program;
+    }
+    let program_size = bytecode.len();
+    let mut hash = print_hash.then(|| program_hash(&bytecode));
+    let dump = dump_elf.then(|| elf_dump(&bytecode)).transpose()?;
+    check_output(&output_path)?;
+    let output = if compress {
+        self::compress(&bytecode)
+            .map_err(|e| CliError::ProgramWriteError { msg: e.to_string() })?
+    } else {
+        bytecode
+    };
+    write_output(&output_path, &output)?;
+
+    match report {
+        Some((Format::Text, report)) => {
+            let report = report.render(previous.as_ref());
+            status(to_stdout, report.trim_end_matches('\n'));
+        }
+        Some((Format::Json, report)) => {
+            let mut json = report.to_json(previous.as_ref());
+            if let Some(hash) = hash.take() {
+                json["hash"] = hash.to_string().into();
+            }
+            status(to_stdout, json);
+        }
+        None => {
+            status(to_stdout, linked(program_size, to_stdout, &output_path))
+        }
+    }
+    // as `sha256sum` prints it, which names stdin `-` too
+    if let Some(hash) = hash {
+        status(to_stdout, format_args!("{hash}  {}", output_path.display()));
+    }
+    if let Some(dump) = dump {
+        status(to_stdout, dump.trim_end_matches('\n'));
+    }
+
+    Ok(())
+}
+
+/// Writes `contents` to `path`, or to stdout if `path` is [`STDOUT`].
+fn write_output(path: &Path, contents: &[u8]) -> Result<(), CliError> {
+    let _stage = stage("write");
+    let written = if path == Path::new(STDOUT) {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(contents).and_then(|()| stdout.flush())
+    } else {
+        write_atomic(path, contents)
+    };
+    written.map_err(|e| CliError::ProgramWriteError { msg: e.to_string() })
+}
+
+/// The status line of a program of `size` bytes written to `path`.
+fn linked(size: usize, to_stdout: bool, path: &Path) -> String {
+    if to_stdout {
+        format!("Successfully linked {size} bytes.")
+    } else {
+        format!("Successfully linked {size} bytes to {}.", path.display())
+    }
+}
+
+/// Prints a status line, to stderr when stdout carries the program.
+fn status(to_stderr: bool, line: impl fmt::Display) {
+    if to_stderr {
+        eprintln!("{line}");
+    } else {
+        println!("{line}");
+    }
+}
+
+fn is_stdin(input: &Path) -> bool {
+    input == Path::new(STDIN)
+}
+
+/// How the link map names `input`.
+fn input_label(input: &Path) -> String {
+    if is_stdin(input) {
+        "<stdin>".to_owned()
+    } else {
+        input.display().to_string()
+    }
+}
+
+/// Reads an input file, or stdin for [`STDIN`], decompressing it if it's
+/// gzip-compressed.
+fn read_input(path: &Path) -> Result<Vec<u8>, CliError> {
+    let source = if is_stdin(path) {
+        let mut source = Vec::new();
+        io::stdin().lock().read_to_end(&mut source).map(|_| source)
+    } else {
+        fs::read(path)
+    }
+    .map_err(|e| CliError::ProgramReadError { msg: e.to_string() })?;
+    if source.starts_with(&GZIP_MAGIC) {
+        decompress(&source)
+            .map_err(|e| CliError::ProgramReadError { msg: e.to_string() })
+    } else {
+        Ok(source)
+    }
+}
+
+/// `<dir>/<stem>.so`, or `<dir>/<stem>.so.gz` when compressing. A trailing
+/// `.gz` on the input is not part of the stem, and stdin's is `main`.
+fn output_path(input: &Path, compress: bool) -> PathBuf {
+    let extension = if compress { "so.gz" } else { "so" };
+    if is_stdin(input) {
+        return PathBuf::from(format!("main.{extension}"));
+    }
+    let input = match input.extension() {
+        Some(ext) if ext == "gz" => input.with_extension(""),
+        _ => input.to_path_buf(),
+    };
+    let src_name =
+        input.file_stem().and_then(|s| s.to_str()).unwrap_or("main");
+    input
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(format!("{src_name}.{extension}"))
+}
+
+/// `program` with its `.so` or `.so.gz` extension replaced by `.s`.
+fn asm_path(program: &Path) -> PathBuf {
+    let program = match program.extension() {
+        Some(ext) if ext == "gz" => program.with_extension(""),
+        _ => program.to_path_buf(),
+    };
+    program.with_extension("s")
+}
+
+/// Where `--split-debug` writes the debug companion of `program`.
+fn debug_path(program: &Path) -> PathBuf {
+    let program = match program.extension() {
+        Some(ext) if ext == "gz" => program.with_extension(""),
+        _ => program.to_path_buf(),
+    };
+    program.with_extension("debug.so")
+}
+
+/// Prints the section layout of `source`, see `--list-sections`.
+fn print_sections(source: &[u8]) -> Result<(), CliError> {
+    println!(
+        "{:<24} {:<8} {:>10} {:>10} {:>6} {:>7}",
+        "name", "kind", "address", "size", "align", "relocs"
+    );
+    for section in inspect_sections(source)? {
+        println!(
+            "{:<24} {:<8} {:>#10x} {:>#10x} {:>6} {:>7}",
+            section.name,
+            section.kind,
+            section.address,
+            section.size,
+            section.align,
+            section.relocation_count
+        );
+    }
+    Ok(())
+}
+
+/// Prints the sections, symbols, relocations and disassembled .text of
+/// `source`, an object or a linked program, for `sbpf-link inspect`.
+fn inspect(source: &[u8]) -> Result<(), CliError> {
+    println!("Sections:");
+    print_sections(source)?;
+
+    println!("\nSymbols:");
+    println!(
+        "{:>18} {:>8} {:<8} {:<6} {:<16} name",
+        "address", "size", "kind", "bind", "section"
+    );
+    for symbol in inspect_symbols(source)? {
+        println!(
+            "{:>#18x} {:>#8x} {:<8} {:<6} {:<16} {}",
+            symbol.address,
+            symbol.size,
+            symbol.kind,
+            if symbol.global { "global" } else { "local" },
+            symbol.section.as_deref().unwrap_or("*UND*"),
+            symbol.name
+        );
+    }
+
+    println!("\nRelocations:");
+    println!("{:<16} {:>10} {:<18} symbol", "section", "offset", "type");
+    for relocation in inspect_relocations(source)? {
+        println!(
+            "{:<16} {:>#10x} {:<18} {}",
+            relocation.section,
+            relocation.offset,
+            relocation.kind,
+            relocation.symbol
+        );
+    }
+
+    println!("\nDisassembly of .text:");
+    disassemble(source)?.iter().for_each(print_instruction_text);
+    Ok(())
+}
+
+/// Prints `instruction` as `objdump -dr` does: under the symbols at its
+/// address, with its bytes, and followed by the relocations patching it.
+fn print_instruction_text(instruction: &DisassembledInstruction) {
+    for label in &instruction.labels {
+        println!("\n{:016x} <{label}>:", instruction.address);
+    }
+    // an `lddw` takes two lines of eight bytes
+    for (line, chunk) in instruction.bytes.chunks(8).enumerate() {
+        let hex: Vec<_> = chunk.iter().map(|b| format!("{b:02x}")).collect();
+        if line == 0 {
+            println!(
+                "{:>8x}:\t{}\t{}",
+                instruction.address,
+                hex.join(" "),
+                instruction.asm
+            );
+        } else {
+            println!("{:>8}\t{}", "", hex.join(" "));
+        }
+    }
+    for relocation in &instruction.relocations {
+        println!(
+            "{:>8}\t\t{:x}:  {}\t{}",
+            "", instruction.address, relocation.kind, relocation.symbol
+        );
+    }
+}
+
+/// Prints the frame size and call depth of `function`, flagging frames
+/// over the limit.
+fn print_stack_text(function: &StackUsage) {
+    let depth = function
+        .call_depth
+        .map_or_else(|| String::from("recursive"), |depth| depth.to_string());
+    let flag = if function.overflows() { "  OVERFLOW" } else { "" };
+    println!(
+        "{:>8} {:>9}  {}{flag}",
+        function.frame_size, depth, function.name
+    );
+}
+
+fn stack_json(functions: &[StackUsage]) -> serde_json::Value {
+    functions
+        .iter()
+        .map(|function| {
+            serde_json::json!({
+                "name": function.name,
+                "offset": function.offset,
+                "frame_size": function.frame_size,
+                "call_depth": function.call_depth,
+                "calls": function.calls,
+                "overflows": function.overflows(),
+            })
+        })
+        .collect()
+}
+
+/// Prints the compute unit bound of `function`, or why it has none.
+fn print_compute_text(function: &ComputeEstimate) {
+    match (function.compute_units, &function.unbounded_by) {
+        (Some(units), _) => println!("{units:>10}  {}", function.name),
+        (None, reason) => println!(
+            "{:>10}  {} ({})",
+            "unbounded",
+            function.name,
+            reason.as_deref().unwrap_or("unknown")
+        ),
+    }
+}
+
+fn compute_json(functions: &[ComputeEstimate]) -> serde_json::Value {
+    functions
+        .iter()
+        .map(|function| {
+            serde_json::json!({
+                "name": function.name,
+                "offset": function.offset,
+                "compute_units": function.compute_units,
+                "unbounded_by": function.unbounded_by,
+            })
+        })
+        .collect()
+}
+
+/// Prints a `hexdump -C` style dump of `rodata`, headed by its name, size
+/// and, for string-like data, the string itself.
+fn print_rodata_text(rodata: &RodataInfo) {
+    match rodata.as_str() {
+        Some(text) => {
+            println!("{} ({} bytes) {text:?}", rodata.name, rodata.bytes.len())
+        }
+        None => println!("{} ({} bytes)", rodata.name, rodata.bytes.len()),
+    }
+    for (line, chunk) in rodata.bytes.chunks(16).enumerate() {
+        let hex: Vec<_> = chunk.iter().map(|b| format!("{b:02x}")).collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }
+            })
+            .collect();
+        println!("  {:08x}  {:<47}  |{ascii}|", line * 16, hex.join(" "));
+    }
+}
+
+fn rodata_json(rodata: &[RodataInfo]) -> serde_json::Value {
+    rodata
+        .iter()
+        .map(|rodata| {
+            serde_json::json!({
+                "name": rodata.name,
+                "offset": rodata.offset,
+                "size": rodata.bytes.len(),
+                "bytes": rodata
+                    .bytes
+                    .iter()
+                    .map(|b| format!("{b:02x}"))
+                    .collect::<String>(),
+                "string": rodata.as_str(),
+            })
+        })
+        .collect()
+}
+
+fn print_xref_text(xref: &SymbolReferences) {
+    match &xref.section {
+        Some(section) => println!("{} ({section})", xref.symbol),
+        None => println!("{} (undefined)", xref.symbol),
+    }
+    for reference in &xref.references {
+        match reference.instruction {
+            Some(opcode) => println!(
+                "  {}+{:#x}  {opcode}",
+                reference.section, reference.offset
+            ),
+            None => {
+                println!("  {}+{:#x}", reference.section, reference.offset)
+            }
+        }
+    }
+}
+
+fn xref_json(xrefs: &[SymbolReferences]) -> serde_json::Value {
+    xrefs
+        .iter()
+        .map(|xref| {
+            serde_json::json!({
+                "symbol": xref.symbol,
+                "section": xref.section,
+                "references": xref
+                    .references
+                    .iter()
+                    .map(|reference| serde_json::json!({
+                        "section": reference.section,
+                        "offset": reference.offset,
+                        "instruction": reference
+                            .instruction
+                            .map(|opcode| opcode.to_string()),
+                    }))
+                    .collect::<Vec<_>>(),
+            })
+        })
+        .collect()
+}
+
+/// Compiles the `bitcode` inputs, named `labels` in messages, into one
+/// object with LLVM, see [`compile_bitcode`].
+#[cfg(feature = "llvm")]
+fn compile(
+    bitcode: &[PathBuf],
+    _labels: &[String],
+    options: &LinkOptions,
+) -> Result<Vec<u8>, CliError> {
+    Ok(compile_bitcode(bitcode, options)?)
+}
+
+/// Fails: without the `llvm` feature, there's no LLVM to compile the
+/// `bitcode` inputs, named `labels`, with.
+#[cfg(not(feature = "llvm"))]
+fn compile(
+    _bitcode: &[PathBuf],
+    labels: &[String],
+    _options: &LinkOptions,
+) -> Result<Vec<u8>, CliError> {
+    Err(CliError::ProgramReadError {
+        msg: format!(
+            "{} is LLVM bitcode or IR, which needs sbpf-link built with the `llvm` feature",
+            labels.join(", ")
+        ),
+    })
+}
+
+/// The contents of every gzip member of `bytes`, one after the other, as
+/// `gunzip` decompresses concatenated `.gz` files.
+fn decompress(bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    MultiGzDecoder::new(bytes).read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+fn compress(bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(bytes)?;
+    encoder.finish()
+}
@@ -1,4 +1,12 @@
-use std::{env, ffi::CString, fs, path::PathBuf, str::FromStr};
+use std::{
+    env,
+    ffi::CString,
+    fs,
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+    process,
+    str::FromStr,
+};
 
 #[cfg(any(
     feature = "rust-llvm-19",
@@ -6,9 +14,38 @@ use std::{env, ffi::CString, fs, path::PathBuf, str::FromStr};
     feature = "rust-llvm-21"
 ))]
 use aya_rustc_llvm_proxy as _;
-use bpf_linker::{Cpu, Linker, LinkerOptions, OptLevel, OutputType};
-use clap::{Parser, error::ErrorKind};
-use sbpf_linker::{SbpfLinkerError, link_program};
+use bpf_linker::{Cpu, LinkerOptions, OptLevel, OutputType};
+use clap::{CommandFactory as _, Parser, ValueEnum, error::ErrorKind};
+use sbpf_linker::{
+    LinkOptions, SbpfLinkerError,
+    archive::{archive_members, is_archive},
+    bitcode::{defined_functions, is_bitcode},
+    btf::add_btf,
+    cache::{CacheKey, LinkCache},
+    config::with_defaults,
+    debug::{Strip, add_symbols, split_debug},
+    defsym::Defsym,
+    diagnostic::{
+        ErrorFormat, Location, WarningOptions, init_logging, json_diagnostic,
+    },
+    export::{expand_exports, is_pattern},
+    icf::Icf,
+    layout::SectionStart,
+    lines::line_map,
+    link_objects_verbose, link_objects_with_map, link_program_with_options,
+    link_with_llvm,
+    lld::{is_lld_invocation, translate_args},
+    llvm_object,
+    loader::LoaderVersion,
+    output::{STDOUT, write_atomic},
+    program_hash,
+    rename::parse_rename_map,
+    response::expand_response_files,
+    script::parse_linker_script,
+    splice::InstructionSequence,
+    timings::{collect_timings, stage},
+    version::SbpfVersion,
+};
 
 #[derive(Debug, thiserror::Error)]
 enum CliError {
@@ -20,14 +57,42 @@ enum CliError {
     ClapParseError,
     #[error("SBPF Linker Error. Error detail: ({0}).")]
     SbpfLinkerError(#[from] SbpfLinkerError),
-    #[error("Program Read Error. Error detail: ({msg}).")]
-    ProgramReadError { msg: String },
     #[error("Program Write Error. Error detail: ({msg}).")]
     ProgramWriteError { msg: String },
     //     #[error("unknown emission type: `{0}` - expected one of: `llvm-bc`, `asm`, `llvm-ir`, `obj`")]
     //     InvalidOutputType(String),
 }
 
+impl CliError {
+    /// The error as a rustc style JSON diagnostic, pointing into `file`,
+    /// where the object LLVM produced was kept, if it's about one of its
+    /// sections.
+    fn to_json(&self, file: Option<&Path>) -> serde_json::Value {
+        let (code, message, mut location) = match self {
+            Self::SbpfLinkerError(error) => {
+                (error.code(), error.to_string(), error.location())
+            }
+            Self::ProgramWriteError { msg } => {
+                ("program_write_error", msg.clone(), Location::default())
+            }
+            error => {
+                ("invalid_arguments", error.to_string(), Location::default())
+            }
+        };
+        if location.section.is_some() && location.file.is_none() {
+            location.file = file.map(Path::to_path_buf);
+        }
+        json_diagnostic("error", Some(code), &message, &location)
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum IcfMode {
+    None,
+    Safe,
+    All,
+}
+
 #[derive(Copy, Clone, Debug)]
 struct CliOptLevel(OptLevel);
 
@@ -48,7 +113,7 @@ impl FromStr for CliOptLevel {
 }
 
 #[derive(Debug, Parser)]
-#[command(version)]
+#[command(version, args_override_self = true)]
 struct CommandLine {
     /// LLVM target triple. When not provided, the target is inferred from the inputs
     #[clap(long)]
@@ -58,11 +123,40 @@ struct CommandLine {
     #[clap(long, default_value = "generic")]
     cpu: Cpu,
 
-    /// Write output to <output>
+    /// Name the program after <output>, where `--keep-obj` writes the
+    /// intermediate object. With `-`, the program is written to stdout
     #[clap(short, long, required = true)]
     output: PathBuf,
 
-    /// Emit BTF information
+    /// Write the program to <path>, or to stdout if it's `-`. Defaults to
+    /// `<stem>.so` next to `--output`
+    #[clap(long, value_name = "path")]
+    so_output: Option<PathBuf>,
+
+    /// Also write the intermediate object to `--output`, which is otherwise
+    /// only kept in memory
+    #[clap(long)]
+    keep_obj: bool,
+
+    /// Fail instead of overwriting an existing program or debug companion
+    #[clap(long)]
+    no_clobber: bool,
+
+    /// Print the program's hash as verified build tooling computes it: the
+    /// SHA-256 of the `.so`, without trailing zero bytes
+    #[clap(long)]
+    print_hash: bool,
+
+    /// Reuse the program linked before from the same inputs and options,
+    /// stored in `dir`, instead of running LLVM and relinking again, and
+    /// store it there otherwise. Only links of just the program are cached,
+    /// not those also keeping the object, dumping the module or writing a
+    /// line map, debug info or BTF, and none that raised warnings
+    #[clap(long, value_name = "dir")]
+    cache_dir: Option<PathBuf>,
+
+    /// Emit BTF information, and keep it in the program as `.BTF` and
+    /// `.BTF.ext`
     #[clap(long)]
     btf: bool,
 
@@ -79,9 +173,10 @@ struct CommandLine {
     #[clap(short = 'O', default_value = "2")]
     optimize: Vec<CliOptLevel>,
 
-    /// Export the symbols specified in the file `path`. The symbols must be separated by new lines
+    /// Export the symbols specified in the file `path`. The symbols must be separated by new lines.
+    /// Can be repeated to combine several files
     #[clap(long, value_name = "path")]
-    export_symbols: Option<PathBuf>,
+    export_symbols: Vec<PathBuf>,
 
     /// Try hard to unroll loops. Useful when targeting kernels that don't support loops
     #[clap(long)]
@@ -113,28 +208,311 @@ struct CommandLine {
     #[clap(required = true)]
     inputs: Vec<PathBuf>,
 
-    /// Comma separated list of symbols to export. See also `--export-symbols`
+    /// Comma separated list of symbols to export. A glob pattern, e.g.
+    /// `*process_instruction*`, exports every function of the inputs it
+    /// matches, and fails if none does. See also `--export-symbols`
     #[clap(long, value_name = "symbols", use_value_delimiter = true, action = clap::ArgAction::Append)]
     export: Vec<String>,
 
+    /// Warn about exports that aren't functions in .text, e.g. a typo in
+    /// `--export-symbols`, instead of failing the link
+    #[clap(long)]
+    allow_undefined_exports: bool,
+
+    /// Stamp a minimum VM version (0-3) into the program and encode its
+    /// instructions for it, e.g. `lddw` as `mov32` and `hor64` from v2.
+    /// Fails if the program uses instructions that version can't execute
+    #[clap(long, value_name = "n")]
+    require_vm_version: Option<SbpfVersion>,
+
+    /// Target SBPF version (v0-v3), the same as `--require-vm-version`:
+    /// stamps the version into the program and rewrites the instructions it
+    /// has removed, e.g. `lddw` from v2 on. `--no-deprecated` fails on them
+    /// instead
+    #[clap(
+        long,
+        value_name = "version",
+        conflicts_with = "require_vm_version"
+    )]
+    sbpf_version: Option<SbpfVersion>,
+
+    /// Define `symbol` as an alias of another symbol or as an absolute
+    /// constant, e.g. `--defsym alias=existing` or `--defsym base=0x1000`.
+    /// An alias of a function can be the `--entry` or an export
+    #[clap(long, value_name = "symbol=expression")]
+    defsym: Vec<Defsym>,
+
+    /// Fail if the program uses instructions deprecated in the VM version
+    /// given by `--require-vm-version` or `--sbpf-version`
+    #[clap(long)]
+    no_deprecated: bool,
+
+    /// Report every decision the linker makes about `symbol`. Can be
+    /// repeated
+    #[clap(long, value_name = "symbol")]
+    trace_symbol: Vec<String>,
+
+    /// Keep the first of several definitions of a symbol, with a warning,
+    /// instead of failing
+    #[clap(long)]
+    allow_multiple_definition: bool,
+
+    /// Fail if the program would embed any read-only data, e.g. string
+    /// literals
+    #[clap(long)]
+    no_rodata: bool,
+
+    /// Lay out read-only constants with the same bytes once, e.g. string
+    /// literals repeated across objects. Distinct constants may then share
+    /// an address
+    #[clap(long)]
+    dedup_rodata: bool,
+
+    /// Rename symbols as listed in the file `path`, one `old_name new_name`
+    /// pair per line
+    #[clap(long, value_name = "path")]
+    rename_symbols: Option<PathBuf>,
+
+    /// Instructions to run on entry, as sbpf assembly (separated by `;`) or
+    /// hex-encoded bytecode
+    #[clap(long, value_name = "asm-or-bytes")]
+    prologue: Option<InstructionSequence>,
+
+    /// Instructions to run before every `exit`, as sbpf assembly (separated
+    /// by `;`) or hex-encoded bytecode
+    #[clap(long, value_name = "asm-or-bytes")]
+    epilogue: Option<InstructionSequence>,
+
+    /// Start execution at the function `symbol` instead of at the start of
+    /// .text
+    #[clap(long, value_name = "symbol")]
+    entry: Option<String>,
+
+    /// Decode the linked program again and fail unless it holds exactly
+    /// the instructions that were assembled
+    #[clap(long)]
+    verify: bool,
+
+    /// Skip the checks the loader's verifier makes over the linked program,
+    /// for programs it rejects that the loader doesn't
+    #[clap(long)]
+    no_verifier: bool,
+
+    /// Fail if a function uses more than the 4 KiB of stack its frame holds
+    #[clap(long)]
+    check_stack: bool,
+
+    /// Fail if a function uses more than `bytes` of stack, at most the
+    /// 4096 of its frame
+    #[clap(long, value_name = "bytes")]
+    stack_size: Option<u64>,
+
+    /// Bytes of heap the program expects, from 32 KiB to 256 KiB in
+    /// multiples of 1 KiB. Transactions request it, so this only checks the
+    /// size and, above the 32 KiB they get anyway, warns that they have to
+    #[clap(long, value_name = "bytes")]
+    heap_size: Option<u64>,
+
+    /// Drop functions and constants unreachable from the entry point and
+    /// the exports
+    #[clap(long)]
+    gc_sections: bool,
+
+    /// Fold functions with identical code into one: `safe` only those
+    /// whose address nothing takes, `all` any
+    #[clap(long, value_enum, value_name = "mode", default_value = "none")]
+    icf: IcfMode,
+
+    /// Place input sections as the GNU ld style linker script `path`
+    /// says, e.g. the platform tools' `bpf.ld`
+    #[clap(short = 'T', long, value_name = "path")]
+    script: Option<PathBuf>,
+
+    /// Lay out the sections defining the symbols listed in the file `path`,
+    /// one per line, first and in that order, as lld does. Orders functions
+    /// one by one if they're built with `-ffunction-sections`
+    #[clap(long, value_name = "path")]
+    symbol_ordering_file: Option<PathBuf>,
+
+    /// Load `.text` or `.rodata` at `address` instead of at its offset in
+    /// the file, e.g. `--section-start .text=0x0`. Can be repeated. Takes
+    /// `--require-vm-version v1` or later
+    #[clap(long, value_name = "section=address")]
+    section_start: Vec<SectionStart>,
+
+    /// Keep `.rodata` right after `.text` when `--section-start` moves
+    /// `.text`, instead of where it is
+    #[clap(long)]
+    rodata_after_text: bool,
+
+    /// Emit the stricter layout SBPF v3 loaders expect: `.text` alone at
+    /// 0x0, `.rodata` alone at 0x100000000 and nothing else loaded. Fails
+    /// if the program can't have it. Takes `--require-vm-version v3`
+    #[clap(long)]
+    strict_elf: bool,
+
+    /// Fail if the program won't load on the given loader (v1-v4)
+    #[clap(long, value_name = "loader")]
+    verify_loader: Option<LoaderVersion>,
+
+    /// Write the source line of each instruction of the program to `path`,
+    /// from the DWARF line tables LLVM emits, one `address file:line` per
+    /// line
+    #[clap(long, value_name = "path")]
+    line_map: Option<PathBuf>,
+
+    /// Add a build ID to the program and write its symbols and line tables
+    /// to a `.debug.so` companion next to it, for debuggers to find by the
+    /// build ID
+    #[clap(long, conflicts_with_all = ["strip_debug", "no_strip"])]
+    split_debug: bool,
+
+    /// Keep only the dynamic symbols of the exports, which the loader
+    /// needs. This is the default
+    #[clap(long, conflicts_with_all = ["strip_debug", "no_strip"])]
+    strip_all: bool,
+
+    /// Keep a symbol table of every function and rodata symbol, but no
+    /// debug info
+    #[clap(long, conflicts_with = "no_strip")]
+    strip_debug: bool,
+
+    /// Keep the symbol table and the DWARF line tables LLVM emits
+    #[clap(long)]
+    no_strip: bool,
+
+    /// Make the output byte-identical wherever it's linked, by writing
+    /// source paths relative to the current directory
+    #[clap(long)]
+    reproducible: bool,
+
     /// Whether to treat LLVM errors as fatal.
     #[clap(long, action = clap::ArgAction::Set, default_value_t = true)]
     fatal_errors: bool,
 
+    /// Enable a warning (`-W<name>`, e.g. `-Wunreachable-code`), disable it
+    /// (`-Wno-<name>`), enable all of them (`-Wall`) or turn them into
+    /// errors (`-Werror`). Can be repeated
+    #[clap(short = 'W', value_name = "warning")]
+    warning_flags: Vec<String>,
+
+    /// How to print errors and warnings: as text, or as rustc style JSON
+    /// diagnostics, one per line
+    #[clap(long, value_enum, default_value_t = ErrorFormat::Human)]
+    error_format: ErrorFormat,
+
+    /// Log what the linker does: each step with `-v`, and every section,
+    /// relocation and function it handles with `-vv`
+    #[clap(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Write the `-v` log to `path` instead of stderr
+    #[clap(long, value_name = "path")]
+    log_file: Option<PathBuf>,
+
+    /// Print to stderr how long each stage of the link took, and the peak
+    /// memory by its end
+    #[clap(long)]
+    timings: bool,
+
     // The options below are for wasm-ld compatibility
     #[clap(long = "debug", hide = true)]
     _debug: bool,
+
+    /// The linker flavor rustc asks for, which only turns on the ld.lld
+    /// compatibility, see `lld::translate_args`
+    #[clap(long = "flavor", hide = true)]
+    _flavor: Option<String>,
 }
 
 fn main() -> Result<(), CliError> {
-    let args = env::args().map(|arg| {
-        if arg == "-flavor" { "--flavor".to_string() } else { arg }
-    });
+    let args = match expand_response_files(env::args()) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("error: {err}");
+            return Err(err.into());
+        }
+    };
+    // run by rustc, or as a drop-in `ld.lld`
+    let args = if is_lld_invocation(&args) {
+        match translate_args(&args) {
+            Ok(lld) => lld.args,
+            Err(err) => {
+                eprintln!("error: {err}");
+                return Err(err.into());
+            }
+        }
+    } else {
+        args
+    };
+    let args =
+        match with_defaults("sbpf-linker", &CommandLine::command(), args) {
+            Ok(args) => args,
+            Err(err) => {
+                eprintln!("error: {err}");
+                return Err(err.into());
+            }
+        };
+
+    let command_line: CommandLine = match Parser::try_parse_from(args) {
+        Ok(command_line) => command_line,
+        Err(err) => match err.kind() {
+            ErrorKind::DisplayHelp | ErrorKind::DisplayVersion => {
+                print!("{err}");
+                return Ok(());
+            }
+            _ => {
+                // Let Clap handle its own error display for better formatting
+                eprintln!("{err}");
+                return Err(CliError::ClapParseError);
+            }
+        },
+    };
+
+    // section offsets point into the object LLVM produces, which is only
+    // on disk, at --output, with --keep-obj
+    let object = (command_line.keep_obj
+        && command_line.output != Path::new(STDOUT))
+    .then(|| command_line.output.clone());
+    let error_format = command_line.error_format;
+    let log_file = match &command_line.log_file {
+        Some(path) => match fs::File::create(path) {
+            Ok(log_file) => Some(log_file),
+            Err(err) => {
+                eprintln!("error: can't create {}: {err}", path.display());
+                return Err(CliError::ProgramWriteError {
+                    msg: err.to_string(),
+                });
+            }
+        },
+        None => None,
+    };
+    init_logging(error_format, object.clone(), command_line.verbose, log_file);
+
+    let result = if command_line.timings {
+        let (result, timings) = collect_timings(|| link(command_line));
+        eprintln!("{timings}");
+        result
+    } else {
+        link(command_line)
+    };
+    if let (Err(error), ErrorFormat::Json) = (&result, error_format) {
+        eprintln!("{}", error.to_json(object.as_deref()));
+        process::exit(1);
+    }
+    result
+}
 
+fn link(command_line: CommandLine) -> Result<(), CliError> {
     let CommandLine {
         target,
         cpu,
         output,
+        so_output,
+        keep_obj,
+        no_clobber,
+        print_hash,
+        cache_dir,
         btf,
         allow_bpf_trap,
         libs,
@@ -148,48 +526,150 @@ fn main() -> Result<(), CliError> {
         disable_memory_builtins,
         inputs,
         export,
+        allow_undefined_exports,
+        require_vm_version,
+        sbpf_version,
+        defsym,
+        no_deprecated,
+        trace_symbol,
+        allow_multiple_definition,
+        no_rodata,
+        dedup_rodata,
+        rename_symbols,
+        prologue,
+        epilogue,
+        entry,
+        verify_loader,
+        verify,
+        no_verifier,
+        check_stack,
+        stack_size,
+        heap_size,
+        gc_sections,
+        icf,
+        script,
+        symbol_ordering_file,
+        section_start,
+        rodata_after_text,
+        strict_elf,
+        line_map: line_map_path,
+        split_debug: split,
+        strip_all: _,
+        strip_debug,
+        no_strip,
+        reproducible,
         fatal_errors,
+        warning_flags,
+        error_format: _,
+        verbose: _,
+        log_file: _,
+        timings: _,
         _debug,
-    } = match Parser::try_parse_from(args) {
-        Ok(command_line) => command_line,
-        Err(err) => match err.kind() {
-            ErrorKind::DisplayHelp | ErrorKind::DisplayVersion => {
-                print!("{err}");
-                return Ok(());
-            }
-            _ => {
-                // Let Clap handle its own error display for better formatting
-                eprintln!("{err}");
-                return Err(CliError::ClapParseError);
-            }
-        },
-    };
+        _flavor,
+    } = command_line;
 
-    let export_symbols =
-        export_symbols.map(fs::read_to_string).transpose().map_err(|e| {
+    let export_symbols = export_symbols
+        .into_iter()
+        .map(fs::read_to_string)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| {
             CliError::SbpfLinkerError(SbpfLinkerError::ObjectFileReadError(e))
         })?;
 
-    // TODO: the data is owned by this call frame; we could make this zero-alloc.
-    let export_symbols = export_symbols
-        .as_deref()
-        .into_iter()
-        .flat_map(str::lines)
+    // a symbol listed more than once, in --export-symbols files or by
+    // --export, is exported once, where it's first listed
+    let mut exports: Vec<String> = Vec::new();
+    for symbol in export_symbols
+        .iter()
+        .flat_map(|symbols| symbols.lines())
         .map(str::to_owned)
         .chain(export)
-        .map(Into::into)
-        .collect();
+    {
+        if !exports.contains(&symbol) {
+            exports.push(symbol);
+        }
+    }
+    // LLVM keeps the exports by name, so patterns are matched against the
+    // functions the bitcode inputs define
+    let exports = if exports.iter().any(|export| is_pattern(export)) {
+        let mut functions = Vec::new();
+        for input in &inputs {
+            let bytes = fs::read(input).map_err(|e| {
+                CliError::SbpfLinkerError(
+                    SbpfLinkerError::ObjectFileReadError(e),
+                )
+            })?;
+            if is_archive(&bytes) {
+                for member in archive_members(&bytes)? {
+                    if is_bitcode(member.data) {
+                        functions
+                            .extend(defined_functions(input, member.data)?);
+                    }
+                }
+            } else if is_bitcode(&bytes) {
+                functions.extend(defined_functions(input, &bytes)?);
+            }
+        }
+        expand_exports(&exports, functions.iter().map(String::as_str))?
+    } else {
+        exports
+    };
+    let export_symbols = exports.iter().cloned().map(Into::into).collect();
+
+    let linker_script = match script {
+        Some(path) => Some(parse_linker_script(
+            &fs::read_to_string(path).map_err(|e| {
+                CliError::SbpfLinkerError(
+                    SbpfLinkerError::ObjectFileReadError(e),
+                )
+            })?,
+        )?),
+        None => None,
+    };
+
+    let symbol_ordering = match symbol_ordering_file {
+        Some(path) => fs::read_to_string(path)
+            .map_err(|e| {
+                CliError::SbpfLinkerError(
+                    SbpfLinkerError::ObjectFileReadError(e),
+                )
+            })?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_owned)
+            .collect(),
+        None => Vec::new(),
+    };
+
+    let renames = match rename_symbols {
+        Some(path) => {
+            parse_rename_map(&fs::read_to_string(path).map_err(|e| {
+                CliError::SbpfLinkerError(
+                    SbpfLinkerError::ObjectFileReadError(e),
+                )
+            })?)?
+        }
+        None => Vec::new(),
+    };
+
+    let mut warnings = WarningOptions::default();
+    for flag in &warning_flags {
+        warnings.apply(flag)?;
+    }
 
     let optimize = match *optimize.as_slice() {
         [] => unreachable!("emit has a default value"),
         [.., CliOptLevel(optimize)] => optimize,
     };
 
-    let mut linker = Linker::new(LinkerOptions {
+    let first_input = inputs[0].clone();
+    let linker_options = LinkerOptions {
         target,
         cpu,
         cpu_features: String::new(),
         inputs,
+        // the object is written to `output` after the link if it's kept
         output: output.clone(),
         output_type: OutputType::Object,
         libs,
@@ -206,33 +686,221 @@ fn main() -> Result<(), CliError> {
         disable_memory_builtins,
         btf,
         allow_bpf_trap,
+    };
+    let options = LinkOptions {
+        require_vm_version: require_vm_version.or(sbpf_version),
+        defsyms: defsym,
+        reject_deprecated: no_deprecated,
+        trace_symbols: trace_symbol,
+        allow_multiple_definition,
+        forbid_rodata: no_rodata,
+        dedup_rodata,
+        renames,
+        prologue,
+        epilogue,
+        entry,
+        exports,
+        allow_undefined_exports,
+        verify_loader,
+        verify_round_trip: verify,
+        skip_verifier: no_verifier,
+        check_stack,
+        stack_size,
+        heap_size,
+        gc_sections,
+        icf: match icf {
+            IcfMode::None => Icf::None,
+            IcfMode::Safe => Icf::Safe,
+            IcfMode::All => Icf::All,
+        },
+        linker_script,
+        symbol_ordering,
+        section_starts: section_start,
+        rodata_after_text,
+        strict_elf,
+        warnings,
+        reproducible,
+    };
+
+    let stdout = Path::new(STDOUT);
+    let output_path = so_output.unwrap_or_else(|| {
+        if output == stdout {
+            return stdout.to_path_buf();
+        }
+        let src_name =
+            output.file_stem().and_then(|s| s.to_str()).unwrap_or("main");
+        output
+            .parent()
+            .unwrap_or_else(|| std::path::Path::new("."))
+            .join(format!("{src_name}.so"))
     });
 
-    linker.link().map_err(|e| {
-        CliError::SbpfLinkerError(SbpfLinkerError::LinkerError(e))
-    })?;
+    // with the program on stdout, the debug companion is named after the
+    // first input and status lines go to stderr
+    let to_stdout = output_path == stdout;
+    let debug_path = if to_stdout {
+        first_input.with_extension("debug.so")
+    } else {
+        output_path.with_extension("debug.so")
+    };
+    let check_output = |path: &Path| {
+        if no_clobber && path.exists() {
+            return Err(SbpfLinkerError::OutputExists(path.to_path_buf()));
+        }
+        Ok(())
+    };
+    check_output(&output_path)?;
+    if split {
+        check_output(&debug_path)?;
+    }
+
+    let strip = if no_strip {
+        Strip::Nothing
+    } else if strip_debug {
+        Strip::Debug
+    } else {
+        Strip::All
+    };
+    // keyed by the inputs as LLVM reads them, and how it compiles them
+    let cache = match cache_dir {
+        Some(dir)
+            if !keep_obj
+                && linker_options.dump_module.is_none()
+                && line_map_path.is_none()
+                && !split
+                && strip == Strip::All
+                && !linker_options.btf
+                && options.trace_symbols.is_empty() =>
+        {
+            let mut key = CacheKey::new(&options);
+            key.add(
+                format!(
+                    "{:?}",
+                    (
+                        &linker_options.target,
+                        &linker_options.cpu,
+                        &linker_options.libs,
+                        &linker_options.optimize,
+                        linker_options.unroll_loops,
+                        linker_options.ignore_inline_never,
+                        &linker_options.llvm_args,
+                        linker_options.disable_expand_memcpy_in_order,
+                        linker_options.disable_memory_builtins,
+                        linker_options.btf,
+                        linker_options.allow_bpf_trap,
+                        fatal_errors,
+                    )
+                )
+                .as_bytes(),
+            );
+            for input in &linker_options.inputs {
+                key.add(&fs::read(input).map_err(|e| {
+                    CliError::SbpfLinkerError(
+                        SbpfLinkerError::ObjectFileReadError(e),
+                    )
+                })?);
+            }
+            Some((LinkCache::new(dir), key))
+        }
+        _ => None,
+    };
+    let cached = cache.as_ref().and_then(|(cache, key)| {
+        let program = cache.get(key)?;
+        tracing::debug!("using the program cached as {key}");
+        Some(program)
+    });
+    // the object LLVM produced, if it's kept
+    let mut kept_object = None;
+    let bytecode = if let Some(bytecode) = cached {
+        bytecode
+    } else {
+        tracing::debug!(
+            "compiling {} input(s) with LLVM",
+            linker_options.inputs.len()
+        );
+        let btf = linker_options.btf;
+        if line_map_path.is_some() || split || strip != Strip::All || btf {
+            // the line tables and BTF are read from the object LLVM produced
+            let object = llvm_object(linker_options, fatal_errors)?;
+            let (mut bytecode, map) =
+                link_objects_with_map(&[&object], &options)?;
+            let lines = line_map(&[&object], &map, &options)?;
+            if let Some(path) = &line_map_path {
+                write_atomic(path, lines.render()).map_err(|e| {
+                    CliError::ProgramWriteError { msg: e.to_string() }
+                })?;
+            }
+            match strip {
+                Strip::All => {}
+                Strip::Debug => add_symbols(&mut bytecode, &map, None)?,
+                Strip::Nothing => {
+                    add_symbols(&mut bytecode, &map, Some(&lines))?
+                }
+            }
+            if btf {
+                add_btf(&mut bytecode, &[&object], &map, &options)?;
+            }
+            if keep_obj {
+                kept_object = Some(object);
+            }
+            if split {
+                let split = split_debug(&bytecode, &map, &lines)?;
+                write_atomic(&debug_path, split.debug).map_err(|e| {
+                    CliError::ProgramWriteError { msg: e.to_string() }
+                })?;
+                split.program
+            } else {
+                bytecode
+            }
+        } else if let Some((cache, key)) = &cache {
+            let object = llvm_object(linker_options, fatal_errors)?;
+            let linked = link_objects_verbose(&[&object], &options)?;
+            if linked.diagnostics.is_empty() {
+                cache.store(key, &linked.program);
+            }
+            linked.program
+        } else if keep_obj {
+            let object = llvm_object(linker_options, fatal_errors)?;
+            let program = link_program_with_options(&object, &options)?;
+            kept_object = Some(object);
+            program
+        } else {
+            link_with_llvm(linker_options, fatal_errors, &options)?
+        }
+    };
 
-    if fatal_errors && linker.has_errors() {
-        return Err(CliError::SbpfLinkerError(
-            SbpfLinkerError::LlvmDiagnosticError,
-        ));
+    let write = stage("write");
+    let written = if to_stdout {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(&bytecode).and_then(|()| stdout.flush())
+    } else {
+        write_atomic(&output_path, &bytecode)
+    };
+    written.map_err(|e| CliError::ProgramWriteError { msg: e.to_string() })?;
+    drop(write);
+    // as `sha256sum` prints it, which names stdin `-` too
+    if print_hash {
+        let hash =
+            format!("{}  {}", program_hash(&bytecode), output_path.display());
+        if to_stdout {
+            eprintln!("{hash}");
+        } else {
+            println!("{hash}");
+        }
     }
 
-    let program = std::fs::read(&output)
-        .map_err(|e| CliError::ProgramReadError { msg: e.to_string() })?;
-    let bytecode =
-        link_program(&program).map_err(CliError::SbpfLinkerError)?;
-
-    let src_name = std::path::Path::new(&output)
-        .file_stem()
-        .and_then(|s| s.to_str())
-        .unwrap_or("main");
-    let output_path = std::path::Path::new(&output)
-        .parent()
-        .unwrap_or_else(|| std::path::Path::new("."))
-        .join(format!("{src_name}.so"));
-    std::fs::write(output_path, bytecode)
-        .map_err(|e| CliError::ProgramWriteError { msg: e.to_string() })?;
+    // with the default names, e.g. `-o program.so`, the program takes the
+    // object's place
+    let replaces_object = output == output_path
+        || output == stdout
+        || fs::canonicalize(&output).ok()
+            == fs::canonicalize(&output_path).ok();
+    if let Some(object) = kept_object
+        && !replaces_object
+    {
+        write_atomic(&output, object)
+            .map_err(|e| CliError::ProgramWriteError { msg: e.to_string() })?;
+    }
 
     Ok(())
 }
@@ -0,0 +1,252 @@
+use std::path::Path;
+#[cfg(feature = "llvm")]
+use std::{
+    borrow::Cow,
+    env,
+    ffi::CStr,
+    fs,
+    path::PathBuf,
+    process, ptr, slice,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+#[cfg(feature = "llvm")]
+use bpf_linker::llvm_sys::{
+    LLVMLinkage,
+    bit_writer::LLVMWriteBitcodeToMemoryBuffer,
+    core::{
+        LLVMContextCreate, LLVMContextDispose,
+        LLVMCreateMemoryBufferWithMemoryRangeCopy, LLVMDisposeMemoryBuffer,
+        LLVMDisposeMessage, LLVMDisposeModule, LLVMGetBufferSize,
+        LLVMGetBufferStart, LLVMGetFirstFunction, LLVMGetLinkage,
+        LLVMGetNextFunction, LLVMGetValueName2, LLVMIsDeclaration,
+    },
+    ir_reader::LLVMParseIRInContext,
+    prelude::LLVMModuleRef,
+};
+#[cfg(feature = "llvm")]
+use bpf_linker::{Cpu, LinkerOptions, OptLevel, OutputType};
+
+#[cfg(feature = "llvm")]
+use crate::{
+    LinkOptions, SbpfLinkerError,
+    export::{expand_exports, is_pattern},
+    llvm_object,
+};
+
+/// Magic bytes at the start of an LLVM bitcode file.
+pub const BITCODE_MAGIC: &[u8] = b"BC\xc0\xde";
+
+/// Magic bytes of the wrapper some platforms put around bitcode.
+pub const BITCODE_WRAPPER_MAGIC: &[u8] = b"\xde\xc0\x17\x0b";
+
+/// Extension of textual LLVM IR files.
+pub const LLVM_IR_EXTENSION: &str = "ll";
+
+/// Distinguishes the scratch directories of concurrent calls.
+#[cfg(feature = "llvm")]
+static SCRATCH_ID: AtomicUsize = AtomicUsize::new(0);
+
+pub fn is_bitcode(bytes: &[u8]) -> bool {
+    bytes.starts_with(BITCODE_MAGIC)
+        || bytes.starts_with(BITCODE_WRAPPER_MAGIC)
+}
+
+/// Whether `path` names textual LLVM IR, by its extension.
+pub fn is_llvm_ir(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == LLVM_IR_EXTENSION)
+}
+
+/// Compiles the LLVM bitcode and textual IR files `inputs` into one BPF
+/// object with LLVM through `bpf_linker`, as `sbpf-linker` does with its
+/// default settings, ready to be relinked. The program's entry point and
+/// exports are kept; LLVM internalizes everything else.
+///
+/// Textual IR is assembled to bitcode first, since `bpf_linker` only reads
+/// bitcode. The intermediate files live in a scratch directory under
+/// [`env::temp_dir`] that is removed afterwards.
+#[cfg(feature = "llvm")]
+pub fn compile_bitcode(
+    inputs: &[PathBuf],
+    options: &LinkOptions,
+) -> Result<Vec<u8>, SbpfLinkerError> {
+    // LLVM keeps the exports by name, so patterns are matched against the
+    // functions the inputs define first
+    let expanded;
+    let options = if options.exports.iter().any(|export| is_pattern(export)) {
+        let mut functions = Vec::new();
+        for input in inputs {
+            functions.extend(defined_functions(input, &fs::read(input)?)?);
+        }
+        expanded = LinkOptions {
+            exports: expand_exports(
+                &options.exports,
+                functions.iter().map(String::as_str),
+            )?,
+            ..options.clone()
+        };
+        &expanded
+    } else {
+        options
+    };
+
+    let scratch = Scratch::new()?;
+    let mut bitcode = Vec::with_capacity(inputs.len());
+    for (i, input) in inputs.iter().enumerate() {
+        if is_llvm_ir(input) {
+            let path = scratch.0.join(format!("{i}.bc"));
+            fs::write(&path, assemble_ir(input, &fs::read(input)?)?)?;
+            bitcode.push(path);
+        } else {
+            bitcode.push(input.clone());
+        }
+    }
+
+    llvm_object(
+        LinkerOptions {
+            target: None,
+            cpu: Cpu::Generic,
+            cpu_features: String::new(),
+            inputs: bitcode,
+            // `llvm_object` picks the file the object goes through
+            output: PathBuf::new(),
+            output_type: OutputType::Object,
+            libs: Vec::new(),
+            optimize: OptLevel::Default,
+            export_symbols: options
+                .roots()
+                .into_iter()
+                .map(|symbol| Cow::Owned(symbol.to_owned()))
+                .collect(),
+            unroll_loops: false,
+            ignore_inline_never: false,
+            dump_module: None,
+            llvm_args: Vec::new(),
+            disable_expand_memcpy_in_order: false,
+            disable_memory_builtins: false,
+            btf: false,
+            allow_bpf_trap: false,
+        },
+        true,
+    )
+}
+
+/// A directory for intermediate files, removed when dropped.
+#[cfg(feature = "llvm")]
+struct Scratch(PathBuf);
+
+#[cfg(feature = "llvm")]
+impl Scratch {
+    fn new() -> Result<Self, SbpfLinkerError> {
+        let path = env::temp_dir().join(format!(
+            "sbpf-link-{}-{}",
+            process::id(),
+            SCRATCH_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&path)?;
+        Ok(Self(path))
+    }
+}
+
+#[cfg(feature = "llvm")]
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Parses the textual IR `text`, read from `path`, and writes it back as
+/// bitcode.
+#[cfg(feature = "llvm")]
+fn assemble_ir(path: &Path, text: &[u8]) -> Result<Vec<u8>, SbpfLinkerError> {
+    with_module(path, text, |module| {
+        // SAFETY: the module is valid while this runs, and the buffer is
+        // disposed of once copied
+        unsafe {
+            let written = LLVMWriteBitcodeToMemoryBuffer(module);
+            let bitcode = slice::from_raw_parts(
+                LLVMGetBufferStart(written).cast::<u8>(),
+                LLVMGetBufferSize(written),
+            )
+            .to_vec();
+            LLVMDisposeMemoryBuffer(written);
+            bitcode
+        }
+    })
+}
+
+/// The names of the functions the bitcode or textual IR `module`, read
+/// from `path`, defines and could export, e.g. to match export patterns
+/// against before LLVM internalizes what isn't exported. Internal functions
+/// aren't, LLVM drops them whether exported or not.
+#[cfg(feature = "llvm")]
+pub fn defined_functions(
+    path: &Path,
+    module: &[u8],
+) -> Result<Vec<String>, SbpfLinkerError> {
+    with_module(path, module, |module| {
+        let mut names = Vec::new();
+        // SAFETY: the module is valid while this runs, and the names are
+        // copied out of it
+        unsafe {
+            let mut function = LLVMGetFirstFunction(module);
+            while !function.is_null() {
+                let linkage = LLVMGetLinkage(function);
+                if LLVMIsDeclaration(function) == 0
+                    && linkage != LLVMLinkage::LLVMInternalLinkage
+                    && linkage != LLVMLinkage::LLVMPrivateLinkage
+                {
+                    let mut len = 0;
+                    let name = LLVMGetValueName2(function, &mut len);
+                    let name = slice::from_raw_parts(name.cast::<u8>(), len);
+                    names.push(String::from_utf8_lossy(name).into_owned());
+                }
+                function = LLVMGetNextFunction(function);
+            }
+        }
+        names
+    })
+}
+
+/// Runs `f` on the module LLVM parses from the bitcode or textual IR
+/// `bytes`, read from `path`.
+#[cfg(feature = "llvm")]
+fn with_module<T>(
+    path: &Path,
+    bytes: &[u8],
+    f: impl FnOnce(LLVMModuleRef) -> T,
+) -> Result<T, SbpfLinkerError> {
+    // SAFETY: every object created here is disposed of before returning,
+    // and the parser takes ownership of the buffer it's given
+    unsafe {
+        let context = LLVMContextCreate();
+        let buffer = LLVMCreateMemoryBufferWithMemoryRangeCopy(
+            bytes.as_ptr().cast(),
+            bytes.len(),
+            c"input".as_ptr(),
+        );
+        let mut module = ptr::null_mut();
+        let mut message = ptr::null_mut();
+        let result = if LLVMParseIRInContext(
+            context,
+            buffer,
+            &mut module,
+            &mut message,
+        ) != 0
+        {
+            let detail =
+                CStr::from_ptr(message).to_string_lossy().into_owned();
+            LLVMDisposeMessage(message);
+            Err(SbpfLinkerError::InvalidLlvmIr {
+                path: path.to_path_buf(),
+                detail,
+            })
+        } else {
+            let result = f(module);
+            LLVMDisposeModule(module);
+            Ok(result)
+        };
+        LLVMContextDispose(context);
+        result
+    }
+}
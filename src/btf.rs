@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+
+use object::{
+    File, Object as _, ObjectSection as _, ObjectSymbol as _,
+    RelocationTarget, elf,
+};
+
+use crate::{
+    LinkOptions, SbpfLinkerError,
+    byteparser::section_data,
+    elf::{NewSection, append_sections},
+    lines::CodeAddresses,
+    map::LinkMap,
+};
+
+/// Magic number both `.BTF` and `.BTF.ext` start with.
+const BTF_MAGIC: u16 = 0xeb9f;
+/// Size of the `.BTF` header: magic, version, flags, header length, and the
+/// offset and length of the types and the strings.
+const BTF_HEADER_SIZE: usize = 24;
+/// Size of the `.BTF.ext` header written: magic, version, flags, header
+/// length, and the offset and length of the function and line info.
+const BTF_EXT_HEADER_SIZE: usize = 24;
+
+/// Adds the BTF of the input among `sources` that has it to `program`,
+/// linked from them with `options` into the layout `map`, so that tools
+/// reading types off the program keep working.
+///
+/// `.BTF` is copied as it is. The function and line info of `.BTF.ext` are
+/// moved to where their instructions ended up, all under `.text`; those of
+/// functions that didn't make it into the program are left out, as are the
+/// CO-RE relocations, which the program has no use for once linked.
+///
+/// BTF of several inputs isn't merged: a warning is logged and the program
+/// gets none.
+pub fn add_btf(
+    program: &mut Vec<u8>,
+    sources: &[&[u8]],
+    map: &LinkMap,
+    options: &LinkOptions,
+) -> Result<(), SbpfLinkerError> {
+    let mut with_btf = Vec::new();
+    for (input, source) in sources.iter().enumerate() {
+        let obj = File::parse(*source)?;
+        if obj.section_by_name(".BTF").is_some() {
+            with_btf.push((input, obj));
+        }
+    }
+    let (input, obj) = match with_btf.len() {
+        0 => return Ok(()),
+        1 => with_btf.swap_remove(0),
+        count => {
+            tracing::warn!(
+                "{count} inputs have BTF, which isn't merged; the program \
+                 gets none"
+            );
+            return Ok(());
+        }
+    };
+    let Some(text) = map.sections.iter().find(|s| s.name == ".text") else {
+        return Ok(());
+    };
+
+    let btf = match obj.section_by_name(".BTF") {
+        Some(section) => section_data(&section)?,
+        None => return Ok(()),
+    };
+    let (mut types, mut strings) = read_btf(&btf)?;
+    let text_name = match string_offset(&strings, ".text") {
+        Some(offset) => offset,
+        None => {
+            let offset = strings.len() as u32;
+            strings.extend_from_slice(b".text\0");
+            offset
+        }
+    };
+
+    let ext = match obj.section_by_name(".BTF.ext") {
+        Some(section) => {
+            // the instruction offsets are relocated against the symbol of
+            // the code they're in
+            let mut symbols = HashMap::new();
+            for (offset, relocation) in section.relocations() {
+                if let RelocationTarget::Symbol(index) = relocation.target() {
+                    let symbol = obj.symbol_by_index(index)?;
+                    symbols.insert(offset as usize, symbol.address());
+                }
+            }
+            let data = section_data(&section)?;
+            let mut addresses = CodeAddresses::new(&obj, input, map, options);
+            let mut moved = |name_offset: u32, offset: u32, site: usize| {
+                let Some(section) = string(&strings, name_offset)
+                    .and_then(|name| obj.section_by_name(name))
+                else {
+                    return Ok(None);
+                };
+                let offset = u64::from(offset)
+                    + symbols.get(&site).copied().unwrap_or_default();
+                let address = addresses.address(section.index(), offset)?;
+                Ok(address.and_then(|address| {
+                    u32::try_from(address - text.address).ok()
+                }))
+            };
+            Some(rewrite_ext(&data, text_name, &mut moved)?)
+        }
+        None => None,
+    };
+
+    let mut header = Vec::with_capacity(BTF_HEADER_SIZE);
+    header.extend_from_slice(&btf[..4]);
+    for field in [
+        BTF_HEADER_SIZE as u32,
+        0,
+        types.len() as u32,
+        types.len() as u32,
+        strings.len() as u32,
+    ] {
+        header.extend_from_slice(&field.to_le_bytes());
+    }
+    header.append(&mut types);
+    header.append(&mut strings);
+    let btf = header;
+    let mut sections = vec![NewSection {
+        name: ".BTF",
+        sh_type: elf::SHT_PROGBITS,
+        data: &btf,
+        align: 4,
+        entsize: 0,
+        link: None,
+        info: 0,
+    }];
+    if let Some(ext) = &ext {
+        sections.push(NewSection {
+            name: ".BTF.ext",
+            sh_type: elf::SHT_PROGBITS,
+            data: ext,
+            align: 4,
+            entsize: 0,
+            link: None,
+            info: 0,
+        });
+    }
+    append_sections(program, &sections)
+}
+
+/// Where a `.BTF.ext` record goes, see [`rewrite_ext`].
+type MoveRecord<'a> =
+    dyn FnMut(u32, u32, usize) -> Result<Option<u32>, SbpfLinkerError> + 'a;
+
+fn invalid(detail: &str) -> SbpfLinkerError {
+    SbpfLinkerError::InvalidBtf(detail.to_owned())
+}
+
+fn u32_at(data: &[u8], at: usize) -> Option<u32> {
+    let bytes = data.get(at..at.checked_add(4)?)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// The `len` bytes `offset` past the header of `data`, which is
+/// `header_len` long.
+fn block(
+    data: &[u8],
+    header_len: usize,
+    offset: u32,
+    len: u32,
+) -> Option<&[u8]> {
+    let start = header_len.checked_add(offset as usize)?;
+    data.get(start..start.checked_add(len as usize)?)
+}
+
+/// The types and the strings of the `.BTF` section `data`.
+fn read_btf(data: &[u8]) -> Result<(Vec<u8>, Vec<u8>), SbpfLinkerError> {
+    if data.get(..2) != Some(&BTF_MAGIC.to_le_bytes()) {
+        return Err(invalid(".BTF doesn't start with the BTF magic"));
+    }
+    let field = |at| {
+        u32_at(data, at).ok_or_else(|| invalid(".BTF header is truncated"))
+    };
+    let header_len = field(4)? as usize;
+    let types = block(data, header_len, field(8)?, field(12)?)
+        .ok_or_else(|| invalid("the types run past the end of .BTF"))?;
+    let strings = block(data, header_len, field(16)?, field(20)?)
+        .ok_or_else(|| invalid("the strings run past the end of .BTF"))?;
+    Ok((types.to_vec(), strings.to_vec()))
+}
+
+/// The string at `offset` in the BTF `strings`.
+fn string(strings: &[u8], offset: u32) -> Option<&str> {
+    let rest = strings.get(offset as usize..)?;
+    let end = rest.iter().position(|&byte| byte == 0)?;
+    std::str::from_utf8(&rest[..end]).ok()
+}
+
+/// Offset of the string `name` in the BTF `strings`, if it's there.
+fn string_offset(strings: &[u8], name: &str) -> Option<u32> {
+    let mut offset = 0;
+    for string in strings.split(|&byte| byte == 0) {
+        if string == name.as_bytes() {
+            return Some(offset as u32);
+        }
+        offset += string.len() + 1;
+    }
+    None
+}
+
+/// The `.BTF.ext` section `data` with the instruction offset of each
+/// function and line info record replaced by `moved` of the name offset of
+/// the code section it's in, its offset there, and where in `data` that's
+/// read from: its offset in .text of the program, or `None` to drop it.
+/// The records are put in offset order in a single section named by
+/// `text_name`.
+fn rewrite_ext(
+    data: &[u8],
+    text_name: u32,
+    moved: &mut MoveRecord<'_>,
+) -> Result<Vec<u8>, SbpfLinkerError> {
+    if data.get(..2) != Some(&BTF_MAGIC.to_le_bytes()) {
+        return Err(invalid(".BTF.ext doesn't start with the BTF magic"));
+    }
+    let field = |at| {
+        u32_at(data, at).ok_or_else(|| invalid(".BTF.ext header is truncated"))
+    };
+    let header_len = field(4)? as usize;
+    let mut infos = Vec::new();
+    for at in [8, 16] {
+        let (offset, len) = (field(at)?, field(at + 4)?);
+        let start = header_len + offset as usize;
+        let info = block(data, header_len, offset, len).ok_or_else(|| {
+            invalid("the info runs past the end of .BTF.ext")
+        })?;
+        infos.push(rewrite_info(info, start, text_name, moved)?);
+    }
+
+    let mut ext = Vec::new();
+    ext.extend_from_slice(&data[..4]);
+    let func_len = infos[0].len() as u32;
+    for field in [
+        BTF_EXT_HEADER_SIZE as u32,
+        0,
+        func_len,
+        func_len,
+        infos[1].len() as u32,
+    ] {
+        ext.extend_from_slice(&field.to_le_bytes());
+    }
+    infos.iter().for_each(|info| ext.extend_from_slice(info));
+    Ok(ext)
+}
+
+/// The function or line info block `info`, at `start` in `.BTF.ext`,
+/// rewritten as [`rewrite_ext`] says. Empty if `info` is.
+fn rewrite_info(
+    info: &[u8],
+    start: usize,
+    text_name: u32,
+    moved: &mut MoveRecord<'_>,
+) -> Result<Vec<u8>, SbpfLinkerError> {
+    if info.is_empty() {
+        return Ok(Vec::new());
+    }
+    let truncated = || invalid("a .BTF.ext info block is truncated");
+    let record_size = u32_at(info, 0).ok_or_else(truncated)? as usize;
+    if record_size < 4 {
+        return Err(invalid("a .BTF.ext record is too small"));
+    }
+    let mut records: Vec<(u32, &[u8])> = Vec::new();
+    let mut at = 4;
+    while at < info.len() {
+        let name = u32_at(info, at).ok_or_else(truncated)?;
+        let count = u32_at(info, at + 4).ok_or_else(truncated)? as usize;
+        at += 8;
+        for _ in 0..count {
+            let record =
+                info.get(at..at + record_size).ok_or_else(truncated)?;
+            if let Some(offset) = moved(
+                name,
+                u32_at(record, 0).ok_or_else(truncated)?,
+                start + at,
+            )? {
+                records.push((offset, &record[4..]));
+            }
+            at += record_size;
+        }
+    }
+    records.sort_by_key(|(offset, _)| *offset);
+
+    let mut rewritten = Vec::new();
+    rewritten.extend_from_slice(&(record_size as u32).to_le_bytes());
+    rewritten.extend_from_slice(&text_name.to_le_bytes());
+    rewritten.extend_from_slice(&(records.len() as u32).to_le_bytes());
+    for (offset, rest) in records {
+        rewritten.extend_from_slice(&offset.to_le_bytes());
+        rewritten.extend_from_slice(rest);
+    }
+    Ok(rewritten)
+}
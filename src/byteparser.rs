@@ -1,146 +1,1802 @@
 use sbpf_assembler::ast::AST;
-use sbpf_assembler::astnode::{ASTNode, ROData};
+use sbpf_assembler::astnode::{ASTNode, Label, ROData};
+use sbpf_assembler::dynsym::RelocationType;
 use sbpf_assembler::instruction::Instruction;
 use sbpf_assembler::lexer::{ImmediateValue, Token};
 use sbpf_assembler::parser::ParseResult;
+use sbpf_assembler::syscall::SYSCALLS;
 use sbpf_common::opcode::Opcode;
 
-use object::RelocationTarget::Symbol;
+use object::elf::SHF_STRINGS;
+use object::read::{Relocation, Section, SectionIndex, Symbol, SymbolIndex};
 use object::{File, Object as _, ObjectSection as _, ObjectSymbol as _};
+use object::{RelocationFlags, RelocationTarget, SectionFlags, SymbolKind};
 
-use std::collections::HashMap;
+use rayon::iter::ParallelIterator as _;
+use rayon::slice::ParallelSlice as _;
 
-use crate::SbpfLinkerError;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+use crate::defsym::{DefsymValue, resolve_defsyms};
+use crate::diagnostic::{
+    self, Diagnostic, DiagnosticKind, LARGE_RODATA_SIZE, Location,
+};
+use crate::loader::MAX_PROGRAM_SIZE;
+use crate::merge::R_BPF_64_ABS32;
+use crate::rename::Renames;
+use crate::splice::splice_prologue_epilogue;
+use crate::version::{self, SbpfVersion};
+use crate::{LinkOptions, SbpfLinkerError, TRACE_SYMBOL_TARGET, timings};
 
 pub fn parse_bytecode(bytes: &[u8]) -> Result<ParseResult, SbpfLinkerError> {
+    parse_bytecode_with_options(bytes, &LinkOptions::default())
+}
+
+/// Parses an upstream BPF object into the program's sections. Sections are
+/// found by name, not file position, and processed in phases: every rodata
+/// symbol is collected, then .text is parsed, and only then are relocations
+/// resolved. Each relocation thus sees the complete rodata layout no matter
+/// how the sections are ordered in the file.
+pub fn parse_bytecode_with_options(
+    bytes: &[u8],
+    options: &LinkOptions,
+) -> Result<ParseResult, SbpfLinkerError> {
+    parse_bytecode_with_diagnostics(bytes, options, &mut Vec::new())
+}
+
+/// [`parse_bytecode_with_options`], collecting warnings into `diagnostics`.
+pub(crate) fn parse_bytecode_with_diagnostics(
+    bytes: &[u8],
+    options: &LinkOptions,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Result<ParseResult, SbpfLinkerError> {
+    let parse = timings::stage("parse");
     let mut ast = AST::new();
 
     let obj = File::parse(bytes)?;
 
-    // Find rodata section - could be .rodata, .rodata.str1.1, etc.
-    let ro_section = obj.sections().find(|s| {
-        s.name().map(|name| name.starts_with(".rodata")).unwrap_or(false)
-    });
+    // from here on symbols are only referred to by their new names
+    let renames = Renames::new(
+        &options.renames,
+        obj.symbols().filter_map(|symbol| symbol.name().ok()),
+    )?;
 
-    // Ensure there's only one .rodata section
-    let rodata_count = obj
-        .sections()
-        .filter(|s| {
-            s.name().map(|name| name.starts_with(".rodata")).unwrap_or(false)
-        })
-        .count();
-    assert!(rodata_count <= 1, "Multiple .rodata sections found");
+    // defsyms may alias symbols of the object or syscalls resolved by the
+    // loader
+    let defsyms = resolve_defsyms(&options.defsyms, |name| {
+        obj.symbols().any(|symbol| {
+            symbol.name().is_ok_and(|own| renames.get(own) == name)
+        }) || SYSCALLS.values().any(|syscall| *syscall == name)
+    })?;
+
+    for symbol in obj.symbols() {
+        let Ok(name) = symbol.name() else { continue };
+        let name = renames.get(name);
+        if !options.traces(name) {
+            continue;
+        }
+        match symbol.section_index() {
+            Some(index) => tracing::info!(
+                target: TRACE_SYMBOL_TARGET,
+                "{name}: defined in {} at {:#x} ({} bytes)",
+                obj.section_by_index(index)?.name()?,
+                symbol.address(),
+                symbol.size()
+            ),
+            None => tracing::info!(
+                target: TRACE_SYMBOL_TARGET,
+                "{name}: undefined"
+            ),
+        }
+    }
 
-    let mut rodata_table = HashMap::new();
-    if let Some(ref ro_section) = ro_section {
-        // only handle symbols in the .rodata section for now
-        let mut rodata_offset = 0;
+    let data_sections = data_sections(&obj);
+    tracing::debug!(
+        "collecting {} data section(s) into .rodata",
+        data_sections.len()
+    );
+    let mut rodata_table = Vec::new();
+    let mut rodata_offset: u64 = 0;
+    // where each constant merged with `dedup_rodata` is, by its bytes
+    let mut constants: HashMap<Vec<u8>, Vec<u64>> = HashMap::new();
+    for section in &data_sections {
+        let section_name = section.name()?;
+        // pointers stored in the section, which make equal bytes point
+        // elsewhere
+        let pointers: Vec<u64> =
+            section.relocations().map(|(offset, _)| offset).collect();
         for symbol in obj.symbols() {
-            if symbol.section_index() == Some(ro_section.index())
-                && symbol.size() > 0
+            if symbol.section_index() == Some(section.index())
+                && symbol.kind() != SymbolKind::Section
+                && symbol.size() == 0
+                && let Ok(name) = symbol.name()
+                && !name.is_empty()
             {
-                let mut bytes = Vec::new();
-                for i in 0..symbol.size() {
-                    bytes.push(ImmediateValue::Int(i64::from(
-                        ro_section.data().unwrap()
-                            [(symbol.address() + i) as usize],
-                    )));
-                }
-                ast.rodata_nodes.push(ASTNode::ROData {
-                    rodata: ROData {
-                        name: symbol.name().unwrap().to_owned(),
-                        args: vec![
-                            Token::Directive(String::from("byte"), 0..1), //
-                            Token::VectorLiteral(bytes.clone(), 0..1),
-                        ],
-                        span: 0..1,
-                    },
-                    offset: rodata_offset,
-                });
-                rodata_table.insert(
-                    symbol.address(),
-                    symbol.name().unwrap().to_owned(),
+                diagnostic::warn(
+                    diagnostics,
+                    &options.warnings,
+                    DiagnosticKind::ZeroSizeSymbol,
+                    Location::at(section_name, symbol.address()),
+                    format!(
+                        "`{}` in {section_name} has no size and is not collected",
+                        renames.get(name),
+                    ),
                 );
-                rodata_offset += symbol.size();
             }
         }
-        ast.set_rodata_size(rodata_offset);
-    }
 
-    for section in obj.sections() {
-        if section.name() == Ok(".text") {
-            // parse text section and build instruction nodes
-            // lddw takes 16 bytes, other instructions take 8 bytes
-            let mut offset = 0;
-            while offset < section.data().unwrap().len() {
-                let node_len =
-                    match Opcode::from_u8(section.data().unwrap()[offset]) {
-                        Some(Opcode::Lddw) => 16,
-                        _ => 8,
-                    };
-                let node = &section.data().unwrap()[offset..offset + node_len];
-                let instruction = Instruction::from_bytes(node);
-                if let Err(error) = instruction {
-                    return Err(SbpfLinkerError::InstructionParseError(
-                        error.to_string(),
+        if !is_read_only_data(section_name) && section.size() > 0 {
+            diagnostic::warn(
+                diagnostics,
+                &options.warnings,
+                DiagnosticKind::WritableData,
+                Location::at(section_name, 0),
+                format!(
+                    "{section_name} is laid out in the read-only .rodata, so stores to its globals fault"
+                ),
+            );
+        }
+
+        let aligned = align_section(rodata_offset, section)?;
+        pad_rodata(&mut ast, section_name, &mut rodata_offset, aligned);
+
+        tracing::trace!(
+            "{section_name}: {} bytes at .rodata offset {rodata_offset:#x}",
+            section.size()
+        );
+        for chunk in collect_rodata(&obj, section)? {
+            let name = renames.get(&chunk.name);
+            if rodata_table.iter().any(|s: &RodataSymbol| s.name == name) {
+                if !options.allow_multiple_definition {
+                    return Err(SbpfLinkerError::MultipleDefinition(
+                        name.to_owned(),
                     ));
-                } else {
-                    ast.nodes.push(ASTNode::Instruction {
-                        instruction: instruction.unwrap(),
-                        offset: offset as u64,
-                    });
                 }
-                offset += node_len;
+                // like GNU ld, keep the first definition and drop the rest.
+                // References by name resolve to the first one.
+                diagnostic::warn(
+                    diagnostics,
+                    &options.warnings,
+                    DiagnosticKind::MultipleDefinition,
+                    Location::at(section_name, chunk.address),
+                    format!(
+                        "multiple definition of `{name}`, ignoring the one at {section_name} offset {:#x}",
+                        chunk.address
+                    ),
+                );
+                continue;
             }
+            let size = chunk.bytes.len() as u64;
+            let mergeable = options.dedup_rodata
+                && !pointers.iter().any(|&at| {
+                    (chunk.address..chunk.address + size).contains(&at)
+                });
+            let same = constants
+                .get(chunk.bytes.as_ref())
+                .filter(|_| mergeable)
+                .and_then(|offsets| {
+                    offsets.iter().copied().find(|at| at % chunk.align == 0)
+                });
+            let offset = if let Some(same) = same {
+                // a label holding nothing, on the constant it's the same as
+                ast.rodata_nodes.push(byte_node(name.to_owned(), &[], same));
+                if options.traces(name) {
+                    tracing::info!(
+                        target: TRACE_SYMBOL_TARGET,
+                        "{name}: merged with the same {size} bytes at .rodata offset {same:#x}",
+                    );
+                }
+                same
+            } else {
+                if size >= LARGE_RODATA_SIZE {
+                    diagnostic::warn(
+                        diagnostics,
+                        &options.warnings,
+                        DiagnosticKind::LargeRodata,
+                        Location::at(section_name, chunk.address),
+                        format!(
+                            "`{name}` embeds {size} bytes of read-only data"
+                        ),
+                    );
+                }
+                let aligned = rodata_offset.next_multiple_of(chunk.align);
+                pad_rodata(&mut ast, name, &mut rodata_offset, aligned);
+                ast.rodata_nodes.push(byte_node(
+                    name.to_owned(),
+                    &chunk.bytes,
+                    rodata_offset,
+                ));
+                if options.traces(name) {
+                    tracing::info!(
+                        target: TRACE_SYMBOL_TARGET,
+                        "{name}: collected into .rodata at offset {rodata_offset:#x} ({size} bytes)",
+                    );
+                }
+                if mergeable {
+                    constants
+                        .entry(chunk.bytes.to_vec())
+                        .or_default()
+                        .push(rodata_offset);
+                }
+                let offset = rodata_offset;
+                rodata_offset += size;
+                offset
+            };
+            rodata_table.push(RodataSymbol {
+                name: name.to_owned(),
+                symbol: chunk.symbol,
+                section: section.index(),
+                address: chunk.address,
+                size,
+                offset,
+            });
+        }
+    }
+    ast.set_rodata_size(rodata_offset);
 
-            if let Some(ref ro_section) = ro_section {
-                // handle relocations
-                for rel in section.relocations() {
-                    // only handle relocations for symbols in the .rodata section for now
-                    let symbol = match rel.1.target() {
-                        Symbol(sym) => Some(obj.symbol_by_index(sym).unwrap()),
-                        _ => None,
-                    };
+    if options.forbid_rodata && data_sections.iter().any(|s| s.size() > 0) {
+        let mut symbols: Vec<_> =
+            rodata_table.iter().map(|s| s.name.clone()).collect();
+        // e.g. string literals only referenced through the section
+        if symbols.is_empty() {
+            for section in &data_sections {
+                symbols.push(section.name()?.to_owned());
+            }
+        }
+        return Err(SbpfLinkerError::UnexpectedRodata { symbols });
+    }
+    let mut offset_labels = HashSet::new();
 
-                    if symbol.unwrap().section_index()
-                        == Some(ro_section.index())
+    let text_section = obj.section_by_name(".text");
+    let mut text_size = 0;
+    if let Some(ref section) = text_section {
+        let (nodes, size) = parse_text(&obj, section)?;
+        ast.nodes.extend(nodes);
+        text_size = size;
+        tracing::debug!(
+            ".text: {} instructions in {text_size} bytes",
+            ast.nodes.len()
+        );
+    }
+    ast.set_text_size(text_size);
+    check_jumps(&ast.nodes)?;
+
+    // syscalls are called by name
+    for node in &mut ast.nodes {
+        if let ASTNode::Instruction { instruction, .. } = node
+            && instruction.opcode == Opcode::Call
+            && let Some(Token::Identifier(name, _)) =
+                instruction.operands.last_mut()
+        {
+            *name = renames.get(name).to_owned();
+        }
+    }
+
+    drop(parse);
+    let _relocations = timings::stage("relocations");
+
+    // handle the relocations of every section that makes it into the
+    // program in one pass, dispatching on the section that owns each. This
+    // must come after all sections have been collected.
+    let mut rodata_pointers = Vec::new();
+    let mut text_labels = HashMap::new();
+    let mut relocations = RelocationCounts::default();
+    for section in text_section.iter().chain(&data_sections) {
+        let section_name = section.name()?;
+        let in_text = text_section
+            .as_ref()
+            .is_some_and(|text| text.index() == section.index());
+        // read once, the first time a pointer in it needs its addend
+        let mut data = None;
+        for rel in section.relocations() {
+            let symbol = match rel.1.target() {
+                RelocationTarget::Symbol(sym) => {
+                    Some(obj.symbol_by_index(sym)?)
+                }
+                _ => None,
+            };
+
+            let symbol_name = symbol
+                .as_ref()
+                .and_then(|symbol| symbol.name().ok())
+                .map(|name| renames.get(name))
+                .unwrap_or_default();
+            let r_type = match rel.1.flags() {
+                RelocationFlags::Elf { r_type } => r_type,
+                _ => 0,
+            };
+            if r_type == object::elf::R_BPF_NONE {
+                continue;
+            }
+            tracing::trace!(
+                "relocation (type {r_type}) at {section_name} offset {:#x} against `{symbol_name}`",
+                rel.0
+            );
+            let traced = options.traces(symbol_name);
+            if traced {
+                tracing::info!(
+                    target: TRACE_SYMBOL_TARGET,
+                    "{symbol_name}: referenced by relocation (type {r_type}) at {section_name} offset {:#x}",
+                    rel.0
+                );
+            }
+
+            if !in_text {
+                // a pointer stored in data itself, e.g. a table of string
+                // constants
+                let data = match data {
+                    Some(ref data) => data,
+                    None => data.insert(section_data(section)?),
+                };
+                let pointer = rodata_pointer(
+                    section,
+                    data,
+                    &rel,
+                    symbol.as_ref(),
+                    &rodata_table,
+                    &renames,
+                    text_section.as_ref().map(|text| text.index()),
+                )?;
+                if let PointerTarget::Text(target) = pointer.target {
+                    if target >= text_size || target % 8 != 0 {
+                        return Err(SbpfLinkerError::UnresolvedBranchTarget {
+                            offset: rel.0,
+                            target,
+                        });
+                    }
+                    text_labels.insert(text_label(target), target);
+                }
+                rodata_pointers.push(pointer);
+                relocations.data += 1;
+                continue;
+            }
+
+            let site = RelocationSite {
+                section: section_name,
+                offset: rel.0,
+                symbol: symbol_name,
+            };
+
+            // the type says which instruction the relocation patches: an
+            // address split across the immediates of an `lddw`, or the
+            // 32-bit target of a `call`
+            let patched = match r_type {
+                object::elf::R_BPF_64_64 => Opcode::Lddw,
+                object::elf::R_BPF_64_32 => Opcode::Call,
+                _ => {
+                    return Err(unsupported_type(section_name, rel.0, r_type));
+                }
+            };
+            if site.operand(&mut ast)?.0 != patched {
+                return Err(site.invalid());
+            }
+
+            // references to a --defsym name resolve to its expression: a
+            // constant, or the symbol it aliases, which is then resolved
+            // like references naming it are, addend included
+            let mut symbol = symbol;
+            let mut symbol_name = symbol_name;
+            if let Some(value) = defsyms.get(symbol_name) {
+                if traced {
+                    tracing::info!(
+                        target: TRACE_SYMBOL_TARGET,
+                        "{symbol_name}: resolved by --defsym to {value}"
+                    );
+                }
+                match value {
+                    DefsymValue::Constant(value) => {
+                        *site.operand(&mut ast)?.1 = Token::ImmediateValue(
+                            ImmediateValue::Int(*value),
+                            0..1,
+                        );
+                        relocations.defsyms += 1;
+                        continue;
+                    }
+                    DefsymValue::Symbol(target) => {
+                        symbol = alias_target(&obj, &renames, target);
+                        symbol_name = target;
+                    }
+                }
+                // a syscall the object doesn't refer to by its own name
+                if symbol.is_none()
+                    && let (Opcode::Call, operand) = site.operand(&mut ast)?
+                {
+                    *operand = Token::Identifier(symbol_name.to_owned(), 0..1);
+                    relocations.syscalls += 1;
+                    continue;
+                }
+            }
+
+            let Some(symbol) = symbol else {
+                return Err(site.invalid());
+            };
+
+            // syscalls, by name. The loader resolves each through the
+            // murmur3 hash of its name.
+            if symbol.is_undefined()
+                && let (Opcode::Call, operand) = site.operand(&mut ast)?
+            {
+                if !SYSCALLS.values().any(|syscall| *syscall == symbol_name) {
+                    diagnostic::warn(
+                        diagnostics,
+                        &options.warnings,
+                        DiagnosticKind::UnknownSyscall,
+                        Location::at(".text", rel.0),
+                        format!(
+                            "call at .text offset {:#x} to `{symbol_name}`, which is undefined and not a known syscall",
+                            rel.0
+                        ),
+                    );
+                }
+                if traced {
+                    tracing::info!(
+                        target: TRACE_SYMBOL_TARGET,
+                        "{symbol_name}: called as a syscall at .text offset {:#x}",
+                        rel.0
+                    );
+                }
+                *operand = Token::Identifier(symbol_name.to_owned(), 0..1);
+                relocations.syscalls += 1;
+                continue;
+            }
+
+            // calls between functions. As in libbpf, the target is the
+            // symbol's address plus the immediate, in instructions, plus one
+            if symbol.section_index() == Some(section.index()) {
+                let (opcode, operand) = site.operand(&mut ast)?;
+                // a function's address, e.g. for a table of callbacks. It
+                // becomes a label the assembler resolves, which makes the
+                // program dynamic so the loader rebases it.
+                if opcode == Opcode::Lddw {
+                    let addend = match operand {
+                        Token::ImmediateValue(
+                            ImmediateValue::Int(addend),
+                            _,
+                        ) => *addend,
+                        _ => 0,
+                    };
+                    let target = symbol.address().wrapping_add_signed(addend);
+                    if target >= text_size || target % 8 != 0 {
+                        return Err(SbpfLinkerError::UnresolvedBranchTarget {
+                            offset: rel.0,
+                            target,
+                        });
+                    }
+                    let label = if addend == 0
+                        && symbol.kind() != SymbolKind::Section
+                        && !symbol_name.is_empty()
                     {
-                        // addend is not explicit in the relocation entry, but implicitly encoded
-                        // as the immediate value of the instruction
-                        let addend = match ast
-                            .get_instruction_at_offset(rel.0)
-                            .unwrap()
-                            .operands
-                            .last()
-                            .unwrap()
-                            .clone()
-                        {
-                            Token::ImmediateValue(
-                                ImmediateValue::Int(val),
-                                _,
-                            ) => val,
-                            _ => 0,
-                        };
-
-                        // Replace the immediate value with the rodata label
-                        let ro_label = &rodata_table[&(addend as u64)];
-                        let ro_label_name = ro_label.clone();
-                        let node: &mut Instruction =
-                            ast.get_instruction_at_offset(rel.0).unwrap();
-                        let last_idx = node.operands.len() - 1;
-                        node.operands[last_idx] =
-                            Token::Identifier(ro_label_name, 0..1);
+                        symbol_name.to_owned()
+                    } else {
+                        text_label(target)
+                    };
+                    if traced {
+                        tracing::info!(
+                            target: TRACE_SYMBOL_TARGET,
+                            "{symbol_name}: address taken at .text offset {:#x}, resolved to .text offset {target:#x}",
+                            rel.0
+                        );
+                    }
+                    *operand = Token::Identifier(label.clone(), 0..1);
+                    text_labels.insert(label, target);
+                    relocations.calls += 1;
+                    continue;
+                }
+                if let (
+                    Opcode::Call,
+                    Token::ImmediateValue(ImmediateValue::Int(imm), _),
+                ) = (opcode, operand)
+                {
+                    let target =
+                        symbol.address().wrapping_add_signed((*imm + 1) * 8);
+                    if target >= text_size || target % 8 != 0 {
+                        return Err(SbpfLinkerError::UnresolvedBranchTarget {
+                            offset: rel.0,
+                            target,
+                        });
                     }
+                    if traced {
+                        tracing::info!(
+                            target: TRACE_SYMBOL_TARGET,
+                            "{symbol_name}: call at .text offset {:#x} resolved to .text offset {target:#x}",
+                            rel.0
+                        );
+                    }
+                    *imm = (target as i64 - rel.0 as i64) / 8 - 1;
+                    relocations.calls += 1;
+                    continue;
+                }
+            }
+
+            // only handle relocations for symbols in data sections for now
+            if let Some(data_section) =
+                symbol.section_index().filter(|index| {
+                    data_sections.iter().any(|s| s.index() == *index)
+                })
+            {
+                // addend is not explicit in the relocation entry, but implicitly encoded
+                // as the immediate value of the instruction
+                let addend = match site.operand(&mut ast)?.1 {
+                    Token::ImmediateValue(ImmediateValue::Int(val), _) => *val,
+                    _ => 0,
+                };
+
+                let Some((ro_symbol, delta)) = resolve_reference(
+                    &rodata_table,
+                    &renames,
+                    data_section,
+                    &symbol,
+                    addend,
+                ) else {
+                    return Err(SbpfLinkerError::UnresolvedRodataReference {
+                        offset: rel.0,
+                        target: symbol.address().wrapping_add_signed(addend),
+                    });
+                };
+
+                if traced || options.traces(&ro_symbol.name) {
+                    tracing::info!(
+                        target: TRACE_SYMBOL_TARGET,
+                        "{}: relocation at .text offset {:#x} against {symbol_name}{addend:+} resolved to offset {delta} into it",
+                        ro_symbol.name,
+                        rel.0
+                    );
                 }
+
+                // Replace the immediate value with the rodata label, or
+                // with a label synthesized `delta` bytes into it
+                let ro_label_name = if delta == 0 {
+                    ro_symbol.name.clone()
+                } else {
+                    let name = format!("{}+{delta}", ro_symbol.name);
+                    if offset_labels.insert(name.clone()) {
+                        ast.rodata_nodes.push(byte_node(
+                            name.clone(),
+                            &[],
+                            ro_symbol.offset + delta,
+                        ));
+                    }
+                    name
+                };
+                *site.operand(&mut ast)?.1 =
+                    Token::Identifier(ro_label_name, 0..1);
+                relocations.rodata += 1;
             } else {
-                if section.relocations().count() > 0 {
-                    assert!(false, "Relocations found but no .rodata section");
+                diagnostic::warn(
+                    diagnostics,
+                    &options.warnings,
+                    DiagnosticKind::IgnoredRelocation,
+                    Location::at(".text", rel.0),
+                    format!(
+                        "ignoring unsupported relocation at .text offset {:#x} against `{symbol_name}`",
+                        rel.0
+                    ),
+                );
+                relocations.ignored += 1;
+            }
+        }
+    }
+    tracing::debug!(
+        "rewrote {} relocation(s): {} call(s) and function address(es), {} \
+         reference(s) to .rodata, {} syscall(s), {} --defsym reference(s) \
+         and {} pointer(s) in data; ignored {}",
+        relocations.data
+            + relocations.defsyms
+            + relocations.syscalls
+            + relocations.calls
+            + relocations.rodata,
+        relocations.calls,
+        relocations.rodata,
+        relocations.syscalls,
+        relocations.defsyms,
+        relocations.data,
+        relocations.ignored
+    );
+
+    // where the function `name` starts in .text. A --defsym alias names the
+    // function it stands for, as references to it do.
+    let function_address = |name: &str| {
+        let name = match defsyms.get(name) {
+            Some(DefsymValue::Symbol(target)) => target.as_str(),
+            _ => name,
+        };
+        text_section
+            .as_ref()
+            .and_then(|text| text_symbol(&obj, text, &renames, name))
+            .filter(|address| *address < text_size && address % 8 == 0)
+    };
+
+    // the entry point is exported in .dynsym, and found by its label once
+    // the layout is final. Without an explicit entry, it's `entrypoint` if
+    // the program defines one.
+    let entry = options.entry.as_deref().unwrap_or(DEFAULT_ENTRY);
+    match function_address(entry) {
+        Some(address) => {
+            text_labels.insert(entry.to_owned(), address);
+            ast.entry_label = Some(entry.to_owned());
+        }
+        None if options.entry.is_some() => {
+            return Err(SbpfLinkerError::UndefinedEntrySymbol(
+                entry.to_owned(),
+            ));
+        }
+        None if text_size > 0 => diagnostic::warn(
+            diagnostics,
+            &options.warnings,
+            DiagnosticKind::MissingEntrypoint,
+            Location::default(),
+            format!(
+                "no `{DEFAULT_ENTRY}` function in .text, execution starts at its first instruction"
+            ),
+        ),
+        None => {}
+    }
+
+    // exported functions go into .dynsym next to the entry point
+    let mut exports = Vec::new();
+    for export in &options.exports {
+        match function_address(export) {
+            Some(address) => {
+                text_labels.insert(export.clone(), address);
+                exports.push(export);
+            }
+            None if !options.allow_undefined_exports => {
+                return Err(SbpfLinkerError::UndefinedExport(export.clone()));
+            }
+            None => diagnostic::warn(
+                diagnostics,
+                &options.warnings,
+                DiagnosticKind::UnresolvedExport,
+                Location::default(),
+                format!(
+                    "`{export}` isn't a function in .text and is not exported"
+                ),
+            ),
+        }
+    }
+
+    // in a fixed order, whatever the map's
+    let mut text_labels: Vec<_> = text_labels.into_iter().collect();
+    text_labels.sort();
+    for (name, offset) in text_labels {
+        ast.nodes.push(ASTNode::Label {
+            label: Label { name, span: 0..1 },
+            offset,
+        });
+    }
+
+    if options.prologue.is_some() || options.epilogue.is_some() {
+        text_size = splice_prologue_epilogue(
+            &mut ast.nodes,
+            ast.entry_label.as_deref(),
+            options.prologue.as_ref(),
+            options.epilogue.as_ref(),
+        )?;
+        ast.set_text_size(text_size);
+    }
+
+    // pointers hold absolute addresses, which depend on the final .text
+    // layout. Storing them turns the program dynamic, so its headers take up
+    // 64 bytes plus three 56-byte program headers.
+    for pointer in &rodata_pointers {
+        let target = match pointer.target {
+            PointerTarget::Rodata(target) => text_size + target,
+            // where the instruction ended up after splicing
+            PointerTarget::Text(origin) => ast
+                .nodes
+                .iter()
+                .find_map(|node| match node {
+                    ASTNode::Label { label, offset }
+                        if label.name == text_label(origin) =>
+                    {
+                        Some(*offset)
+                    }
+                    _ => None,
+                })
+                .unwrap_or(origin),
+        };
+        let address = 64 + 3 * 56 + target;
+        write_rodata(
+            &mut ast.rodata_nodes,
+            pointer.slot,
+            &address.to_le_bytes(),
+        );
+    }
+
+    let mut parse_result = ast
+        .build_program()
+        .map_err(|errors| SbpfLinkerError::BuildProgramError { errors })?;
+    for export in exports {
+        let offset =
+            parse_result.code_section.get_nodes().iter().find_map(|node| {
+                match node {
+                    ASTNode::Label { label, offset }
+                        if label.name == *export =>
+                    {
+                        Some(*offset)
+                    }
+                    _ => None,
                 }
+            });
+        if let Some(offset) = offset {
+            parse_result
+                .dynamic_symbols
+                .add_entry_point(export.clone(), offset);
+        }
+    }
+    // the loader rebases each pointer by the program's load address
+    for pointer in rodata_pointers {
+        parse_result.prog_is_static = false;
+        parse_result.relocation_data.add_rel_dyn(
+            text_size + pointer.slot,
+            RelocationType::RSbf64Relative,
+            String::new(),
+        );
+    }
+
+    Ok(parse_result)
+}
+
+/// How many relocations of each kind were rewritten, for `-v`.
+#[derive(Default)]
+struct RelocationCounts {
+    /// Pointers stored in data sections.
+    data: usize,
+    defsyms: usize,
+    syscalls: usize,
+    /// Calls between functions and their addresses taken.
+    calls: usize,
+    /// References from .text to read-only data.
+    rodata: usize,
+    ignored: usize,
+}
+
+/// Size of .text from which it's parsed in parallel, a chunk of functions
+/// per task.
+const PARALLEL_TEXT_SIZE: usize = 64 * 1024;
+
+/// Size of the smallest chunk of .text parsed as one task, so that tasks
+/// don't each parse a tiny function.
+const MIN_TEXT_CHUNK: usize = 16 * 1024;
+
+/// The instructions of .text, and the bytes they take without the padding
+/// after the last. Large sections are split at function symbols and the
+/// chunks parsed in parallel, which gives the same nodes in the same order
+/// as parsing sequentially, and the same first error.
+fn parse_text(
+    obj: &File,
+    section: &Section,
+) -> Result<(Vec<ASTNode>, u64), SbpfLinkerError> {
+    let data = section.data()?;
+    let version = SbpfVersion::of_object(obj);
+    let sequential = || {
+        let chunk = parse_instructions(data, 0, data.len(), version)?;
+        Ok((chunk.nodes, chunk.stop as u64))
+    };
+    if data.len() < PARALLEL_TEXT_SIZE {
+        return sequential();
+    }
+
+    let mut starts: Vec<usize> = obj
+        .symbols()
+        .filter(|symbol| {
+            symbol.section_index() == Some(section.index())
+                && symbol.kind() == SymbolKind::Text
+        })
+        .map(|symbol| symbol.address() as usize)
+        .filter(|&address| address % 8 == 0 && address < data.len())
+        .collect();
+    starts.sort_unstable();
+    let mut bounds = vec![0];
+    for start in starts {
+        if start - bounds[bounds.len() - 1] >= MIN_TEXT_CHUNK {
+            bounds.push(start);
+        }
+    }
+    bounds.push(data.len());
+
+    let chunks: Vec<_> = bounds
+        .par_windows(2)
+        .map(|bounds| parse_instructions(data, bounds[0], bounds[1], version))
+        .collect();
+    let mut nodes = Vec::new();
+    let mut stop = 0;
+    for (chunk, bounds) in chunks.into_iter().zip(bounds.windows(2)) {
+        // a symbol that isn't at an instruction boundary, e.g. inside an
+        // lddw, split an instruction
+        if bounds[0] != stop {
+            return sequential();
+        }
+        let chunk = chunk?;
+        nodes.extend(chunk.nodes);
+        stop = chunk.stop;
+        if chunk.padding {
+            break;
+        }
+    }
+    Ok((nodes, stop as u64))
+}
+
+/// Instructions parsed from a chunk of .text.
+pub(crate) struct TextChunk {
+    pub nodes: Vec<ASTNode>,
+    /// Where parsing stopped: the end of the chunk, past it if the last
+    /// instruction crosses it, or the start of the padding.
+    stop: usize,
+    /// Whether what follows `stop` is padding, up to the end of .text.
+    padding: bool,
+}
+
+/// Parses the instructions of `data`, all of .text encoded for `version`,
+/// that start from `start` up to `end`.
+pub(crate) fn parse_instructions(
+    data: &[u8],
+    start: usize,
+    end: usize,
+    version: SbpfVersion,
+) -> Result<TextChunk, SbpfLinkerError> {
+    // lddw takes 16 bytes, other instructions take 8 bytes
+    let mut nodes = Vec::new();
+    let mut offset = start;
+    while offset < end {
+        // sh_size may include alignment padding after the last
+        // instruction. No instruction starts with a zero byte, so
+        // trailing zeros at an instruction boundary are padding.
+        if data[offset..].iter().all(|&byte| byte == 0) {
+            return Ok(TextChunk { nodes, stop: offset, padding: true });
+        }
+        // decoded as v0 has it, like the rest of the linker reads code
+        let mut node = [0; 16];
+        let head = data.len().min(offset + 8) - offset;
+        node[..head].copy_from_slice(&data[offset..offset + head]);
+        version::decode_as_v0(&mut node[..8], version);
+        let node_len = match Opcode::from_u8(node[0]) {
+            Some(Opcode::Lddw) => 16,
+            Some(_) => 8,
+            None => {
+                return Err(SbpfLinkerError::UnknownOpcode {
+                    opcode: data[offset],
+                    offset: offset as u64,
+                });
+            }
+        };
+        let Some(rest) = data.get(offset + 8..offset + node_len) else {
+            return Err(SbpfLinkerError::InstructionParseError(format!(
+                "instruction at offset {offset} needs {node_len} bytes but .text ends at {}",
+                data.len()
+            )));
+        };
+        node[8..node_len].copy_from_slice(rest);
+        let instruction =
+            Instruction::from_bytes(&node[..node_len]).map_err(|error| {
+                SbpfLinkerError::InstructionParseError(error.to_string())
+            })?;
+        nodes
+            .push(ASTNode::Instruction { instruction, offset: offset as u64 });
+        offset += node_len;
+    }
+    Ok(TextChunk { nodes, stop: offset, padding: false })
+}
+
+/// Checks that every jump among `nodes`, the code of .text, lands on the
+/// start of one of them: not outside .text, and not in the middle of an
+/// instruction such as the second half of an `lddw`. Fails with
+/// [`SbpfLinkerError::UnresolvedBranchTarget`] at the first that doesn't.
+fn check_jumps(nodes: &[ASTNode]) -> Result<(), SbpfLinkerError> {
+    let starts: HashSet<u64> = nodes
+        .iter()
+        .filter_map(|node| match node {
+            ASTNode::Instruction { offset, .. } => Some(*offset),
+            _ => None,
+        })
+        .collect();
+    for node in nodes {
+        let ASTNode::Instruction { instruction, offset } = node else {
+            continue;
+        };
+        // the jump offset, in instructions from the next one, is the last
+        // operand
+        let Some(Token::ImmediateValue(ImmediateValue::Int(off), _)) =
+            instruction.operands.last().filter(|_| instruction.is_jump())
+        else {
+            continue;
+        };
+        let target = offset.wrapping_add_signed((off + 1) * 8);
+        if !starts.contains(&target) {
+            return Err(SbpfLinkerError::UnresolvedBranchTarget {
+                offset: *offset,
+                target,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Where a relocation in .text applies.
+struct RelocationSite<'a> {
+    section: &'a str,
+    offset: u64,
+    symbol: &'a str,
+}
+
+impl RelocationSite<'_> {
+    fn invalid(&self) -> SbpfLinkerError {
+        SbpfLinkerError::InvalidRelocationSite {
+            section: self.section.to_owned(),
+            offset: self.offset,
+            symbol: self.symbol.to_owned(),
+        }
+    }
+
+    /// The opcode of the instruction the relocation applies to, and the
+    /// operand it patches: the instruction's last.
+    fn operand<'ast>(
+        &self,
+        ast: &'ast mut AST,
+    ) -> Result<(Opcode, &'ast mut Token), SbpfLinkerError> {
+        let instruction: Option<&mut Instruction> =
+            ast.get_instruction_at_offset(self.offset);
+        instruction
+            .and_then(|instruction| {
+                let opcode = instruction.opcode;
+                instruction
+                    .operands
+                    .last_mut()
+                    .map(|operand| (opcode, operand))
+            })
+            .ok_or_else(|| self.invalid())
+    }
+}
+
+/// 64-bit absolute address, as emitted for `.quad symbol` in data sections.
+/// Missing from `object::elf`.
+pub(crate) const R_BPF_64_ABS64: u32 = 2;
+
+/// 32-bit absolute address the loader doesn't rebase, as emitted in debug
+/// info. Missing from `object::elf`.
+pub(crate) const R_BPF_64_NODYLD32: u32 = 4;
+
+/// 64-bit address relative to where the program is loaded, as the Solana
+/// toolchain emits for pointers in data. Applied like [`R_BPF_64_ABS64`],
+/// since the loader rebases those anyway. Missing from `object::elf`.
+pub(crate) const R_BPF_64_RELATIVE: u32 = 8;
+
+/// The name of the relocation type `r_type`, or its number if it's not a
+/// BPF one.
+pub(crate) fn relocation_type_name(r_type: u32) -> String {
+    match r_type {
+        object::elf::R_BPF_NONE => "R_BPF_NONE".to_owned(),
+        object::elf::R_BPF_64_64 => "R_BPF_64_64".to_owned(),
+        R_BPF_64_ABS64 => "R_BPF_64_ABS64".to_owned(),
+        R_BPF_64_ABS32 => "R_BPF_64_ABS32".to_owned(),
+        R_BPF_64_NODYLD32 => "R_BPF_64_NODYLD32".to_owned(),
+        R_BPF_64_RELATIVE => "R_BPF_64_RELATIVE".to_owned(),
+        object::elf::R_BPF_64_32 => "R_BPF_64_32".to_owned(),
+        _ => r_type.to_string(),
+    }
+}
+
+/// [`SbpfLinkerError::UnsupportedRelocationType`] for a relocation of type
+/// `r_type` at `offset` in `section`.
+fn unsupported_type(
+    section: &str,
+    offset: u64,
+    r_type: u32,
+) -> SbpfLinkerError {
+    SbpfLinkerError::UnsupportedRelocationType {
+        section: section.to_owned(),
+        offset,
+        kind: relocation_type_name(r_type),
+    }
+}
+
+/// The symbol execution starts at unless [`LinkOptions::entry`] says
+/// otherwise.
+pub(crate) const DEFAULT_ENTRY: &str = "entrypoint";
+
+/// A relocation owned by .rodata, in terms of offsets into the program's
+/// .rodata.
+struct RodataPointer {
+    /// Where the pointer is stored.
+    slot: u64,
+    /// What it points to.
+    target: PointerTarget,
+}
+
+enum PointerTarget {
+    /// An offset into the program's .rodata.
+    Rodata(u64),
+    /// An offset into the input .text, e.g. an entry of a vtable.
+    Text(u64),
+}
+
+/// Resolves the relocation `rel`, at its offset into the data section
+/// `section` whose contents are `data`. Only 64-bit pointers to collected
+/// data or into `text_section` are supported for now, failing with
+/// [`SbpfLinkerError::UnsupportedRelocationType`] for any other type.
+fn rodata_pointer(
+    section: &Section,
+    data: &[u8],
+    &(offset, ref rel): &(u64, Relocation),
+    symbol: Option<&Symbol>,
+    rodata_table: &[RodataSymbol],
+    renames: &Renames,
+    text_section: Option<SectionIndex>,
+) -> Result<RodataPointer, SbpfLinkerError> {
+    let unsupported = || SbpfLinkerError::UnsupportedRelocation {
+        section: section.name().unwrap_or_default().to_owned(),
+        offset,
+        symbol: symbol
+            .and_then(|symbol| symbol.name().ok())
+            .unwrap_or_default()
+            .to_owned(),
+    };
+
+    // only 64-bit pointers, which the loader can rebase
+    let RelocationFlags::Elf { r_type } = rel.flags() else {
+        return Err(unsupported());
+    };
+    if r_type != R_BPF_64_ABS64 && r_type != R_BPF_64_RELATIVE {
+        return Err(unsupported_type(
+            section.name().unwrap_or_default(),
+            offset,
+            r_type,
+        ));
+    }
+    let Some(symbol) = symbol else { return Err(unsupported()) };
+    let Some(symbol_section) = symbol.section_index().filter(|index| {
+        Some(*index) == text_section
+            || rodata_table.iter().any(|collected| collected.section == *index)
+    }) else {
+        return Err(unsupported());
+    };
+    let Some(holder) = rodata_table.iter().find(|holder| {
+        holder.section == section.index()
+            && holder.address <= offset
+            && offset + 8 <= holder.address + holder.size
+    }) else {
+        return Err(unsupported());
+    };
+
+    // as in .text, the addend is implicit: it's the value stored in the slot
+    let addend = data
+        .get(offset as usize..offset as usize + 8)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u64::from_le_bytes)
+        .ok_or_else(unsupported)?;
+    let slot = holder.offset + (offset - holder.address);
+
+    if Some(symbol_section) == text_section {
+        return Ok(RodataPointer {
+            slot,
+            target: PointerTarget::Text(symbol.address().wrapping_add(addend)),
+        });
+    }
+
+    let (target_symbol, delta) = resolve_reference(
+        rodata_table,
+        renames,
+        symbol_section,
+        symbol,
+        addend as i64,
+    )
+    .ok_or(SbpfLinkerError::UnresolvedRodataReference {
+        offset,
+        target: symbol.address().wrapping_add(addend),
+    })?;
+
+    Ok(RodataPointer {
+        slot,
+        target: PointerTarget::Rodata(target_symbol.offset + delta),
+    })
+}
+
+/// Address of the function `name`, by its name in the program, in `text`.
+fn text_symbol(
+    obj: &File,
+    text: &Section,
+    renames: &Renames,
+    name: &str,
+) -> Option<u64> {
+    obj.symbols()
+        .find(|symbol| {
+            symbol.section_index() == Some(text.index())
+                && symbol.kind() != SymbolKind::Section
+                && symbol.name().is_ok_and(|own| renames.get(own) == name)
+        })
+        .map(|symbol| symbol.address())
+}
+
+/// The symbol of `obj` a `--defsym` alias of `name`, by its name in the
+/// program, stands for: its definition, or else an undefined reference to
+/// it, e.g. a syscall. `None` if the object doesn't name it at all.
+fn alias_target<'data, 'file>(
+    obj: &'file File<'data>,
+    renames: &Renames,
+    name: &str,
+) -> Option<Symbol<'data, 'file>> {
+    let named = || {
+        obj.symbols().filter(|symbol| {
+            symbol.kind() != SymbolKind::Section
+                && symbol.name().is_ok_and(|own| renames.get(own) == name)
+        })
+    };
+    named().find(|symbol| !symbol.is_undefined()).or_else(|| named().next())
+}
+
+/// Name of the label synthesized at the input .text offset `offset`.
+fn text_label(offset: u64) -> String {
+    format!(".text+{offset:#x}")
+}
+
+/// Pads the program's .rodata, which ends at `offset`, with zeros up to
+/// `aligned`, where what's named `name` starts.
+fn pad_rodata(ast: &mut AST, name: &str, offset: &mut u64, aligned: u64) {
+    if aligned > *offset {
+        ast.rodata_nodes.push(byte_node(
+            format!(".pad.{name}"),
+            &vec![0; (aligned - *offset) as usize],
+            *offset,
+        ));
+        *offset = aligned;
+    }
+}
+
+/// A .rodata node labeled `name` holding `bytes`.
+fn byte_node(name: String, bytes: &[u8], offset: u64) -> ASTNode {
+    ASTNode::ROData {
+        rodata: ROData {
+            name,
+            args: vec![
+                Token::Directive(String::from("byte"), 0..1),
+                Token::VectorLiteral(
+                    bytes
+                        .iter()
+                        .map(|byte| ImmediateValue::Int(i64::from(*byte)))
+                        .collect(),
+                    0..1,
+                ),
+            ],
+            span: 0..1,
+        },
+        offset,
+    }
+}
+
+/// Overwrites the emitted .rodata bytes starting at `offset`.
+fn write_rodata(nodes: &mut [ASTNode], offset: u64, bytes: &[u8]) {
+    for node in nodes {
+        let ASTNode::ROData { rodata, offset: start } = node else { continue };
+        let Some(Token::VectorLiteral(values, _)) = rodata.args.get_mut(1)
+        else {
+            continue;
+        };
+        let Some(at) = offset.checked_sub(*start) else { continue };
+        let at = at as usize;
+        if at + bytes.len() <= values.len() {
+            for (value, byte) in values[at..].iter_mut().zip(bytes) {
+                *value = ImmediateValue::Int(i64::from(*byte));
             }
-            ast.set_text_size(section.size());
+            return;
         }
     }
+}
+
+/// The contents of `section`, decompressed if it's an `SHF_COMPRESSED`
+/// section.
+pub(crate) fn section_data<'data>(
+    section: &Section<'data, '_>,
+) -> Result<Cow<'data, [u8]>, SbpfLinkerError> {
+    section.uncompressed_data().map_err(|_| {
+        SbpfLinkerError::SectionDataUnavailable {
+            name: section.name().unwrap_or_default().to_owned(),
+        }
+    })
+}
+
+/// The sections collected into the program's .rodata, in layout order:
+/// every `.rodata*` and `.data.rel.ro*` section (e.g. `.rodata.str1.1`, or
+/// a table of pointers to functions and constants), then the rest of
+/// `.data*` and `.bss*`. SBPF programs have no writable memory of their
+/// own, so mutable globals end up in the program's only data section too.
+/// Their initial values can be read, but stores fault, hence a
+/// [`DiagnosticKind::WritableData`] warning.
+pub(crate) fn data_sections<'data, 'file>(
+    obj: &'file File<'data>,
+) -> Vec<Section<'data, 'file>> {
+    let collected = |read_only: bool| {
+        obj.sections().filter(move |s| {
+            s.name().is_ok_and(|name| {
+                is_data_section(name) && is_read_only_data(name) == read_only
+            })
+        })
+    };
+    collected(true).chain(collected(false)).collect()
+}
+
+/// Whether a section named `name` holds read-only data: constants, or
+/// `.data.rel.ro*` ones, which the compiler only keeps apart from them
+/// because their pointers are relocated at load time.
+fn is_read_only_data(name: &str) -> bool {
+    [".rodata", ".data.rel.ro"].iter().any(|prefix| name.starts_with(prefix))
+}
+
+/// Whether a section named `name` is one of [`data_sections`].
+pub(crate) fn is_data_section(name: &str) -> bool {
+    [".rodata", ".data", ".bss"].iter().any(|prefix| name.starts_with(prefix))
+}
+
+/// Where `section` starts in the program's .rodata if the previous sections
+/// end at `offset`.
+pub(crate) fn align_section(
+    offset: u64,
+    section: &Section,
+) -> Result<u64, SbpfLinkerError> {
+    let align = section.align().max(1);
+    if !align.is_power_of_two() || align > MAX_PROGRAM_SIZE as u64 {
+        return Err(SbpfLinkerError::InvalidSectionAlignment {
+            name: section.name()?.to_owned(),
+            align,
+        });
+    }
+    Ok(offset.next_multiple_of(align))
+}
+
+/// A piece of a data section collected into the program's .rodata.
+pub(crate) struct RodataChunk<'data> {
+    /// The symbol's name, or `<section>+<offset>` for an anonymous constant.
+    pub name: String,
+    /// The symbol, or `None` for an anonymous constant.
+    pub symbol: Option<SymbolIndex>,
+    /// Address in the input section.
+    pub address: u64,
+    /// Alignment it needs in the program: as much as its address in the
+    /// section has, up to the alignment of the section. Aligned loads from
+    /// it keep working that way.
+    pub align: u64,
+    /// Borrowed from the object unless the section had to be decompressed
+    /// or is `.bss`.
+    pub bytes: Cow<'data, [u8]>,
+}
+
+/// The sized symbols of `ro_section` with their bytes, plus the anonymous
+/// constants (e.g. string literals) only referenced through the section
+/// symbol, in address order. Laying them out in address order keeps data
+/// that is adjacent in the object adjacent in the program. Symbols in a
+/// `.bss` section, which has no contents in the file, are zero-filled.
+pub(crate) fn collect_rodata<'data>(
+    obj: &File<'data>,
+    ro_section: &Section<'data, '_>,
+) -> Result<Vec<RodataChunk<'data>>, SbpfLinkerError> {
+    let data = if ro_section.kind() == object::SectionKind::UninitializedData {
+        if ro_section.size() > MAX_PROGRAM_SIZE as u64 {
+            return Err(SbpfLinkerError::SectionTooLarge {
+                name: ro_section.name()?.to_owned(),
+                size: ro_section.size(),
+            });
+        }
+        Cow::Owned(vec![0; ro_section.size() as usize])
+    } else {
+        section_data(ro_section)?
+    };
+    let slice = |range: Range<usize>| match &data {
+        Cow::Borrowed(data) => Cow::Borrowed(&data[range]),
+        Cow::Owned(data) => Cow::Owned(data[range].to_vec()),
+    };
+    let mut chunks = obj
+        .symbols()
+        .filter(|symbol| {
+            symbol.section_index() == Some(ro_section.index())
+                && symbol.size() > 0
+        })
+        .map(|symbol| {
+            let start = symbol.address() as usize;
+            let name = symbol.name().unwrap_or_default().to_owned();
+            let end = start
+                .checked_add(symbol.size() as usize)
+                .filter(|&end| end <= data.len())
+                .ok_or_else(|| {
+                    SbpfLinkerError::RodataOutOfBounds(name.clone())
+                })?;
+            Ok(RodataChunk {
+                name,
+                symbol: Some(symbol.index()),
+                address: symbol.address(),
+                align: chunk_align(symbol.address(), ro_section),
+                bytes: slice(start..end),
+            })
+        })
+        .collect::<Result<Vec<_>, SbpfLinkerError>>()?;
+
+    let mut anonymous = section_references(obj, ro_section)?;
+    anonymous.retain(|&at| {
+        at < data.len() as u64
+            && !chunks.iter().any(|chunk| {
+                (chunk.address..chunk.address + chunk.bytes.len() as u64)
+                    .contains(&at)
+            })
+    });
+    anonymous.sort_unstable();
+    anonymous.dedup();
 
-    ast.build_program()
-        .map_err(|errors| SbpfLinkerError::BuildProgramError { errors })
+    // an anonymous constant runs up to the next one or the next symbol. In
+    // a section of NUL-terminated strings it ends with the NUL.
+    let strings = matches!(
+        ro_section.flags(),
+        SectionFlags::Elf { sh_flags } if sh_flags & u64::from(SHF_STRINGS) != 0
+    );
+    let section_name = ro_section.name()?;
+    for (i, &start) in anonymous.iter().enumerate() {
+        let mut end = anonymous
+            .get(i + 1)
+            .copied()
+            .into_iter()
+            .chain(chunks.iter().map(|chunk| chunk.address))
+            .filter(|&address| address > start)
+            .fold(data.len() as u64, u64::min);
+        if strings
+            && let Some(nul) = data[start as usize..end as usize]
+                .iter()
+                .position(|&byte| byte == 0)
+        {
+            end = start + nul as u64 + 1;
+        }
+        chunks.push(RodataChunk {
+            name: format!("{section_name}+{start:#x}"),
+            symbol: None,
+            address: start,
+            align: chunk_align(start, ro_section),
+            bytes: slice(start as usize..end as usize),
+        });
+    }
+    chunks.sort_by_key(|chunk| chunk.address);
+
+    Ok(chunks)
+}
+
+/// Alignment of what's at `address` in `section`, see
+/// [`RodataChunk::align`].
+fn chunk_align(address: u64, section: &Section) -> u64 {
+    let align = section.align().max(1);
+    if address == 0 {
+        return align;
+    }
+    align.min(1 << address.trailing_zeros())
+}
+
+/// Offsets into `section` referred to through its section symbol, by
+/// relocations anywhere in the object.
+fn section_references(
+    obj: &File,
+    section: &Section,
+) -> Result<Vec<u64>, SbpfLinkerError> {
+    let mut references = Vec::new();
+    for holder in obj.sections() {
+        let mut data = None;
+        for (offset, rel) in holder.relocations() {
+            let RelocationTarget::Symbol(index) = rel.target() else {
+                continue;
+            };
+            let symbol = obj.symbol_by_index(index)?;
+            if symbol.kind() != SymbolKind::Section
+                || symbol.section_index() != Some(section.index())
+            {
+                continue;
+            }
+            let data = match data {
+                Some(ref data) => data,
+                None => data.insert(section_data(&holder)?),
+            };
+            let at = offset as usize;
+            let instruction = if holder.kind() == object::SectionKind::Text {
+                data.get(at).copied().and_then(Opcode::from_u8)
+            } else {
+                None
+            };
+            references.push(symbol.address().wrapping_add(implicit_addend(
+                data,
+                at,
+                instruction,
+            )));
+        }
+    }
+    Ok(references)
+}
+
+/// The addend of a REL relocation at `at`, which is stored in the bytes it
+/// applies to: the immediate of an `lddw`, or a 64-bit slot in data.
+pub(crate) fn implicit_addend(
+    data: &[u8],
+    at: usize,
+    instruction: Option<Opcode>,
+) -> u64 {
+    let word = |from: usize| {
+        data.get(from..from + 4)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map_or(0, u32::from_le_bytes)
+    };
+    match instruction {
+        Some(Opcode::Lddw) => {
+            u64::from(word(at + 4)) | u64::from(word(at + 12)) << 32
+        }
+        Some(_) => 0,
+        None => u64::from(word(at)) | u64::from(word(at + 4)) << 32,
+    }
+}
+
+/// A symbol collected into the program's .rodata.
+pub(crate) struct RodataSymbol {
+    pub name: String,
+    /// The symbol it was collected from, or `None` for an anonymous
+    /// constant.
+    pub symbol: Option<SymbolIndex>,
+    /// The input section defining the symbol.
+    pub section: SectionIndex,
+    /// Address in the input section.
+    pub address: u64,
+    pub size: u64,
+    /// Offset in the emitted .rodata.
+    pub offset: u64,
+}
+
+/// The rodata symbol a relocation against `symbol`, defined in `section`,
+/// with `addend` refers to, along with the offset into it. A collected
+/// symbol is referred to itself, even if others share its address, and a
+/// named one whose duplicate was dropped through the first definition of
+/// its name. An addend of the symbol's size points just past its end, as
+/// a pointer to the end of an array does, and stays with the symbol
+/// rather than going to the one that follows it in the input. Section
+/// symbols, and addends that reach out of the symbol, e.g. into the next
+/// one, resolve by the address they reach, with [`resolve_rodata`].
+fn resolve_reference<'a>(
+    rodata_table: &'a [RodataSymbol],
+    renames: &Renames,
+    section: SectionIndex,
+    symbol: &Symbol,
+    addend: i64,
+) -> Option<(&'a RodataSymbol, u64)> {
+    let definition = rodata_table
+        .iter()
+        .find(|s| s.symbol == Some(symbol.index()) && s.section == section)
+        .or_else(|| {
+            let name = renames.get(symbol.name().ok()?);
+            (symbol.kind() != SymbolKind::Section)
+                .then(|| rodata_table.iter().find(|s| s.name == name))
+                .flatten()
+        });
+    let Some(definition) = definition else {
+        // section symbols have address 0, so this is the target's offset
+        // into its section
+        let base = symbol.address();
+        return resolve_rodata(
+            rodata_table,
+            section,
+            base,
+            base.wrapping_add_signed(addend),
+        );
+    };
+    match u64::try_from(addend) {
+        Ok(delta) if delta <= definition.size => Some((definition, delta)),
+        _ => resolve_rodata(
+            rodata_table,
+            definition.section,
+            definition.address,
+            definition.address.wrapping_add_signed(addend),
+        ),
+    }
+}
+
+/// Finds the rodata symbol a reference to `target` in `section` should be
+/// expressed against, along with the offset of `target` from its start.
+/// Exact symbol addresses resolve directly. Otherwise `target` may point
+/// anywhere into the run of back-to-back symbols starting at the one
+/// containing `base`, or just past its end, as a pointer to the end of an
+/// array does, and resolves relative to the symbol of the run it's in (the
+/// last one past the end), which needn't be next to the others in the
+/// program: it may have been padded or merged with
+/// [`LinkOptions::dedup_rodata`].
+pub(crate) fn resolve_rodata(
+    symbols: &[RodataSymbol],
+    section: SectionIndex,
+    base: u64,
+    target: u64,
+) -> Option<(&RodataSymbol, u64)> {
+    let symbols: Vec<_> =
+        symbols.iter().filter(|s| s.section == section).collect();
+    if let Some(symbol) = symbols.iter().find(|s| s.address == target) {
+        return Some((symbol, 0));
+    }
+
+    let first = symbols
+        .iter()
+        .position(|s| s.address <= base && base < s.address + s.size)?;
+    let mut end = symbols[first].address + symbols[first].size;
+    let mut containing = symbols[first];
+    for symbol in &symbols[first + 1..] {
+        if symbol.address != end {
+            break;
+        }
+        if symbol.address <= target {
+            containing = symbol;
+        }
+        end += symbol.size;
+    }
+
+    (symbols[first].address..=end)
+        .contains(&target)
+        .then(|| (containing, target - containing.address))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write as _;
+
+    use flate2::{Compression, write::ZlibEncoder};
+    use object::SectionKind;
+    use object::elf::{
+        ELFCOMPRESS_ZLIB, R_BPF_64_32, R_BPF_64_64, SHF_ALLOC, SHF_COMPRESSED,
+    };
+
+    use super::*;
+    use crate::link_program;
+    use crate::testing::{EXIT, TestObject, call, lddw, mov64, program};
+
+    /// The instructions of `parsed`, each with its offset and the value of
+    /// its last operand, if it's an immediate or an address.
+    fn instructions(parsed: &ParseResult) -> Vec<(u64, Opcode, Option<i64>)> {
+        parsed
+            .code_section
+            .get_nodes()
+            .iter()
+            .filter_map(|node| match node {
+                ASTNode::Instruction { instruction, offset } => Some((
+                    *offset,
+                    instruction.opcode,
+                    match instruction.operands.last() {
+                        Some(Token::ImmediateValue(
+                            ImmediateValue::Int(value)
+                            | ImmediateValue::Addr(value),
+                            _,
+                        )) => Some(*value),
+                        _ => None,
+                    },
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn defsym(defsym: &str) -> LinkOptions {
+        LinkOptions {
+            defsyms: vec![defsym.parse().unwrap()],
+            ..LinkOptions::default()
+        }
+    }
+
+    #[test]
+    fn single_lddw_text() {
+        let parsed = parse_bytecode(&program(&lddw(0, 42))).unwrap();
+        let opcodes: Vec<_> = instructions(&parsed)
+            .into_iter()
+            .map(|(offset, opcode, _)| (offset, opcode))
+            .collect();
+        assert_eq!(opcodes, [(0, Opcode::Lddw)]);
+        assert_eq!(parsed.code_section.get_size(), 16);
+    }
+
+    #[test]
+    fn truncated_lddw_text() {
+        let Err(error) = parse_bytecode(&program(&lddw(0, 42)[..15])) else {
+            panic!("a truncated lddw parsed");
+        };
+        assert!(
+            matches!(error, SbpfLinkerError::InstructionParseError(_)),
+            "{error:?}"
+        );
+    }
+
+    #[test]
+    fn defsym_alias_call_is_local() {
+        let mut object = TestObject::new();
+        let text = [call(), EXIT, mov64(0, 0), EXIT].concat();
+        let text_id = object.section(".text", SectionKind::Text, &text);
+        object.symbol("entrypoint", text_id, 0, 16);
+        object.symbol("helper", text_id, 16, 16);
+        let alias = object.undefined("alias_fn");
+        object.relocation(text_id, 0, alias, R_BPF_64_32);
+
+        let parsed = parse_bytecode_with_options(
+            &object.write(),
+            &defsym("alias_fn=helper"),
+        )
+        .unwrap();
+        // to `helper`, from the instruction after the call
+        assert_eq!(instructions(&parsed)[0], (0, Opcode::Call, Some(1)));
+        assert!(parsed.relocation_data.get_rel_dyns().is_empty());
+    }
+
+    #[test]
+    fn defsym_alias_keeps_addend() {
+        let mut object = TestObject::new();
+        let text = [&lddw(1, 2)[..], &EXIT].concat();
+        let text_id = object.section(".text", SectionKind::Text, &text);
+        object.symbol("entrypoint", text_id, 0, 24);
+        let rodata_id = object.section(
+            ".rodata",
+            SectionKind::ReadOnlyData,
+            b"hello!\0\0",
+        );
+        object.symbol("msg", rodata_id, 0, 8);
+        let alias = object.undefined("alias_msg");
+        object.relocation(text_id, 0, alias, R_BPF_64_64);
+
+        let parsed = parse_bytecode_with_options(
+            &object.write(),
+            &defsym("alias_msg=msg"),
+        )
+        .unwrap();
+        // two bytes into `msg`, which starts .rodata, right after the
+        // headers and .text
+        assert_eq!(
+            instructions(&parsed)[0],
+            (0, Opcode::Lddw, Some(64 + 3 * 56 + 24 + 2))
+        );
+    }
+
+    #[test]
+    fn reference_into_adjacent_rodata_symbol() {
+        let mut object = TestObject::new();
+        let text = [&lddw(1, 10)[..], &EXIT].concat();
+        let text_id = object.section(".text", SectionKind::Text, &text);
+        object.symbol("entrypoint", text_id, 0, 24);
+        let rodata_id = object.section(
+            ".rodata",
+            SectionKind::ReadOnlyData,
+            b"first\0\0\0second\0\0",
+        );
+        let first = object.symbol("first", rodata_id, 0, 8);
+        object.symbol("second", rodata_id, 8, 8);
+        // `first` plus 10: two bytes into `second`
+        object.relocation(text_id, 0, first, R_BPF_64_64);
+
+        let parsed = parse_bytecode(&object.write()).unwrap();
+        assert_eq!(
+            instructions(&parsed)[0],
+            (0, Opcode::Lddw, Some(64 + 3 * 56 + 24 + 10))
+        );
+        assert!(parsed.data_section.get_nodes().iter().any(|node| matches!(
+            node,
+            ASTNode::ROData { rodata, offset: 10 } if rodata.name == "second+2"
+        )));
+    }
+
+    /// An object whose code loads `msg`, with .rodata before .text in the
+    /// file if `rodata_first`.
+    fn rodata_reference(rodata_first: bool) -> Vec<u8> {
+        let mut object = TestObject::new();
+        let text = [&lddw(1, 0)[..], &EXIT].concat();
+        let rodata = |object: &mut TestObject| {
+            object.section(".rodata", SectionKind::ReadOnlyData, b"hello!\0\0")
+        };
+        let rodata_id = rodata_first.then(|| rodata(&mut object));
+        let text_id = object.section(".text", SectionKind::Text, &text);
+        let rodata_id = rodata_id.unwrap_or_else(|| rodata(&mut object));
+        object.symbol("entrypoint", text_id, 0, 24);
+        let msg = object.symbol("msg", rodata_id, 0, 8);
+        object.relocation(text_id, 0, msg, R_BPF_64_64);
+        object.write()
+    }
+
+    #[test]
+    fn rodata_after_text_in_file() {
+        let text_first = link_program(&rodata_reference(false)).unwrap();
+        let rodata_first = link_program(&rodata_reference(true)).unwrap();
+        assert_eq!(text_first, rodata_first);
+    }
+
+    /// An object whose code loads `msg`, in a .rodata zlib-compressed if
+    /// `compressed`.
+    fn compressed_rodata(compressed: bool) -> Vec<u8> {
+        const RODATA: &[u8] = b"hello!\0\0";
+        let mut object = TestObject::new();
+        let text = [&lddw(1, 0)[..], &EXIT].concat();
+        let text_id = object.section(".text", SectionKind::Text, &text);
+        object.symbol("entrypoint", text_id, 0, 24);
+        let rodata_id = if compressed {
+            let mut data = Vec::new();
+            data.extend_from_slice(&ELFCOMPRESS_ZLIB.to_le_bytes());
+            data.extend_from_slice(&0u32.to_le_bytes());
+            data.extend_from_slice(&(RODATA.len() as u64).to_le_bytes());
+            data.extend_from_slice(&8u64.to_le_bytes());
+            let mut encoder = ZlibEncoder::new(data, Compression::best());
+            encoder.write_all(RODATA).unwrap();
+            let data = encoder.finish().unwrap();
+            let id =
+                object.section(".rodata", SectionKind::ReadOnlyData, &data);
+            object.flags(id, u64::from(SHF_ALLOC | SHF_COMPRESSED));
+            id
+        } else {
+            object.section(".rodata", SectionKind::ReadOnlyData, RODATA)
+        };
+        // the symbol's size is the uncompressed one
+        let msg = object.symbol("msg", rodata_id, 0, 8);
+        object.relocation(text_id, 0, msg, R_BPF_64_64);
+        object.write()
+    }
+
+    #[test]
+    fn compressed_rodata_is_decompressed() {
+        let plain = link_program(&compressed_rodata(false)).unwrap();
+        let compressed = link_program(&compressed_rodata(true)).unwrap();
+        assert_eq!(plain, compressed);
+    }
+
+    #[test]
+    fn writable_data_warns() {
+        let mut object = TestObject::new();
+        let text = [&lddw(1, 0)[..], &EXIT].concat();
+        let text_id = object.section(".text", SectionKind::Text, &text);
+        object.symbol("entrypoint", text_id, 0, 24);
+        let data_id = object.section(".data", SectionKind::Data, &[1; 8]);
+        let counter = object.symbol("counter", data_id, 0, 8);
+        object.relocation(text_id, 0, counter, R_BPF_64_64);
+
+        let mut diagnostics = Vec::new();
+        parse_bytecode_with_diagnostics(
+            &object.write(),
+            &LinkOptions::default(),
+            &mut diagnostics,
+        )
+        .unwrap();
+        let kinds: Vec<_> = diagnostics.iter().map(|d| d.kind).collect();
+        assert_eq!(kinds, [DiagnosticKind::WritableData]);
+    }
+
+    #[test]
+    fn reference_past_end_of_rodata_symbol() {
+        let mut object = TestObject::new();
+        let text = [&lddw(1, 8)[..], &EXIT].concat();
+        let text_id = object.section(".text", SectionKind::Text, &text);
+        object.symbol("entrypoint", text_id, 0, 24);
+        let early_id = object.section(
+            ".rodata.early",
+            SectionKind::ReadOnlyData,
+            b"second!\0",
+        );
+        object.symbol("early", early_id, 0, 8);
+        let rodata_id = object.section(
+            ".rodata",
+            SectionKind::ReadOnlyData,
+            b"first!\0\0second!\0",
+        );
+        let first = object.symbol("first", rodata_id, 0, 8);
+        // merged with `early`, away from `first`
+        object.symbol("second", rodata_id, 8, 8);
+        // `first` plus 8: just past its end
+        object.relocation(text_id, 0, first, R_BPF_64_64);
+
+        let options =
+            LinkOptions { dedup_rodata: true, ..LinkOptions::default() };
+        let parsed =
+            parse_bytecode_with_options(&object.write(), &options).unwrap();
+        // `early`, then `first`
+        assert_eq!(
+            instructions(&parsed)[0],
+            (0, Opcode::Lddw, Some(64 + 3 * 56 + 24 + 8 + 8))
+        );
+    }
+
+    #[test]
+    fn trailing_zeros_are_padding() {
+        let text = [mov64(0, 0), EXIT, [0; 8]].concat();
+        let parsed = parse_bytecode(&program(&text)).unwrap();
+        let opcodes: Vec<_> = instructions(&parsed)
+            .into_iter()
+            .map(|(offset, opcode, _)| (offset, opcode))
+            .collect();
+        assert_eq!(opcodes, [(0, Opcode::Mov64Imm), (8, Opcode::Exit)]);
+        assert_eq!(parsed.code_section.get_size(), 16);
+    }
 }
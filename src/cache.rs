@@ -0,0 +1,135 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest as _, Sha256};
+
+use crate::{
+    LinkOptions, LinkOutput, SbpfLinkerError, link_objects_verbose,
+    output::write_atomic, program_hash, timings,
+};
+
+/// What a program was linked from: its inputs, its options and the version
+/// of the linker, hashed. Displays as hex, which names the program in a
+/// [`LinkCache`].
+#[derive(Clone)]
+pub struct CacheKey {
+    hasher: Sha256,
+}
+
+impl CacheKey {
+    /// The key of a link with `options`, before any input is
+    /// [added](CacheKey::add).
+    pub fn new(options: &LinkOptions) -> Self {
+        let mut key = Self { hasher: Sha256::new() };
+        key.add(env!("CARGO_PKG_VERSION").as_bytes());
+        key.add(format!("{options:?}").as_bytes());
+        key
+    }
+
+    /// Adds an input, or anything else the program depends on that the
+    /// options don't hold, e.g. the options LLVM compiled it with. Order
+    /// matters, as it does for the inputs of a link.
+    pub fn add(&mut self, bytes: &[u8]) -> &mut Self {
+        // so that the boundaries between additions count
+        self.hasher.update((bytes.len() as u64).to_le_bytes());
+        self.hasher.update(bytes);
+        self
+    }
+
+    fn digest(&self) -> [u8; 32] {
+        self.hasher.clone().finalize().into()
+    }
+}
+
+impl fmt::Display for CacheKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.digest().iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
+
+/// A directory of linked programs by their [`CacheKey`], so that relinking
+/// what hasn't changed, e.g. on every save in a watch-mode build, only
+/// reads the program back.
+///
+/// Only links that raised no warnings are cached, so that those raised are
+/// raised again until fixed, and none that trace symbols with
+/// [`LinkOptions::trace_symbols`], whose events a cached program wouldn't
+/// repeat.
+#[derive(Clone, Debug)]
+pub struct LinkCache {
+    dir: PathBuf,
+}
+
+impl LinkCache {
+    /// A cache in `dir`, created when a program is first stored.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The program stored for `key`, if any. One that can't be read, e.g.
+    /// removed meanwhile, isn't there either.
+    pub fn get(&self, key: &CacheKey) -> Option<Vec<u8>> {
+        let _stage = timings::stage("cache");
+        fs::read(self.path(key)).ok()
+    }
+
+    /// Stores `program` for `key`, replacing what's there.
+    pub fn put(&self, key: &CacheKey, program: &[u8]) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        write_atomic(&self.path(key), program)
+    }
+
+    /// [`link_objects_verbose`] through the cache: the program stored for
+    /// `sources` and `options` if there is one, and linked and stored
+    /// otherwise. A program that can't be stored is still returned, with a
+    /// warning logged.
+    pub fn link_objects(
+        &self,
+        sources: &[&[u8]],
+        options: &LinkOptions,
+    ) -> Result<LinkOutput, SbpfLinkerError> {
+        if !options.trace_symbols.is_empty() {
+            return link_objects_verbose(sources, options);
+        }
+        let mut key = CacheKey::new(options);
+        for source in sources {
+            key.add(source);
+        }
+        let (cached, timings) = timings::collect_timings(|| self.get(&key));
+        if let Some(program) = cached {
+            tracing::debug!("using the program cached as {key}");
+            return Ok(LinkOutput {
+                hash: program_hash(&program),
+                program,
+                diagnostics: Vec::new(),
+                timings,
+            });
+        }
+        let output = link_objects_verbose(sources, options)?;
+        if output.diagnostics.is_empty() {
+            self.store(&key, &output.program);
+        }
+        Ok(output)
+    }
+
+    /// [`put`](LinkCache::put), logging a failure as a warning rather than
+    /// failing a link that succeeded.
+    pub fn store(&self, key: &CacheKey, program: &[u8]) {
+        if let Err(err) = self.put(key, program) {
+            tracing::warn!(
+                "can't cache the program in {}: {err}",
+                self.dir.display()
+            );
+        }
+    }
+
+    fn path(&self, key: &CacheKey) -> PathBuf {
+        self.dir.join(format!("{key}.so"))
+    }
+}
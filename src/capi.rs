@@ -0,0 +1,161 @@
+use std::{
+    cell::RefCell,
+    ffi::{CString, c_char, c_int},
+    panic::{self, AssertUnwindSafe},
+    ptr, slice,
+};
+
+use crate::{SbpfLinkerError, link_objects, link_program};
+
+thread_local! {
+    /// The message and code of the last failure on this thread.
+    static LAST_ERROR: RefCell<Option<(CString, CString)>> =
+        const { RefCell::new(None) };
+}
+
+/// Relinks the upstream BPF object in `source_len` bytes at `source` into
+/// an SBPF program, as `link_program` does, and hands it to the caller in
+/// `*program` and `*program_len`. The program is the caller's to free with
+/// [`sbpf_program_free`].
+///
+/// Returns 0 if linked, and -1 otherwise, with [`sbpf_last_error`] and
+/// [`sbpf_last_error_code`] saying why until the next call on the thread.
+///
+/// # Safety
+///
+/// `source` must point to `source_len` readable bytes, or may be null if
+/// `source_len` is 0. `program` and `program_len` must be valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sbpf_link_program(
+    source: *const u8,
+    source_len: usize,
+    program: *mut *mut u8,
+    program_len: *mut usize,
+) -> c_int {
+    // SAFETY: the caller guarantees the buffer, as documented above
+    let source = unsafe { elements(source, source_len) };
+    // SAFETY: the caller guarantees the out pointers
+    unsafe { hand_over(|| link_program(source), program, program_len) }
+}
+
+/// Relinks the `count` upstream BPF objects at `sources`, each
+/// `source_lens[i]` bytes long, into one SBPF program, as `link_objects`
+/// does. The program is handed over, and failures reported, as by
+/// [`sbpf_link_program`].
+///
+/// # Safety
+///
+/// `sources` and `source_lens` must point to `count` elements, each source
+/// to as many readable bytes as its length says, as for
+/// [`sbpf_link_program`]. `program` and `program_len` must be valid for
+/// writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sbpf_link_objects(
+    sources: *const *const u8,
+    source_lens: *const usize,
+    count: usize,
+    program: *mut *mut u8,
+    program_len: *mut usize,
+) -> c_int {
+    // SAFETY: the caller guarantees both arrays and every buffer in them
+    let sources: Vec<&[u8]> = unsafe {
+        let pointers = elements(sources, count);
+        let lens = elements(source_lens, count);
+        pointers
+            .iter()
+            .zip(lens)
+            .map(|(&source, &len)| elements(source, len))
+            .collect()
+    };
+    // SAFETY: the caller guarantees the out pointers
+    unsafe { hand_over(|| link_objects(&sources), program, program_len) }
+}
+
+/// Frees a program returned by [`sbpf_link_program`] or
+/// [`sbpf_link_objects`]. Does nothing if `program` is null.
+///
+/// # Safety
+///
+/// `program` and `program_len` must be as returned by one of those
+/// functions, and the program not already freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sbpf_program_free(
+    program: *mut u8,
+    program_len: usize,
+) {
+    if program.is_null() {
+        return;
+    }
+    // SAFETY: the caller guarantees this is a boxed slice `hand_over` made
+    drop(unsafe {
+        Box::from_raw(ptr::slice_from_raw_parts_mut(program, program_len))
+    });
+}
+
+/// The message of the last failure on this thread, as displayed by the
+/// CLI, or null if there was none. Valid until the next call on the
+/// thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn sbpf_last_error() -> *const c_char {
+    LAST_ERROR.with_borrow(|error| {
+        error.as_ref().map_or(ptr::null(), |(message, _)| message.as_ptr())
+    })
+}
+
+/// The code of the last failure on this thread, as in the CLI's JSON
+/// errors (e.g. `undefined_symbol`), or null if there was none. Valid until
+/// the next call on the thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn sbpf_last_error_code() -> *const c_char {
+    LAST_ERROR.with_borrow(|error| {
+        error.as_ref().map_or(ptr::null(), |(_, code)| code.as_ptr())
+    })
+}
+
+/// Runs `link`, handing its program over in `*program` and `*program_len`
+/// or recording its error. A panic is recorded as an error too, rather
+/// than unwinding into the caller.
+///
+/// # Safety
+///
+/// `program` and `program_len` must be valid for writes.
+unsafe fn hand_over(
+    link: impl FnOnce() -> Result<Vec<u8>, SbpfLinkerError>,
+    program: *mut *mut u8,
+    program_len: *mut usize,
+) -> c_int {
+    let result = panic::catch_unwind(AssertUnwindSafe(link));
+    let (message, code) = match result {
+        Ok(Ok(linked)) => {
+            LAST_ERROR.set(None);
+            let linked = Box::into_raw(linked.into_boxed_slice());
+            // SAFETY: the caller guarantees the out pointers
+            unsafe {
+                *program_len = linked.len();
+                *program = linked.cast();
+            }
+            return 0;
+        }
+        Ok(Err(error)) => (error.to_string(), error.code()),
+        Err(_) => ("The linker panicked.".to_owned(), "panic"),
+    };
+    // errors never hold a NUL, but a stray one mustn't lose the message
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    let code = CString::new(code).unwrap_or_default();
+    LAST_ERROR.set(Some((message, code)));
+    -1
+}
+
+/// The `len` elements at `data`, which may be null if `len` is 0.
+///
+/// # Safety
+///
+/// Unless `len` is 0, `data` must point to `len` initialized elements that
+/// outlive `'a`.
+unsafe fn elements<'a, T>(data: *const T, len: usize) -> &'a [T] {
+    if len == 0 {
+        return &[];
+    }
+    // SAFETY: the caller guarantees the elements
+    unsafe { slice::from_raw_parts(data, len) }
+}
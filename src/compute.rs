@@ -0,0 +1,374 @@
+use std::collections::{HashMap, HashSet};
+
+use object::File;
+use sbpf_common::opcode::Opcode;
+
+use crate::{
+    SbpfLinkerError,
+    analysis::{Callee, RawInstruction, Text},
+};
+
+/// What the runtime charges for a syscall without a cost of its own.
+pub const SYSCALL_BASE_COST: u64 = 100;
+
+/// Base costs of the syscalls that don't cost [`SYSCALL_BASE_COST`], from
+/// the runtime's default compute budget. Costs that grow with the size of
+/// the input aren't known statically, so only the base is counted.
+const SYSCALL_COSTS: &[(&str, u64)] = &[
+    ("sol_create_program_address", 1500),
+    ("sol_try_find_program_address", 1500),
+    ("sol_sha256", 85),
+    ("sol_keccak256", 85),
+    ("sol_blake3", 85),
+    ("sol_secp256k1_recover", 25_000),
+    ("sol_curve_validate_point", 169),
+    ("sol_curve_group_op", 2208),
+    ("sol_invoke_signed_c", 1000),
+    ("sol_invoke_signed_rust", 1000),
+    ("sol_memcpy_", 10),
+    ("sol_memmove_", 10),
+    ("sol_memcmp_", 10),
+    ("sol_memset_", 10),
+];
+
+/// The estimated cost of one function of .text.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ComputeEstimate {
+    pub name: String,
+    pub offset: u64,
+    /// Upper bound on the compute units one call of the function consumes,
+    /// its callees and syscalls included. `None` if there is no bound.
+    pub compute_units: Option<u64>,
+    /// Why [`Self::compute_units`] is `None`, e.g. a loop whose trip count
+    /// can't be derived.
+    pub unbounded_by: Option<String>,
+}
+
+/// Estimates the worst-case compute units of every function in the .text
+/// of `source`, in address order. Every instruction costs one unit and each
+/// syscall its base cost; a loop counts for its trip count if the counter
+/// is set to a constant before it, stepped by a constant in it and compared
+/// with a constant to leave it. Other loops, recursion and indirect calls
+/// leave the function unbounded.
+pub fn compute_units(
+    source: &[u8],
+) -> Result<Vec<ComputeEstimate>, SbpfLinkerError> {
+    let obj = File::parse(source)?;
+    let Some(text) = Text::parse(&obj)? else {
+        return Ok(Vec::new());
+    };
+
+    let mut estimates = HashMap::new();
+    Ok((0..text.functions.len())
+        .map(|function| {
+            let estimate =
+                estimate(&text, function, &mut estimates, &mut HashSet::new());
+            let (offset, name) = &text.functions[function];
+            ComputeEstimate {
+                name: name.clone(),
+                offset: *offset,
+                compute_units: estimate.as_ref().ok().copied(),
+                unbounded_by: estimate.err(),
+            }
+        })
+        .collect())
+}
+
+/// Worst-case cost of `function`, or why there is no bound.
+fn estimate(
+    text: &Text,
+    function: usize,
+    estimates: &mut HashMap<usize, Result<u64, String>>,
+    active: &mut HashSet<usize>,
+) -> Result<u64, String> {
+    if let Some(estimate) = estimates.get(&function) {
+        return estimate.clone();
+    }
+    let name = &text.functions[function].1;
+    if !active.insert(function) {
+        return Err(format!("recursion through `{name}`"));
+    }
+
+    let body: Vec<&RawInstruction> = text
+        .instructions
+        .iter()
+        .filter(|instruction| {
+            text.function_at(instruction.offset) == Some(function)
+        })
+        .collect();
+    let mut costs = Vec::with_capacity(body.len());
+    let mut result = Ok(());
+    for instruction in &body {
+        let cost = if instruction.is(Opcode::Callx) {
+            Err(format!(
+                "indirect call at .text offset {:#x}",
+                instruction.offset
+            ))
+        } else if instruction.is(Opcode::Call) {
+            match text.callee(instruction) {
+                Callee::Function(callee) => {
+                    estimate(text, callee, estimates, active).map_err(|_| {
+                        format!("call to `{}`", text.functions[callee].1)
+                    })
+                }
+                Callee::Syscall(name) => Ok(syscall_cost(&name)),
+                Callee::Unknown => Ok(SYSCALL_BASE_COST),
+            }
+        } else {
+            Ok(0)
+        };
+        match cost {
+            Ok(cost) => costs.push(1 + cost),
+            Err(reason) => {
+                result = Err(reason);
+                break;
+            }
+        }
+    }
+    let estimate = result.and_then(|()| worst_path(&body, &costs));
+
+    active.remove(&function);
+    estimates.insert(function, estimate.clone());
+    estimate
+}
+
+/// Cost of the most expensive path through the function `body`, given the
+/// cost of each instruction, with each loop counted for its trip count.
+fn worst_path(body: &[&RawInstruction], costs: &[u64]) -> Result<u64, String> {
+    let index = |offset: u64| {
+        body.binary_search_by_key(&offset, |instruction| instruction.offset)
+            .ok()
+    };
+    let successors = |at: usize| -> Vec<usize> {
+        let instruction = body[at];
+        let mut next = Vec::new();
+        if !instruction.is(Opcode::Exit) && !instruction.is(Opcode::Ja) {
+            next.push(at + 1);
+        }
+        if is_jump(instruction) {
+            next.extend(index(instruction.jump_target()));
+        }
+        next.retain(|&next| next < body.len());
+        next
+    };
+
+    // loops, by the instruction they start at, with the back edge to it
+    let mut loops: HashMap<usize, Vec<usize>> = HashMap::new();
+    for at in 0..body.len() {
+        for next in successors(at) {
+            if next <= at {
+                loops.entry(next).or_default().push(at);
+            }
+        }
+    }
+    // inner loops first, so outer ones can account for them
+    let mut loops: Vec<(usize, Vec<usize>)> = loops.into_iter().collect();
+    loops.sort_by_key(|(head, latches)| {
+        latches.iter().max().copied().unwrap_or(*head) - head
+    });
+    // (head, latch, cost of running the loop to completion)
+    let mut regions: Vec<(usize, usize, u64)> = Vec::new();
+    for (head, latches) in loops {
+        let offset = body[head].offset;
+        let [latch] = latches[..] else {
+            return Err(format!(
+                "loop at .text offset {offset:#x} with several back edges"
+            ));
+        };
+        if regions
+            .iter()
+            .any(|&(start, end, _)| start < head && head <= end && end < latch)
+        {
+            return Err(format!(
+                "loop at .text offset {offset:#x} overlapping another"
+            ));
+        }
+        let trips = trip_count(body, head, latch).ok_or_else(|| {
+            format!(
+                "loop at .text offset {offset:#x} without a derivable bound"
+            )
+        })?;
+        let once = region_cost(costs, &regions, head, latch);
+        let mut cost = trips.saturating_mul(once);
+        // a loop tested at its head tests once more to leave
+        if body[latch].is(Opcode::Ja) {
+            cost = cost.saturating_add(costs[head]);
+        }
+        regions.push((head, latch, cost));
+    }
+
+    // with every loop taken as a whole, every edge goes forward
+    let outermost = |at: usize| {
+        regions
+            .iter()
+            .filter(|&&(head, latch, _)| head <= at && at <= latch)
+            .max_by_key(|&&(head, latch, _)| latch - head)
+            .copied()
+    };
+    let mut worst = vec![0u64; body.len()];
+    let mut at = body.len();
+    while at > 0 {
+        at -= 1;
+        let (head, latch, cost) = match outermost(at) {
+            Some(region) if region.1 == at => region,
+            Some(_) => continue,
+            None => (at, at, costs[at]),
+        };
+        let mut rest = 0;
+        let edges = (head..=latch).flat_map(|from| {
+            successors(from).into_iter().map(move |next| (from, next))
+        });
+        for (from, next) in edges {
+            if next > latch {
+                rest = rest.max(worst[next]);
+            } else if next < head {
+                return Err(format!(
+                    "jump at .text offset {:#x} into an enclosing loop",
+                    body[from].offset
+                ));
+            }
+        }
+        // jumps into the middle of a loop are counted as entering it
+        worst[head..=latch].fill(cost.saturating_add(rest));
+        at = head;
+    }
+    Ok(worst.first().copied().unwrap_or(0))
+}
+
+/// Cost of one pass over `body[head..=latch]`, counting each loop of
+/// `regions` nested in it for its whole run.
+fn region_cost(
+    costs: &[u64],
+    regions: &[(usize, usize, u64)],
+    head: usize,
+    latch: usize,
+) -> u64 {
+    let mut cost = 0u64;
+    let mut at = head;
+    while at <= latch {
+        let nested = regions
+            .iter()
+            .filter(|&&(start, end, _)| start == at && end <= latch)
+            .max_by_key(|&&(start, end, _)| end - start);
+        match nested {
+            Some(&(_, end, whole)) => {
+                cost = cost.saturating_add(whole);
+                at = end + 1;
+            }
+            None => {
+                cost = cost.saturating_add(costs[at]);
+                at += 1;
+            }
+        }
+    }
+    cost
+}
+
+/// How often the loop from `body[head]` to the back edge at `body[latch]`
+/// runs, if its counter is set to a constant before it and stepped by a
+/// constant once in it, and either the back edge or a compare at the head
+/// tests it against a constant.
+fn trip_count(
+    body: &[&RawInstruction],
+    head: usize,
+    latch: usize,
+) -> Option<u64> {
+    use Opcode::*;
+
+    // (counter, bound, condition the loop continues under)
+    let (counter, bound, condition) = if body[latch].is(Ja) {
+        let test = body[head];
+        let exits = body
+            .iter()
+            .position(|instruction| instruction.offset == test.jump_target())
+            .is_none_or(|target| target > latch);
+        if !is_jump(test) || !exits {
+            return None;
+        }
+        // the loop runs while the exit isn't taken
+        let condition = match Opcode::from_u8(test.opcode)? {
+            JgeImm | JsgeImm => JltImm,
+            JgtImm | JsgtImm => JleImm,
+            JleImm | JsleImm => JgtImm,
+            JltImm | JsltImm => JgeImm,
+            JeqImm => JneImm,
+            _ => return None,
+        };
+        (test.dst, test.imm, condition)
+    } else {
+        let test = body[latch];
+        let condition = match Opcode::from_u8(test.opcode)? {
+            JltImm | JsltImm => JltImm,
+            JleImm | JsleImm => JleImm,
+            JgtImm | JsgtImm => JgtImm,
+            JgeImm | JsgeImm => JgeImm,
+            JneImm => JneImm,
+            _ => return None,
+        };
+        (test.dst, test.imm, condition)
+    };
+
+    let mut steps = body[head..=latch]
+        .iter()
+        .filter(|instruction| writes(instruction, counter));
+    let step = match steps.next() {
+        Some(step) if step.is(Add64Imm) || step.is(Add32Imm) => step.imm,
+        Some(step) if step.is(Sub64Imm) || step.is(Sub32Imm) => -step.imm,
+        _ => return None,
+    };
+    if steps.next().is_some() {
+        return None;
+    }
+    let init = body[..head]
+        .iter()
+        .rev()
+        .find(|instruction| writes(instruction, counter))?;
+    if !init.is(Mov64Imm) && !init.is(Mov32Imm) {
+        return None;
+    }
+
+    let (distance, step) = match condition {
+        JltImm | JleImm | JneImm if step > 0 => (bound - init.imm, step),
+        JgtImm | JgeImm | JneImm if step < 0 => (init.imm - bound, -step),
+        _ => return None,
+    };
+    let trips = match condition {
+        JleImm | JgeImm => distance.div_euclid(step) + 1,
+        JneImm if distance % step == 0 => distance / step,
+        JneImm => return None,
+        _ => distance.div_euclid(step) + i64::from(distance % step != 0),
+    };
+    // the body runs at least once before the back edge is reached
+    Some(u64::try_from(trips).unwrap_or(0).max(1))
+}
+
+fn is_jump(instruction: &RawInstruction) -> bool {
+    // BPF_JMP, but not call and exit
+    instruction.opcode & 0x07 == 0x05
+        && !instruction.is(Opcode::Call)
+        && !instruction.is(Opcode::Callx)
+        && !instruction.is(Opcode::Exit)
+}
+
+/// Whether `instruction` may overwrite `register`.
+fn writes(instruction: &RawInstruction, register: u8) -> bool {
+    match instruction.opcode & 0x07 {
+        // calls clobber the argument and return registers
+        0x05 if instruction.is(Opcode::Call)
+            || instruction.is(Opcode::Callx) =>
+        {
+            register <= 5
+        }
+        // loads and ALU operations
+        0x00 | 0x01 | 0x04 | 0x07 => instruction.dst == register,
+        _ => false,
+    }
+}
+
+fn syscall_cost(name: &str) -> u64 {
+    SYSCALL_COSTS
+        .iter()
+        .find(|(syscall, _)| *syscall == name)
+        .map_or(SYSCALL_BASE_COST, |(_, cost)| *cost)
+}
@@ -0,0 +1,260 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use clap::Command;
+use toml::{Table, Value};
+
+use crate::SbpfLinkerError;
+
+/// Name of the file [`default_args`] reads, in the current directory or one
+/// above it.
+pub const CONFIG_FILE: &str = "sbpf-linker.toml";
+
+/// Prefix of the environment variables [`default_args`] reads, e.g.
+/// `SBPF_LINKER_SBPF_VERSION=v2`.
+pub const ENV_PREFIX: &str = "SBPF_LINKER_";
+
+/// How a command line option is written, for [`default_args`] to set it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OptionSpec {
+    /// The option itself, e.g. `--sbpf-version` or `-O`.
+    pub flag: String,
+    /// Whether it takes a value, as opposed to being a switch.
+    pub takes_value: bool,
+}
+
+impl OptionSpec {
+    /// The option set to `value`, as one argument, e.g. `--entry=main` or
+    /// `-Oz`, so that it also works for optional values.
+    fn with_value(&self, value: &str) -> String {
+        if self.flag.starts_with("--") {
+            format!("{}={value}", self.flag)
+        } else {
+            format!("{}{value}", self.flag)
+        }
+    }
+}
+
+/// Arguments giving the options of `binary` (`sbpf-link` or `sbpf-linker`)
+/// their defaults: those of the nearest [`CONFIG_FILE`] from `dir` up, and
+/// of [`ENV_PREFIX`] environment variables, which replace the file's. Both
+/// name options as their long form without dashes (`sbpf-version`), `_`
+/// working for `-` in variables, and `option` says how the option by a
+/// name is written.
+///
+/// The arguments go before the actual ones, which so take precedence.
+/// Options the config file sets outside of a `[sbpf-link]` or
+/// `[sbpf-linker]` table apply to both binaries, and are skipped by the one
+/// that doesn't have them. An unknown option in `binary`'s own table is an
+/// error.
+pub fn default_args(
+    binary: &str,
+    dir: &Path,
+    option: impl Fn(&str) -> Option<OptionSpec>,
+) -> Result<Vec<String>, SbpfLinkerError> {
+    // the arguments setting each option
+    let mut settings: Vec<(String, Vec<String>)> = match find_config(dir) {
+        Some(path) => config_settings(&path, binary, &option)?,
+        None => Vec::new(),
+    };
+
+    // in a fixed order, whatever the environment's
+    let mut variables: Vec<(String, String)> = env::vars()
+        .filter_map(|(name, value)| {
+            let name = name.strip_prefix(ENV_PREFIX)?;
+            Some((name.to_lowercase().replace('_', "-"), value))
+        })
+        .collect();
+    variables.sort();
+    for (name, value) in variables {
+        // variables other tools or the other binary read aren't errors
+        let Some(spec) = option(&name) else { continue };
+        let args = if spec.takes_value {
+            vec![spec.with_value(&value)]
+        } else {
+            match value.to_lowercase().as_str() {
+                "1" | "true" | "yes" | "on" => vec![spec.flag],
+                "" | "0" | "false" | "no" | "off" => Vec::new(),
+                _ => {
+                    return Err(SbpfLinkerError::InvalidEnvironment(format!(
+                        "{ENV_PREFIX}{} is `{value}`, but must be true or \
+                         false",
+                        name.to_uppercase().replace('-', "_")
+                    )));
+                }
+            }
+        };
+        settings.retain(|(set, _)| *set != name);
+        settings.push((name, args));
+    }
+    Ok(settings.into_iter().flat_map(|(_, args)| args).collect())
+}
+
+/// The arguments setting each option the config file `path` gives
+/// `binary`.
+fn config_settings(
+    path: &Path,
+    binary: &str,
+    option: impl Fn(&str) -> Option<OptionSpec>,
+) -> Result<Vec<(String, Vec<String>)>, SbpfLinkerError> {
+    let invalid = |detail: String| SbpfLinkerError::InvalidConfig {
+        path: path.to_path_buf(),
+        detail,
+    };
+    let text = fs::read_to_string(path).map_err(|e| invalid(e.to_string()))?;
+    let config: Table = text
+        .parse()
+        .map_err(|e: toml::de::Error| invalid(e.message().to_owned()))?;
+    // each setting, and whether it's in `binary`'s own table
+    let mut entries = Vec::new();
+    for (key, value) in &config {
+        match value {
+            Value::Table(table) if key == binary => entries
+                .extend(table.iter().map(|(key, value)| (key, value, true))),
+            Value::Table(_) if key == "sbpf-link" || key == "sbpf-linker" => {}
+            _ => entries.push((key, value, false)),
+        }
+    }
+
+    let mut settings: Vec<(String, Vec<String>)> = Vec::new();
+    for (key, value, own) in entries {
+        let spec = match option(key) {
+            Some(spec) => spec,
+            // settings for both binaries may be the other one's options
+            None if !own => continue,
+            None => {
+                return Err(invalid(format!(
+                    "`{binary}` has no option `{key}`"
+                )));
+            }
+        };
+        let values = match value {
+            Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        let mut args = Vec::new();
+        for value in values {
+            match value {
+                Value::Boolean(set) if !spec.takes_value => {
+                    if *set {
+                        args.push(spec.flag.clone());
+                    }
+                }
+                Value::String(value) => args.push(spec.with_value(value)),
+                Value::Integer(_) | Value::Float(_) | Value::Boolean(_)
+                    if spec.takes_value =>
+                {
+                    args.push(spec.with_value(&value.to_string()));
+                }
+                _ => {
+                    return Err(invalid(format!(
+                        "`{key}` can't be set to {value}"
+                    )));
+                }
+            }
+        }
+        // a binary's own table overrides what applies to both
+        settings.retain(|(set, _)| set != key);
+        settings.push((key.clone(), args));
+    }
+    Ok(settings)
+}
+
+/// How the option of `command` by `name` is written, for the config file
+/// and environment to set it, see [`default_args`]. `name` is its long form
+/// or its id.
+pub fn option_spec(command: &Command, name: &str) -> Option<OptionSpec> {
+    let id = name.replace('-', "_");
+    let arg = command.get_arguments().find(|arg| {
+        !arg.is_positional()
+            && (arg.get_long() == Some(name) || arg.get_id() == id.as_str())
+    })?;
+    let flag = match arg.get_long() {
+        Some(long) => format!("--{long}"),
+        None => format!("-{}", arg.get_short()?),
+    };
+    Some(OptionSpec { flag, takes_value: arg.get_action().takes_values() })
+}
+
+/// `args`, the command line of `binary` parsed by `command`, with the
+/// [`default_args`] from the current directory inserted after the
+/// program's name, so that the actual arguments override them.
+pub fn with_defaults(
+    binary: &str,
+    command: &Command,
+    args: Vec<String>,
+) -> Result<Vec<String>, SbpfLinkerError> {
+    let dir = env::current_dir().unwrap_or_default();
+    let defaults =
+        default_args(binary, &dir, |name| option_spec(command, name))?;
+    let mut args = args.into_iter();
+    Ok(args.next().into_iter().chain(defaults).chain(args).collect())
+}
+
+/// The nearest [`CONFIG_FILE`], in `dir` or one of its ancestors.
+pub fn find_config(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(CONFIG_FILE))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::*;
+
+    /// `sbpf-link`'s options, as far as the tests set them.
+    fn option(name: &str) -> Option<OptionSpec> {
+        let takes_value = match name {
+            "sbpf-version" => true,
+            "gc-sections" => false,
+            _ => return None,
+        };
+        Some(OptionSpec { flag: format!("--{name}"), takes_value })
+    }
+
+    /// The settings of a config file holding `text`.
+    fn settings(
+        name: &str,
+        text: &str,
+    ) -> Result<Vec<(String, Vec<String>)>, SbpfLinkerError> {
+        let path = env::temp_dir()
+            .join(format!("sbpf-linker-{}-{name}.toml", process::id()));
+        fs::write(&path, text).unwrap();
+        let settings = config_settings(&path, "sbpf-link", option);
+        fs::remove_file(&path).unwrap();
+        settings
+    }
+
+    #[test]
+    fn other_binarys_shared_option_is_skipped() {
+        let settings = settings(
+            "shared",
+            "sbpf-version = \"v2\"\noptimize = \"z\"\n\n[sbpf-link]\ngc-sections = true\n",
+        )
+        .unwrap();
+        assert_eq!(
+            settings,
+            [
+                ("gc-sections".to_owned(), vec!["--gc-sections".to_owned()]),
+                (
+                    "sbpf-version".to_owned(),
+                    vec!["--sbpf-version=v2".to_owned()]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_option_in_own_table_fails() {
+        let error =
+            settings("own", "[sbpf-link]\noptimize = \"z\"\n").unwrap_err();
+        assert!(
+            matches!(error, SbpfLinkerError::InvalidConfig { .. }),
+            "{error:?}"
+        );
+    }
+}
@@ -0,0 +1,405 @@
+use gimli::{
+    LittleEndian,
+    write::{
+        Address, AttributeValue, DwarfUnit, EndianVec, LineProgram,
+        LineString, Sections,
+    },
+};
+use object::{
+    FileFlags, Object as _, ObjectSection as _, elf,
+    write::{
+        StringId,
+        elf::{FileHeader, SectionHeader, SectionIndex, Sym, Writer},
+    },
+};
+use sha2::{Digest as _, Sha256};
+
+use crate::{
+    SbpfLinkerError,
+    elf::{
+        BUILD_ID_SECTION, NewSection, SYMBOL_SIZE, add_build_id,
+        append_sections, build_id_note,
+    },
+    lines::LineMap,
+    map::{LinkMap, MapSymbolKind},
+};
+
+/// Size of a GNU build ID, as `ld --build-id=sha1` makes them.
+const BUILD_ID_SIZE: usize = 20;
+
+/// A linked program split into what's deployed and what's kept for
+/// debugging it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SplitDebug {
+    /// The program, with a `.note.gnu.build-id` section added.
+    pub program: Vec<u8>,
+    /// The debug companion: the program's sections without their contents,
+    /// a symbol table of every function and rodata symbol, and DWARF line
+    /// tables, along with the same build ID note.
+    pub debug: Vec<u8>,
+    /// Truncated SHA-256 of the program before the note was added.
+    pub build_id: [u8; BUILD_ID_SIZE],
+}
+
+/// What of a linked program's symbols and debug info is kept in the `.so`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Strip {
+    /// Only the dynamic symbols of the exports, which the loader needs.
+    /// Programs are emitted this way unless told otherwise.
+    #[default]
+    All,
+    /// A symbol table of every function and rodata symbol as well, but no
+    /// debug info.
+    Debug,
+    /// The symbol table, and DWARF line tables of the inputs.
+    Nothing,
+}
+
+/// Splits `program`, whose layout is `map` and source lines `lines`, into
+/// the program to deploy and a debug companion a debugger finds by its
+/// build ID, like `objcopy --only-keep-debug` would.
+pub fn split_debug(
+    program: &[u8],
+    map: &LinkMap,
+    lines: &LineMap,
+) -> Result<SplitDebug, SbpfLinkerError> {
+    let linked = object::File::parse(program)?;
+    let e_flags = match linked.flags() {
+        FileFlags::Elf { e_flags, .. } => e_flags,
+        _ => 0,
+    };
+    let mut build_id = [0; BUILD_ID_SIZE];
+    build_id.copy_from_slice(&Sha256::digest(program)[..BUILD_ID_SIZE]);
+
+    let mut stripped = program.to_vec();
+    add_build_id(&mut stripped, &build_id)?;
+    let debug =
+        debug_companion(map, lines, linked.entry(), e_flags, &build_id)?;
+    Ok(SplitDebug { program: stripped, debug, build_id })
+}
+
+/// Adds a `.symtab` of every function and rodata symbol of `map` to
+/// `program`, as [`Strip::Debug`] keeps, and with `lines` their DWARF line
+/// tables too, as [`Strip::Nothing`] does.
+pub fn add_symbols(
+    program: &mut Vec<u8>,
+    map: &LinkMap,
+    lines: Option<&LineMap>,
+) -> Result<(), SbpfLinkerError> {
+    let linked = object::File::parse(program.as_slice())?;
+    let indices: Vec<u16> = map
+        .sections
+        .iter()
+        .map(|section| {
+            linked
+                .section_by_name(&section.name)
+                .map_or(elf::SHN_ABS, |section| section.index().0 as u16)
+        })
+        .collect();
+
+    let mut symtab = vec![0; SYMBOL_SIZE];
+    let mut strtab = vec![0];
+    for symbol in &map.symbols {
+        let st_type = match symbol.kind {
+            MapSymbolKind::Function => elf::STT_FUNC,
+            MapSymbolKind::Rodata => elf::STT_OBJECT,
+        };
+        let st_shndx = section_of(map, symbol.address)
+            .map_or(elf::SHN_ABS, |section| indices[section]);
+        symtab.extend_from_slice(&(strtab.len() as u32).to_le_bytes());
+        symtab.push((elf::STB_GLOBAL << 4) | st_type);
+        symtab.push(elf::STV_DEFAULT);
+        symtab.extend_from_slice(&st_shndx.to_le_bytes());
+        symtab.extend_from_slice(&symbol.address.to_le_bytes());
+        symtab.extend_from_slice(&symbol.size.to_le_bytes());
+        strtab.extend_from_slice(symbol.name.as_bytes());
+        strtab.push(0);
+    }
+
+    let dwarf = match lines {
+        Some(lines) => dwarf_sections(map, lines)?,
+        None => Vec::new(),
+    };
+    let mut sections = vec![
+        NewSection {
+            name: ".symtab",
+            sh_type: elf::SHT_SYMTAB,
+            data: &symtab,
+            align: 8,
+            entsize: SYMBOL_SIZE,
+            link: Some(1),
+            // every symbol is global
+            info: 1,
+        },
+        NewSection {
+            name: ".strtab",
+            sh_type: elf::SHT_STRTAB,
+            data: &strtab,
+            align: 1,
+            entsize: 0,
+            link: None,
+            info: 0,
+        },
+    ];
+    sections.extend(dwarf.iter().map(|(name, data)| NewSection {
+        name,
+        sh_type: elf::SHT_PROGBITS,
+        data,
+        align: 1,
+        entsize: 0,
+        link: None,
+        info: 0,
+    }));
+    append_sections(program, &sections)
+}
+
+/// Index of the section of `map` that `address` is in.
+fn section_of(map: &LinkMap, address: u64) -> Option<usize> {
+    map.sections.iter().position(|section| {
+        (section.address..section.address + section.size).contains(&address)
+    })
+}
+
+/// The debug companion of a program with `map` and `lines`.
+fn debug_companion(
+    map: &LinkMap,
+    lines: &LineMap,
+    entry: u64,
+    e_flags: u32,
+    build_id: &[u8],
+) -> Result<Vec<u8>, SbpfLinkerError> {
+    let dwarf = dwarf_sections(map, lines)?;
+    let note = build_id_note(build_id);
+
+    let mut file = Vec::new();
+    let mut writer = Writer::new(object::Endianness::Little, true, &mut file);
+    writer.reserve_file_header();
+    writer.reserve_null_section_index();
+
+    // the program's sections, where they're loaded but without contents
+    let sections: Vec<(StringId, SectionIndex)> = map
+        .sections
+        .iter()
+        .map(|section| {
+            (
+                writer.add_section_name(section.name.as_bytes()),
+                writer.reserve_section_index(),
+            )
+        })
+        .collect();
+    let note_name = writer.add_section_name(BUILD_ID_SECTION.as_bytes());
+    writer.reserve_section_index();
+    let dwarf: Vec<(StringId, &[u8])> = dwarf
+        .iter()
+        .map(|(name, data)| {
+            writer.reserve_section_index();
+            (writer.add_section_name(name.as_bytes()), data.as_slice())
+        })
+        .collect();
+
+    writer.reserve_null_symbol_index();
+    let symbols: Vec<(StringId, Option<SectionIndex>)> = map
+        .symbols
+        .iter()
+        .map(|symbol| {
+            writer.reserve_symbol_index(None);
+            (
+                writer.add_string(symbol.name.as_bytes()),
+                section_of(map, symbol.address)
+                    .map(|section| sections[section].1),
+            )
+        })
+        .collect();
+    writer.reserve_symtab_section_index();
+    writer.reserve_strtab_section_index();
+    writer.reserve_shstrtab_section_index();
+
+    let note_offset = writer.reserve(note.len(), 4);
+    let dwarf_offsets: Vec<usize> =
+        dwarf.iter().map(|(_, data)| writer.reserve(data.len(), 1)).collect();
+    writer.reserve_symtab();
+    writer.reserve_strtab();
+    writer.reserve_shstrtab();
+    writer.reserve_section_headers();
+
+    writer.write_file_header(&FileHeader {
+        os_abi: elf::ELFOSABI_NONE,
+        abi_version: 0,
+        e_type: elf::ET_DYN,
+        e_machine: elf::EM_BPF,
+        e_entry: entry,
+        e_flags,
+    })?;
+    writer.write_align(4);
+    writer.write(&note);
+    for (_, data) in &dwarf {
+        writer.write(data);
+    }
+
+    writer.write_null_symbol();
+    for (symbol, (name, section)) in map.symbols.iter().zip(&symbols) {
+        let st_type = match symbol.kind {
+            MapSymbolKind::Function => elf::STT_FUNC,
+            MapSymbolKind::Rodata => elf::STT_OBJECT,
+        };
+        writer.write_symbol(&Sym {
+            name: Some(*name),
+            section: *section,
+            st_info: (elf::STB_GLOBAL << 4) | st_type,
+            st_other: elf::STV_DEFAULT,
+            st_shndx: if section.is_some() { 0 } else { elf::SHN_ABS },
+            st_value: symbol.address,
+            st_size: symbol.size,
+        });
+    }
+    writer.write_strtab();
+    writer.write_shstrtab();
+
+    writer.write_null_section_header();
+    for (section, (name, _)) in map.sections.iter().zip(&sections) {
+        let sh_flags = if section.name == ".text" {
+            elf::SHF_ALLOC | elf::SHF_EXECINSTR
+        } else {
+            elf::SHF_ALLOC
+        };
+        writer.write_section_header(&SectionHeader {
+            name: Some(*name),
+            sh_type: elf::SHT_NOBITS,
+            sh_flags: sh_flags.into(),
+            sh_addr: section.address,
+            sh_offset: 0,
+            sh_size: section.size,
+            sh_link: 0,
+            sh_info: 0,
+            sh_addralign: 8,
+            sh_entsize: 0,
+        });
+    }
+    writer.write_section_header(&SectionHeader {
+        name: Some(note_name),
+        sh_type: elf::SHT_NOTE,
+        sh_flags: 0,
+        sh_addr: 0,
+        sh_offset: note_offset as u64,
+        sh_size: note.len() as u64,
+        sh_link: 0,
+        sh_info: 0,
+        sh_addralign: 4,
+        sh_entsize: 0,
+    });
+    for ((name, data), offset) in dwarf.iter().zip(dwarf_offsets) {
+        writer.write_section_header(&SectionHeader {
+            name: Some(*name),
+            sh_type: elf::SHT_PROGBITS,
+            sh_flags: 0,
+            sh_addr: 0,
+            sh_offset: offset as u64,
+            sh_size: data.len() as u64,
+            sh_link: 0,
+            sh_info: 0,
+            sh_addralign: 1,
+            sh_entsize: 0,
+        });
+    }
+    writer.write_symtab_section_header(1);
+    writer.write_strtab_section_header();
+    writer.write_shstrtab_section_header();
+    Ok(file)
+}
+
+/// The DWARF sections describing a program with `map` and `lines`: a
+/// compilation unit spanning .text, with a subprogram per function and a
+/// line table of the rows. Empty if there's no .text.
+fn dwarf_sections(
+    map: &LinkMap,
+    lines: &LineMap,
+) -> Result<Vec<(&'static str, Vec<u8>)>, SbpfLinkerError> {
+    let invalid = |error: gimli::write::Error| {
+        SbpfLinkerError::InvalidDebugInfo(error.to_string())
+    };
+    let Some(text) = map.sections.iter().find(|s| s.name == ".text") else {
+        return Ok(Vec::new());
+    };
+    let encoding = gimli::Encoding {
+        format: gimli::Format::Dwarf32,
+        version: 4,
+        address_size: 8,
+    };
+    let line_string = |string: &str| LineString::String(string.into());
+    let name = lines.rows.first().map_or("program", |row| row.file.as_str());
+    let (directory, file) = split_path(name);
+
+    let mut dwarf = DwarfUnit::new(encoding);
+    let mut program = LineProgram::new(
+        encoding,
+        Default::default(),
+        line_string(directory),
+        None,
+        line_string(file),
+        None,
+    );
+    program.begin_sequence(Some(Address::Constant(text.address)));
+    for row in &lines.rows {
+        let (directory, file) = split_path(&row.file);
+        let directory = program.add_directory(line_string(directory));
+        let file = program.add_file(line_string(file), directory, None);
+        let current = program.row();
+        current.address_offset = row.address - text.address;
+        current.file = file;
+        current.line = row.line;
+        current.column = row.column;
+        program.generate_row();
+    }
+    program.end_sequence(text.size);
+    dwarf.unit.line_program = program;
+
+    let root = dwarf.unit.root();
+    let unit = dwarf.unit.get_mut(root);
+    unit.set(gimli::DW_AT_name, AttributeValue::String(name.into()));
+    unit.set(gimli::DW_AT_comp_dir, AttributeValue::String(directory.into()));
+    unit.set(
+        gimli::DW_AT_low_pc,
+        AttributeValue::Address(Address::Constant(text.address)),
+    );
+    unit.set(gimli::DW_AT_high_pc, AttributeValue::Udata(text.size));
+    unit.set(gimli::DW_AT_stmt_list, AttributeValue::LineProgramRef);
+    for symbol in &map.symbols {
+        if symbol.kind != MapSymbolKind::Function {
+            continue;
+        }
+        let id = dwarf.unit.add(root, gimli::DW_TAG_subprogram);
+        let function = dwarf.unit.get_mut(id);
+        function.set(
+            gimli::DW_AT_name,
+            AttributeValue::String(symbol.name.as_bytes().into()),
+        );
+        function.set(
+            gimli::DW_AT_low_pc,
+            AttributeValue::Address(Address::Constant(symbol.address)),
+        );
+        function.set(gimli::DW_AT_high_pc, AttributeValue::Udata(symbol.size));
+    }
+
+    let mut sections = Sections::new(EndianVec::new(LittleEndian));
+    dwarf.write(&mut sections).map_err(invalid)?;
+    let mut written = Vec::new();
+    sections
+        .for_each(|id, data| -> Result<(), gimli::write::Error> {
+            if !data.slice().is_empty() {
+                written.push((id.name(), data.slice().to_vec()));
+            }
+            Ok(())
+        })
+        .map_err(invalid)?;
+    Ok(written)
+}
+
+/// The directory and file name of the full `path` of a line table row.
+fn split_path(path: &str) -> (&str, &str) {
+    match path.rsplit_once('/') {
+        Some(("", name)) => ("/", name),
+        Some((directory, name)) => (directory, name),
+        None => (".", path),
+    }
+}
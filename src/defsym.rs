@@ -0,0 +1,112 @@
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use crate::SbpfLinkerError;
+
+/// A symbol defined at link time, as with GNU ld's `--defsym name=expr`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Defsym {
+    pub name: String,
+    pub value: DefsymValue,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DefsymValue {
+    /// `name=0x1000`: references resolve to the absolute value.
+    Constant(i64),
+    /// `name=other`: references resolve as if they named `other`.
+    Symbol(String),
+}
+
+impl fmt::Display for DefsymValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Constant(value) => write!(f, "{value:#x}"),
+            Self::Symbol(name) => f.write_str(name),
+        }
+    }
+}
+
+impl FromStr for Defsym {
+    type Err = SbpfLinkerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || SbpfLinkerError::InvalidDefsym(s.to_owned());
+
+        let (name, expr) = s.split_once('=').ok_or_else(invalid)?;
+        let (name, expr) = (name.trim(), expr.trim());
+        if !is_symbol_name(name) {
+            return Err(invalid());
+        }
+
+        let value = if let Some(value) = parse_constant(expr) {
+            DefsymValue::Constant(value)
+        } else if is_symbol_name(expr) {
+            DefsymValue::Symbol(expr.to_owned())
+        } else {
+            return Err(invalid());
+        };
+
+        Ok(Self { name: name.to_owned(), value })
+    }
+}
+
+/// Resolves each defsym through alias chains until it reaches either a
+/// constant or a symbol that isn't itself defined by another defsym.
+/// `is_defined` reports whether the input object has a symbol by that name.
+pub(crate) fn resolve_defsyms(
+    defsyms: &[Defsym],
+    is_defined: impl Fn(&str) -> bool,
+) -> Result<HashMap<String, DefsymValue>, SbpfLinkerError> {
+    let by_name: HashMap<&str, &DefsymValue> =
+        defsyms.iter().map(|d| (d.name.as_str(), &d.value)).collect();
+
+    let mut resolved = HashMap::new();
+    for defsym in defsyms {
+        let mut value = &defsym.value;
+        let mut hops = 0;
+        while let DefsymValue::Symbol(target) = value {
+            match by_name.get(target.as_str()) {
+                Some(next) if hops < defsyms.len() => value = next,
+                Some(_) => {
+                    return Err(SbpfLinkerError::DefsymCycle(
+                        defsym.name.clone(),
+                    ));
+                }
+                None if is_defined(target) => break,
+                None => {
+                    return Err(SbpfLinkerError::UndefinedDefsymTarget {
+                        name: defsym.name.clone(),
+                        target: target.clone(),
+                    });
+                }
+            }
+            hops += 1;
+        }
+        resolved.insert(defsym.name.clone(), value.clone());
+    }
+
+    Ok(resolved)
+}
+
+pub(crate) fn parse_constant(expr: &str) -> Option<i64> {
+    let (negative, digits) = match expr.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, expr),
+    };
+    let value = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()? as i64,
+        None => digits.parse::<u64>().ok()? as i64,
+    };
+    Some(if negative { value.wrapping_neg() } else { value })
+}
+
+fn is_symbol_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || "_.$".contains(c))
+}
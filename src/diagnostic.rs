@@ -0,0 +1,420 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::File,
+    io::{self, IsTerminal as _},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use clap::ValueEnum;
+use tracing::{
+    Event, Level, Subscriber,
+    field::{Field, Visit},
+};
+use tracing_subscriber::{
+    EnvFilter,
+    fmt::format::{Writer, debug_fn},
+    layer::{Context, Layer, SubscriberExt as _},
+    util::SubscriberInitExt as _,
+};
+
+use crate::{SbpfLinkerError, TRACE_SYMBOL_TARGET};
+
+/// A non-fatal problem found while linking. Every diagnostic is also emitted
+/// as a `tracing` warning, or an error with `-Werror`, with its code and
+/// location as the fields named by [`EVENT_FIELDS`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    pub message: String,
+    pub location: Location,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum DiagnosticKind {
+    /// A duplicate symbol definition was dropped, see
+    /// [`LinkOptions::allow_multiple_definition`](crate::LinkOptions::allow_multiple_definition).
+    MultipleDefinition,
+    /// A rodata symbol without a size, which isn't collected into the
+    /// program.
+    ZeroSizeSymbol,
+    /// A relocation the linker doesn't handle and left unresolved.
+    IgnoredRelocation,
+    /// A call to an undefined function that isn't a syscall the linker
+    /// knows of. It's left for the loader to resolve.
+    UnknownSyscall,
+    /// The program has no `entrypoint` function and no other entry was
+    /// given, so execution starts at the start of .text.
+    MissingEntrypoint,
+    /// A symbol listed in [`LinkOptions::exports`](crate::LinkOptions::exports)
+    /// that isn't a function in .text, which only fails the link without
+    /// [`LinkOptions::allow_undefined_exports`](crate::LinkOptions::allow_undefined_exports).
+    UnresolvedExport,
+    /// A rodata symbol of at least [`LARGE_RODATA_SIZE`] bytes.
+    LargeRodata,
+    /// A function neither the entry point nor an export can reach, which
+    /// [`LinkOptions::gc_sections`](crate::LinkOptions::gc_sections) would
+    /// drop.
+    UnreachableCode,
+    /// A function the entry point or an export can reach that doesn't end
+    /// in an `exit` or a jump, so execution runs on into whatever follows
+    /// it.
+    FallThrough,
+    /// A non-empty `.data` or `.bss` section. Its mutable globals end up in
+    /// the program's read-only .rodata, so stores to them fault.
+    WritableData,
+    /// A [`LinkOptions::heap_size`](crate::LinkOptions::heap_size) above
+    /// the heap every transaction gets, which transactions calling the
+    /// program have to request.
+    HeapRequest,
+}
+
+/// Size from which a rodata symbol is a [`DiagnosticKind::LargeRodata`].
+pub const LARGE_RODATA_SIZE: u64 = 4096;
+
+impl DiagnosticKind {
+    pub const ALL: [Self; 11] = [
+        Self::MultipleDefinition,
+        Self::ZeroSizeSymbol,
+        Self::IgnoredRelocation,
+        Self::UnknownSyscall,
+        Self::MissingEntrypoint,
+        Self::UnresolvedExport,
+        Self::LargeRodata,
+        Self::UnreachableCode,
+        Self::FallThrough,
+        Self::WritableData,
+        Self::HeapRequest,
+    ];
+
+    /// The identifier of the kind in machine-readable output. With `-`
+    /// for `_`, it's also the name `-W` flags take.
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::MultipleDefinition => "multiple_definition",
+            Self::ZeroSizeSymbol => "zero_size_symbol",
+            Self::IgnoredRelocation => "unknown_relocation",
+            Self::UnknownSyscall => "unknown_syscall",
+            Self::MissingEntrypoint => "missing_entrypoint",
+            Self::UnresolvedExport => "unresolved_export",
+            Self::LargeRodata => "large_rodata",
+            Self::UnreachableCode => "unreachable_code",
+            Self::FallThrough => "fall_through",
+            Self::WritableData => "writable_data",
+            Self::HeapRequest => "heap_request",
+        }
+    }
+
+    /// Whether the warning is emitted unless disabled. The others, which
+    /// are common in working programs, need `-W<name>` or `-Wall`.
+    pub fn enabled_by_default(self) -> bool {
+        !matches!(self, Self::LargeRodata | Self::UnreachableCode)
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        let code = name.replace('-', "_");
+        Self::ALL.into_iter().find(|kind| kind.code() == code)
+    }
+}
+
+/// Which warnings are emitted, and whether they fail the link, as set by
+/// `-W` flags.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WarningOptions {
+    /// Warnings enabled or disabled by a flag, the last one winning.
+    overrides: BTreeMap<DiagnosticKind, bool>,
+    /// Emit warnings as errors, and fail the link if there are any.
+    pub errors: bool,
+}
+
+impl WarningOptions {
+    /// Applies `-W<flag>`: `<name>` enables the warning by that name and
+    /// `no-<name>` disables it, `all` enables every warning and `error`
+    /// turns them into errors.
+    pub fn apply(&mut self, flag: &str) -> Result<(), SbpfLinkerError> {
+        match flag {
+            "error" => self.errors = true,
+            "no-error" => self.errors = false,
+            "all" => {
+                self.overrides.extend(DiagnosticKind::ALL.map(|k| (k, true)))
+            }
+            _ => {
+                let (name, enabled) = match flag.strip_prefix("no-") {
+                    Some(name) => (name, false),
+                    None => (flag, true),
+                };
+                let kind =
+                    DiagnosticKind::from_name(name).ok_or_else(|| {
+                        SbpfLinkerError::UnknownWarning(flag.to_owned())
+                    })?;
+                self.overrides.insert(kind, enabled);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_enabled(&self, kind: DiagnosticKind) -> bool {
+        self.overrides
+            .get(&kind)
+            .copied()
+            .unwrap_or_else(|| kind.enabled_by_default())
+    }
+}
+
+/// Where in the linker's input a diagnostic or error points, as far as
+/// that's known. Offsets are relative to the section.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Location {
+    pub file: Option<PathBuf>,
+    pub section: Option<String>,
+    pub offset: Option<u64>,
+}
+
+impl Location {
+    pub(crate) fn at(section: &str, offset: u64) -> Self {
+        Self {
+            file: None,
+            section: Some(section.to_owned()),
+            offset: Some(offset),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Fields the `tracing` event of a [`Diagnostic`] has besides its message.
+pub const EVENT_FIELDS: [&str; 3] = ["code", "section", "offset"];
+
+/// Emits a diagnostic, unless `warnings` disable it: collects it into
+/// `diagnostics` and logs it as a `tracing` event.
+pub(crate) fn warn(
+    diagnostics: &mut Vec<Diagnostic>,
+    warnings: &WarningOptions,
+    kind: DiagnosticKind,
+    location: Location,
+    message: String,
+) {
+    if !warnings.is_enabled(kind) {
+        return;
+    }
+    let (code, section, offset) =
+        (kind.code(), location.section.as_deref(), location.offset);
+    if warnings.errors {
+        tracing::error!(code, section, offset, "{message}");
+    } else {
+        tracing::warn!(code, section, offset, "{message}");
+    }
+    diagnostics.push(Diagnostic { kind, message, location });
+}
+
+/// Fails the link after warnings if `warnings` turn them into errors.
+pub(crate) fn check(
+    diagnostics: &[Diagnostic],
+    warnings: &WarningOptions,
+) -> Result<(), SbpfLinkerError> {
+    if warnings.errors && !diagnostics.is_empty() {
+        return Err(SbpfLinkerError::WarningsAsErrors(diagnostics.len()));
+    }
+    Ok(())
+}
+
+/// A diagnostic as the JSON object rustc's `--error-format json` would
+/// print for it. `level` is `error`, `warning` or `note`; the location is
+/// the diagnostic's only span, if it has one.
+pub fn json_diagnostic(
+    level: &str,
+    code: Option<&str>,
+    message: &str,
+    location: &Location,
+) -> serde_json::Value {
+    let mut rendered = match code {
+        Some(code) => format!("{level}[{code}]: {message}\n"),
+        None => format!("{level}: {message}\n"),
+    };
+    let file = location.file.as_ref().map(|file| file.display().to_string());
+    if let Some(file) = &file {
+        rendered.push_str(&format!("  --> {file}"));
+        if let (Some(section), Some(offset)) =
+            (&location.section, location.offset)
+        {
+            rendered.push_str(&format!(":{section}+{offset:#x}"));
+        }
+        rendered.push('\n');
+    }
+    let spans = if file.is_some() || location.section.is_some() {
+        vec![serde_json::json!({
+            "file_name": file,
+            "section": location.section,
+            "byte_offset": location.offset,
+            "is_primary": true,
+        })]
+    } else {
+        Vec::new()
+    };
+    serde_json::json!({
+        "$message_type": "diagnostic",
+        "message": message,
+        "code": code.map(|code| serde_json::json!({
+            "code": code,
+            "explanation": null,
+        })),
+        "level": level,
+        "spans": spans,
+        "children": [],
+        "rendered": rendered,
+    })
+}
+
+/// A `tracing` layer writing events to stderr as rustc style JSON
+/// diagnostics, one per line. Events of a [`Diagnostic`] keep its code and
+/// location; those pointing into a section are attributed to `file`, the
+/// object the linker read, if given.
+pub struct JsonDiagnostics {
+    pub file: Option<PathBuf>,
+}
+
+impl<S: Subscriber> Layer<S> for JsonDiagnostics {
+    fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+        let mut fields = EventFields::default();
+        event.record(&mut fields);
+        let level = match *event.metadata().level() {
+            Level::ERROR => "error",
+            Level::WARN => "warning",
+            _ => "note",
+        };
+        if fields.location.section.is_some() {
+            fields.location.file.clone_from(&self.file);
+        }
+        eprintln!(
+            "{}",
+            json_diagnostic(
+                level,
+                fields.code.as_deref(),
+                &fields.message,
+                &fields.location
+            )
+        );
+    }
+}
+
+/// How the binaries print errors and warnings, as `--error-format` sets it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ErrorFormat {
+    Human,
+    /// See [`JsonDiagnostics`].
+    Json,
+}
+
+/// Logs warnings, plus the events requested with `--trace-symbol`, to
+/// stderr in `format`, as the binaries do. As JSON, those pointing into a
+/// section are attributed to `file`. `verbose` adds what the linker does,
+/// see [`log_filter`], which goes to `log_file` instead if given.
+pub fn init_logging(
+    format: ErrorFormat,
+    file: Option<PathBuf>,
+    verbose: u8,
+    log_file: Option<File>,
+) {
+    let filter = EnvFilter::new(log_filter(if log_file.is_some() {
+        0
+    } else {
+        verbose
+    }));
+    let log = log_file.map(|log_file| {
+        tracing_subscriber::fmt::layer()
+            .with_writer(Mutex::new(log_file))
+            .with_ansi(false)
+            .fmt_fields(debug_fn(write_text_field))
+            .with_filter(EnvFilter::new(log_filter(verbose)))
+    });
+    match format {
+        ErrorFormat::Human => tracing_subscriber::registry()
+            .with(log)
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .with_writer(io::stderr)
+                    .with_ansi(io::stderr().is_terminal())
+                    .without_time()
+                    .with_target(false)
+                    .fmt_fields(debug_fn(write_text_field))
+                    .with_filter(filter),
+            )
+            .init(),
+        ErrorFormat::Json => tracing_subscriber::registry()
+            .with(log)
+            .with(JsonDiagnostics { file }.with_filter(filter))
+            .init(),
+    }
+}
+
+/// The `tracing` filter the binaries log with at `verbosity`, their number
+/// of `-v`s: warnings and what `--trace-symbol` asks for, plus a summary of
+/// each step of the link at 1, and at 2 or more every section, relocation
+/// and function each step handles. It covers `bpf_linker`, i.e. LLVM, too.
+pub fn log_filter(verbosity: u8) -> String {
+    let level = match verbosity {
+        0 => return format!("warn,{TRACE_SYMBOL_TARGET}=info"),
+        1 => "debug",
+        _ => "trace",
+    };
+    format!(
+        "warn,sbpf_linker={level},sbpf_link={level},bpf_linker={level},\
+         {TRACE_SYMBOL_TARGET}=info"
+    )
+}
+
+/// Writes the fields of an event the way `tracing`'s default formatter
+/// does, except for the [`EVENT_FIELDS`] of diagnostics, e.g. with
+/// `tracing_subscriber::fmt::format::debug_fn`.
+pub fn write_text_field(
+    writer: &mut Writer<'_>,
+    field: &Field,
+    value: &dyn fmt::Debug,
+) -> fmt::Result {
+    match field.name() {
+        "message" => write!(writer, "{value:?}"),
+        name if EVENT_FIELDS.contains(&name) => Ok(()),
+        name => write!(writer, " {name}={value:?}"),
+    }
+}
+
+/// The message, code and location of an event, with any other fields
+/// appended to the message.
+#[derive(Default)]
+struct EventFields {
+    message: String,
+    code: Option<String>,
+    location: Location,
+}
+
+impl Visit for EventFields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "code" => self.code = Some(value.to_owned()),
+            "section" => self.location.section = Some(value.to_owned()),
+            _ => self.record_debug(field, &value),
+        }
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        match field.name() {
+            "offset" => self.location.offset = Some(value),
+            _ => self.record_debug(field, &value),
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        match field.name() {
+            "message" => self.message.insert_str(0, &format!("{value:?}")),
+            name => self.message.push_str(&format!(" {name}={value:?}")),
+        }
+    }
+}
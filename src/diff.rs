@@ -0,0 +1,584 @@
+use std::{collections::HashMap, fmt::Write as _};
+
+use object::{File, Object as _, ObjectSection as _};
+
+use crate::{
+    SbpfLinkerError,
+    byteparser::section_data,
+    inspect::{
+        DisassembledInstruction, RelocationInfo, SectionKind, disassemble,
+        inspect_relocations, inspect_sections, inspect_symbols,
+    },
+    size::demangle,
+};
+
+/// Lines of unchanged code [`ProgramDiff::render`] shows around a change.
+const CONTEXT: usize = 3;
+/// Beyond this many pairs of instructions, the changed part of a function
+/// is shown as removed and added as a whole rather than aligned.
+const MAX_ALIGNED: usize = 1 << 22;
+
+/// How two programs, or objects, differ: the functions whose code changed,
+/// the rodata whose contents did, and the size of every section.
+///
+/// Functions and rodata are paired by their demangled names, so the hashes
+/// a toolchain upgrade changes don't tell them apart. Instructions are
+/// compared by what they do rather than where they are: calls, and
+/// addresses loaded or stored in rodata, name the function or section they
+/// point to, so that code merely moved by the change of another function
+/// compares equal.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ProgramDiff {
+    /// The functions that differ, in the order of the second program, then
+    /// those only the first has.
+    pub functions: Vec<FunctionDiff>,
+    /// The rodata that differs, ordered as the functions are.
+    pub rodata: Vec<RodataDiff>,
+    /// Every section of either program, in the order of the second, then
+    /// `total` for the whole file.
+    pub sizes: Vec<SizeDelta>,
+}
+
+/// A function of either program whose code differs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FunctionDiff {
+    pub name: String,
+    /// Its size in the first program, `None` if only the second has it.
+    pub before: Option<u64>,
+    /// Its size in the second program, `None` if only the first has it.
+    pub after: Option<u64>,
+    /// Its instructions, aligned between the two.
+    pub lines: Vec<DiffLine>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiffLine {
+    Same(String),
+    Removed(String),
+    Added(String),
+}
+
+/// A rodata symbol, or a rodata section without any, whose contents
+/// differ.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RodataDiff {
+    pub name: String,
+    pub before: Option<u64>,
+    pub after: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SizeDelta {
+    pub name: String,
+    pub before: u64,
+    pub after: u64,
+}
+
+impl ProgramDiff {
+    /// Whether the two behave the same: no function or rodata differs.
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty() && self.rodata.is_empty()
+    }
+
+    /// The diff as text: a `diff -u` style hunk for every function, with
+    /// context around the changes, the rodata that changed, and a table of
+    /// the section sizes.
+    pub fn render(&self) -> String {
+        let mut diff = String::new();
+        for function in &self.functions {
+            let _ = writeln!(
+                diff,
+                "@@ {} ({}) @@",
+                function.name,
+                sizes(function.before, function.after)
+            );
+            let changed: Vec<usize> = function
+                .lines
+                .iter()
+                .enumerate()
+                .filter(|(_, line)| !matches!(line, DiffLine::Same(_)))
+                .map(|(index, _)| index)
+                .collect();
+            let mut shown = None;
+            for (index, line) in function.lines.iter().enumerate() {
+                let near =
+                    changed.iter().any(|&at| index.abs_diff(at) <= CONTEXT);
+                if !near {
+                    continue;
+                }
+                if shown.is_some_and(|last| last + 1 < index) {
+                    diff.push_str("   ...\n");
+                }
+                shown = Some(index);
+                let _ = match line {
+                    DiffLine::Same(asm) => writeln!(diff, "   {asm}"),
+                    DiffLine::Removed(asm) => writeln!(diff, "-  {asm}"),
+                    DiffLine::Added(asm) => writeln!(diff, "+  {asm}"),
+                };
+            }
+            diff.push('\n');
+        }
+        if !self.rodata.is_empty() {
+            diff.push_str("Rodata\n");
+            for rodata in &self.rodata {
+                let _ = writeln!(
+                    diff,
+                    "  {} ({})",
+                    rodata.name,
+                    sizes(rodata.before, rodata.after)
+                );
+            }
+            diff.push('\n');
+        }
+        diff.push_str("Sizes\n");
+        for size in &self.sizes {
+            let delta = size.after as i64 - size.before as i64;
+            let _ = writeln!(
+                diff,
+                "{:>8} {:>8} {delta:>+8}  {}",
+                size.before, size.after, size.name
+            );
+        }
+        if self.is_empty() {
+            diff.push_str("\nNo function or rodata differs.\n");
+        }
+        diff
+    }
+}
+
+/// `before -> after` bytes, or what says only one program has it.
+fn sizes(before: Option<u64>, after: Option<u64>) -> String {
+    match (before, after) {
+        (Some(before), Some(after)) => {
+            format!("{before} -> {after} bytes")
+        }
+        (Some(before), None) => format!("removed, {before} bytes"),
+        (None, Some(after)) => format!("added, {after} bytes"),
+        (None, None) => String::new(),
+    }
+}
+
+/// Diffs the program `after` against `before`, see [`ProgramDiff`]. Either
+/// can be an object or a linked program; a stripped program only names its
+/// exported functions, so the rest of its code is compared as part of
+/// them.
+pub fn diff_programs(
+    before: &[u8],
+    after: &[u8],
+) -> Result<ProgramDiff, SbpfLinkerError> {
+    let (before, after) = (Program::read(before)?, Program::read(after)?);
+
+    let mut functions = Vec::new();
+    for (name, old, new) in pair(&before.functions, &after.functions) {
+        let (old_lines, new_lines) = (
+            old.map_or(&[][..], |f| f.lines.as_slice()),
+            new.map_or(&[][..], |f| f.lines.as_slice()),
+        );
+        if old.is_some() && new.is_some() && old_lines == new_lines {
+            continue;
+        }
+        functions.push(FunctionDiff {
+            name: name.to_owned(),
+            before: old.map(|function| function.size),
+            after: new.map(|function| function.size),
+            lines: align(old_lines, new_lines),
+        });
+    }
+
+    let rodata = pair(&before.rodata, &after.rodata)
+        .into_iter()
+        .filter(|(_, old, new)| {
+            old.map(|rodata| &rodata.contents)
+                != new.map(|rodata| &rodata.contents)
+        })
+        .map(|(name, old, new)| RodataDiff {
+            name: name.to_owned(),
+            before: old.map(|rodata| rodata.contents.0.len() as u64),
+            after: new.map(|rodata| rodata.contents.0.len() as u64),
+        })
+        .collect();
+
+    let size_of = |program: &Program, name: &str| {
+        program
+            .sections
+            .iter()
+            .find(|(section, _)| section == name)
+            .map_or(0, |(_, size)| *size)
+    };
+    let mut names: Vec<&str> =
+        after.sections.iter().map(|(name, _)| name.as_str()).collect();
+    for (name, _) in &before.sections {
+        if !names.contains(&name.as_str()) {
+            names.push(name);
+        }
+    }
+    let mut sizes: Vec<SizeDelta> = names
+        .into_iter()
+        .map(|name| SizeDelta {
+            name: name.to_owned(),
+            before: size_of(&before, name),
+            after: size_of(&after, name),
+        })
+        .collect();
+    sizes.push(SizeDelta {
+        name: "total".to_owned(),
+        before: before.total,
+        after: after.total,
+    });
+
+    Ok(ProgramDiff { functions, rodata, sizes })
+}
+
+/// Something paired between the programs by its name.
+trait Named {
+    fn name(&self) -> &str;
+}
+
+/// Each of `after` paired with the one of `before` by the same name, then
+/// what only `before` has.
+fn pair<'a, T: Named>(
+    before: &'a [T],
+    after: &'a [T],
+) -> Vec<(&'a str, Option<&'a T>, Option<&'a T>)> {
+    let by_name: HashMap<&str, &T> =
+        before.iter().map(|item| (item.name(), item)).collect();
+    let mut pairs: Vec<_> = after
+        .iter()
+        .map(|item| {
+            (item.name(), by_name.get(item.name()).copied(), Some(item))
+        })
+        .collect();
+    pairs.extend(
+        before
+            .iter()
+            .filter(|item| !after.iter().any(|new| new.name() == item.name()))
+            .map(|item| (item.name(), Some(item), None)),
+    );
+    pairs
+}
+
+/// `before` and `after` aligned on their longest common subsequence, past
+/// what they start and end with alike.
+fn align(before: &[String], after: &[String]) -> Vec<DiffLine> {
+    let prefix =
+        before.iter().zip(after).take_while(|(old, new)| old == new).count();
+    let suffix = before[prefix..]
+        .iter()
+        .rev()
+        .zip(after[prefix..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+    let old = &before[prefix..before.len() - suffix];
+    let new = &after[prefix..after.len() - suffix];
+
+    let mut lines: Vec<DiffLine> =
+        before[..prefix].iter().cloned().map(DiffLine::Same).collect();
+    if old.len().saturating_mul(new.len()) > MAX_ALIGNED {
+        lines.extend(old.iter().cloned().map(DiffLine::Removed));
+        lines.extend(new.iter().cloned().map(DiffLine::Added));
+    } else {
+        // lengths of the common subsequences of the tails
+        let width = new.len() + 1;
+        let mut common = vec![0u32; (old.len() + 1) * width];
+        for i in (0..old.len()).rev() {
+            for j in (0..new.len()).rev() {
+                common[i * width + j] = if old[i] == new[j] {
+                    common[(i + 1) * width + j + 1] + 1
+                } else {
+                    common[(i + 1) * width + j].max(common[i * width + j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < old.len() || j < new.len() {
+            if i < old.len() && j < new.len() && old[i] == new[j] {
+                lines.push(DiffLine::Same(old[i].clone()));
+                (i, j) = (i + 1, j + 1);
+            } else if j == new.len()
+                || (i < old.len()
+                    && common[(i + 1) * width + j]
+                        >= common[i * width + j + 1])
+            {
+                lines.push(DiffLine::Removed(old[i].clone()));
+                i += 1;
+            } else {
+                lines.push(DiffLine::Added(new[j].clone()));
+                j += 1;
+            }
+        }
+    }
+    lines.extend(
+        before[before.len() - suffix..].iter().cloned().map(DiffLine::Same),
+    );
+    lines
+}
+
+/// A function as it's compared.
+struct Function {
+    name: String,
+    start: u64,
+    size: u64,
+    /// Its instructions, see [`Program::line`].
+    lines: Vec<String>,
+}
+
+impl Named for Function {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Rodata as it's compared.
+struct Rodata {
+    name: String,
+    /// Its bytes with the relocated ones zeroed, and at which offsets they
+    /// were next to what they point to.
+    contents: (Vec<u8>, Vec<(u64, String)>),
+}
+
+impl Named for Rodata {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// What of a program is compared.
+struct Program {
+    total: u64,
+    /// Every named section, with its size.
+    sections: Vec<(String, u64)>,
+    /// In address order, each spanning to the next one.
+    functions: Vec<Function>,
+    rodata: Vec<Rodata>,
+    /// Every allocated section: name, address and size.
+    allocated: Vec<(String, u64, u64)>,
+}
+
+impl Program {
+    fn read(source: &[u8]) -> Result<Self, SbpfLinkerError> {
+        let obj = File::parse(source)?;
+        let sections = inspect_sections(source)?;
+        let symbols = inspect_symbols(source)?;
+        let relocations = inspect_relocations(source)?;
+        let instructions = disassemble(source)?;
+
+        let mut program = Self {
+            total: source.len() as u64,
+            sections: sections
+                .iter()
+                .filter(|section| !section.name.is_empty())
+                .map(|section| (section.name.clone(), section.size))
+                .collect(),
+            functions: Vec::new(),
+            rodata: Vec::new(),
+            allocated: sections
+                .iter()
+                .filter(|section| {
+                    section.address > 0
+                        && section.kind != SectionKind::Metadata
+                })
+                .map(|section| {
+                    (section.name.clone(), section.address, section.size)
+                })
+                .collect(),
+        };
+
+        // the stripped program only names its exports
+        let mut starts: Vec<(u64, &str)> = symbols
+            .iter()
+            .filter(|symbol| {
+                symbol.section.as_deref() == Some(".text")
+                    && !matches!(symbol.kind, "section" | "file")
+                    && !symbol.name.is_empty()
+            })
+            .map(|symbol| (symbol.address, symbol.name.as_str()))
+            .collect();
+        starts.sort_by_key(|(address, _)| *address);
+        starts.dedup_by_key(|(address, _)| *address);
+        let text = sections.iter().find(|section| section.name == ".text");
+        let (base, end) = text
+            .map_or((0, 0), |text| (text.address, text.address + text.size));
+        if end > base && starts.first().is_none_or(|(at, _)| *at > base) {
+            starts.insert(0, (base, ".text"));
+        }
+        let names = unique_names(starts.iter().map(|(_, name)| *name));
+        for (index, ((start, _), name)) in starts.iter().zip(names).enumerate()
+        {
+            let next = starts.get(index + 1).map_or(end, |(next, _)| *next);
+            program.functions.push(Function {
+                name,
+                start: *start,
+                size: next - start,
+                lines: Vec::new(),
+            });
+        }
+        let lines: Vec<(u64, String)> = instructions
+            .iter()
+            .map(|instruction| {
+                (instruction.address, program.line(instruction))
+            })
+            .collect();
+        for function in &mut program.functions {
+            let range = function.start..function.start + function.size;
+            function.lines = lines
+                .iter()
+                .filter(|(address, _)| range.contains(address))
+                .map(|(_, line)| line.clone())
+                .collect();
+        }
+
+        // by their symbols, or as a whole if they have none
+        let mut rodata = Vec::new();
+        for section in obj.sections() {
+            let name = section.name()?;
+            let kind = SectionKind::from(section.kind());
+            if !matches!(kind, SectionKind::ReadOnlyData | SectionKind::Data)
+                || name.starts_with(".eh_frame")
+            {
+                continue;
+            }
+            let data = section_data(&section)?;
+            let mut ranges: Vec<(&str, u64, u64)> = symbols
+                .iter()
+                .filter(|symbol| {
+                    symbol.section.as_deref() == Some(name)
+                        && symbol.size > 0
+                        && symbol.kind == "object"
+                })
+                .map(|symbol| {
+                    let offset = symbol.address - section.address();
+                    (symbol.name.as_str(), offset, offset + symbol.size)
+                })
+                .collect();
+            if ranges.is_empty() {
+                ranges.push((name, 0, data.len() as u64));
+            }
+            for (symbol, start, end) in ranges {
+                let mut bytes = data
+                    .get(start as usize..end as usize)
+                    .unwrap_or_default()
+                    .to_vec();
+                let mut pointers = Vec::new();
+                for relocation in &relocations {
+                    if relocation.section != name
+                        || !(start..end).contains(&relocation.offset)
+                    {
+                        continue;
+                    }
+                    let at = relocation.offset - start;
+                    let Some(slot) =
+                        bytes.get_mut(at as usize..at as usize + 8)
+                    else {
+                        continue;
+                    };
+                    let stored = u64::from_le_bytes(
+                        slot.try_into().unwrap_or_default(),
+                    );
+                    slot.fill(0);
+                    pointers.push((at, program.target(relocation, stored)));
+                }
+                rodata.push((symbol, (bytes, pointers)));
+            }
+        }
+        let names = unique_names(rodata.iter().map(|(name, _)| *name));
+        program.rodata = rodata
+            .into_iter()
+            .zip(names)
+            .map(|((_, contents), name)| Rodata { name, contents })
+            .collect();
+        Ok(program)
+    }
+
+    /// `instruction` rendered so that it compares equal in another program
+    /// if it does the same: what it refers to through a relocation or a
+    /// call by name, rather than by address.
+    fn line(&self, instruction: &DisassembledInstruction) -> String {
+        let asm = &instruction.asm;
+        // the operand naming where it points
+        let (head, last) = match asm.rsplit_once(", ") {
+            Some((head, last)) => (format!("{head}, "), last),
+            None => match asm.split_once(' ') {
+                Some((head, last)) => (format!("{head} "), last),
+                None => return asm.clone(),
+            },
+        };
+        if let Some(relocation) = instruction.relocations.first() {
+            let stored = match instruction.bytes.get(..16) {
+                // the halves of an `lddw`
+                Some(bytes) if bytes[0] == 0x18 => {
+                    let half = |at: usize| {
+                        u64::from(u32::from_le_bytes(
+                            bytes[at..at + 4].try_into().unwrap_or_default(),
+                        ))
+                    };
+                    half(4) | half(12) << 32
+                }
+                _ => 0,
+            };
+            return format!("{head}{}", self.target(relocation, stored));
+        }
+        if asm.starts_with("call ")
+            && let Ok(offset) = last.parse::<i64>()
+        {
+            let target =
+                instruction.address.wrapping_add_signed((offset + 1) * 8);
+            return format!("{head}{}", self.describe(target));
+        }
+        match last.chars().next() {
+            Some(c) if c.is_alphabetic() || c == '_' => {
+                format!("{head}{}", demangle(last))
+            }
+            _ => asm.clone(),
+        }
+    }
+
+    /// What `relocation`, with `stored` at the place it patches, points to.
+    fn target(&self, relocation: &RelocationInfo, stored: u64) -> String {
+        match (relocation.symbol.as_str(), stored) {
+            ("", _) => self.describe(stored),
+            (symbol, 0) => demangle(symbol),
+            (symbol, addend) => format!("{}+{addend:#x}", demangle(symbol)),
+        }
+    }
+
+    /// `address` as the function or allocated section it's in, with the
+    /// offset there.
+    fn describe(&self, address: u64) -> String {
+        let function = self.functions.iter().find(|function| {
+            (function.start..function.start + function.size).contains(&address)
+        });
+        let (name, start) = match function {
+            Some(function) => (function.name.as_str(), function.start),
+            None => match self.allocated.iter().find(|(_, start, size)| {
+                (*start..start + size).contains(&address)
+            }) {
+                Some((name, start, _)) => (name.as_str(), *start),
+                None => return format!("{address:#x}"),
+            },
+        };
+        match address - start {
+            0 => name.to_owned(),
+            offset => format!("{name}+{offset:#x}"),
+        }
+    }
+}
+
+/// `names` demangled, and numbered from the second on if several demangle
+/// alike, e.g. instances of a generic.
+fn unique_names<'a>(names: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    names
+        .map(|name| {
+            let name = demangle(name);
+            let count = seen.entry(name.clone()).or_default();
+            *count += 1;
+            match *count {
+                1 => name,
+                count => format!("{name}#{count}"),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,289 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::Write as _;
+
+use sbpf_assembler::{
+    astnode::{ASTNode, ROData},
+    dynsym::RelocationType,
+    instruction::Instruction,
+    lexer::{ImmediateValue, Token},
+    parser::ParseResult,
+};
+use sbpf_common::opcode::Opcode;
+
+/// Renders the program `parse_result` describes as sbpf assembly: the
+/// exported symbols, then .text with its labels, then .rodata as `.byte`
+/// directives. Calls within .text, and branches and loads of labeled
+/// targets, name their target; call targets without a label get one named
+/// after their offset. Loads into the middle of a constant name it plus
+/// the offset, e.g. `msg+2`.
+///
+/// Names the linker makes up, like `.text+0x28`, are rewritten into
+/// identifiers, so the output assembles back into the same program.
+pub(crate) fn render_asm(parse_result: &ParseResult) -> String {
+    let code = parse_result.code_section.get_nodes();
+    let data = parse_result.data_section.get_nodes();
+    let mut labels: BTreeMap<u64, Vec<String>> = BTreeMap::new();
+    for node in code {
+        if let ASTNode::Label { label, offset } = node {
+            labels.entry(*offset).or_default().push(label.name.clone());
+        }
+    }
+    for node in code {
+        if let ASTNode::Instruction { instruction, offset } = node
+            && instruction.opcode == Opcode::Call
+            && let Some(target) = branch_target(instruction, 0, *offset)
+        {
+            labels
+                .entry(target)
+                .or_insert_with(|| vec![format!("func_{target:#x}")]);
+        }
+    }
+    // the constants, by offset; labels holding nothing are referred to by
+    // what they point into
+    let constants: BTreeMap<u64, &ROData> = data
+        .iter()
+        .filter_map(|node| match node {
+            ASTNode::ROData { rodata, offset } if rodata.get_size() > 0 => {
+                Some((*offset, rodata))
+            }
+            _ => None,
+        })
+        .collect();
+    let rodata_offsets: HashMap<&str, u64> = data
+        .iter()
+        .filter_map(|node| match node {
+            ASTNode::ROData { rodata, offset } => {
+                Some((rodata.name.as_str(), *offset))
+            }
+            _ => None,
+        })
+        .collect();
+
+    let names = identifiers(
+        labels
+            .values()
+            .flatten()
+            .chain(constants.values().map(|rodata| &rodata.name)),
+    );
+    let labels: BTreeMap<u64, Vec<String>> = labels
+        .into_iter()
+        .map(|(offset, labels)| {
+            (
+                offset,
+                labels
+                    .iter()
+                    .map(|label| names[label.as_str()].clone())
+                    .collect(),
+            )
+        })
+        .collect();
+    let name = |name: &str| {
+        names.get(name).cloned().unwrap_or_else(|| name.to_owned())
+    };
+
+    // lddw of a label resolves to its address, the relocation keeps the name
+    let loads: HashMap<u64, String> = parse_result
+        .relocation_data
+        .get_rel_dyns()
+        .into_iter()
+        .filter(|(_, kind, _)| *kind == RelocationType::RSbf64Relative)
+        .map(|(offset, _, target)| {
+            let operand = match rodata_offsets.get(target.as_str()) {
+                Some(&at) => match constants.range(..=at).next_back() {
+                    Some((start, rodata)) if *start == at => {
+                        name(&rodata.name)
+                    }
+                    Some((start, rodata)) => {
+                        format!("{}+{}", name(&rodata.name), at - start)
+                    }
+                    None => name(&target),
+                },
+                None => name(&target),
+            };
+            (offset, operand)
+        })
+        .collect();
+
+    let mut asm = String::new();
+    for (entry, _) in parse_result.dynamic_symbols.get_entry_points() {
+        let _ = writeln!(asm, ".globl {}", name(&entry));
+    }
+    asm.push_str(".text\n");
+    for node in code {
+        let ASTNode::Instruction { instruction, offset } = node else {
+            continue;
+        };
+        for label in labels.get(offset).into_iter().flatten() {
+            let _ = writeln!(asm, "{label}:");
+        }
+        let line = match loads.get(offset) {
+            Some(target) => {
+                format!("lddw {}, {target}", render_operands(instruction, 0))
+            }
+            None => render_instruction(instruction, *offset, &labels),
+        };
+        let _ = writeln!(asm, "    {line}");
+    }
+
+    if !constants.is_empty() {
+        asm.push_str(".rodata\n");
+    }
+    for rodata in constants.values() {
+        let label = name(&rodata.name);
+        match rodata.args.get(1) {
+            Some(Token::VectorLiteral(values, _)) => {
+                let bytes: Vec<_> = values
+                    .iter()
+                    .map(|value| format!("{:#04x}", immediate(value) as u8))
+                    .collect();
+                let _ = writeln!(asm, "{label}: .byte {}", bytes.join(", "));
+            }
+            Some(Token::StringLiteral(text, _)) => {
+                let _ = writeln!(asm, "{label}: .ascii {text:?}");
+            }
+            _ => {}
+        }
+    }
+    asm
+}
+
+/// The identifier each of `names` is written as: itself if it is one,
+/// else, for names like `.text+0x28`, the name with each character an
+/// identifier can't hold turned into `_`, made unique among the rest.
+fn identifiers<'a>(
+    names: impl IntoIterator<Item = &'a String>,
+) -> HashMap<String, String> {
+    let names: BTreeSet<&str> =
+        names.into_iter().map(String::as_str).collect();
+    let mut taken: HashSet<String> = names
+        .iter()
+        .filter(|name| is_identifier(name))
+        .map(|name| (*name).to_owned())
+        .collect();
+    names
+        .into_iter()
+        .map(|name| {
+            if is_identifier(name) {
+                return (name.to_owned(), name.to_owned());
+            }
+            let mut base: String = name
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+            if !base.starts_with(|c: char| c == '_' || c.is_ascii_alphabetic())
+            {
+                base.insert(0, '_');
+            }
+            let mut identifier = base.clone();
+            for n in 1.. {
+                if taken.insert(identifier.clone()) {
+                    break;
+                }
+                identifier = format!("{base}_{n}");
+            }
+            (name.to_owned(), identifier)
+        })
+        .collect()
+}
+
+/// Whether the assembler reads `name` as one identifier.
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c == '_' || c.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c == '.' || c.is_ascii_alphanumeric())
+}
+
+/// `instruction`, found at .text offset `offset`, as a line of assembly.
+pub(crate) fn render_instruction(
+    instruction: &Instruction,
+    offset: u64,
+    labels: &BTreeMap<u64, Vec<String>>,
+) -> String {
+    let operands = &instruction.operands;
+    let operand = |index: usize| render_operands(instruction, index);
+    let target = |index: usize| {
+        let label = branch_target(instruction, index, offset)
+            .and_then(|target| labels.get(&target))
+            .and_then(|names| names.first());
+        match (label, operands.get(index)) {
+            (Some(label), _) => label.clone(),
+            (None, Some(Token::ImmediateValue(value, _))) => {
+                format!("{:+}", immediate(value))
+            }
+            _ => operand(index),
+        }
+    };
+    let memory = |register: usize, displacement: usize| {
+        let displacement = match operands.get(displacement) {
+            Some(Token::ImmediateValue(value, _)) => immediate(value),
+            _ => 0,
+        };
+        format!("[{}{displacement:+}]", operand(register))
+    };
+
+    let opcode = instruction.opcode;
+    let name = opcode.to_str();
+    match opcode {
+        Opcode::Exit => name.to_owned(),
+        Opcode::Callx => format!("callx {}", operand(0)),
+        Opcode::Call => format!("call {}", target(0)),
+        Opcode::Ja => format!("ja {}", target(0)),
+        Opcode::Be | Opcode::Le => {
+            format!("{name}{} {}", operand(1), operand(0))
+        }
+        Opcode::Ldxb | Opcode::Ldxh | Opcode::Ldxw | Opcode::Ldxdw => {
+            format!("{name} {}, {}", operand(0), memory(1, 2))
+        }
+        Opcode::Stb | Opcode::Sth | Opcode::Stw | Opcode::Stdw => {
+            format!("{name} {}, {}", memory(0, 1), operand(2))
+        }
+        Opcode::Stxb | Opcode::Stxh | Opcode::Stxw | Opcode::Stxdw => {
+            format!("{name} {}, {}", memory(0, 2), operand(1))
+        }
+        _ if instruction.is_jump() => {
+            format!("{name} {}, {}, {}", operand(0), operand(1), target(2))
+        }
+        _ => {
+            let operands: Vec<_> =
+                operands.iter().map(render_operand).collect();
+            format!("{name} {}", operands.join(", "))
+        }
+    }
+}
+
+/// Where the relative branch or call `instruction` at `offset` goes, if its
+/// `index`th operand is a relative target. Targets count instructions from
+/// the next one.
+fn branch_target(
+    instruction: &Instruction,
+    index: usize,
+    offset: u64,
+) -> Option<u64> {
+    match instruction.operands.get(index)? {
+        Token::ImmediateValue(value, _) => {
+            let relative = immediate(value).checked_add(1)?.checked_mul(8)?;
+            offset.checked_add_signed(relative)
+        }
+        _ => None,
+    }
+}
+
+fn render_operands(instruction: &Instruction, index: usize) -> String {
+    instruction.operands.get(index).map(render_operand).unwrap_or_default()
+}
+
+pub(crate) fn render_operand(token: &Token) -> String {
+    match token {
+        Token::Register(n, _) => format!("r{n}"),
+        Token::ImmediateValue(value, _) => immediate(value).to_string(),
+        Token::Identifier(name, _) | Token::Label(name, _) => name.clone(),
+        Token::StringLiteral(text, _) => format!("{text:?}"),
+        _ => String::from("?"),
+    }
+}
+
+fn immediate(value: &ImmediateValue) -> i64 {
+    match value {
+        ImmediateValue::Int(value) | ImmediateValue::Addr(value) => *value,
+    }
+}
@@ -0,0 +1,244 @@
+use object::{Object as _, ObjectSection as _, elf};
+
+use crate::SbpfLinkerError;
+
+/// `e_machine` of programs for the SBPF ISA rather than upstream BPF, as
+/// Solana's toolchain names it.
+pub(crate) const EM_SBPF: u16 = 263;
+
+/// Size of an `Elf64_Sym`.
+pub(crate) const SYMBOL_SIZE: usize = 24;
+
+/// Size of an `Elf64_Shdr`.
+const SECTION_HEADER_SIZE: usize = 64;
+
+/// Points the `sh_link` of `.dynamic`, `.dynsym` and `.rel.dyn` in the
+/// emitted `program` at the sections they refer to. The assembler hardcodes
+/// their indices for a program with a `.rodata`, so without one they're off
+/// by one and tools, `object` included, can't read the dynamic symbols.
+pub(crate) fn fix_section_links(program: &mut [u8]) {
+    let read = |program: &[u8], at: usize, size: usize| {
+        program.get(at..at + size).map(|bytes| {
+            let mut value = [0; 8];
+            value[..size].copy_from_slice(bytes);
+            u64::from_le_bytes(value)
+        })
+    };
+    let (Some(shoff), Some(shnum)) =
+        (read(program, 0x28, 8), read(program, 0x3c, 2))
+    else {
+        return;
+    };
+    let header = |index: usize| shoff as usize + index * SECTION_HEADER_SIZE;
+    let find = |program: &[u8], sh_type: u32, alloc: bool| {
+        (0..shnum as usize).find(|&index| {
+            read(program, header(index) + 4, 4) == Some(u64::from(sh_type))
+                && read(program, header(index) + 8, 8).is_some_and(|flags| {
+                    flags & u64::from(elf::SHF_ALLOC) != 0 || !alloc
+                })
+        })
+    };
+    let (Some(dynsym), Some(dynstr)) = (
+        find(program, elf::SHT_DYNSYM, false),
+        find(program, elf::SHT_STRTAB, true),
+    ) else {
+        return;
+    };
+
+    let mut links = vec![(dynsym, dynstr)];
+    links
+        .extend(find(program, elf::SHT_DYNAMIC, false).map(|at| (at, dynstr)));
+    links.extend(find(program, elf::SHT_REL, false).map(|at| (at, dynsym)));
+    for (index, link) in links {
+        if let Some(sh_link) =
+            program.get_mut(header(index) + 40..header(index) + 44)
+        {
+            sh_link.copy_from_slice(&(link as u32).to_le_bytes());
+        }
+    }
+}
+
+/// Moves the `.dynsym` symbols of the emitted `program`, which the assembler
+/// all puts at the start of .text, to their offsets in `exports`. `e_entry`
+/// follows the symbol `entry`, the one the loader looks the entry up by.
+pub(crate) fn place_exports(
+    program: &mut [u8],
+    exports: &[(String, u64)],
+    entry: &str,
+) -> Result<(), SbpfLinkerError> {
+    let mut values = Vec::new();
+    {
+        let obj = object::File::parse(&*program)?;
+        if let (Some(dynsym), Some(dynstr)) =
+            (obj.section_by_name(".dynsym"), obj.section_by_name(".dynstr"))
+        {
+            let strings = dynstr.data()?;
+            let (start, size) = dynsym.file_range().unwrap_or_default();
+            for at in (start..start + size).step_by(SYMBOL_SIZE) {
+                let at = at as usize;
+                let Some(symbol) = program.get(at..at + SYMBOL_SIZE) else {
+                    break;
+                };
+                let st_name = u32::from_le_bytes([
+                    symbol[0], symbol[1], symbol[2], symbol[3],
+                ]) as usize;
+                let Some(name) = strings
+                    .get(st_name..)
+                    .and_then(|rest| rest.split(|&byte| byte == 0).next())
+                else {
+                    continue;
+                };
+                if let Some((_, offset)) = exports
+                    .iter()
+                    .find(|(export, _)| export.as_bytes() == name)
+                {
+                    values.push((at + 8, *offset));
+                }
+            }
+        }
+    }
+
+    if let Some((_, offset)) = exports.iter().find(|(name, _)| name == entry) {
+        // e_entry
+        values.push((24, *offset));
+    }
+    for (at, offset) in values {
+        if let Some(bytes) = program.get_mut(at..at + 8) {
+            let mut value = [0; 8];
+            value.copy_from_slice(bytes);
+            let value = u64::from_le_bytes(value) + offset;
+            bytes.copy_from_slice(&value.to_le_bytes());
+        }
+    }
+    Ok(())
+}
+
+/// Name of the section holding the note [`add_build_id`] adds.
+pub(crate) const BUILD_ID_SECTION: &str = ".note.gnu.build-id";
+
+/// The `.note.gnu.build-id` section's contents for `id`: an `Elf64_Nhdr`
+/// of type `NT_GNU_BUILD_ID` named `GNU`, then the ID.
+pub(crate) fn build_id_note(id: &[u8]) -> Vec<u8> {
+    let mut note = Vec::new();
+    note.extend_from_slice(&4u32.to_le_bytes());
+    note.extend_from_slice(&(id.len() as u32).to_le_bytes());
+    note.extend_from_slice(&elf::NT_GNU_BUILD_ID.to_le_bytes());
+    note.extend_from_slice(elf::ELF_NOTE_GNU);
+    note.push(0);
+    note.extend_from_slice(id);
+    note.resize(note.len().next_multiple_of(4), 0);
+    note
+}
+
+/// Adds a non-allocated `.note.gnu.build-id` section holding `id` to the
+/// emitted `program`, see [`append_sections`].
+pub(crate) fn add_build_id(
+    program: &mut Vec<u8>,
+    id: &[u8],
+) -> Result<(), SbpfLinkerError> {
+    append_sections(
+        program,
+        &[NewSection {
+            name: BUILD_ID_SECTION,
+            sh_type: elf::SHT_NOTE,
+            data: &build_id_note(id),
+            align: 4,
+            entsize: 0,
+            link: None,
+            info: 0,
+        }],
+    )
+}
+
+/// A non-allocated section for [`append_sections`] to add.
+pub(crate) struct NewSection<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) sh_type: u32,
+    pub(crate) data: &'a [u8],
+    pub(crate) align: usize,
+    pub(crate) entsize: usize,
+    /// The section `sh_link` points at, by its index among those added.
+    pub(crate) link: Option<usize>,
+    pub(crate) info: u32,
+}
+
+/// Adds non-allocated `sections` to the emitted `program`, after its own.
+/// Their names go at the end of the section name table, and their contents
+/// and headers after everything else, so nothing the loader maps moves.
+pub(crate) fn append_sections(
+    program: &mut Vec<u8>,
+    sections: &[NewSection<'_>],
+) -> Result<(), SbpfLinkerError> {
+    let read = |program: &[u8], at: usize, size: usize| {
+        let mut value = [0; 8];
+        value[..size].copy_from_slice(&program[at..at + size]);
+        u64::from_le_bytes(value) as usize
+    };
+    let write = |program: &mut [u8], at: usize, size: usize, value: usize| {
+        program[at..at + size]
+            .copy_from_slice(&(value as u64).to_le_bytes()[..size]);
+    };
+    // validates the headers read below
+    object::File::parse(program.as_slice())?;
+    let (shoff, shnum, shstrndx) = (
+        read(program, 0x28, 8),
+        read(program, 0x3c, 2),
+        read(program, 0x3e, 2),
+    );
+    let mut headers =
+        program[shoff..shoff + shnum * SECTION_HEADER_SIZE].to_vec();
+    let names = shstrndx * SECTION_HEADER_SIZE;
+    let (names_offset, names_size) =
+        (read(&headers, names + 24, 8), read(&headers, names + 32, 8));
+
+    // the section name table is usually last before the headers; if not, a
+    // copy of it is
+    let end = names_offset + names_size;
+    let mut names_data = program[names_offset..end].to_vec();
+    if program[end..shoff].iter().all(|&byte| byte == 0)
+        && headers.chunks(SECTION_HEADER_SIZE).all(|header| {
+            read(header, 4, 4) == elf::SHT_NOBITS as usize
+                || read(header, 24, 8) + read(header, 32, 8) <= end
+        })
+    {
+        program.truncate(names_offset);
+    } else {
+        program.truncate(shoff);
+    }
+    let name_offsets: Vec<usize> = sections
+        .iter()
+        .map(|section| {
+            let name = names_data.len();
+            names_data.extend_from_slice(section.name.as_bytes());
+            names_data.push(0);
+            name
+        })
+        .collect();
+    write(&mut headers, names + 24, 8, program.len());
+    write(&mut headers, names + 32, 8, names_data.len());
+    program.extend_from_slice(&names_data);
+
+    for (section, name) in sections.iter().zip(name_offsets) {
+        program.resize(program.len().next_multiple_of(section.align), 0);
+        let mut header = vec![0; SECTION_HEADER_SIZE];
+        write(&mut header, 0, 4, name);
+        write(&mut header, 4, 4, section.sh_type as usize);
+        write(&mut header, 24, 8, program.len());
+        write(&mut header, 32, 8, section.data.len());
+        if let Some(link) = section.link {
+            write(&mut header, 40, 4, shnum + link);
+        }
+        write(&mut header, 44, 4, section.info as usize);
+        write(&mut header, 48, 8, section.align);
+        write(&mut header, 56, 8, section.entsize);
+        headers.extend_from_slice(&header);
+        program.extend_from_slice(section.data);
+    }
+
+    program.resize(program.len().next_multiple_of(8), 0);
+    let shoff = program.len();
+    program.extend_from_slice(&headers);
+    write(program, 0x28, 8, shoff);
+    write(program, 0x3c, 2, shnum + sections.len());
+    Ok(())
+}
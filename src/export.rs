@@ -0,0 +1,226 @@
+use object::{
+    File, Object as _, ObjectSection as _, ObjectSymbol as _, SymbolKind,
+};
+
+use crate::{LinkOptions, SbpfLinkerError, rename::Renames};
+
+/// Whether the export `export` is a glob pattern rather than a name: it has
+/// a `*`, which matches any run of characters, a `?`, which matches one, or
+/// a `[...]` set of characters, e.g. `[a-z]` or `[!_]`.
+pub fn is_pattern(export: &str) -> bool {
+    export.contains(['*', '?', '['])
+}
+
+/// Whether the glob `pattern`, see [`is_pattern`], matches all of `name`.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // where to go on from after the last `*`: the pattern past it, and the
+    // name past what it matched so far
+    let mut backtrack = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, n));
+                p += 1;
+                continue;
+            }
+            Some('?') => {
+                p += 1;
+                n += 1;
+                continue;
+            }
+            Some('[') => {
+                if let Some((matched, end)) = match_set(&pattern, p, name[n]) {
+                    if matched {
+                        p = end;
+                        n += 1;
+                        continue;
+                    }
+                } else if name[n] == '[' {
+                    // an unclosed `[` is itself
+                    p += 1;
+                    n += 1;
+                    continue;
+                }
+            }
+            Some(&c) if c == name[n] => {
+                p += 1;
+                n += 1;
+                continue;
+            }
+            _ => {}
+        }
+        // the last `*` takes one more character
+        let Some((after_star, taken)) = backtrack else { return false };
+        backtrack = Some((after_star, taken + 1));
+        p = after_star;
+        n = taken + 1;
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Whether the set `[...]` at `pattern[start]` matches `c`, and where the
+/// pattern goes on after it. `None` if the set isn't closed.
+fn match_set(
+    pattern: &[char],
+    start: usize,
+    c: char,
+) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+    let negated = matches!(pattern.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let &low = pattern.get(i)?;
+        // a `]` right at the start is one of the set
+        if low == ']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+        match (pattern.get(i + 1), pattern.get(i + 2)) {
+            (Some('-'), Some(&high)) if high != ']' => {
+                matched |= (low..=high).contains(&c);
+                i += 3;
+            }
+            _ => {
+                matched |= low == c;
+                i += 1;
+            }
+        }
+    }
+}
+
+/// `exports` with each pattern replaced by the names of `functions` it
+/// matches, in name order, after the names before it. Names are kept as
+/// they are, checked where they're exported. Fails with
+/// [`SbpfLinkerError::UnmatchedExportPattern`] for a pattern that matches
+/// none of `functions`, which is likely mistyped.
+pub fn expand_exports<'a>(
+    exports: &[String],
+    functions: impl IntoIterator<Item = &'a str>,
+) -> Result<Vec<String>, SbpfLinkerError> {
+    let mut functions: Vec<&str> = functions.into_iter().collect();
+    functions.sort_unstable();
+    functions.dedup();
+    let mut expanded: Vec<String> = Vec::new();
+    for export in exports {
+        if !is_pattern(export) {
+            if !expanded.contains(export) {
+                expanded.push(export.clone());
+            }
+            continue;
+        }
+        let mut matched = false;
+        for function in &functions {
+            if matches(export, function) {
+                matched = true;
+                if !expanded.iter().any(|name| name == function) {
+                    expanded.push((*function).to_owned());
+                }
+            }
+        }
+        if !matched {
+            return Err(SbpfLinkerError::UnmatchedExportPattern(
+                export.clone(),
+            ));
+        }
+    }
+    Ok(expanded)
+}
+
+/// `options` with the export patterns expanded against the functions in
+/// the .text of `source`, by their names after `--rename-symbols`. `None`
+/// if there are no patterns.
+pub(crate) fn expand_in(
+    source: &[u8],
+    options: &LinkOptions,
+) -> Result<Option<LinkOptions>, SbpfLinkerError> {
+    if !options.exports.iter().any(|export| is_pattern(export)) {
+        return Ok(None);
+    }
+    let obj = File::parse(source)?;
+    let renames = Renames::new(
+        &options.renames,
+        obj.symbols().filter_map(|symbol| symbol.name().ok()),
+    )?;
+    let text = obj.section_by_name(".text").map(|text| text.index());
+    // the assembler's local labels aren't functions
+    let functions: Vec<&str> = obj
+        .symbols()
+        .filter(|symbol| {
+            text.is_some()
+                && symbol.section_index() == text
+                && symbol.kind() != SymbolKind::Section
+        })
+        .filter_map(|symbol| symbol.name().ok())
+        .filter(|name| !name.is_empty() && !name.starts_with(".L"))
+        .map(|name| renames.get(name))
+        .collect();
+    Ok(Some(LinkOptions {
+        exports: expand_exports(&options.exports, functions)?,
+        ..options.clone()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn star_backtracks() {
+        assert!(matches("*", ""));
+        assert!(matches("process_*", "process_instruction"));
+        // the first `_` isn't the one `*_b` needs
+        assert!(matches("a*_b", "a_x_b"));
+        assert!(matches("*a*b*", "xxaybzz"));
+        assert!(!matches("a*_b", "a_x_c"));
+        assert!(!matches("*a", "ab"));
+    }
+
+    #[test]
+    fn question_mark_is_one_character() {
+        assert!(matches("f?o", "foo"));
+        assert!(!matches("f?o", "fo"));
+        assert!(!matches("f?o", "fooo"));
+    }
+
+    #[test]
+    fn sets() {
+        assert!(matches("v[0-9]", "v7"));
+        assert!(!matches("v[0-9]", "vx"));
+        assert!(matches("[a-cx]", "x"));
+        assert!(!matches("[a-cx]", "d"));
+        // a `-` next to the closing `]` is itself
+        assert!(matches("[a-]", "-"));
+    }
+
+    #[test]
+    fn negated_sets() {
+        for pattern in ["[!_]*", "[^_]*"] {
+            assert!(matches(pattern, "entrypoint"));
+            assert!(!matches(pattern, "_private"));
+        }
+    }
+
+    #[test]
+    fn leading_bracket_is_in_the_set() {
+        assert!(matches("[]a]", "]"));
+        assert!(matches("[]a]", "a"));
+        assert!(!matches("[]a]", "b"));
+        assert!(!matches("[!]]", "]"));
+        assert!(matches("[!]]", "x"));
+    }
+
+    #[test]
+    fn unclosed_bracket_is_itself() {
+        assert!(is_pattern("f[o"));
+        assert!(matches("f[o", "f[o"));
+        assert!(!matches("f[o", "fo"));
+        assert!(matches("*[", "a["));
+    }
+}
@@ -0,0 +1,673 @@
+use std::collections::{HashMap, HashSet};
+
+use object::write::{
+    Object as OutputObject, Relocation as OutputRelocation,
+    Symbol as OutputSymbol, SymbolSection,
+};
+use object::{
+    Architecture, BinaryFormat, Endianness, File, Object as _,
+    ObjectSection as _, ObjectSymbol as _, RelocationFlags, RelocationTarget,
+    Section, SectionFlags, SectionKind, SymbolFlags, SymbolKind, SymbolScope,
+};
+use sbpf_common::opcode::Opcode;
+
+use crate::analysis::decode;
+use crate::byteparser::{
+    DEFAULT_ENTRY, R_BPF_64_ABS64, R_BPF_64_RELATIVE, align_section,
+    collect_rodata, data_sections, section_data,
+};
+use crate::icf::{self, Icf, Site, Target};
+use crate::inspect::{FunctionSize, function_sizes, function_starts};
+use crate::merge::{R_BPF_64_ABS32, defined_symbol};
+use crate::rename::Renames;
+use crate::{LinkOptions, SbpfLinkerError, timings};
+
+/// A piece of a section that is kept or dropped as a whole: a function of
+/// .text, or a constant of a data section along with the padding after it.
+struct Unit {
+    /// Position of its section in the sections carried over.
+    section: usize,
+    start: u64,
+    end: u64,
+}
+
+/// A relocation in a section carried over whose symbol is defined in one,
+/// with the address it resolves to.
+struct Reference {
+    holder: usize,
+    offset: u64,
+    r_type: u32,
+    /// Position of the symbol's section, and the symbol's address.
+    section: usize,
+    base: u64,
+    /// Whether the symbol is a section symbol, which stays at the start of
+    /// its section.
+    anonymous: bool,
+    target: u64,
+}
+
+/// The functions `--gc-sections` drops from `source`, in address order.
+pub(crate) fn unreachable_functions(
+    source: &[u8],
+    options: &LinkOptions,
+) -> Result<Vec<FunctionSize>, SbpfLinkerError> {
+    let Some(collected) = collect(source, options, true, Icf::None)? else {
+        return Ok(Vec::new());
+    };
+    let kept: HashSet<String> = function_sizes(&collected)?
+        .into_iter()
+        .map(|function| function.name)
+        .collect();
+    let mut dropped: Vec<_> = function_sizes(source)?
+        .into_iter()
+        .filter(|function| !kept.contains(&function.name))
+        .collect();
+    dropped.sort_by_key(|function| function.offset);
+    Ok(dropped)
+}
+
+/// The functions of `source` the entry point or an export can reach whose
+/// last instruction isn't an `exit` or a jump, in address order. Functions
+/// are the symbols typed as such, each up to the next one or the end of
+/// .text, so the labels inside them don't split them.
+pub(crate) fn falling_through(
+    source: &[u8],
+    options: &LinkOptions,
+) -> Result<Vec<FunctionSize>, SbpfLinkerError> {
+    let obj = File::parse(source)?;
+    let Some(text) = obj.section_by_name(".text") else {
+        return Ok(Vec::new());
+    };
+    let instructions = decode(&section_data(&text)?);
+    let mut starts: Vec<(u64, &str)> = obj
+        .symbols()
+        .filter(|symbol| {
+            symbol.section_index() == Some(text.index())
+                && symbol.kind() == SymbolKind::Text
+        })
+        .filter_map(|symbol| Some((symbol.address(), symbol.name().ok()?)))
+        .collect();
+    starts.sort_by_key(|(address, _)| *address);
+    starts.dedup_by_key(|(address, _)| *address);
+
+    let mut falling = Vec::new();
+    for (i, &(start, name)) in starts.iter().enumerate() {
+        let end = starts.get(i + 1).map_or(text.size(), |(next, _)| *next);
+        let last = instructions[..instructions
+            .partition_point(|instruction| instruction.offset < end)]
+            .last()
+            .filter(|instruction| instruction.offset >= start);
+        if last
+            .is_some_and(|last| !last.is(Opcode::Exit) && !last.is(Opcode::Ja))
+        {
+            falling.push(FunctionSize {
+                name: name.to_owned(),
+                offset: start,
+                size: end - start,
+            });
+        }
+    }
+    if falling.is_empty() {
+        return Ok(falling);
+    }
+    let unreachable: HashSet<String> = unreachable_functions(source, options)?
+        .into_iter()
+        .map(|function| function.name)
+        .collect();
+    falling.retain(|function| !unreachable.contains(&function.name));
+    Ok(falling)
+}
+
+/// With `gc`, drops the functions and constants the program can't reach
+/// from its entry point or an export, like GNU ld's `--gc-sections`, and
+/// folds identical functions into one as `icf` says. Functions are those of
+/// [`function_starts`] and constants those the linker collects into
+/// .rodata, so an object compiled into a single .text and .rodata is
+/// collected as finely as one with a section per symbol. Returns the
+/// object without what's dropped, its symbols moved to where what they're
+/// folded into is, `None` if that's nothing.
+pub(crate) fn collect(
+    source: &[u8],
+    options: &LinkOptions,
+    gc: bool,
+    icf: Icf,
+) -> Result<Option<Vec<u8>>, SbpfLinkerError> {
+    let _stage = timings::stage("gc");
+    let obj = File::parse(source)?;
+    let Some(text) = obj.section_by_name(".text") else {
+        return Ok(None);
+    };
+    let sections: Vec<Section> =
+        std::iter::once(text).chain(data_sections(&obj)).collect();
+    let position =
+        |index| sections.iter().position(|section| section.index() == index);
+
+    let functions = function_starts(&obj, &sections[0])?;
+    let mut units = Vec::new();
+    // index of the first unit of each section
+    let mut first = Vec::with_capacity(sections.len());
+    for (at, section) in sections.iter().enumerate() {
+        // rejects corrupt alignments, which the layout below relies on
+        align_section(0, section)?;
+        let mut starts: Vec<u64> = if at == 0 {
+            functions.iter().map(|(start, _)| *start).collect()
+        } else {
+            collect_rodata(&obj, section)?
+                .into_iter()
+                .map(|chunk| chunk.address)
+                .collect()
+        };
+        starts.insert(0, 0);
+        starts.dedup();
+        first.push(units.len());
+        for (i, &start) in starts.iter().enumerate() {
+            let end = starts.get(i + 1).copied().unwrap_or(section.size());
+            units.push(Unit { section: at, start, end: end.max(start) });
+        }
+    }
+    first.push(units.len());
+    // the unit holding `address` of the section at `at`. An address one
+    // past the end belongs to the last unit.
+    let unit_at = |at: usize, address: u64| {
+        let units = &units[first[at]..first[at + 1]];
+        let unit = units.partition_point(|unit| unit.start <= address);
+        (unit > 0 && address <= sections[at].size())
+            .then(|| first[at] + unit - 1)
+    };
+
+    let contents = sections
+        .iter()
+        .map(|section| {
+            Ok(if section.kind() == SectionKind::UninitializedData {
+                Vec::new()
+            } else {
+                section_data(section)?.into_owned()
+            })
+        })
+        .collect::<Result<Vec<_>, SbpfLinkerError>>()?;
+
+    let mut references = Vec::new();
+    for (holder, section) in sections.iter().enumerate() {
+        for (offset, rel) in section.relocations() {
+            let (
+                RelocationTarget::Symbol(index),
+                RelocationFlags::Elf { r_type },
+            ) = (rel.target(), rel.flags())
+            else {
+                continue;
+            };
+            let symbol = obj.symbol_by_index(index)?;
+            let Some(at) = symbol.section_index().and_then(position) else {
+                continue;
+            };
+            let Some(addend) = addend(&contents[holder], offset, r_type)
+            else {
+                continue;
+            };
+            references.push(Reference {
+                holder,
+                offset,
+                r_type,
+                section: at,
+                base: symbol.address(),
+                anonymous: symbol.kind() == SymbolKind::Section,
+                target: symbol.address().wrapping_add_signed(addend),
+            });
+        }
+    }
+    let relocated: HashSet<u64> =
+        sections[0].relocations().map(|(offset, _)| offset).collect();
+    // jumps, and calls resolved without a relocation, by their offset and
+    // target in .text
+    let instructions = decode(&contents[0]);
+    let branches: Vec<(u64, u64)> = instructions
+        .iter()
+        .filter_map(|instruction| {
+            let target = if instruction.is(Opcode::Call) {
+                (instruction.src == 1
+                    && instruction.imm != -1
+                    && !relocated.contains(&instruction.offset))
+                .then(|| {
+                    instruction
+                        .offset
+                        .wrapping_add_signed((instruction.imm + 1) * 8)
+                })?
+            } else if instruction.opcode & 0x07 == 0x05
+                && !instruction.is(Opcode::Callx)
+                && !instruction.is(Opcode::Exit)
+            {
+                instruction.jump_target()
+            } else {
+                return None;
+            };
+            Some((instruction.offset, target))
+        })
+        .collect();
+
+    // what each unit needs kept along with it
+    let mut edges: Vec<Vec<usize>> = vec![Vec::new(); units.len()];
+    for reference in &references {
+        let (Some(from), Some(target)) = (
+            unit_at(reference.holder, reference.offset),
+            unit_at(reference.section, reference.target),
+        ) else {
+            continue;
+        };
+        // a reference past the symbol it's against only resolves while
+        // everything in between stays in place
+        let base = if reference.anonymous {
+            target
+        } else {
+            unit_at(reference.section, reference.base).unwrap_or(target)
+        };
+        edges[from].extend(base.min(target)..=base.max(target));
+    }
+    for &(offset, target) in &branches {
+        if let (Some(from), Some(target)) =
+            (unit_at(0, offset), unit_at(0, target))
+        {
+            edges[from].push(target);
+        }
+    }
+    // a function without a final exit or jump runs into the next one
+    let falls_through: Vec<bool> = (first[0]..first[1])
+        .map(|unit| {
+            let Unit { start, end, .. } = units[unit];
+            let last = instructions[..instructions
+                .partition_point(|instruction| instruction.offset < end)]
+                .last()
+                .filter(|instruction| instruction.offset >= start);
+            last.is_some_and(|last| {
+                !last.is(Opcode::Exit) && !last.is(Opcode::Ja)
+            }) && unit + 1 < first[1]
+        })
+        .collect();
+    for (unit, _) in falls_through.iter().enumerate().filter(|(_, f)| **f) {
+        edges[unit].push(unit + 1);
+    }
+
+    let renames = Renames::new(
+        &options.renames,
+        obj.symbols().filter_map(|symbol| symbol.name().ok()),
+    )?;
+    let entry = options.entry.as_deref().unwrap_or(DEFAULT_ENTRY);
+    let roots = options.roots();
+    let mut pending: Vec<usize> = obj
+        .symbols()
+        .filter(|symbol| {
+            symbol.kind() != SymbolKind::Section
+                && symbol
+                    .name()
+                    .is_ok_and(|name| roots.contains(&renames.get(name)))
+        })
+        .filter_map(|symbol| {
+            symbol
+                .section_index()
+                .and_then(position)
+                .and_then(|at| unit_at(at, symbol.address()))
+        })
+        .collect();
+    // without an entry function, execution starts at the top of .text
+    if !obj.symbols().any(|symbol| {
+        symbol.section_index() == Some(sections[0].index())
+            && symbol.name().is_ok_and(|name| renames.get(name) == entry)
+    }) {
+        pending.push(first[0]);
+    }
+    let entered = pending.clone();
+    let mut reachable = vec![!gc; units.len()];
+    while let Some(unit) = pending.pop() {
+        if gc && !std::mem::replace(&mut reachable[unit], true) {
+            pending.extend(&edges[unit]);
+        }
+    }
+
+    let mut folded = vec![None; units.len()];
+    if icf != Icf::None {
+        let sites = code_sites(&obj, &sections, &contents[0], &branches)?;
+        let mut pinned: Vec<bool> = (first[0]..first[1])
+            .map(|unit| {
+                !reachable[unit]
+                    || units[unit].start == units[unit].end
+                    || falls_through[unit]
+                    || unit > 0 && falls_through[unit - 1]
+            })
+            .collect();
+        if icf == Icf::Safe {
+            // what's entered from outside, or whose address is loaded or
+            // stored
+            let taken = references
+                .iter()
+                .filter(|reference| {
+                    reference.section == 0
+                        && reference.r_type != object::elf::R_BPF_64_32
+                })
+                .filter_map(|reference| unit_at(0, reference.target));
+            for unit in entered.iter().copied().chain(taken) {
+                if unit < first[1] {
+                    pinned[unit] = true;
+                }
+            }
+        }
+        let ranges: Vec<_> =
+            units[first[0]..first[1]].iter().map(|u| u.start..u.end).collect();
+        for (unit, into) in icf::fold(&contents[0], &ranges, &sites, &pinned)
+            .into_iter()
+            .enumerate()
+        {
+            if let Some(into) = into {
+                tracing::debug!(
+                    "folding `{}` into `{}`, which is identical to it",
+                    functions[unit].1,
+                    functions[into].1
+                );
+            }
+            folded[unit] = into;
+        }
+    }
+    // empty units, e.g. the padding before the first constant of a
+    // section, cost nothing
+    if folded.iter().all(Option::is_none)
+        && units
+            .iter()
+            .zip(&reachable)
+            .all(|(unit, reachable)| *reachable || unit.start == unit.end)
+    {
+        return Ok(None);
+    }
+
+    let mut dropped = vec![0; sections.len()];
+    for (unit, _) in units.iter().zip(&reachable).filter(|(_, kept)| !**kept) {
+        let name = sections[unit.section].name().unwrap_or_default();
+        tracing::trace!(
+            "dropping {name} {:#x}..{:#x}, which nothing reachable uses",
+            unit.start,
+            unit.end
+        );
+        dropped[unit.section] += unit.end - unit.start;
+    }
+    for (section, dropped) in sections.iter().zip(dropped) {
+        if dropped > 0 {
+            tracing::debug!(
+                "dropped {dropped} of the {} bytes of {}",
+                section.size(),
+                section.name().unwrap_or_default()
+            );
+        }
+    }
+
+    // lay the kept units out back to back, each at its old offset modulo
+    // its section's alignment
+    let mut placed = vec![None; units.len()];
+    let mut sizes = vec![0; sections.len()];
+    for (index, unit) in units.iter().enumerate() {
+        if !reachable[index] || folded[index].is_some() {
+            continue;
+        }
+        let align = sections[unit.section].align().max(1);
+        let cursor = sizes[unit.section];
+        let start = cursor + (unit.start.wrapping_sub(cursor) & (align - 1));
+        placed[index] = Some(start);
+        sizes[unit.section] = start + (unit.end - unit.start);
+    }
+    // where `address` of the section at `at` ends up, in what it's folded
+    // into if it is
+    let relocate = |at: usize, address: u64| {
+        let unit = unit_at(at, address)?;
+        let into = folded[unit].unwrap_or(unit);
+        Some(placed[into]? + (address - units[unit].start))
+    };
+    // the same for what's there itself, which isn't if it's folded
+    let keep = |at: usize, address: u64| {
+        unit_at(at, address)
+            .filter(|&unit| folded[unit].is_none())
+            .and_then(|_| relocate(at, address))
+    };
+
+    let mut data: Vec<Vec<u8>> =
+        sizes.iter().map(|&size| vec![0; size as usize]).collect();
+    for (index, unit) in units.iter().enumerate() {
+        let (Some(start), Some(bytes)) = (
+            placed[index],
+            contents[unit.section].get(unit.start as usize..unit.end as usize),
+        ) else {
+            continue;
+        };
+        data[unit.section][start as usize..][..bytes.len()]
+            .copy_from_slice(bytes);
+    }
+    for &(offset, target) in &branches {
+        let (Some(from), Some(to)) = (keep(0, offset), relocate(0, target))
+        else {
+            continue;
+        };
+        let distance = (to as i64 - from as i64) / 8 - 1;
+        let at = from as usize;
+        if data[0][at] == Opcode::Call.to_bytecode() {
+            data[0][at + 4..at + 8]
+                .copy_from_slice(&(distance as i32).to_le_bytes());
+        } else {
+            data[0][at + 2..at + 4]
+                .copy_from_slice(&(distance as i16).to_le_bytes());
+        }
+    }
+    for reference in &references {
+        let (Some(offset), Some(target)) = (
+            keep(reference.holder, reference.offset),
+            relocate(reference.section, reference.target),
+        ) else {
+            continue;
+        };
+        let base = if reference.anonymous {
+            0
+        } else {
+            relocate(reference.section, reference.base).unwrap_or(0)
+        };
+        set_addend(
+            &mut data[reference.holder],
+            offset,
+            reference.r_type,
+            target.wrapping_sub(base) as i64,
+        );
+    }
+
+    let mut output = OutputObject::new(
+        BinaryFormat::Elf,
+        Architecture::Bpf,
+        Endianness::Little,
+    );
+    let mut ids = Vec::with_capacity(sections.len());
+    for (at, section) in sections.iter().enumerate() {
+        // a data section left empty would still be padded to its alignment
+        if at > 0 && sizes[at] == 0 {
+            ids.push(None);
+            continue;
+        }
+        let id = output.add_section(
+            Vec::new(),
+            section.name()?.as_bytes().to_vec(),
+            section.kind(),
+        );
+        // the contents are written out uncompressed
+        if let SectionFlags::Elf { sh_flags } = section.flags() {
+            output.section_mut(id).flags = SectionFlags::Elf {
+                sh_flags: sh_flags & !u64::from(object::elf::SHF_COMPRESSED),
+            };
+        }
+        let align = section.align().max(1);
+        if section.kind() == SectionKind::UninitializedData {
+            output.section_mut(id).append_bss(sizes[at], align);
+        } else {
+            output.section_mut(id).append_data(&data[at], align);
+        }
+        ids.push(Some(id));
+    }
+
+    let mut symbols = HashMap::new();
+    let mut undefined = HashMap::new();
+    for symbol in obj.symbols() {
+        let name = symbol.name().unwrap_or_default();
+        let placement =
+            symbol.section_index().and_then(position).map(|at| (at, ids[at]));
+        let id = match (symbol.kind(), placement) {
+            (SymbolKind::File, _) => continue,
+            // in a section left out
+            (_, Some((_, None))) => continue,
+            (SymbolKind::Section, Some((_, Some(id)))) => {
+                output.section_symbol(id)
+            }
+            (SymbolKind::Section, None) => continue,
+            (_, Some((at, Some(id)))) => {
+                // dropped along with its unit
+                let Some(value) = relocate(at, symbol.address()) else {
+                    continue;
+                };
+                output.add_symbol(defined_symbol(
+                    name.to_owned(),
+                    &symbol,
+                    Some((id, value)),
+                ))
+            }
+            _ if symbol.is_undefined() || symbol.section_index().is_some() => {
+                *undefined.entry(name.to_owned()).or_insert_with(|| {
+                    output.add_symbol(OutputSymbol {
+                        name: name.as_bytes().to_vec(),
+                        value: 0,
+                        size: 0,
+                        kind: SymbolKind::Unknown,
+                        scope: SymbolScope::Unknown,
+                        weak: false,
+                        section: SymbolSection::Undefined,
+                        flags: SymbolFlags::None,
+                    })
+                })
+            }
+            _ => output.add_symbol(defined_symbol(
+                name.to_owned(),
+                &symbol,
+                None,
+            )),
+        };
+        symbols.insert(symbol.index(), id);
+    }
+
+    for (holder, section) in sections.iter().enumerate() {
+        let Some(id) = ids[holder] else { continue };
+        for (offset, rel) in section.relocations() {
+            let RelocationTarget::Symbol(index) = rel.target() else {
+                continue;
+            };
+            // relocations in dropped units go with them
+            let (Some(&symbol), Some(offset)) =
+                (symbols.get(&index), keep(holder, offset))
+            else {
+                continue;
+            };
+            output.add_relocation(
+                id,
+                OutputRelocation {
+                    offset,
+                    symbol,
+                    addend: 0,
+                    flags: rel.flags(),
+                },
+            )?;
+        }
+    }
+
+    Ok(Some(output.write()?))
+}
+
+/// The fields of the instructions in `code`, the contents of .text, whose
+/// bytes say where they point: relocated ones, along with what their
+/// symbols resolve to, and the `branches` of .text.
+fn code_sites(
+    obj: &File<'_>,
+    sections: &[Section<'_, '_>],
+    code: &[u8],
+    branches: &[(u64, u64)],
+) -> Result<Vec<Site>, SbpfLinkerError> {
+    let mut sites = Vec::new();
+    for (offset, rel) in sections[0].relocations() {
+        let (RelocationTarget::Symbol(index), RelocationFlags::Elf { r_type }) =
+            (rel.target(), rel.flags())
+        else {
+            continue;
+        };
+        let symbol = obj.symbol_by_index(index)?;
+        let addend = addend(code, offset, r_type).unwrap_or_default();
+        let address = symbol.address().wrapping_add_signed(addend);
+        let target = match sections.iter().position(|section| {
+            Some(section.index()) == symbol.section_index()
+        }) {
+            Some(0) => Target::Code(address),
+            Some(at) => Target::Other(format!("{r_type} {at} {address:#x}")),
+            None => Target::Other(format!(
+                "{r_type} {}{addend:+}",
+                symbol.name().unwrap_or_default()
+            )),
+        };
+        // the immediates, across both halves of an `lddw`
+        let end = if r_type == object::elf::R_BPF_64_64 { 16 } else { 8 };
+        sites.push(Site { field: offset + 4..offset + end, target });
+    }
+    for &(offset, target) in branches {
+        let field = if code.get(offset as usize)
+            == Some(&Opcode::Call.to_bytecode())
+        {
+            offset + 4..offset + 8
+        } else {
+            offset + 2..offset + 4
+        };
+        sites.push(Site { field, target: Target::Code(target) });
+    }
+    Ok(sites)
+}
+
+/// The addend of the REL relocation of type `r_type` at `offset` in
+/// `data`, in bytes from its symbol. `None` for relocations that don't
+/// address code or data.
+fn addend(data: &[u8], offset: u64, r_type: u32) -> Option<i64> {
+    let at = offset as usize;
+    let word = |from: usize| -> Option<u32> {
+        Some(u32::from_le_bytes(data.get(from..from + 4)?.try_into().ok()?))
+    };
+    Some(match r_type {
+        // lddw: the address, split across both immediates
+        object::elf::R_BPF_64_64 => {
+            (u64::from(word(at + 4)?) | u64::from(word(at + 12)?) << 32) as i64
+        }
+        // call: the target in instructions, relative to the next one
+        object::elf::R_BPF_64_32 => (i64::from(word(at + 4)? as i32) + 1) * 8,
+        R_BPF_64_ABS64 | R_BPF_64_RELATIVE => {
+            u64::from_le_bytes(data.get(at..at + 8)?.try_into().ok()?) as i64
+        }
+        R_BPF_64_ABS32 => i64::from(word(at)?),
+        _ => return None,
+    })
+}
+
+/// Stores `addend` for the relocation of type `r_type` at `offset`, the
+/// inverse of [`addend`].
+fn set_addend(data: &mut [u8], offset: u64, r_type: u32, addend: i64) {
+    let at = offset as usize;
+    let mut write = |from: usize, bytes: &[u8]| {
+        if let Some(slot) = data.get_mut(from..from + bytes.len()) {
+            slot.copy_from_slice(bytes);
+        }
+    };
+    match r_type {
+        object::elf::R_BPF_64_64 => {
+            write(at + 4, &(addend as u32).to_le_bytes());
+            write(at + 12, &((addend as u64 >> 32) as u32).to_le_bytes());
+        }
+        object::elf::R_BPF_64_32 => {
+            write(at + 4, &((addend / 8 - 1) as i32).to_le_bytes());
+        }
+        R_BPF_64_ABS64 | R_BPF_64_RELATIVE => {
+            write(at, &addend.to_le_bytes());
+        }
+        R_BPF_64_ABS32 => write(at, &(addend as u32).to_le_bytes()),
+        _ => {}
+    }
+}
@@ -0,0 +1,207 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+};
+
+/// Which functions with identical code are folded into one, like lld's
+/// `--icf`. Monomorphized generics often compile to the same instructions
+/// many times over.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Icf {
+    /// Keep every function, the default.
+    #[default]
+    None,
+    /// Fold only functions whose address nothing sees, which calls can't
+    /// tell apart: not the entry point, an export or a `--defsym` target,
+    /// and none loaded as a pointer or stored in data.
+    Safe,
+    /// Fold any identical functions, even if code compares their
+    /// addresses.
+    All,
+}
+
+/// What a field in the code refers to, for telling functions apart.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Target {
+    /// An address in the code itself, e.g. of a called function.
+    Code(u64),
+    /// Anything else, e.g. a constant or a syscall, described the same way
+    /// only for the same one.
+    Other(String),
+}
+
+/// A field of an instruction whose bytes hold where it points rather than
+/// what it does, e.g. the immediate of a relocated `lddw` or the offset of
+/// a jump.
+pub(crate) struct Site {
+    /// The bytes of the field, in the code.
+    pub field: Range<u64>,
+    pub target: Target,
+}
+
+/// Where in a function a site is, and what it points to outside the code.
+type Outside<'a> = (u64, Option<&'a str>);
+
+/// Which of `functions`, ranges of `code` in address order, are folded
+/// into which: the first of every set of identical functions is kept, and
+/// the rest fold into it. Functions are identical if their instructions
+/// are, apart from the `sites`, and the sites point to the same things,
+/// or to the same places in identical functions. `pinned` ones are never
+/// folded, nor kept for others.
+pub(crate) fn fold(
+    code: &[u8],
+    functions: &[Range<u64>],
+    sites: &[Site],
+    pinned: &[bool],
+) -> Vec<Option<usize>> {
+    let function_at = |address: u64| {
+        let after = functions.partition_point(|f| f.start <= address);
+        (after > 0 && address < functions[after - 1].end).then(|| after - 1)
+    };
+    let mut sites_of: Vec<Vec<&Site>> = vec![Vec::new(); functions.len()];
+    for site in sites {
+        if let Some(function) = function_at(site.field.start) {
+            sites_of[function].push(site);
+        }
+    }
+
+    // first apart by their instructions and what they point outside the
+    // code, then by which classes their code targets are in, until that
+    // splits no class further
+    let mut classes: Vec<usize> = Vec::with_capacity(functions.len());
+    let mut keys: HashMap<(Vec<u8>, Vec<Outside<'_>>), usize> = HashMap::new();
+    for (function, range) in functions.iter().enumerate() {
+        // a class of its own, past those of the keys
+        if pinned[function] {
+            classes.push(functions.len() + function);
+            continue;
+        }
+        let mut bytes = code
+            .get(range.start as usize..range.end as usize)
+            .unwrap_or_default()
+            .to_vec();
+        let mut targets = Vec::new();
+        for site in &sites_of[function] {
+            let field = (site.field.start - range.start) as usize
+                ..(site.field.end.min(range.end) - range.start) as usize;
+            bytes[field].fill(0);
+            targets.push((
+                site.field.start - range.start,
+                match &site.target {
+                    Target::Code(_) => None,
+                    Target::Other(other) => Some(other.as_str()),
+                },
+            ));
+        }
+        let next = keys.len();
+        classes.push(*keys.entry((bytes, targets)).or_insert(next));
+    }
+    loop {
+        let mut keys: HashMap<(usize, Vec<(usize, u64)>), usize> =
+            HashMap::new();
+        let refined: Vec<usize> = (0..functions.len())
+            .map(|function| {
+                let targets = sites_of[function]
+                    .iter()
+                    .filter_map(|site| match site.target {
+                        Target::Code(target) => {
+                            Some(function_at(target).map_or(
+                                (usize::MAX, target),
+                                |at| {
+                                    (classes[at], target - functions[at].start)
+                                },
+                            ))
+                        }
+                        Target::Other(_) => None,
+                    })
+                    .collect();
+                let next = keys.len();
+                *keys.entry((classes[function], targets)).or_insert(next)
+            })
+            .collect();
+        let stable =
+            keys.len() == classes.iter().collect::<HashSet<_>>().len();
+        classes = refined;
+        if stable {
+            break;
+        }
+    }
+
+    let mut kept: HashMap<usize, usize> = HashMap::new();
+    (0..functions.len())
+        .map(|function| {
+            if pinned[function] {
+                return None;
+            }
+            let first = *kept.entry(classes[function]).or_insert(function);
+            (first != function).then_some(first)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use object::SectionKind;
+    use object::elf::{R_BPF_64_32, R_BPF_64_64};
+    use sbpf_common::opcode::Opcode;
+
+    use super::*;
+    use crate::program::{Operand, ParsedRelocationKind};
+    use crate::testing::{EXIT, TestObject, call, lddw, mov64};
+    use crate::{LinkOptions, parse_program};
+
+    /// Where a program calling twins `a` and `b`, and loading `b`'s
+    /// address, calls and loads from once linked with `icf`.
+    fn twins(icf: Icf) -> (Vec<u64>, u64) {
+        let mut object = TestObject::new();
+        let twin = [mov64(0, 0), EXIT].concat();
+        let text =
+            [&call()[..], &call(), &lddw(1, 0), &EXIT, &twin, &twin].concat();
+        let text_id = object.section(".text", SectionKind::Text, &text);
+        object.symbol("entrypoint", text_id, 0, 40);
+        let a = object.symbol("a", text_id, 40, 16);
+        let b = object.symbol("b", text_id, 56, 16);
+        object.relocation(text_id, 0, a, R_BPF_64_32);
+        object.relocation(text_id, 8, b, R_BPF_64_32);
+        object.relocation(text_id, 16, b, R_BPF_64_64);
+
+        let options = LinkOptions { icf, ..LinkOptions::default() };
+        let program = parse_program(&object.write(), &options).unwrap();
+        let calls = program
+            .instructions
+            .iter()
+            .filter(|instruction| instruction.opcode == Opcode::Call)
+            .map(|instruction| match instruction.operands[..] {
+                [Operand::Immediate(imm)] => {
+                    instruction.offset.wrapping_add_signed((imm + 1) * 8)
+                }
+                _ => panic!("a call within .text"),
+            })
+            .collect();
+        let loaded = program
+            .relocations
+            .iter()
+            .find(|relocation| {
+                relocation.kind == ParsedRelocationKind::Address
+            })
+            .and_then(|relocation| {
+                program
+                    .functions
+                    .iter()
+                    .find(|function| function.name == relocation.symbol)
+            })
+            .map(|function| function.offset)
+            .unwrap();
+        (calls, loaded)
+    }
+
+    #[test]
+    fn safe_keeps_function_whose_address_is_taken() {
+        assert_eq!(twins(Icf::Safe), (vec![40, 56], 56));
+    }
+
+    #[test]
+    fn all_folds_function_whose_address_is_taken() {
+        assert_eq!(twins(Icf::All), (vec![40, 40], 40));
+    }
+}
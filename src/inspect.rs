@@ -0,0 +1,789 @@
+use std::fmt::{self, Write as _};
+
+use std::collections::BTreeMap;
+
+use object::{
+    File, LittleEndian, Object as _, ObjectSection as _, ObjectSymbol as _,
+    ObjectSymbolTable as _, Relocation, RelocationFlags, RelocationTarget,
+    Section, Symbol, SymbolKind, elf,
+    read::elf::{Dyn as _, ElfFile64, ProgramHeader as _, SectionHeader as _},
+};
+use sbpf_assembler::astnode::ASTNode;
+use sbpf_common::opcode::Opcode;
+
+use crate::{
+    SbpfLinkerError,
+    byteparser::{
+        RodataSymbol, align_section, collect_rodata, data_sections,
+        implicit_addend, parse_instructions, relocation_type_name,
+        resolve_rodata, section_data,
+    },
+    disasm::render_instruction,
+    elf::EM_SBPF,
+    version::SbpfVersion,
+};
+
+/// Layout of one section of an object file or linked program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SectionInfo {
+    pub name: String,
+    pub kind: SectionKind,
+    pub address: u64,
+    pub size: u64,
+    pub align: u64,
+    pub relocation_count: usize,
+}
+
+/// A rodata symbol as it is embedded into the linked program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RodataInfo {
+    pub name: String,
+    /// Offset in the program's .rodata.
+    pub offset: u64,
+    pub bytes: Vec<u8>,
+}
+
+impl RodataInfo {
+    /// The bytes as text, if they are printable ASCII (allowing common
+    /// whitespace and trailing NULs).
+    pub fn as_str(&self) -> Option<&str> {
+        let end =
+            self.bytes.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+        let text = &self.bytes[..end];
+        (!text.is_empty()
+            && text.iter().all(|&b| {
+                b.is_ascii_graphic()
+                    || matches!(b, b' ' | b'\t' | b'\n' | b'\r')
+            }))
+        .then(|| std::str::from_utf8(text).ok())
+        .flatten()
+    }
+}
+
+/// Everything in an object that refers to one symbol through a relocation.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct SymbolReferences {
+    pub symbol: String,
+    /// Section defining the symbol, `None` if it's undefined (e.g. a
+    /// syscall).
+    pub section: Option<String>,
+    pub references: Vec<Reference>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct Reference {
+    /// Section holding the relocation.
+    pub section: String,
+    pub offset: u64,
+    /// The referencing instruction, `None` for references from data.
+    pub instruction: Option<Opcode>,
+}
+
+/// A symbol of an object file or linked program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SymbolInfo {
+    pub name: String,
+    /// Section defining the symbol, `None` if it's undefined or absolute.
+    pub section: Option<String>,
+    pub address: u64,
+    pub size: u64,
+    /// `func`, `object`, `section`, `file` or `notype`.
+    pub kind: &'static str,
+    pub global: bool,
+}
+
+/// A relocation of an object file, or one the loader applies to a linked
+/// program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RelocationInfo {
+    /// Section the relocation patches.
+    pub section: String,
+    /// Offset of what it patches in `section`.
+    pub offset: u64,
+    /// Its type, e.g. `R_BPF_64_64`.
+    pub kind: String,
+    /// The symbol it refers to, empty if none.
+    pub symbol: String,
+}
+
+/// One instruction of .text, as [`disassemble`] decodes it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DisassembledInstruction {
+    /// Address in the program, which is the offset in .text for an object.
+    pub address: u64,
+    pub bytes: Vec<u8>,
+    /// As sbpf assembly, in the SBPF v0 encoding the linker reads code in.
+    pub asm: String,
+    /// The symbols at its address.
+    pub labels: Vec<String>,
+    /// The relocations patching it.
+    pub relocations: Vec<RelocationInfo>,
+}
+
+/// The extent of one function in .text.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FunctionSize {
+    pub name: String,
+    pub offset: u64,
+    pub size: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum SectionKind {
+    Text,
+    ReadOnlyData,
+    Data,
+    UninitializedData,
+    Debug,
+    /// Symbol tables, string tables, relocations and the like.
+    Metadata,
+    Other,
+}
+
+impl From<object::SectionKind> for SectionKind {
+    fn from(kind: object::SectionKind) -> Self {
+        use object::SectionKind as K;
+
+        match kind {
+            K::Text => Self::Text,
+            K::ReadOnlyData | K::ReadOnlyDataWithRel | K::ReadOnlyString => {
+                Self::ReadOnlyData
+            }
+            K::Data | K::Tls => Self::Data,
+            K::UninitializedData | K::UninitializedTls | K::Common => {
+                Self::UninitializedData
+            }
+            K::Debug | K::DebugString => Self::Debug,
+            K::Metadata | K::Linker | K::Note | K::OtherString => {
+                Self::Metadata
+            }
+            _ => Self::Other,
+        }
+    }
+}
+
+impl fmt::Display for SectionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Text => "text",
+            Self::ReadOnlyData => "rodata",
+            Self::Data => "data",
+            Self::UninitializedData => "bss",
+            Self::Debug => "debug",
+            Self::Metadata => "metadata",
+            Self::Other => "other",
+        })
+    }
+}
+
+/// Lists every section of `source` in section header order.
+pub fn inspect_sections(
+    source: &[u8],
+) -> Result<Vec<SectionInfo>, SbpfLinkerError> {
+    let obj = File::parse(source)?;
+
+    obj.sections()
+        .map(|section| {
+            Ok(SectionInfo {
+                name: section.name()?.to_owned(),
+                kind: section.kind().into(),
+                address: section.address(),
+                size: section.size(),
+                align: section.align(),
+                relocation_count: section.relocations().count(),
+            })
+        })
+        .collect()
+}
+
+/// Lists the symbols of `source` in symbol table order: its `.symtab`, or
+/// else its `.dynsym`, e.g. for a stripped program.
+pub fn inspect_symbols(
+    source: &[u8],
+) -> Result<Vec<SymbolInfo>, SbpfLinkerError> {
+    let obj = File::parse(source)?;
+    let symbols: Vec<Symbol> = if obj.symbol_table().is_some() {
+        obj.symbols().collect()
+    } else {
+        obj.dynamic_symbols().collect()
+    };
+    symbols
+        .iter()
+        .filter(|symbol| symbol.index().0 != 0)
+        .map(|symbol| {
+            let section = match symbol.section_index() {
+                Some(index) => Some(obj.section_by_index(index)?.name()?),
+                None => None,
+            };
+            Ok(SymbolInfo {
+                name: symbol.name()?.to_owned(),
+                section: section.map(str::to_owned),
+                address: symbol.address(),
+                size: symbol.size(),
+                kind: match symbol.kind() {
+                    SymbolKind::Text => "func",
+                    SymbolKind::Data => "object",
+                    SymbolKind::Section => "section",
+                    SymbolKind::File => "file",
+                    _ => "notype",
+                },
+                global: symbol.is_global(),
+            })
+        })
+        .collect()
+}
+
+/// Lists the relocations of `source` by the section they patch, in section
+/// header order, then those the loader applies to a linked program, by the
+/// address they patch.
+pub fn inspect_relocations(
+    source: &[u8],
+) -> Result<Vec<RelocationInfo>, SbpfLinkerError> {
+    let obj = File::parse(source)?;
+    let info = |symbol: Option<Symbol>,
+                section: String,
+                offset: u64,
+                rel: &Relocation|
+     -> Result<RelocationInfo, SbpfLinkerError> {
+        let symbol = match symbol {
+            // named by the section it stands for
+            Some(symbol) if symbol.kind() == SymbolKind::Section => symbol
+                .section_index()
+                .and_then(|index| obj.section_by_index(index).ok())
+                .and_then(|section| section.name().ok())
+                .unwrap_or_default()
+                .to_owned(),
+            Some(symbol) => symbol.name()?.to_owned(),
+            None => String::new(),
+        };
+        let kind = match rel.flags() {
+            RelocationFlags::Elf { r_type } => relocation_type_name(r_type),
+            _ => String::new(),
+        };
+        Ok(RelocationInfo { section, offset, kind, symbol })
+    };
+
+    let mut relocations = Vec::new();
+    for section in obj.sections() {
+        let name = section.name()?;
+        for (offset, rel) in section.relocations() {
+            let symbol = match rel.target() {
+                RelocationTarget::Symbol(index) => {
+                    Some(obj.symbol_by_index(index)?)
+                }
+                _ => None,
+            };
+            relocations.push(info(symbol, name.to_owned(), offset, &rel)?);
+        }
+    }
+    let mut dynamic: Vec<_> =
+        obj.dynamic_relocations().into_iter().flatten().collect();
+    dynamic.sort_by_key(|(address, _)| *address);
+    for (address, rel) in dynamic {
+        let Some(section) = obj.sections().find(|section| {
+            (section.address()..section.address() + section.size())
+                .contains(&address)
+        }) else {
+            continue;
+        };
+        let symbol = match rel.target() {
+            RelocationTarget::Symbol(index) if index.0 != 0 => obj
+                .dynamic_symbol_table()
+                .and_then(|table| table.symbol_by_index(index).ok()),
+            _ => None,
+        };
+        relocations.push(info(
+            symbol,
+            section.name()?.to_owned(),
+            address - section.address(),
+            &rel,
+        )?);
+    }
+    Ok(relocations)
+}
+
+/// Decodes the .text of `source`, an object or a linked program of any
+/// SBPF version, with the symbols and relocations of each instruction.
+/// Branches and calls within .text name their target's symbol, if it has
+/// one; relocated instructions are rendered as they're stored, since the
+/// relocation says what they refer to.
+pub fn disassemble(
+    source: &[u8],
+) -> Result<Vec<DisassembledInstruction>, SbpfLinkerError> {
+    let obj = File::parse(source)?;
+    let Some(text) = obj.section_by_name(".text") else {
+        return Ok(Vec::new());
+    };
+    let data = section_data(&text)?;
+    let base = text.address();
+
+    let mut labels: BTreeMap<u64, Vec<String>> = BTreeMap::new();
+    for symbol in inspect_symbols(source)? {
+        if symbol.section.as_deref() == Some(".text")
+            && !matches!(symbol.kind, "section" | "file")
+            && !symbol.name.is_empty()
+        {
+            labels.entry(symbol.address).or_default().push(symbol.name);
+        }
+    }
+    let mut relocations: BTreeMap<u64, Vec<RelocationInfo>> = BTreeMap::new();
+    for relocation in inspect_relocations(source)? {
+        if relocation.section == ".text" {
+            relocations
+                .entry(base + relocation.offset)
+                .or_default()
+                .push(relocation);
+        }
+    }
+
+    let chunk = parse_instructions(
+        &data,
+        0,
+        data.len(),
+        SbpfVersion::of_object(&obj),
+    )?;
+    let none = BTreeMap::new();
+    Ok(chunk
+        .nodes
+        .iter()
+        .filter_map(|node| match node {
+            ASTNode::Instruction { instruction, offset } => {
+                Some((instruction, *offset))
+            }
+            _ => None,
+        })
+        .map(|(instruction, offset)| {
+            let address = base + offset;
+            let relocations = relocations.remove(&address).unwrap_or_default();
+            let targets = if relocations.is_empty() { &labels } else { &none };
+            let end = (offset + instruction.get_size()) as usize;
+            DisassembledInstruction {
+                address,
+                bytes: data[offset as usize..end.min(data.len())].to_vec(),
+                asm: render_instruction(instruction, address, targets),
+                labels: labels.get(&address).cloned().unwrap_or_default(),
+                relocations,
+            }
+        })
+        .collect())
+}
+
+/// Lists the rodata symbols `source` embeds, in the order and at the offsets
+/// `link_program` lays them out.
+pub fn extract_rodata(
+    source: &[u8],
+) -> Result<Vec<RodataInfo>, SbpfLinkerError> {
+    let obj = File::parse(source)?;
+
+    let mut rodata = Vec::new();
+    let mut offset = 0;
+    for section in data_sections(&obj) {
+        offset = align_section(offset, &section)?;
+        for chunk in collect_rodata(&obj, &section)? {
+            offset = offset.next_multiple_of(chunk.align);
+            let size = chunk.bytes.len() as u64;
+            rodata.push(RodataInfo {
+                name: chunk.name,
+                offset,
+                bytes: chunk.bytes.into_owned(),
+            });
+            offset += size;
+        }
+    }
+    Ok(rodata)
+}
+
+/// Sizes the functions in the .text section of `source`, largest first. Each
+/// function spans from its symbol to the next function's symbol, or to the
+/// end of .text for the last one, so symbols without an explicit size are
+/// handled too. Code before the first symbol is reported under `.text`.
+pub fn function_sizes(
+    source: &[u8],
+) -> Result<Vec<FunctionSize>, SbpfLinkerError> {
+    let obj = File::parse(source)?;
+    let Some(text) = obj.section_by_name(".text") else {
+        return Ok(Vec::new());
+    };
+
+    let starts = function_starts(&obj, &text)?;
+    let mut sizes: Vec<_> = starts
+        .iter()
+        .enumerate()
+        .map(|(i, (offset, name))| {
+            let end = starts.get(i + 1).map_or(text.size(), |(next, _)| *next);
+            FunctionSize {
+                name: (*name).to_owned(),
+                offset: *offset,
+                size: end - offset,
+            }
+        })
+        .filter(|function| function.size > 0)
+        .collect();
+    sizes.sort_by(|a, b| b.size.cmp(&a.size).then(a.offset.cmp(&b.offset)));
+
+    Ok(sizes)
+}
+
+/// Where the functions in `text` start, in address order, by their symbols.
+/// Code before the first symbol is named `.text`.
+pub(crate) fn function_starts<'data>(
+    obj: &File<'data>,
+    text: &Section<'data, '_>,
+) -> Result<Vec<(u64, &'data str)>, SbpfLinkerError> {
+    let mut starts: Vec<(u64, &str)> = obj
+        .symbols()
+        .filter(|symbol| {
+            symbol.section_index() == Some(text.index())
+                && !matches!(
+                    symbol.kind(),
+                    SymbolKind::Section | SymbolKind::File
+                )
+        })
+        .map(|symbol| Ok((symbol.address(), symbol.name()?)))
+        .collect::<Result<_, SbpfLinkerError>>()?;
+    starts.retain(|(_, name)| !name.is_empty());
+    // aliases share a start; keep the first name
+    starts.sort_by_key(|(address, _)| *address);
+    starts.dedup_by_key(|(address, _)| *address);
+    if starts.first().is_none_or(|(address, _)| *address > 0) {
+        starts.insert(0, (0, ".text"));
+    }
+    Ok(starts)
+}
+
+/// Builds a reverse index of the relocations in `source`: for each symbol,
+/// the instructions and data that refer to it, sorted by
+/// symbol name. References through a section symbol are attributed to the
+/// rodata symbol they land in.
+pub fn cross_references(
+    source: &[u8],
+) -> Result<Vec<SymbolReferences>, SbpfLinkerError> {
+    let obj = File::parse(source)?;
+    let data_sections = data_sections(&obj);
+    let mut rodata_table = Vec::new();
+    for section in &data_sections {
+        for chunk in collect_rodata(&obj, section)? {
+            rodata_table.push(RodataSymbol {
+                name: chunk.name,
+                symbol: chunk.symbol,
+                section: section.index(),
+                address: chunk.address,
+                size: chunk.bytes.len() as u64,
+                offset: 0,
+            });
+        }
+    }
+
+    let mut xrefs = BTreeMap::new();
+    for symbol in obj.symbols() {
+        if !matches!(symbol.kind(), SymbolKind::Section | SymbolKind::File)
+            && symbol.name().is_ok_and(|name| !name.is_empty())
+            && let Some(index) = symbol.section_index()
+        {
+            xrefs.entry(symbol.name()?.to_owned()).or_insert_with(|| {
+                SymbolReferences {
+                    symbol: symbol.name().unwrap_or_default().to_owned(),
+                    section: obj
+                        .section_by_index(index)
+                        .and_then(|section| section.name().map(str::to_owned))
+                        .ok(),
+                    references: Vec::new(),
+                }
+            });
+        }
+    }
+
+    for section in obj.sections() {
+        let is_text = section.kind() == object::SectionKind::Text;
+        let data = section_data(&section)?;
+        for (offset, rel) in section.relocations() {
+            let RelocationTarget::Symbol(index) = rel.target() else {
+                continue;
+            };
+            let symbol = obj.symbol_by_index(index)?;
+            let at = offset as usize;
+            let instruction = if is_text {
+                data.get(at).copied().and_then(Opcode::from_u8)
+            } else {
+                None
+            };
+
+            let (name, defined_in) = match symbol.section_index() {
+                Some(symbol_section) => {
+                    let section_name =
+                        obj.section_by_index(symbol_section)?.name()?;
+                    let in_rodata = data_sections
+                        .iter()
+                        .any(|section| section.index() == symbol_section);
+                    let name = if symbol.kind() == SymbolKind::Section {
+                        let target = symbol.address().wrapping_add(
+                            implicit_addend(&data, at, instruction),
+                        );
+                        match resolve_rodata(
+                            &rodata_table,
+                            symbol_section,
+                            symbol.address(),
+                            target,
+                        ) {
+                            Some((ro_symbol, _)) if in_rodata => {
+                                ro_symbol.name.clone()
+                            }
+                            _ => format!("{section_name}+{target:#x}"),
+                        }
+                    } else {
+                        symbol.name()?.to_owned()
+                    };
+                    (name, Some(section_name.to_owned()))
+                }
+                None => (symbol.name()?.to_owned(), None),
+            };
+
+            xrefs
+                .entry(name.clone())
+                .or_insert_with(|| SymbolReferences {
+                    symbol: name,
+                    section: defined_in,
+                    references: Vec::new(),
+                })
+                .references
+                .push(Reference {
+                    section: section.name()?.to_owned(),
+                    offset,
+                    instruction,
+                });
+        }
+    }
+
+    Ok(xrefs
+        .into_values()
+        .map(|mut xref| {
+            xref.references.sort_by(|a, b| {
+                (&a.section, a.offset).cmp(&(&b.section, b.offset))
+            });
+            xref
+        })
+        .collect())
+}
+
+/// A `readelf -hlSd` style dump of the linked program `program`: its ELF
+/// header, program headers, section headers and dynamic entries, i.e. what
+/// the loader reads of it.
+pub fn dump_elf(program: &[u8]) -> Result<String, SbpfLinkerError> {
+    let elf = ElfFile64::<LittleEndian>::parse(program)?;
+    let header = elf.elf_header();
+    let endian = elf.endian();
+    let mut dump = String::new();
+
+    let e_flags = header.e_flags.get(endian);
+    let _ = writeln!(dump, "ELF Header:");
+    for (field, value) in [
+        ("Type", e_type_name(header.e_type.get(endian))),
+        ("Machine", e_machine_name(header.e_machine.get(endian))),
+        (
+            "Flags",
+            match SbpfVersion::from_e_flags(e_flags) {
+                Some(version) => format!("{e_flags:#x} (SBPF {version})"),
+                None => format!("{e_flags:#x}"),
+            },
+        ),
+        ("Entry point", format!("{:#x}", header.e_entry.get(endian))),
+        (
+            "Program headers",
+            format!(
+                "{} at {:#x}",
+                header.e_phnum.get(endian),
+                header.e_phoff.get(endian)
+            ),
+        ),
+        (
+            "Section headers",
+            format!(
+                "{} at {:#x}",
+                header.e_shnum.get(endian),
+                header.e_shoff.get(endian)
+            ),
+        ),
+        (
+            "Section names",
+            format!("section {}", header.e_shstrndx.get(endian)),
+        ),
+    ] {
+        let _ = writeln!(dump, "  {:<18} {value}", format!("{field}:"));
+    }
+
+    let _ = writeln!(dump, "\nProgram Headers:");
+    let _ = writeln!(
+        dump,
+        "  {:<12} {:>10} {:>18} {:>18} {:>10} {:>10} {:<5} {:>6}",
+        "Type",
+        "Offset",
+        "VirtAddr",
+        "PhysAddr",
+        "FileSiz",
+        "MemSiz",
+        "Flg",
+        "Align"
+    );
+    for segment in elf.elf_program_headers() {
+        let p_flags = segment.p_flags(endian);
+        let _ = writeln!(
+            dump,
+            "  {:<12} {:>#10x} {:>#18x} {:>#18x} {:>#10x} {:>#10x} {:<5} {:>#6x}",
+            p_type_name(segment.p_type(endian)),
+            segment.p_offset(endian),
+            segment.p_vaddr(endian),
+            segment.p_paddr(endian),
+            segment.p_filesz(endian),
+            segment.p_memsz(endian),
+            flag_letters(&[
+                (p_flags & elf::PF_R != 0, 'R'),
+                (p_flags & elf::PF_W != 0, 'W'),
+                (p_flags & elf::PF_X != 0, 'E'),
+            ]),
+            segment.p_align(endian)
+        );
+    }
+
+    let sections = elf.elf_section_table();
+    let _ = writeln!(dump, "\nSection Headers:");
+    let _ = writeln!(
+        dump,
+        "  [Nr] {:<18} {:<10} {:>18} {:>8} {:>8} {:>4} {:<4} {:>3} {:>4} {:>3}",
+        "Name",
+        "Type",
+        "Address",
+        "Offset",
+        "Size",
+        "ES",
+        "Flg",
+        "Lk",
+        "Inf",
+        "Al"
+    );
+    let mut dynamic = None;
+    for (index, section) in sections.iter().enumerate() {
+        let name = sections.section_name(endian, section).unwrap_or_default();
+        let sh_type = section.sh_type(endian);
+        let sh_flags = section.sh_flags(endian);
+        let _ = writeln!(
+            dump,
+            "  [{index:>2}] {:<18} {:<10} {:>#18x} {:>#8x} {:>#8x} {:>4x} {:<4} \
+             {:>3} {:>4} {:>3}",
+            String::from_utf8_lossy(name),
+            sh_type_name(sh_type),
+            section.sh_addr(endian),
+            section.sh_offset(endian),
+            section.sh_size(endian),
+            section.sh_entsize(endian),
+            flag_letters(&[
+                (sh_flags & u64::from(elf::SHF_WRITE) != 0, 'W'),
+                (sh_flags & u64::from(elf::SHF_ALLOC) != 0, 'A'),
+                (sh_flags & u64::from(elf::SHF_EXECINSTR) != 0, 'X'),
+            ]),
+            section.sh_link(endian),
+            section.sh_info(endian),
+            section.sh_addralign(endian)
+        );
+        if sh_type == elf::SHT_DYNAMIC {
+            dynamic = section.dynamic(endian, program)?.map(|(dyns, _)| dyns);
+        }
+    }
+
+    if let Some(dyns) = dynamic {
+        let _ = writeln!(dump, "\nDynamic Section:");
+        for entry in dyns {
+            let tag = entry.d_tag(endian) as u32;
+            let _ = writeln!(
+                dump,
+                "  {:<18} {:#x}",
+                d_tag_name(tag),
+                entry.d_val(endian)
+            );
+            if tag == elf::DT_NULL {
+                break;
+            }
+        }
+    }
+    Ok(dump)
+}
+
+fn flag_letters(flags: &[(bool, char)]) -> String {
+    flags.iter().filter(|(set, _)| *set).map(|(_, letter)| letter).collect()
+}
+
+fn e_type_name(e_type: u16) -> String {
+    match e_type {
+        elf::ET_REL => "REL (relocatable object)".to_owned(),
+        elf::ET_EXEC => "EXEC (executable)".to_owned(),
+        elf::ET_DYN => "DYN (shared object)".to_owned(),
+        _ => format!("{e_type:#x}"),
+    }
+}
+
+fn e_machine_name(e_machine: u16) -> String {
+    match e_machine {
+        elf::EM_BPF => "BPF".to_owned(),
+        EM_SBPF => "SBPF".to_owned(),
+        _ => format!("{e_machine:#x}"),
+    }
+}
+
+fn p_type_name(p_type: u32) -> String {
+    match p_type {
+        elf::PT_NULL => "NULL".to_owned(),
+        elf::PT_LOAD => "LOAD".to_owned(),
+        elf::PT_DYNAMIC => "DYNAMIC".to_owned(),
+        elf::PT_INTERP => "INTERP".to_owned(),
+        elf::PT_NOTE => "NOTE".to_owned(),
+        elf::PT_PHDR => "PHDR".to_owned(),
+        elf::PT_GNU_STACK => "GNU_STACK".to_owned(),
+        _ => format!("{p_type:#x}"),
+    }
+}
+
+fn sh_type_name(sh_type: u32) -> String {
+    match sh_type {
+        elf::SHT_NULL => "NULL".to_owned(),
+        elf::SHT_PROGBITS => "PROGBITS".to_owned(),
+        elf::SHT_SYMTAB => "SYMTAB".to_owned(),
+        elf::SHT_STRTAB => "STRTAB".to_owned(),
+        elf::SHT_RELA => "RELA".to_owned(),
+        elf::SHT_HASH => "HASH".to_owned(),
+        elf::SHT_DYNAMIC => "DYNAMIC".to_owned(),
+        elf::SHT_NOTE => "NOTE".to_owned(),
+        elf::SHT_NOBITS => "NOBITS".to_owned(),
+        elf::SHT_REL => "REL".to_owned(),
+        elf::SHT_DYNSYM => "DYNSYM".to_owned(),
+        _ => format!("{sh_type:#x}"),
+    }
+}
+
+fn d_tag_name(d_tag: u32) -> String {
+    match d_tag {
+        elf::DT_NULL => "NULL".to_owned(),
+        elf::DT_NEEDED => "NEEDED".to_owned(),
+        elf::DT_HASH => "HASH".to_owned(),
+        elf::DT_STRTAB => "STRTAB".to_owned(),
+        elf::DT_SYMTAB => "SYMTAB".to_owned(),
+        elf::DT_STRSZ => "STRSZ".to_owned(),
+        elf::DT_SYMENT => "SYMENT".to_owned(),
+        elf::DT_SONAME => "SONAME".to_owned(),
+        elf::DT_REL => "REL".to_owned(),
+        elf::DT_RELSZ => "RELSZ".to_owned(),
+        elf::DT_RELENT => "RELENT".to_owned(),
+        elf::DT_TEXTREL => "TEXTREL".to_owned(),
+        elf::DT_FLAGS => "FLAGS".to_owned(),
+        elf::DT_GNU_HASH => "GNU_HASH".to_owned(),
+        elf::DT_RELCOUNT => "RELCOUNT".to_owned(),
+        _ => format!("{d_tag:#x}"),
+    }
+}
@@ -0,0 +1,578 @@
+use std::str::FromStr;
+
+use object::{Object as _, ObjectSection as _, SectionFlags, elf};
+use sbpf_assembler::syscall::SYSCALLS;
+
+use crate::{
+    SbpfLinkerError,
+    byteparser::R_BPF_64_RELATIVE,
+    defsym::parse_constant,
+    elf::{EM_SBPF, SYMBOL_SIZE},
+    inspect::inspect_relocations,
+    version::SbpfVersion,
+};
+
+/// Sections whose address can be set, see [`SectionStart`].
+const MOVABLE: [&str; 2] = [".text", ".rodata"];
+
+/// Size of an `Elf64_Phdr`.
+const PROGRAM_HEADER_SIZE: usize = 56;
+/// Size of an `Elf64_Shdr`.
+const SECTION_HEADER_SIZE: usize = 64;
+/// Size of an `Elf64_Rel`.
+const REL_SIZE: usize = 16;
+/// Size of an `Elf64_Ehdr`, which the program headers follow.
+const ELF_HEADER_SIZE: usize = 64;
+
+/// Where strict loaders map `.text`, see [`strict_layout`].
+pub const STRICT_TEXT_START: u64 = 0;
+/// Where strict loaders map `.rodata`, the start of the next 4 GiB region.
+pub const STRICT_RODATA_START: u64 = 1 << 32;
+
+/// The address a section of the program starts at, as with lld's
+/// `--section-start section=address`. Only `.text` and `.rodata` can be
+/// placed; the file is laid out as it always is, and only the addresses
+/// the program is loaded at change.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SectionStart {
+    pub section: String,
+    pub address: u64,
+}
+
+impl FromStr for SectionStart {
+    type Err = SbpfLinkerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |detail: &str| SbpfLinkerError::InvalidSectionStart {
+            section: s.to_owned(),
+            detail: detail.to_owned(),
+        };
+
+        let (section, address) = s
+            .split_once('=')
+            .ok_or_else(|| invalid("expects `section=address`"))?;
+        let (section, address) = (section.trim(), address.trim());
+        if !MOVABLE.contains(&section) {
+            return Err(invalid("only .text and .rodata can be placed"));
+        }
+        let address = parse_constant(address)
+            .filter(|_| !address.starts_with('-'))
+            .ok_or_else(|| invalid("expects an address, e.g. `0x1000`"))?;
+        Ok(Self { section: section.to_owned(), address: address as u64 })
+    }
+}
+
+/// A section of the program that moves, from its address `old` to `new`.
+struct Moved {
+    name: &'static str,
+    old: u64,
+    new: u64,
+    size: u64,
+}
+
+/// Moves `.text` and `.rodata` of the emitted `program` to the addresses in
+/// `starts`. With `rodata_after_text`, `.rodata` stays right after `.text`
+/// wherever that goes, otherwise it stays where it is unless it's given a
+/// start of its own.
+///
+/// The file keeps its layout; the section and program headers, the entry
+/// point, the `.dynsym` symbols, and the `.rel.dyn` relocations with the
+/// addresses they patch follow the sections. A segment loading both
+/// sections only keeps the first if they're moved apart, which leaves the
+/// other to loaders that map the sections themselves. Without
+/// `program_headers`, those are left for [`strict_layout`] to replace.
+pub(crate) fn move_sections(
+    program: &mut [u8],
+    starts: &[SectionStart],
+    rodata_after_text: bool,
+    program_headers: bool,
+) -> Result<(), SbpfLinkerError> {
+    let invalid =
+        |section: &str, detail: String| SbpfLinkerError::InvalidSectionStart {
+            section: section.to_owned(),
+            detail,
+        };
+    let start_of =
+        |name: &str| starts.iter().rev().find(|start| start.section == name);
+    if rodata_after_text && start_of(".rodata").is_some() {
+        return Err(invalid(
+            ".rodata",
+            "conflicts with --rodata-after-text".to_owned(),
+        ));
+    }
+
+    let mut moved: Vec<Moved> = Vec::new();
+    // every allocated section, as (name, address, size, in-file offset)
+    let mut allocated = Vec::new();
+    let (mut dynsym, mut rel_dyn) = (None, None);
+    let mut headers = Vec::new();
+    {
+        let obj = object::File::parse(&*program)?;
+        for start in starts {
+            if obj.section_by_name(&start.section).is_none() {
+                return Err(invalid(
+                    &start.section,
+                    format!("the program has no {}", start.section),
+                ));
+            }
+        }
+        for section in obj.sections() {
+            let name = section.name().unwrap_or_default();
+            let SectionFlags::Elf { sh_flags } = section.flags() else {
+                continue;
+            };
+            if sh_flags & u64::from(elf::SHF_ALLOC) == 0 {
+                continue;
+            }
+            let offset = section.file_range().map_or(0, |(offset, _)| offset);
+            allocated.push((
+                name.to_owned(),
+                section.address(),
+                section.size(),
+                offset,
+            ));
+            match name {
+                ".dynsym" => dynsym = section.file_range(),
+                ".rel.dyn" => rel_dyn = section.file_range(),
+                _ => {}
+            }
+            let Some(&movable) = MOVABLE.iter().find(|&&m| m == name) else {
+                continue;
+            };
+            let new = match start_of(movable) {
+                Some(start) => start.address,
+                None => match moved.first() {
+                    Some(text)
+                        if movable == ".rodata" && rodata_after_text =>
+                    {
+                        section
+                            .address()
+                            .wrapping_add(text.new.wrapping_sub(text.old))
+                    }
+                    _ => continue,
+                },
+            };
+            if section.align() > 1 && new % section.align() != 0 {
+                return Err(invalid(
+                    movable,
+                    format!(
+                        "{new:#x} isn't aligned to the {} bytes it needs",
+                        section.align()
+                    ),
+                ));
+            }
+            headers.push((section.index().0, new));
+            moved.push(Moved {
+                name: movable,
+                old: section.address(),
+                new,
+                size: section.size(),
+            });
+        }
+    }
+    if moved.is_empty() {
+        return Ok(());
+    }
+
+    let moving = |address: u64| {
+        moved
+            .iter()
+            .find(|m| (m.old..m.old + m.size.max(1)).contains(&address))
+            .map_or(address, |m| m.new + (address - m.old))
+    };
+    // where in the file what's loaded at `address` is
+    let file_offset = |address: u64| {
+        allocated.iter().find_map(|(_, start, size, offset)| {
+            (*start..start + size)
+                .contains(&address)
+                .then(|| (offset + (address - start)) as usize)
+        })
+    };
+
+    for m in &moved {
+        let end = m.new.checked_add(m.size).ok_or_else(|| {
+            invalid(
+                m.name,
+                format!("{:#x} runs past the address space", m.new),
+            )
+        })?;
+        for (other, start, size, _) in &allocated {
+            if other == m.name || *size == 0 || m.size == 0 {
+                continue;
+            }
+            let start = moving(*start);
+            if start < end && m.new < start.saturating_add(*size) {
+                return Err(invalid(
+                    m.name,
+                    format!("{:#x} puts it over {other}", m.new),
+                ));
+            }
+        }
+    }
+
+    // each relocated address is patched before its `r_offset` moves
+    if let Some((start, size)) = rel_dyn {
+        for at in (start..start + size).step_by(REL_SIZE) {
+            let at = at as usize;
+            let (Some(r_offset), Some(r_info)) =
+                (read(program, at, 8), read(program, at + 8, 8))
+            else {
+                break;
+            };
+            if r_info as u32 == R_BPF_64_RELATIVE
+                && let Some(slot) = file_offset(r_offset)
+            {
+                let in_text =
+                    allocated.iter().any(|(name, start, size, _)| {
+                        name == ".text"
+                            && (*start..start + size).contains(&r_offset)
+                    });
+                if in_text {
+                    // the immediates of the `lddw` pair hold the halves
+                    if let (Some(low), Some(high)) = (
+                        read(program, slot + 4, 4),
+                        read(program, slot + 12, 4),
+                    ) {
+                        let value = moving(low | high << 32);
+                        write(program, slot + 4, 4, value & 0xffff_ffff);
+                        write(program, slot + 12, 4, value >> 32);
+                    }
+                } else if let Some(value) = read(program, slot, 8) {
+                    write(program, slot, 8, moving(value));
+                }
+            }
+            write(program, at, 8, moving(r_offset));
+        }
+    }
+
+    if let Some((start, size)) = dynsym {
+        for at in (start..start + size).step_by(SYMBOL_SIZE) {
+            let at = at as usize;
+            // st_shndx, then st_value
+            if read(program, at + 6, 2).is_some_and(|shndx| shndx != 0)
+                && let Some(value) = read(program, at + 8, 8)
+            {
+                write(program, at + 8, 8, moving(value));
+            }
+        }
+    }
+
+    if let Some(entry) = read(program, 0x18, 8) {
+        write(program, 0x18, 8, moving(entry));
+    }
+
+    if let Some(shoff) = read(program, 0x28, 8) {
+        for (index, new) in headers {
+            let at = shoff as usize + index * SECTION_HEADER_SIZE;
+            write(program, at + 16, 8, new);
+        }
+    }
+
+    let (Some(phoff), Some(phnum)) =
+        (read(program, 0x20, 8), read(program, 0x38, 2))
+    else {
+        return Ok(());
+    };
+    if !program_headers {
+        return Ok(());
+    }
+    for index in 0..phnum as usize {
+        let at = phoff as usize + index * PROGRAM_HEADER_SIZE;
+        let (Some(p_type), Some(p_offset), Some(p_vaddr), Some(p_memsz)) = (
+            read(program, at, 4),
+            read(program, at + 8, 8),
+            read(program, at + 16, 8),
+            read(program, at + 40, 8),
+        ) else {
+            break;
+        };
+        if p_type != u64::from(elf::PT_LOAD) {
+            continue;
+        }
+        let new = moving(p_vaddr);
+        write(program, at + 16, 8, new);
+        write(program, at + 24, 8, new);
+        // cut the segment short at the first section that moved apart
+        let delta = new.wrapping_sub(p_vaddr);
+        if let Some((name, start, _, offset)) =
+            allocated.iter().find(|(_, start, _, _)| {
+                (p_vaddr..p_vaddr + p_memsz).contains(start)
+                    && moving(*start).wrapping_sub(*start) != delta
+            })
+        {
+            tracing::warn!(
+                "{name} is moved apart from the segment it was loaded with, \
+                 so no program header covers it"
+            );
+            write(program, at + 32, 8, offset - p_offset);
+            write(program, at + 40, 8, start - p_vaddr);
+        }
+    }
+    Ok(())
+}
+
+/// Where `--strict-elf` loads the sections of `program`, for
+/// [`move_sections`]: `.text` at [`STRICT_TEXT_START`] and `.rodata`, if
+/// the program has one, at [`STRICT_RODATA_START`]. Fails if `starts` puts
+/// either anywhere else.
+pub(crate) fn strict_starts(
+    program: &[u8],
+    starts: &[SectionStart],
+    rodata_after_text: bool,
+) -> Result<Vec<SectionStart>, SbpfLinkerError> {
+    let obj = object::File::parse(program)?;
+    let mut strict = Vec::new();
+    for (section, address) in
+        [(".text", STRICT_TEXT_START), (".rodata", STRICT_RODATA_START)]
+    {
+        if obj.section_by_name(section).is_none() {
+            continue;
+        }
+        if let Some(start) = starts
+            .iter()
+            .rev()
+            .find(|start| start.section == section)
+            .filter(|start| start.address != address)
+        {
+            return Err(strict_violation(format!(
+                "--section-start puts {section} at {:#x}, but strict \
+                 loaders map it at {address:#x}; drop the option",
+                start.address
+            )));
+        }
+        strict.push(SectionStart { section: section.to_owned(), address });
+    }
+    if rodata_after_text && strict.len() == 2 {
+        return Err(strict_violation(
+            "strict loaders map .rodata apart from .text; drop \
+             --rodata-after-text"
+                .to_owned(),
+        ));
+    }
+    Ok(strict)
+}
+
+/// Gives `program`, its sections moved by [`strict_starts`], the layout
+/// loaders that parse SBPF v3 programs strictly expect: `e_machine` is
+/// SBPF's, and the program headers are exactly two loads, one of `.text`,
+/// executable only, and one of `.rodata`, read only and empty if the
+/// program has none, laid out right after them in the file. Nothing else
+/// is loaded, the `.dynamic` section included, as strict loaders take the
+/// entry point from the header and relocate nothing. See
+/// [`check_strict_layout`] for the rest of what they check.
+pub(crate) fn strict_layout(
+    program: &mut Vec<u8>,
+) -> Result<(), SbpfLinkerError> {
+    let (text, rodata) = {
+        let obj = object::File::parse(&**program)?;
+        let range = |name: &str| {
+            obj.section_by_name(name).and_then(|section| section.file_range())
+        };
+        (range(".text"), range(".rodata"))
+    };
+    let Some((text_offset, text_size)) = text else {
+        return Err(strict_violation("the program has no .text".to_owned()));
+    };
+    let (Some(phoff), Some(phnum), Some(shoff), Some(shnum)) = (
+        read(program, 0x20, 8),
+        read(program, 0x38, 2),
+        read(program, 0x28, 8),
+        read(program, 0x3c, 2),
+    ) else {
+        return Err(strict_violation(
+            "the ELF header is truncated".to_owned(),
+        ));
+    };
+    if phoff != ELF_HEADER_SIZE as u64 || phnum < 2 {
+        return Err(strict_violation(format!(
+            "the program has {phnum} program header(s) at {phoff:#x}, \
+             where two are rewritten"
+        )));
+    }
+
+    // the file after the first two program headers moves up to them
+    let table_end = ELF_HEADER_SIZE + 2 * PROGRAM_HEADER_SIZE;
+    let shift = (phnum as usize - 2) * PROGRAM_HEADER_SIZE;
+    program.drain(table_end..table_end + shift);
+    let moved = |offset: u64| {
+        if offset as usize >= table_end {
+            offset - shift as u64
+        } else {
+            offset
+        }
+    };
+    let new_shoff = moved(shoff);
+    write(program, 0x28, 8, new_shoff);
+    for index in 0..shnum as usize {
+        let at = new_shoff as usize + index * SECTION_HEADER_SIZE;
+        if read(program, at + 4, 4) == Some(u64::from(elf::SHT_NULL)) {
+            continue;
+        }
+        if let Some(offset) = read(program, at + 24, 8) {
+            write(program, at + 24, 8, moved(offset));
+        }
+    }
+
+    let text_offset = moved(text_offset);
+    let (rodata_offset, rodata_size) = rodata
+        .map_or((text_offset + text_size, 0), |(offset, size)| {
+            (moved(offset), size)
+        });
+    let segments = [
+        (elf::PF_X, text_offset, STRICT_TEXT_START, text_size),
+        (elf::PF_R, rodata_offset, STRICT_RODATA_START, rodata_size),
+    ];
+    for (index, (flags, offset, address, size)) in
+        segments.into_iter().enumerate()
+    {
+        let at = ELF_HEADER_SIZE + index * PROGRAM_HEADER_SIZE;
+        write(program, at, 4, u64::from(elf::PT_LOAD));
+        write(program, at + 4, 4, u64::from(flags));
+        write(program, at + 8, 8, offset);
+        write(program, at + 16, 8, address);
+        write(program, at + 24, 8, address);
+        write(program, at + 32, 8, size);
+        write(program, at + 40, 8, size);
+        write(program, at + 48, 8, 8);
+    }
+    write(program, 0x38, 2, 2);
+    write(program, 0x12, 2, u64::from(EM_SBPF));
+    check_strict_layout(program)
+}
+
+/// Checks that `program` has the layout [`strict_layout`] gives it, and
+/// what else strict loaders reject: it's an SBPF v3 shared object whose
+/// entry point is an instruction of `.text`, `.text` isn't writable and
+/// comes before `.rodata`, and every call the loader would have to
+/// resolve is a syscall, which v3 calls by its hash instead.
+pub fn check_strict_layout(program: &[u8]) -> Result<(), SbpfLinkerError> {
+    let obj = object::File::parse(program)?;
+    let field = |at: usize, size: usize| read(program, at, size).unwrap_or(0);
+    if field(0x10, 2) != u64::from(elf::ET_DYN) {
+        return Err(strict_violation(
+            "the program isn't a shared object".to_owned(),
+        ));
+    }
+    if field(0x12, 2) != u64::from(EM_SBPF) {
+        return Err(strict_violation(format!(
+            "e_machine is {:#x}, not SBPF's {EM_SBPF:#x}",
+            field(0x12, 2)
+        )));
+    }
+    if field(0x30, 4) != u64::from(SbpfVersion::V3.e_flags()) {
+        return Err(strict_violation(format!(
+            "e_flags is {:#x}, not SBPF {}'s; link with \
+             --require-vm-version v3",
+            field(0x30, 4),
+            SbpfVersion::V3
+        )));
+    }
+
+    let (Some(text), rodata) =
+        (obj.section_by_name(".text"), obj.section_by_name(".rodata"))
+    else {
+        return Err(strict_violation("the program has no .text".to_owned()));
+    };
+    if let SectionFlags::Elf { sh_flags } = text.flags()
+        && sh_flags & u64::from(elf::SHF_WRITE) != 0
+    {
+        return Err(strict_violation(".text is writable".to_owned()));
+    }
+    if let Some(rodata) = &rodata
+        && rodata.index().0 < text.index().0
+    {
+        return Err(strict_violation(
+            ".rodata comes before .text in the section table".to_owned(),
+        ));
+    }
+    let entry = field(0x18, 8);
+    if !(text.address()..text.address() + text.size()).contains(&entry)
+        || (entry - text.address()) % 8 != 0
+    {
+        return Err(strict_violation(format!(
+            "the entry point {entry:#x} isn't an instruction of .text"
+        )));
+    }
+
+    let section = |name: &str, default: u64| {
+        obj.section_by_name(name).map_or((default, 0), |section| {
+            (section.address(), section.size())
+        })
+    };
+    let expected = [
+        (elf::PF_X, section(".text", STRICT_TEXT_START)),
+        (elf::PF_R, section(".rodata", STRICT_RODATA_START)),
+    ];
+    if field(0x20, 8) != ELF_HEADER_SIZE as u64
+        || field(0x38, 2) != expected.len() as u64
+    {
+        return Err(strict_violation(
+            "the program headers aren't exactly the loads of .text and \
+             .rodata, right after the ELF header"
+                .to_owned(),
+        ));
+    }
+    let mut offset = (ELF_HEADER_SIZE + 2 * PROGRAM_HEADER_SIZE) as u64;
+    for (index, (flags, (address, size))) in expected.into_iter().enumerate() {
+        let at = ELF_HEADER_SIZE + index * PROGRAM_HEADER_SIZE;
+        let [p_type, p_flags] = [field(at, 4), field(at + 4, 4)];
+        let [p_offset, p_vaddr, p_paddr, p_filesz, p_memsz] =
+            [8, 16, 24, 32, 40].map(|field_at| field(at + field_at, 8));
+        if p_type != u64::from(elf::PT_LOAD)
+            || p_flags != u64::from(flags)
+            || p_offset != offset
+            || p_vaddr != address
+            || p_paddr != address
+            || p_filesz != size
+            || p_memsz != size
+            || offset + size > program.len() as u64
+        {
+            return Err(strict_violation(format!(
+                "program header {index} isn't the {} load at {address:#x} \
+                 of the {size} bytes at file offset {offset:#x}",
+                if flags == elf::PF_X { "executable" } else { "read only" }
+            )));
+        }
+        offset += size;
+    }
+    if text.address() != STRICT_TEXT_START
+        || rodata.is_some_and(|rodata| rodata.address() != STRICT_RODATA_START)
+    {
+        return Err(strict_violation(format!(
+            ".text and .rodata aren't at {STRICT_TEXT_START:#x} and \
+             {STRICT_RODATA_START:#x}"
+        )));
+    }
+
+    for relocation in inspect_relocations(program)? {
+        if relocation.kind == "R_BPF_64_32"
+            && !SYSCALLS.values().any(|syscall| *syscall == relocation.symbol)
+        {
+            return Err(strict_violation(format!(
+                "`{}` is undefined, and strict loaders resolve no symbols; \
+                 link in the object that defines it",
+                relocation.symbol
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn strict_violation(reason: String) -> SbpfLinkerError {
+    SbpfLinkerError::StrictElfViolation(reason)
+}
+
+/// The `size` byte little-endian field at `at` in `program`.
+fn read(program: &[u8], at: usize, size: usize) -> Option<u64> {
+    program.get(at..at + size).map(|bytes| {
+        let mut value = [0; 8];
+        value[..size].copy_from_slice(bytes);
+        u64::from_le_bytes(value)
+    })
+}
+
+fn write(program: &mut [u8], at: usize, size: usize, value: u64) {
+    if let Some(bytes) = program.get_mut(at..at + size) {
+        bytes.copy_from_slice(&value.to_le_bytes()[..size]);
+    }
+}
@@ -1,10 +1,78 @@
+mod analysis;
+pub mod archive;
+pub mod assembly;
+pub mod bitcode;
+pub mod btf;
 pub mod byteparser;
-use std::io;
+pub mod cache;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod compute;
+pub mod config;
+pub mod debug;
+pub mod defsym;
+pub mod diagnostic;
+pub mod diff;
+mod disasm;
+mod elf;
+pub mod export;
+mod gc;
+pub mod icf;
+pub mod inspect;
+pub mod layout;
+pub mod lines;
+pub mod lld;
+pub mod loader;
+pub mod map;
+mod merge;
+pub mod output;
+pub mod program;
+pub mod rename;
+pub mod response;
+pub mod script;
+pub mod size;
+pub mod splice;
+pub mod stack;
+#[cfg(test)]
+mod testing;
+pub mod timings;
+mod verifier;
+mod verify;
+pub mod version;
+use std::{collections::HashMap, fmt, io, path::PathBuf};
+#[cfg(feature = "llvm")]
+use std::{
+    env, fs,
+    sync::{Mutex, PoisonError},
+};
 
-use bpf_linker::LinkerError;
-use byteparser::parse_bytecode;
+#[cfg(feature = "llvm")]
+use bpf_linker::{Linker, LinkerError, LinkerOptions};
+use byteparser::{
+    DEFAULT_ENTRY, parse_bytecode, parse_bytecode_with_diagnostics,
+};
+use defsym::Defsym;
+use diagnostic::{Diagnostic, DiagnosticKind, Location, WarningOptions};
+use icf::Icf;
+use layout::SectionStart;
+use loader::LoaderVersion;
+use map::LinkMap;
+#[cfg(feature = "llvm")]
+use output::TempPath;
+use program::ParsedProgram;
+use rename::SymbolRename;
+use script::LinkerScript;
+use splice::InstructionSequence;
+use timings::{Timings, collect_timings};
 
-use sbpf_assembler::{CompileError, Program};
+use object::{
+    FileFlags, LittleEndian, Object as _, ObjectSection as _,
+    read::elf::{ElfFile64, FileHeader as _},
+};
+use sbpf_assembler::{CompileError, Program, astnode::ASTNode};
+use sbpf_common::opcode::Opcode;
+use sha2::{Digest as _, Sha256};
+use version::SbpfVersion;
 
 #[derive(thiserror::Error, Debug)]
 pub enum SbpfLinkerError {
@@ -12,6 +80,9 @@ pub enum SbpfLinkerError {
     ObjectFileOpenError(#[from] object::Error),
     #[error("Error reading object file. Error detail: ({0}).")]
     ObjectFileReadError(#[from] io::Error),
+    #[error("Error writing merged object. Error detail: ({0}).")]
+    ObjectFileWriteError(#[from] object::write::Error),
+    #[cfg(feature = "llvm")]
     #[error("Linker Error. Error detail: ({0}).")]
     LinkerError(#[from] LinkerError),
     #[error("LLVM issued diagnostic with error severity.")]
@@ -20,12 +91,1185 @@ pub enum SbpfLinkerError {
     BuildProgramError { errors: Vec<CompileError> },
     #[error("Instruction Parse Error. Error detail: ({0}).")]
     InstructionParseError(String),
+    #[error("SBPF version needs to be one of v0-v3 (instead was `{0}`).")]
+    InvalidSbpfVersion(String),
+    #[error("Unknown SBPF version in e_flags ({0:#x}).")]
+    UnknownSbpfVersion(u32),
+    #[error(
+        "Program requires SBPF {required} but declares {declared} as its minimum VM version."
+    )]
+    VersionMismatch { required: SbpfVersion, declared: SbpfVersion },
+    #[error(
+        "--defsym expects `name=symbol` or `name=constant` (instead was `{0}`)."
+    )]
+    InvalidDefsym(String),
+    #[error("--defsym {name} refers to undefined symbol `{target}`.")]
+    UndefinedDefsymTarget { name: String, target: String },
+    #[error("--defsym {0} is defined in terms of itself.")]
+    DefsymCycle(String),
+    #[error(
+        "Relocation at .text offset {offset} points to .rodata offset {target}, which isn't covered by any rodata symbol."
+    )]
+    UnresolvedRodataReference { offset: u64, target: u64 },
+    #[error(
+        "`{opcode}` at .text offset {offset} is deprecated in the target SBPF version."
+    )]
+    DeprecatedOpcode { opcode: Opcode, offset: u64 },
+    #[error(
+        "`{opcode}` at .text offset {offset:#x} has no encoding in SBPF {version}."
+    )]
+    UnencodableInstruction {
+        opcode: Opcode,
+        offset: u64,
+        version: SbpfVersion,
+    },
+    #[error(
+        "Inputs are built for different SBPF versions ({first} and {other})."
+    )]
+    MixedSbpfVersions { first: SbpfVersion, other: SbpfVersion },
+    #[error("--section-start {section}: {detail}.")]
+    InvalidSectionStart { section: String, detail: String },
+    #[error("--strict-elf: {0}.")]
+    StrictElfViolation(String),
+    #[error("--{option} {size}: {detail}.")]
+    InvalidMemorySize { option: &'static str, size: u64, detail: String },
+    #[error("Rodata symbol `{0}` extends past the end of its section.")]
+    RodataOutOfBounds(String),
+    #[error(
+        "Invalid instruction sequence `{sequence}`. Error detail: ({detail})."
+    )]
+    InvalidInstructionSequence { sequence: String, detail: String },
+    #[error(
+        "Branch at .text offset {offset} targets offset {target}, which doesn't start an instruction."
+    )]
+    UnresolvedBranchTarget { offset: u64, target: u64 },
+    #[error(
+        "Branch at .text offset {offset} no longer reaches its target after relayout."
+    )]
+    BranchOutOfRange { offset: u64 },
+    #[error(
+        "Relocation at {section} offset {offset} against `{symbol}` isn't supported."
+    )]
+    UnsupportedRelocation { section: String, offset: u64, symbol: String },
+    #[error(
+        "Relocation of type {kind} at {section} offset {offset:#x} isn't supported."
+    )]
+    UnsupportedRelocationType { section: String, offset: u64, kind: String },
+    #[error("Symbol `{0}` is defined more than once.")]
+    MultipleDefinition(String),
+    #[error("Loader version needs to be one of v1-v4 (instead was `{0}`).")]
+    InvalidLoaderVersion(String),
+    #[error("Program won't load on {loader}: {reason}.")]
+    LoaderViolation { loader: LoaderVersion, reason: String },
+    #[error(
+        "Writing the program to {} would overwrite the input.",
+        .0.display()
+    )]
+    OutputWouldOverwriteInput(PathBuf),
+    #[error(
+        "{} already exists and --no-clobber was given.",
+        .0.display()
+    )]
+    OutputExists(PathBuf),
+    #[error("Program embeds read-only data ({}) but --no-rodata was given.", symbols.join(", "))]
+    UnexpectedRodata { symbols: Vec<String> },
+    #[error(
+        "Invalid rename map line {line}: `{content}` (expected `old_name new_name`)."
+    )]
+    InvalidRenameMap { line: usize, content: String },
+    #[error("Can't rename `{from}` to `{to}`: the name is already taken.")]
+    RenameCollision { from: String, to: String },
+    #[error(
+        "Contents of section `{name}` can't be read (unsupported compression or encoding)."
+    )]
+    SectionDataUnavailable { name: String },
+    #[error("Unknown opcode {opcode:#04x} at .text offset {offset:#x}.")]
+    UnknownOpcode { opcode: u8, offset: u64 },
+    #[error(
+        "Relocation against `{symbol}` at {section} offset {offset:#x} doesn't apply to an instruction operand."
+    )]
+    InvalidRelocationSite { section: String, offset: u64, symbol: String },
+    #[error("Section `{name}` has an invalid alignment of {align}.")]
+    InvalidSectionAlignment { name: String, align: u64 },
+    #[error("Section `{name}` is {size} bytes, more than fits in a program.")]
+    SectionTooLarge { name: String, size: u64 },
+    #[error("Entry symbol `{0}` isn't a function in .text.")]
+    UndefinedEntrySymbol(String),
+    #[error("Export `{0}` isn't a function in .text.")]
+    UndefinedExport(String),
+    #[error("Export pattern `{0}` matches no function.")]
+    UnmatchedExportPattern(String),
+    #[error(
+        "Emitted instruction at .text offset {offset:#x} decodes as `{found}` instead of `{expected}`."
+    )]
+    RoundTripMismatch { offset: u64, expected: String, found: String },
+    #[error(
+        "Function `{function}` uses {size} bytes of stack, more than the {limit} bytes of a frame."
+    )]
+    StackFrameOverflow { function: String, size: u64, limit: u64 },
+    #[error(
+        "Verifier rejects the instruction at .text offset {offset:#x}: {reason}."
+    )]
+    VerifierError { offset: u64, reason: String },
+    #[error(
+        "Nothing to link: no input object, and the archives define nothing the program needs."
+    )]
+    NoInputObjects,
+    #[error(
+        "Archive member `{0}` is LLVM bitcode, which only sbpf-linker can link."
+    )]
+    BitcodeArchiveMember(String),
+    #[error(
+        "Can't parse the LLVM IR in {}. Error detail: ({detail}).",
+        path.display()
+    )]
+    InvalidLlvmIr { path: PathBuf, detail: String },
+    #[error(
+        "Can't assemble {}. Error detail: ({detail}).",
+        path.display()
+    )]
+    InvalidAssembly { path: PathBuf, detail: String },
+    #[error("Invalid linker script at line {line}: {detail}.")]
+    InvalidLinkerScript { line: usize, detail: String },
+    #[error(
+        "Linker script places input in `{0}`, which is neither .text nor a data section."
+    )]
+    UnplaceableOutputSection(String),
+    #[error("Unknown warning `-W{0}`.")]
+    UnknownWarning(String),
+    #[error("Aborting due to {0} warning(s) (-Werror).")]
+    WarningsAsErrors(usize),
+    #[error("Can't read the DWARF line tables. Error detail: ({0}).")]
+    InvalidDebugInfo(String),
+    #[error("Can't read the BTF of the input. Error detail: ({0}).")]
+    InvalidBtf(String),
+    #[error("Invalid size report. Error detail: ({0}).")]
+    InvalidSizeReport(String),
+    #[error(
+        "Can't read the response file {}. Error detail: ({detail}).",
+        path.display()
+    )]
+    InvalidResponseFile { path: PathBuf, detail: String },
+    #[error("Invalid config file {}. Error detail: ({detail}).", path.display())]
+    InvalidConfig { path: PathBuf, detail: String },
+    #[error("Invalid environment variable: {0}.")]
+    InvalidEnvironment(String),
+    #[error("Invalid ld.lld command line: {0}.")]
+    InvalidLldArgument(String),
+    #[error("Linked program is empty (the input has no instructions).")]
+    EmptyOutput,
 }
 
+impl SbpfLinkerError {
+    /// The identifier of the error in machine-readable output.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::ObjectFileOpenError(_) => "object_file_open_error",
+            Self::ObjectFileReadError(_) => "object_file_read_error",
+            Self::ObjectFileWriteError(_) => "object_file_write_error",
+            #[cfg(feature = "llvm")]
+            Self::LinkerError(_) => "linker_error",
+            Self::LlvmDiagnosticError => "llvm_diagnostic_error",
+            Self::BuildProgramError { .. } => "build_program_error",
+            Self::InstructionParseError(_) => "instruction_parse_error",
+            Self::InvalidSbpfVersion(_) => "invalid_sbpf_version",
+            Self::UnknownSbpfVersion(_) => "unknown_sbpf_version",
+            Self::VersionMismatch { .. } => "version_mismatch",
+            Self::InvalidDefsym(_) => "invalid_defsym",
+            Self::UndefinedDefsymTarget { .. } => "undefined_defsym_target",
+            Self::DefsymCycle(_) => "defsym_cycle",
+            Self::UnresolvedRodataReference { .. } => {
+                "unresolved_rodata_reference"
+            }
+            Self::DeprecatedOpcode { .. } => "deprecated_opcode",
+            Self::UnencodableInstruction { .. } => "unencodable_instruction",
+            Self::MixedSbpfVersions { .. } => "mixed_sbpf_versions",
+            Self::InvalidSectionStart { .. } => "invalid_section_start",
+            Self::StrictElfViolation(_) => "strict_elf_violation",
+            Self::InvalidMemorySize { .. } => "invalid_memory_size",
+            Self::RodataOutOfBounds(_) => "rodata_out_of_bounds",
+            Self::InvalidInstructionSequence { .. } => {
+                "invalid_instruction_sequence"
+            }
+            Self::UnresolvedBranchTarget { .. } => "unresolved_branch_target",
+            Self::BranchOutOfRange { .. } => "branch_out_of_range",
+            Self::UnsupportedRelocation { .. } => "unsupported_relocation",
+            Self::UnsupportedRelocationType { .. } => {
+                "unsupported_relocation_type"
+            }
+            Self::MultipleDefinition(_) => "multiple_definition",
+            Self::InvalidLoaderVersion(_) => "invalid_loader_version",
+            Self::LoaderViolation { .. } => "loader_violation",
+            Self::OutputWouldOverwriteInput(_) => {
+                "output_would_overwrite_input"
+            }
+            Self::OutputExists(_) => "output_exists",
+            Self::UnexpectedRodata { .. } => "unexpected_rodata",
+            Self::InvalidRenameMap { .. } => "invalid_rename_map",
+            Self::RenameCollision { .. } => "rename_collision",
+            Self::SectionDataUnavailable { .. } => "section_data_unavailable",
+            Self::UnknownOpcode { .. } => "unknown_opcode",
+            Self::InvalidRelocationSite { .. } => "invalid_relocation_site",
+            Self::InvalidSectionAlignment { .. } => {
+                "invalid_section_alignment"
+            }
+            Self::SectionTooLarge { .. } => "section_too_large",
+            Self::UndefinedEntrySymbol(_) => "undefined_entry_symbol",
+            Self::UndefinedExport(_) => "undefined_export",
+            Self::UnmatchedExportPattern(_) => "unmatched_export_pattern",
+            Self::RoundTripMismatch { .. } => "round_trip_mismatch",
+            Self::StackFrameOverflow { .. } => "stack_frame_overflow",
+            Self::VerifierError { .. } => "verifier_error",
+            Self::NoInputObjects => "no_input_objects",
+            Self::BitcodeArchiveMember(_) => "bitcode_archive_member",
+            Self::InvalidLlvmIr { .. } => "invalid_llvm_ir",
+            Self::InvalidAssembly { .. } => "invalid_assembly",
+            Self::InvalidLinkerScript { .. } => "invalid_linker_script",
+            Self::UnplaceableOutputSection(_) => "unplaceable_output_section",
+            Self::UnknownWarning(_) => "unknown_warning",
+            Self::WarningsAsErrors(_) => "warnings_as_errors",
+            Self::InvalidDebugInfo(_) => "invalid_debug_info",
+            Self::InvalidBtf(_) => "invalid_btf",
+            Self::InvalidSizeReport(_) => "invalid_size_report",
+            Self::InvalidResponseFile { .. } => "invalid_response_file",
+            Self::InvalidConfig { .. } => "invalid_config",
+            Self::InvalidEnvironment(_) => "invalid_environment",
+            Self::InvalidLldArgument(_) => "invalid_lld_argument",
+            Self::EmptyOutput => "empty_output",
+        }
+    }
+
+    /// The part of the input the error is about, if it's about one.
+    pub fn location(&self) -> Location {
+        match self {
+            Self::UnresolvedRodataReference { offset, .. }
+            | Self::DeprecatedOpcode { offset, .. }
+            | Self::UnencodableInstruction { offset, .. }
+            | Self::UnresolvedBranchTarget { offset, .. }
+            | Self::BranchOutOfRange { offset }
+            | Self::UnknownOpcode { offset, .. }
+            | Self::RoundTripMismatch { offset, .. }
+            | Self::VerifierError { offset, .. } => {
+                Location::at(".text", *offset)
+            }
+            Self::UnsupportedRelocation { section, offset, .. }
+            | Self::UnsupportedRelocationType { section, offset, .. }
+            | Self::InvalidRelocationSite { section, offset, .. } => {
+                Location::at(section, *offset)
+            }
+            Self::SectionDataUnavailable { name }
+            | Self::InvalidSectionAlignment { name, .. }
+            | Self::SectionTooLarge { name, .. } => {
+                Location { section: Some(name.clone()), ..Location::default() }
+            }
+            Self::OutputWouldOverwriteInput(path)
+            | Self::OutputExists(path)
+            | Self::InvalidLlvmIr { path, .. }
+            | Self::InvalidAssembly { path, .. }
+            | Self::InvalidResponseFile { path, .. }
+            | Self::InvalidConfig { path, .. } => {
+                Location { file: Some(path.clone()), ..Location::default() }
+            }
+            _ => Location::default(),
+        }
+    }
+}
+
+/// `tracing` target of the events emitted for [`LinkOptions::trace_symbols`].
+pub const TRACE_SYMBOL_TARGET: &str = "sbpf_linker::trace_symbol";
+
+#[derive(Clone, Debug, Default)]
+pub struct LinkOptions {
+    /// Minimum VM version stamped into the program's `e_flags`, and the one
+    /// its instructions are encoded for; by default the version the input
+    /// is built for. Linking fails if the program uses instructions the
+    /// declared version can't execute.
+    pub require_vm_version: Option<SbpfVersion>,
+    /// Link-time symbol definitions. Relocations against these names resolve
+    /// to the given constant or to the aliased symbol.
+    pub defsyms: Vec<Defsym>,
+    /// Fail on instructions deprecated in the declared VM version (the
+    /// input's when none is declared) rather than replacing them.
+    pub reject_deprecated: bool,
+    /// Symbols whose handling is reported through `tracing` events at
+    /// [`TRACE_SYMBOL_TARGET`], like GNU ld's `--trace-symbol`.
+    pub trace_symbols: Vec<String>,
+    /// Keep the first definition of a symbol defined more than once, and
+    /// warn, instead of failing with [`SbpfLinkerError::MultipleDefinition`].
+    pub allow_multiple_definition: bool,
+    /// Fail with [`SbpfLinkerError::UnexpectedRodata`] if the program would
+    /// embed any read-only data.
+    pub forbid_rodata: bool,
+    /// Lay out constants with the same bytes once, e.g. a string literal
+    /// every object has a copy of, and point references to any of them
+    /// there. Constants holding pointers are kept apart, as are those that
+    /// would lose their alignment. Distinct constants may then share an
+    /// address, which only breaks code comparing their addresses.
+    pub dedup_rodata: bool,
+    /// Symbols to rename in the program, see [`rename::parse_rename_map`].
+    pub renames: Vec<SymbolRename>,
+    /// Instructions run on entry, before the entry function's first
+    /// instruction. Branches back to the entry function skip them.
+    pub prologue: Option<InstructionSequence>,
+    /// Instructions run before every `exit`.
+    pub epilogue: Option<InstructionSequence>,
+    /// Function execution starts at, instead of the start of .text. A
+    /// prologue goes right before it, where the program is entered.
+    pub entry: Option<String>,
+    /// Functions exported in the program's `.dynsym` besides the entry
+    /// point, e.g. for tooling that looks functions up by name.
+    pub exports: Vec<String>,
+    /// Warn with [`DiagnosticKind::UnresolvedExport`] about exports that
+    /// aren't functions in .text, instead of failing with
+    /// [`SbpfLinkerError::UndefinedExport`].
+    pub allow_undefined_exports: bool,
+    /// Fail if the program won't load on this loader, as reported by
+    /// [`loader::validate_for_loader`].
+    pub verify_loader: Option<LoaderVersion>,
+    /// Decode the emitted program again and fail with
+    /// [`SbpfLinkerError::RoundTripMismatch`] unless it holds exactly the
+    /// instructions that were assembled, catching encoding bugs.
+    pub verify_round_trip: bool,
+    /// Skip the checks the loader's verifier makes, which otherwise fail
+    /// the link with [`SbpfLinkerError::VerifierError`].
+    pub skip_verifier: bool,
+    /// Fail with [`SbpfLinkerError::StackFrameOverflow`] if a function
+    /// uses more stack than its frame holds, as estimated by
+    /// [`stack::stack_usage`].
+    pub check_stack: bool,
+    /// Bytes of stack a function may use, at most the
+    /// [`STACK_FRAME_SIZE`](stack::STACK_FRAME_SIZE) of a frame. Checks the
+    /// frames against it as [`check_stack`](Self::check_stack) does.
+    pub stack_size: Option<u64>,
+    /// Bytes of heap the program expects, which transactions request
+    /// between [`loader::MIN_HEAP_SIZE`] and [`loader::MAX_HEAP_SIZE`]. It
+    /// isn't encoded into the program, as no loader reads it from there,
+    /// so above the minimum a [`DiagnosticKind::HeapRequest`] warns that
+    /// transactions have to request it.
+    pub heap_size: Option<u64>,
+    /// Drop the functions and constants that the entry point and the
+    /// exports can't reach, like GNU ld's `--gc-sections`.
+    pub gc_sections: bool,
+    /// Fold functions with identical code into one, see [`Icf`].
+    pub icf: Icf,
+    /// Place input sections as the script says instead of taking .text and
+    /// the data sections as they are, see [`script::parse_linker_script`].
+    pub linker_script: Option<LinkerScript>,
+    /// Symbols by whose order the input sections defining them are laid
+    /// out, ahead of the others in their output section, like lld's
+    /// `--symbol-ordering-file`. Whole sections move, so functions are
+    /// only ordered one by one if each has its own, as with
+    /// `-ffunction-sections`.
+    pub symbol_ordering: Vec<String>,
+    /// Addresses `.text` and `.rodata` are loaded at instead of their
+    /// offsets in the file, like lld's `--section-start`, for loaders and
+    /// tools that expect some other layout. Takes SBPF v1 or later: v0
+    /// loaders map every section at its offset.
+    pub section_starts: Vec<SectionStart>,
+    /// Keep `.rodata` right after `.text` when a
+    /// [`section_starts`](Self::section_starts) entry moves `.text`, rather
+    /// than where it is, in the same segment.
+    pub rodata_after_text: bool,
+    /// Emit the layout loaders that parse SBPF v3 programs strictly expect,
+    /// and fail with [`SbpfLinkerError::StrictElfViolation`] if the program
+    /// doesn't satisfy them, see [`layout::check_strict_layout`]. Takes
+    /// SBPF v3.
+    pub strict_elf: bool,
+    /// Which warnings are emitted, and whether they fail the link.
+    pub warnings: WarningOptions,
+    /// Keep what depends on the machine linking out of the output, for
+    /// builds that must be byte-identical anywhere: source paths in line
+    /// tables are made relative to the current directory, or to `~` outside
+    /// of it. Programs are otherwise reproducible already.
+    pub reproducible: bool,
+}
+
+impl LinkOptions {
+    pub(crate) fn traces(&self, symbol: &str) -> bool {
+        self.trace_symbols.iter().any(|traced| traced == symbol)
+    }
+
+    /// Symbols the program needs whatever refers to them: the entry point,
+    /// the exports and the targets of `--defsym` aliases.
+    pub(crate) fn roots(&self) -> Vec<&str> {
+        std::iter::once(self.entry.as_deref().unwrap_or(DEFAULT_ENTRY))
+            .chain(self.exports.iter().map(String::as_str))
+            .chain(self.defsyms.iter().filter_map(
+                |defsym| match &defsym.value {
+                    defsym::DefsymValue::Symbol(target) => {
+                        Some(target.as_str())
+                    }
+                    defsym::DefsymValue::Constant(_) => None,
+                },
+            ))
+            .collect()
+    }
+}
+
+/// Serializes [`link_with_llvm`]. LLVM keeps process-global state (parsed
+/// command line options, target registration) that concurrent links would
+/// race on.
+#[cfg(feature = "llvm")]
+static LLVM_LOCK: Mutex<()> = Mutex::new(());
+
+/// Relinks an upstream BPF object into an SBPF program.
+///
+/// This is a pure function of its arguments and touches no global state, so
+/// it may be called from any number of threads at once.
 pub fn link_program(source: &[u8]) -> Result<Vec<u8>, SbpfLinkerError> {
-    let parse_result = parse_bytecode(source)?;
-    let program = Program::from_parse_result(parse_result);
-    let bytecode = program.emit_bytecode();
+    link_program_with_options(source, &LinkOptions::default())
+}
+
+/// [`link_program`], also returning the warnings raised while linking.
+/// They're emitted as `tracing` warnings too, but this way callers can
+/// surface them without installing a subscriber.
+pub fn link_program_verbose(
+    source: &[u8],
+) -> Result<(Vec<u8>, Vec<Diagnostic>), SbpfLinkerError> {
+    let mut diagnostics = Vec::new();
+    let bytecode =
+        link(source, &LinkOptions::default(), &mut diagnostics, None)?;
+    Ok((bytecode, diagnostics))
+}
+
+/// [`link_program`] with explicit options. Equally safe to call
+/// concurrently.
+pub fn link_program_with_options(
+    source: &[u8],
+    options: &LinkOptions,
+) -> Result<Vec<u8>, SbpfLinkerError> {
+    link(source, options, &mut Vec::new(), None)
+}
+
+/// A linked program, along with its hash, the warnings raised while
+/// linking it and how long that took.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct LinkOutput {
+    pub program: Vec<u8>,
+    /// [`program_hash`] of the program.
+    pub hash: ProgramHash,
+    pub diagnostics: Vec<Diagnostic>,
+    pub timings: Timings,
+}
+
+/// Relinks several upstream BPF objects into one SBPF program, resolving
+/// the references between them as if they had been compiled together. A
+/// single object is linked as with [`link_program`].
+pub fn link_objects(sources: &[&[u8]]) -> Result<Vec<u8>, SbpfLinkerError> {
+    link_objects_with_options(sources, &LinkOptions::default())
+}
+
+/// [`link_objects`] with explicit options.
+pub fn link_objects_with_options(
+    sources: &[&[u8]],
+    options: &LinkOptions,
+) -> Result<Vec<u8>, SbpfLinkerError> {
+    let mut diagnostics = Vec::new();
+    match sources {
+        [source] => link(source, options, &mut diagnostics, None),
+        _ => {
+            let merged =
+                merge::merge_objects(sources, options, &mut diagnostics)?;
+            link(&merged, options, &mut diagnostics, None)
+        }
+    }
+}
+
+/// [`link_objects_with_options`], also returning the program's hash, the
+/// warnings raised while linking and the [`Timings`] of its stages, e.g.
+/// for CI to check a build against the program deployed.
+pub fn link_objects_verbose(
+    sources: &[&[u8]],
+    options: &LinkOptions,
+) -> Result<LinkOutput, SbpfLinkerError> {
+    let mut diagnostics = Vec::new();
+    let (program, timings) = collect_timings(|| match sources {
+        [source] => link(source, options, &mut diagnostics, None),
+        _ => {
+            let merged =
+                merge::merge_objects(sources, options, &mut diagnostics)?;
+            link(&merged, options, &mut diagnostics, None)
+        }
+    });
+    let program = program?;
+    Ok(LinkOutput {
+        hash: program_hash(&program),
+        program,
+        diagnostics,
+        timings,
+    })
+}
 
+/// [`link_objects_with_options`], also returning the map of the program:
+/// where each function and rodata symbol ended up, and which of `sources`
+/// it came from.
+pub fn link_objects_with_map(
+    sources: &[&[u8]],
+    options: &LinkOptions,
+) -> Result<(Vec<u8>, LinkMap), SbpfLinkerError> {
+    let mut diagnostics = Vec::new();
+    let mut map = LinkMap::default();
+    let mut origins = HashMap::new();
+    let bytecode = match sources {
+        [source] => link(source, options, &mut diagnostics, Some(&mut map))?,
+        _ => {
+            let merged = merge::merge_tracked(
+                sources,
+                options,
+                &mut diagnostics,
+                &mut origins,
+            )?;
+            link(&merged, options, &mut diagnostics, Some(&mut map))?
+        }
+    };
+    for symbol in &mut map.symbols {
+        symbol.input = match sources {
+            [_] => Some(0),
+            _ => origins.get(&symbol.symbol).copied(),
+        };
+    }
+    Ok((bytecode, map))
+}
+
+/// Merges several upstream BPF objects into one relocatable object, the way
+/// [`link_objects`] does before linking, e.g. to inspect the result.
+pub fn merge_objects(
+    sources: &[&[u8]],
+    options: &LinkOptions,
+) -> Result<Vec<u8>, SbpfLinkerError> {
+    merge::merge_objects(sources, options, &mut Vec::new())
+}
+
+/// `source` with its sections placed by [`LinkOptions::linker_script`] and
+/// [`LinkOptions::symbol_ordering`], its `.text.*` sections merged into
+/// .text, and little-endian if it's a big-endian (`bpfeb`) object, `None`
+/// if it needs none of that.
+fn place_sections(
+    source: &[u8],
+    options: &LinkOptions,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Result<Option<Vec<u8>>, SbpfLinkerError> {
+    let obj = object::File::parse(source)?;
+    if options.linker_script.is_none()
+        && options.symbol_ordering.is_empty()
+        && obj.is_little_endian()
+        && !obj.sections().any(|section| {
+            section.name().is_ok_and(|name| name.starts_with(".text."))
+        })
+    {
+        return Ok(None);
+    }
+    // a merge of one object is a relayout of its sections
+    merge::merge_objects(&[source], options, diagnostics).map(Some)
+}
+
+/// `source` without what [`LinkOptions::gc_sections`] drops and with the
+/// functions [`LinkOptions::icf`] folds, `None` if that's nothing.
+fn collect_garbage(
+    source: &[u8],
+    options: &LinkOptions,
+) -> Result<Option<Vec<u8>>, SbpfLinkerError> {
+    if !options.gc_sections && options.icf == Icf::None {
+        return Ok(None);
+    }
+    gc::collect(source, options, options.gc_sections, options.icf)
+}
+
+/// Links `source` into a program, writing its layout to `map` if given.
+fn link(
+    source: &[u8],
+    options: &LinkOptions,
+    diagnostics: &mut Vec<Diagnostic>,
+    map: Option<&mut LinkMap>,
+) -> Result<Vec<u8>, SbpfLinkerError> {
+    loader::check_memory_sizes(options)?;
+    if let Some(size) = options.heap_size
+        && size > loader::MIN_HEAP_SIZE
+    {
+        diagnostic::warn(
+            diagnostics,
+            &options.warnings,
+            DiagnosticKind::HeapRequest,
+            Location::default(),
+            format!(
+                "the program expects a heap of {size} bytes, which transactions calling it must request with `RequestHeapFrame`"
+            ),
+        );
+    }
+    let placed = place_sections(source, options, diagnostics)?;
+    let source = placed.as_deref().unwrap_or(source);
+    // the code is read in the v0 encoding, and emitted for the declared
+    // version or else the one it's built for
+    let built_for = SbpfVersion::of_object(&object::File::parse(source)?);
+    let target = options.require_vm_version.unwrap_or(built_for);
+    if built_for >= SbpfVersion::V2 && target < SbpfVersion::V2 {
+        return Err(SbpfLinkerError::VersionMismatch {
+            required: built_for,
+            declared: target,
+        });
+    }
+    let expanded = export::expand_in(source, options)?;
+    let options = expanded.as_ref().unwrap_or(options);
+    if !options.gc_sections
+        && options.warnings.is_enabled(DiagnosticKind::UnreachableCode)
+    {
+        for function in gc::unreachable_functions(source, options)? {
+            diagnostic::warn(
+                diagnostics,
+                &options.warnings,
+                DiagnosticKind::UnreachableCode,
+                Location::at(".text", function.offset),
+                format!(
+                    "`{}` is unreachable from the entry point and the exports",
+                    function.name
+                ),
+            );
+        }
+    }
+    if options.warnings.is_enabled(DiagnosticKind::FallThrough) {
+        for function in gc::falling_through(source, options)? {
+            diagnostic::warn(
+                diagnostics,
+                &options.warnings,
+                DiagnosticKind::FallThrough,
+                Location::at(".text", function.offset),
+                format!(
+                    "`{}` doesn't end in an exit or a jump, so execution runs past its end",
+                    function.name
+                ),
+            );
+        }
+    }
+    let collected = collect_garbage(source, options)?;
+    let source = collected.as_deref().unwrap_or(source);
+    let mut parse_result =
+        parse_bytecode_with_diagnostics(source, options, diagnostics)?;
+    let limit = options.stack_size.unwrap_or(stack::STACK_FRAME_SIZE);
+    if (options.check_stack || options.stack_size.is_some())
+        && let Some(usage) = stack::stack_usage(source)?
+            .into_iter()
+            .find(|usage| usage.frame_size > limit)
+    {
+        return Err(SbpfLinkerError::StackFrameOverflow {
+            function: usage.name,
+            size: usage.frame_size,
+            limit,
+        });
+    }
+    // the loaders expect a shared object, with .dynamic and .dynsym, even
+    // if nothing needs relocating
+    parse_result.prog_is_static = false;
+    // the headers alone make a well-formed ELF, but one the loader has
+    // nothing to execute in
+    if parse_result.code_section.get_size() == 0 {
+        return Err(SbpfLinkerError::EmptyOutput);
+    }
+
+    if let Some(declared) = options.require_vm_version {
+        let required = SbpfVersion::required_by_nodes(
+            parse_result.code_section.get_nodes(),
+        );
+        if required > declared {
+            return Err(SbpfLinkerError::VersionMismatch {
+                required,
+                declared,
+            });
+        }
+    }
+
+    if options.reject_deprecated {
+        for node in parse_result.code_section.get_nodes() {
+            if let ASTNode::Instruction { instruction, offset } = node
+                && target.deprecates(instruction.opcode)
+            {
+                return Err(SbpfLinkerError::DeprecatedOpcode {
+                    opcode: instruction.opcode,
+                    offset: *offset,
+                });
+            }
+        }
+    }
+
+    let mut violations =
+        options.verify_loader.map_or_else(Vec::new, |loader| {
+            loader::instruction_violations(
+                parse_result.code_section.get_nodes(),
+                loader,
+            )
+        });
+
+    // the assembler exports every symbol at the start of .text
+    let exports = parse_result.dynamic_symbols.get_entry_points();
+
+    if !options.trace_symbols.is_empty() {
+        // mirrors the layout Program::from_parse_result produces: headers,
+        // then .text, then .rodata
+        let ph_count = if parse_result.prog_is_static { 0 } else { 3 };
+        let rodata_start =
+            64 + ph_count * 56 + parse_result.code_section.get_size();
+        for node in parse_result.data_section.get_nodes() {
+            if let ASTNode::ROData { rodata, offset } = node
+                && options.traces(&rodata.name)
+            {
+                tracing::info!(
+                    target: TRACE_SYMBOL_TARGET,
+                    "{}: final address {:#x}",
+                    rodata.name,
+                    rodata_start + offset
+                );
+            }
+        }
+    }
+
+    let expected = options
+        .verify_round_trip
+        .then(|| parse_result.code_section.get_nodes().clone());
+    let rodata =
+        map.is_some().then(|| parse_result.data_section.get_nodes().clone());
+
+    let syscalls = if target >= SbpfVersion::V3 {
+        version::static_syscalls(parse_result.code_section.get_nodes())
+    } else {
+        Vec::new()
+    };
+
+    let emit = timings::stage("emit");
+    let mut program = Program::from_parse_result(parse_result);
+    program.elf_header.e_flags = target.e_flags();
+    let mut bytecode = program.emit_bytecode();
+    elf::fix_section_links(&mut bytecode);
+    // the entry point starts out at .text, which everything else is laid
+    // out from
+    if exports.iter().any(|(_, offset)| *offset > 0) {
+        let entry = options.entry.as_deref().unwrap_or(DEFAULT_ENTRY);
+        elf::place_exports(&mut bytecode, &exports, entry)?;
+    }
+    let strict_starts = if options.strict_elf {
+        if target != SbpfVersion::V3 {
+            return Err(SbpfLinkerError::StrictElfViolation(format!(
+                "strict loaders run SBPF v3 programs, not {target}; link \
+                 with --require-vm-version v3"
+            )));
+        }
+        Some(layout::strict_starts(
+            &bytecode,
+            &options.section_starts,
+            options.rodata_after_text,
+        )?)
+    } else {
+        None
+    };
+    if let Some(starts) = &strict_starts {
+        layout::move_sections(&mut bytecode, starts, false, false)?;
+    } else if let Some(start) = options.section_starts.first() {
+        if target == SbpfVersion::V0 {
+            return Err(SbpfLinkerError::InvalidSectionStart {
+                section: start.section.clone(),
+                detail: "SBPF v0 loaders map sections at their offsets in \
+                         the file, so it takes v1 or later"
+                    .to_owned(),
+            });
+        }
+        layout::move_sections(
+            &mut bytecode,
+            &options.section_starts,
+            options.rodata_after_text,
+            true,
+        )?;
+    }
+
+    drop(emit);
+
+    let verify = timings::stage("verify");
+    if let Some(expected) = expected {
+        verify::round_trip(&bytecode, &expected)?;
+    }
+    if !options.skip_verifier {
+        verifier::verify(&bytecode, target)?;
+    }
+    // checked in the v0 encoding, as it was emitted
+    version::encode(&mut bytecode, built_for, target, &syscalls)?;
+    if options.strict_elf {
+        layout::strict_layout(&mut bytecode)?;
+    }
+
+    if let Some(loader) = options.verify_loader {
+        violations.extend(loader::size_violation(bytecode.len(), loader));
+    }
+    if !violations.is_empty() {
+        return Err(violations.remove(0));
+    }
+    drop(verify);
+
+    if let (Some(map), Some(rodata)) = (map, rodata) {
+        *map = map::link_map(source, &rodata, &bytecode, options)?;
+    }
+
+    tracing::debug!(
+        "linked a program of {} bytes with {} export(s)",
+        bytecode.len(),
+        exports.len()
+    );
+    diagnostic::check(diagnostics, &options.warnings)?;
     Ok(bytecode)
 }
+
+/// Renders the program [`link_program_with_options`] would produce from
+/// `source` as sbpf assembly, with labels, `.rodata` directives and resolved
+/// call targets, e.g. to inspect what the relinker did.
+pub fn link_program_asm(
+    source: &[u8],
+    options: &LinkOptions,
+) -> Result<String, SbpfLinkerError> {
+    let mut diagnostics = Vec::new();
+    let placed = place_sections(source, options, &mut diagnostics)?;
+    let source = placed.as_deref().unwrap_or(source);
+    let collected = collect_garbage(source, options)?;
+    let source = collected.as_deref().unwrap_or(source);
+    let parse_result =
+        parse_bytecode_with_diagnostics(source, options, &mut diagnostics)?;
+    diagnostic::check(&diagnostics, &options.warnings)?;
+    Ok(disasm::render_asm(&parse_result))
+}
+
+/// The program [`link_program_with_options`] would produce from `source`,
+/// as its functions, instructions, rodata and relocations, to build
+/// analyses or other tools on.
+pub fn parse_program(
+    source: &[u8],
+    options: &LinkOptions,
+) -> Result<ParsedProgram, SbpfLinkerError> {
+    let mut diagnostics = Vec::new();
+    let placed = place_sections(source, options, &mut diagnostics)?;
+    let source = placed.as_deref().unwrap_or(source);
+    let collected = collect_garbage(source, options)?;
+    let source = collected.as_deref().unwrap_or(source);
+    let parse_result =
+        parse_bytecode_with_diagnostics(source, options, &mut diagnostics)?;
+    diagnostic::check(&diagnostics, &options.warnings)?;
+    Ok(ParsedProgram::new(&parse_result))
+}
+
+/// Links `linker_options.inputs` with LLVM through `bpf_linker`, then relinks
+/// the resulting object with [`link_program_with_options`].
+///
+/// `bpf_linker` can only emit to a file, so the object makes one round trip
+/// through a temporary file the crate owns; `linker_options.output` is
+/// ignored. Everything after reading it back happens in memory; writing the
+/// program is up to the caller.
+///
+/// Calls are serialized process-wide because LLVM's global state isn't
+/// thread-safe, so concurrent callers wait for each other rather than
+/// corrupting each other.
+#[cfg(feature = "llvm")]
+pub fn link_with_llvm(
+    linker_options: LinkerOptions,
+    fatal_errors: bool,
+    options: &LinkOptions,
+) -> Result<Vec<u8>, SbpfLinkerError> {
+    let object = llvm_object(linker_options, fatal_errors)?;
+    link_program_with_options(&object, options)
+}
+
+/// Links `linker_options.inputs` into an object and returns its bytes, the
+/// first half of [`link_with_llvm`]. Serialized like it, and like it,
+/// `linker_options.output` is ignored: the object goes through a
+/// temporary file under [`env::temp_dir`], removed once read back.
+#[cfg(feature = "llvm")]
+pub fn llvm_object(
+    mut linker_options: LinkerOptions,
+    fatal_errors: bool,
+) -> Result<Vec<u8>, SbpfLinkerError> {
+    let object = TempPath::next_to(&env::temp_dir().join("sbpf-linker.o"));
+    linker_options.output = object.path().to_path_buf();
+    let _guard = LLVM_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    let _stage = timings::stage("llvm");
+
+    let mut linker = Linker::new(linker_options);
+    linker.link()?;
+    if fatal_errors && linker.has_errors() {
+        return Err(SbpfLinkerError::LlvmDiagnosticError);
+    }
+
+    Ok(fs::read(object.path())?)
+}
+
+/// Reads the minimum VM version declared in a linked program's `e_flags`.
+pub fn program_vm_version(
+    program: &[u8],
+) -> Result<SbpfVersion, SbpfLinkerError> {
+    let e_flags = match object::File::parse(program)?.flags() {
+        FileFlags::Elf { e_flags, .. } => e_flags,
+        _ => 0,
+    };
+    SbpfVersion::from_e_flags(e_flags)
+        .ok_or(SbpfLinkerError::UnknownSbpfVersion(e_flags))
+}
+
+/// Lowest SBPF version whose instruction set covers every instruction in the
+/// object `source`, i.e. the oldest runtime that can execute the program.
+pub fn minimum_sbpf_version(
+    source: &[u8],
+) -> Result<SbpfVersion, SbpfLinkerError> {
+    let parse_result = parse_bytecode(source)?;
+    Ok(SbpfVersion::required_by_nodes(parse_result.code_section.get_nodes()))
+}
+
+/// The hash of a linked program, see [`program_hash`]. Displays as hex.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ProgramHash(pub [u8; 32]);
+
+impl fmt::Display for ProgramHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
+
+/// The hash of `program` that `solana-verify get-executable-hash` prints,
+/// and so what verified build tooling compares against the program
+/// deployed: its SHA-256 without any trailing zero bytes. Those don't
+/// count, as the account holding a deployed program pads it with zeros.
+pub fn program_hash(program: &[u8]) -> ProgramHash {
+    let end = program.iter().rposition(|&byte| byte != 0).map_or(0, |i| i + 1);
+    ProgramHash(Sha256::digest(&program[..end]).into())
+}
+
+/// Links `source` and hashes the result with SHA-256 over a canonical view
+/// of the program: its entrypoint, `e_flags`, and every section's name,
+/// address and contents in name order. Header and section table placement
+/// don't contribute, so logically identical programs get identical
+/// fingerprints even if their files are laid out differently.
+pub fn program_fingerprint(
+    source: &[u8],
+) -> Result<[u8; 32], SbpfLinkerError> {
+    fingerprint(&link_program(source)?)
+}
+
+/// The [`program_fingerprint`] of the linked program `program`.
+fn fingerprint(program: &[u8]) -> Result<[u8; 32], SbpfLinkerError> {
+    let obj = ElfFile64::<LittleEndian>::parse(program)?;
+    let shstrndx = obj.elf_header().shstrndx(obj.endian(), obj.data())?;
+
+    let mut hasher = Sha256::new();
+    hasher.update(obj.entry().to_le_bytes());
+    if let FileFlags::Elf { e_flags, .. } = obj.flags() {
+        hasher.update(e_flags.to_le_bytes());
+    }
+
+    let mut sections = obj
+        .sections()
+        // the section name table only reflects the order of the section
+        // headers
+        .filter(|section| section.index().0 != shstrndx as usize)
+        .map(|section| Ok((section.name()?, section)))
+        .collect::<Result<Vec<_>, SbpfLinkerError>>()?;
+    sections.retain(|(name, _)| !name.is_empty());
+    sections.sort_by_key(|(name, _)| *name);
+
+    for (name, section) in sections {
+        let data = section.data()?;
+        hasher.update((name.len() as u64).to_le_bytes());
+        hasher.update(name);
+        hasher.update(section.address().to_le_bytes());
+        hasher.update((data.len() as u64).to_le_bytes());
+        hasher.update(data);
+    }
+
+    Ok(hasher.finalize().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::thread;
+
+    use object::elf::{
+        R_BPF_64_32, R_BPF_64_64, SHF_ALLOC, SHF_MERGE, SHF_STRINGS,
+    };
+    use object::read::elf::SectionHeader as _;
+    use object::{SectionIndex, SectionKind};
+
+    use super::*;
+    use crate::testing::{EXIT, TestObject, call, lddw, mov64, program};
+
+    #[test]
+    fn link_program_concurrently() {
+        fn send_sync<T: Send + Sync>() {}
+        send_sync::<LinkOptions>();
+        send_sync::<LinkOutput>();
+
+        let source = program(&[mov64(0, 0), EXIT].concat());
+        let expected = link_program(&source).unwrap();
+        thread::scope(|scope| {
+            let links: Vec<_> = (0..32)
+                .map(|_| scope.spawn(|| link_program(&source)))
+                .collect();
+            for link in links {
+                assert_eq!(link.join().unwrap().unwrap(), expected);
+            }
+        });
+    }
+
+    #[test]
+    fn prologue_runs_on_entry() {
+        // a helper, then the entry function
+        let mut object = TestObject::new();
+        let text = [mov64(0, 1), EXIT, mov64(0, 0), EXIT].concat();
+        let text_id = object.section(".text", SectionKind::Text, &text);
+        object.symbol("helper", text_id, 0, 16);
+        object.symbol("entrypoint", text_id, 16, 16);
+        let options = LinkOptions {
+            prologue: Some("mov64 r6, 7".parse().unwrap()),
+            ..LinkOptions::default()
+        };
+
+        let program =
+            link_program_with_options(&object.write(), &options).unwrap();
+        let obj = object::File::parse(&*program).unwrap();
+        let text = obj.section_by_name(".text").unwrap();
+        let at = (obj.entry() - text.address()) as usize;
+        assert_eq!(
+            text.data().unwrap()[at..at + 16],
+            [mov64(6, 7), mov64(0, 0)].concat()
+        );
+    }
+
+    #[test]
+    fn deprecated_instructions_are_rewritten_unless_rejected() {
+        let source = program(&[&lddw(0, 42)[..], &EXIT].concat());
+        let mut options = LinkOptions {
+            require_vm_version: Some(SbpfVersion::V2),
+            ..LinkOptions::default()
+        };
+        link_program_with_options(&source, &options).unwrap();
+
+        options.reject_deprecated = true;
+        let error = link_program_with_options(&source, &options).unwrap_err();
+        assert!(
+            matches!(error, SbpfLinkerError::DeprecatedOpcode { .. }),
+            "{error:?}"
+        );
+    }
+
+    #[test]
+    fn program_can_end_in_a_call() {
+        let mut object = TestObject::new();
+        let text = [mov64(0, 0), call()].concat();
+        let text_id = object.section(".text", SectionKind::Text, &text);
+        object.symbol("entrypoint", text_id, 0, 16);
+        let abort = object.undefined("abort");
+        object.relocation(text_id, 8, abort, R_BPF_64_32);
+        link_program(&object.write()).unwrap();
+    }
+
+    #[test]
+    fn frame_pointer_moves_from_v1() {
+        // add64 r10, -64
+        let add = [0x07, 10, 0, 0, 0xc0, 0xff, 0xff, 0xff];
+        let source = program(&[add, mov64(0, 0), EXIT].concat());
+        let mut options = LinkOptions::default();
+        let error = link_program_with_options(&source, &options).unwrap_err();
+        assert!(
+            matches!(error, SbpfLinkerError::VerifierError { offset: 0, .. }),
+            "{error:?}"
+        );
+
+        options.require_vm_version = Some(SbpfVersion::V1);
+        link_program_with_options(&source, &options).unwrap();
+
+        let options =
+            LinkOptions { skip_verifier: true, ..LinkOptions::default() };
+        link_program_with_options(&source, &options).unwrap();
+    }
+
+    #[test]
+    fn heap_above_default_warns() {
+        let source = program(&[mov64(0, 0), EXIT].concat());
+        let kinds = |heap_size, warnings: &WarningOptions| {
+            let options = LinkOptions {
+                heap_size: Some(heap_size),
+                warnings: warnings.clone(),
+                ..LinkOptions::default()
+            };
+            let linked = link_objects_verbose(&[&source], &options).unwrap();
+            linked.diagnostics.iter().map(|d| d.kind).collect::<Vec<_>>()
+        };
+        let mut warnings = WarningOptions::default();
+        assert_eq!(kinds(loader::MIN_HEAP_SIZE, &warnings), []);
+        assert_eq!(kinds(64 * 1024, &warnings), [DiagnosticKind::HeapRequest]);
+        warnings.apply("no-heap-request").unwrap();
+        assert_eq!(kinds(64 * 1024, &warnings), []);
+    }
+
+    #[test]
+    fn emitted_asm_assembles_back() {
+        // loads of `msg` 2 bytes in, of an anonymous string, and of a
+        // function only its section's symbol points to
+        let mut object = TestObject::new();
+        let text = [
+            &lddw(1, 2)[..],
+            &lddw(2, 0),
+            &lddw(3, 56),
+            &EXIT,
+            &mov64(0, 0),
+            &EXIT,
+        ]
+        .concat();
+        let text_id = object.section(".text", SectionKind::Text, &text);
+        object.symbol("entrypoint", text_id, 0, 56);
+        let rodata_id =
+            object.section(".rodata", SectionKind::ReadOnlyData, b"msg!");
+        let msg = object.symbol("msg", rodata_id, 0, 4);
+        let strings_id = object.section(
+            ".rodata.str1.1",
+            SectionKind::ReadOnlyString,
+            b"hi\0",
+        );
+        object
+            .flags(strings_id, u64::from(SHF_ALLOC | SHF_MERGE | SHF_STRINGS));
+        let strings = object.section_symbol(strings_id);
+        let functions = object.section_symbol(text_id);
+        object.relocation(text_id, 0, msg, R_BPF_64_64);
+        object.relocation(text_id, 16, strings, R_BPF_64_64);
+        object.relocation(text_id, 32, functions, R_BPF_64_64);
+        let source = object.write();
+        let options = LinkOptions::default();
+
+        let asm = link_program_asm(&source, &options).unwrap();
+        assert!(asm.contains("lddw r1, msg+2"), "{asm}");
+        let reassembled =
+            assembly::assemble_object(Path::new("out.s"), asm.as_bytes())
+                .unwrap();
+        assert_eq!(
+            link_program_with_options(&reassembled, &options).unwrap(),
+            link_program_with_options(&source, &options).unwrap()
+        );
+    }
+
+    #[test]
+    fn fingerprint_skips_section_name_table() {
+        let source = program(&[mov64(0, 0), EXIT].concat());
+        let mut linked = link_program(&source).unwrap();
+        let expected = fingerprint(&linked).unwrap();
+
+        // rename the table itself, a name only the table holds
+        let obj = ElfFile64::<LittleEndian>::parse(&*linked).unwrap();
+        let endian = obj.endian();
+        let shstrndx = obj.elf_header().shstrndx(endian, obj.data()).unwrap();
+        let table =
+            obj.section_by_index(SectionIndex(shstrndx as usize)).unwrap();
+        let (offset, _) = table.file_range().unwrap();
+        let at =
+            offset + u64::from(table.elf_section_header().sh_name(endian));
+        linked[at as usize] = b'_';
+
+        assert_eq!(fingerprint(&linked).unwrap(), expected);
+    }
+
+    #[test]
+    fn empty_text_fails() {
+        let error = link_program(&program(&[])).unwrap_err();
+        assert!(matches!(error, SbpfLinkerError::EmptyOutput), "{error:?}");
+    }
+}
@@ -0,0 +1,344 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, hash_map::Entry},
+    env,
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+use gimli::{ColumnType, DwarfSections, EndianSlice, LittleEndian};
+use object::{
+    File, Object as _, ObjectSection as _, ObjectSymbol as _, RelocationFlags,
+    RelocationTarget, SectionIndex, SectionKind, SymbolKind,
+};
+use sbpf_common::opcode::Opcode;
+
+use crate::{
+    LinkOptions, SbpfLinkerError,
+    analysis::decode,
+    byteparser::{R_BPF_64_ABS64, section_data},
+    map::{LinkMap, MapSymbolKind},
+};
+
+/// An instruction of a linked program, along with the source line it was
+/// compiled from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct LineRow {
+    /// Virtual address in the program.
+    pub address: u64,
+    pub file: String,
+    pub line: u64,
+    /// Column in the line, 0 if the compiler didn't say.
+    pub column: u64,
+}
+
+/// Where each instruction of a linked program came from in the source, as
+/// the DWARF line tables of its inputs say.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct LineMap {
+    /// In address order, one per change of source location.
+    pub rows: Vec<LineRow>,
+}
+
+impl LineMap {
+    /// Renders the map as one `address file:line[:column]` line per row,
+    /// the way `addr2line` prints locations.
+    pub fn render(&self) -> String {
+        let mut lines = String::new();
+        for row in &self.rows {
+            let _ = write!(
+                lines,
+                "{:#018x} {}:{}",
+                row.address, row.file, row.line
+            );
+            if row.column > 0 {
+                let _ = write!(lines, ":{}", row.column);
+            }
+            lines.push('\n');
+        }
+        lines
+    }
+}
+
+/// Bit from which the address a line table row is read at names the input
+/// section it's in, see [`line_rows`].
+const SECTION_SHIFT: u32 = 32;
+
+/// The line map of a program linked from `sources` with `options`, whose
+/// layout is `map`. Rows are moved to the address their function ended up
+/// at, past the epilogues spliced in before it within the function. Rows of
+/// functions that didn't make it into the program, e.g. dropped by
+/// [`LinkOptions::gc_sections`], are left out.
+pub fn line_map(
+    sources: &[&[u8]],
+    map: &LinkMap,
+    options: &LinkOptions,
+) -> Result<LineMap, SbpfLinkerError> {
+    let (cwd, home) = if options.reproducible {
+        (env::current_dir().ok(), env::var_os("HOME").map(PathBuf::from))
+    } else {
+        (None, None)
+    };
+    let mut rows = Vec::new();
+    for (input, source) in sources.iter().enumerate() {
+        let obj = File::parse(*source)?;
+        let mut addresses = CodeAddresses::new(&obj, input, map, options);
+        for (section, offset, location) in line_rows(&obj)? {
+            let Some(address) = addresses.address(section, offset)? else {
+                continue;
+            };
+            let mut file = location.file;
+            if options.reproducible {
+                file =
+                    reproducible_path(&file, cwd.as_deref(), home.as_deref());
+            }
+            rows.push(LineRow { address, file, ..location });
+        }
+    }
+    rows.sort_by_key(|row| row.address);
+    // consecutive rows for the same location say nothing new
+    rows.dedup_by(|row, previous| {
+        (&row.file, row.line, row.column)
+            == (&previous.file, previous.line, previous.column)
+    });
+    Ok(LineMap { rows })
+}
+
+/// Where the code of an input ended up in a program linked from it, found
+/// by the function each instruction is in: moved to the address the
+/// function ended up at, past the epilogues spliced in before it within the
+/// function.
+pub(crate) struct CodeAddresses<'obj, 'data> {
+    obj: &'obj File<'data>,
+    input: usize,
+    map: &'obj LinkMap,
+    epilogue: u64,
+    /// The functions of each section, by start.
+    functions: HashMap<SectionIndex, Vec<(u64, &'data str)>>,
+    /// The exits of each section, read once needed.
+    exits: HashMap<SectionIndex, Vec<u64>>,
+}
+
+impl<'obj, 'data> CodeAddresses<'obj, 'data> {
+    /// The addresses of the code of `obj`, the input `input` of a program
+    /// linked with `options`, whose layout is `map`.
+    pub(crate) fn new(
+        obj: &'obj File<'data>,
+        input: usize,
+        map: &'obj LinkMap,
+        options: &LinkOptions,
+    ) -> Self {
+        let mut functions: HashMap<SectionIndex, Vec<(u64, &str)>> =
+            HashMap::new();
+        for symbol in obj.symbols() {
+            if let (Some(section), Ok(name)) =
+                (symbol.section_index(), symbol.name())
+                && !name.is_empty()
+                && !matches!(
+                    symbol.kind(),
+                    SymbolKind::Section | SymbolKind::File
+                )
+            {
+                functions
+                    .entry(section)
+                    .or_default()
+                    .push((symbol.address(), name));
+            }
+        }
+        functions.values_mut().for_each(|starts| starts.sort_unstable());
+        Self {
+            obj,
+            input,
+            map,
+            epilogue: options.epilogue.as_ref().map_or(0, |e| e.size()),
+            functions,
+            exits: HashMap::new(),
+        }
+    }
+
+    /// The address in the program of the instruction at `offset` in the
+    /// section `section` of the input. `None` if its function didn't make
+    /// it into the program, e.g. dropped by [`LinkOptions::gc_sections`].
+    pub(crate) fn address(
+        &mut self,
+        section: SectionIndex,
+        offset: u64,
+    ) -> Result<Option<u64>, SbpfLinkerError> {
+        let Some((start, name)) = self
+            .functions
+            .get(&section)
+            .and_then(|starts| {
+                starts[..starts.partition_point(|(start, _)| *start <= offset)]
+                    .last()
+            })
+            .copied()
+        else {
+            return Ok(None);
+        };
+        let Some(function) = self.map.symbols.iter().find(|symbol| {
+            symbol.kind == MapSymbolKind::Function
+                && symbol.symbol == name
+                && symbol.input.is_none_or(|from| from == self.input)
+        }) else {
+            return Ok(None);
+        };
+        let spliced = if self.epilogue > 0 {
+            let exits = match self.exits.entry(section) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    entry.insert(exit_offsets(self.obj, section)?)
+                }
+            };
+            let before =
+                |at: u64| exits.partition_point(|&exit| exit < at) as u64;
+            (before(offset) - before(start)) * self.epilogue
+        } else {
+            0
+        };
+        Ok(Some(function.address + (offset - start) + spliced))
+    }
+}
+
+/// `path` as [`LinkOptions::reproducible`] has it: relative to `cwd` if
+/// it's in there, else starting with `~` if it's in `home`.
+fn reproducible_path(
+    path: &str,
+    cwd: Option<&Path>,
+    home: Option<&Path>,
+) -> String {
+    let path = Path::new(path);
+    if let Some(relative) = cwd.and_then(|cwd| path.strip_prefix(cwd).ok()) {
+        return relative.display().to_string();
+    }
+    if let Some(relative) = home.and_then(|home| path.strip_prefix(home).ok())
+    {
+        return Path::new("~").join(relative).display().to_string();
+    }
+    path.display().to_string()
+}
+
+/// Offsets of the `exit` instructions in the section `section` of `obj`.
+fn exit_offsets(
+    obj: &File<'_>,
+    section: SectionIndex,
+) -> Result<Vec<u64>, SbpfLinkerError> {
+    let data = section_data(&obj.section_by_index(section)?)?;
+    Ok(decode(&data)
+        .iter()
+        .filter(|instruction| instruction.is(Opcode::Exit))
+        .map(|instruction| instruction.offset)
+        .collect())
+}
+
+/// The rows of the line tables of `obj`, by the code section and offset
+/// they're about. Addresses in the line programs of a relocatable object
+/// are relocated against the section they point into, so each such
+/// relocation is resolved to the offset in its section, with the section's
+/// index above [`SECTION_SHIFT`].
+fn line_rows(
+    obj: &File<'_>,
+) -> Result<Vec<(SectionIndex, u64, LineRow)>, SbpfLinkerError> {
+    let invalid = |error: gimli::Error| {
+        SbpfLinkerError::InvalidDebugInfo(error.to_string())
+    };
+    let Some(debug_line) = obj.section_by_name(".debug_line") else {
+        return Ok(Vec::new());
+    };
+    let mut line_data = section_data(&debug_line)?.into_owned();
+    for (offset, relocation) in debug_line.relocations() {
+        let (RelocationTarget::Symbol(index), RelocationFlags::Elf { r_type }) =
+            (relocation.target(), relocation.flags())
+        else {
+            continue;
+        };
+        // `DW_LNE_set_address` operands, which are 8 bytes on BPF
+        if r_type != R_BPF_64_ABS64 {
+            continue;
+        }
+        let symbol = obj.symbol_by_index(index)?;
+        let Some(section) = symbol.section_index() else { continue };
+        if obj.section_by_index(section)?.kind() != SectionKind::Text {
+            continue;
+        }
+        let Some(bytes) =
+            line_data.get_mut(offset as usize..offset as usize + 8)
+        else {
+            continue;
+        };
+        let addend = u64::from_le_bytes(bytes.try_into().unwrap_or_default());
+        let target = (section.0 as u64) << SECTION_SHIFT
+            | symbol.address().wrapping_add(addend);
+        bytes.copy_from_slice(&target.to_le_bytes());
+    }
+
+    let sections =
+        DwarfSections::load(|id| -> Result<Cow<'_, [u8]>, SbpfLinkerError> {
+            if id == gimli::SectionId::DebugLine {
+                return Ok(Cow::Borrowed(line_data.as_slice()));
+            }
+            match obj.section_by_name(id.name()) {
+                Some(section) => section_data(&section),
+                None => Ok(Cow::Borrowed(&[])),
+            }
+        })?;
+    let dwarf =
+        sections.borrow(|section| EndianSlice::new(section, LittleEndian));
+
+    // a single text section is where addresses without a section point,
+    // e.g. in a linked object
+    let text = obj.section_by_name(".text").map(|text| text.index());
+    let mut rows = Vec::new();
+    let mut units = dwarf.units();
+    while let Some(header) = units.next().map_err(invalid)? {
+        let unit = dwarf.unit(header).map_err(invalid)?;
+        let Some(program) = unit.line_program.clone() else { continue };
+        let mut program_rows = program.rows();
+        while let Some((header, row)) =
+            program_rows.next_row().map_err(invalid)?
+        {
+            if row.end_sequence() {
+                continue;
+            }
+            let Some(line) = row.line() else { continue };
+            let address = row.address();
+            let section = match address >> SECTION_SHIFT {
+                0 => match text {
+                    Some(text) => text,
+                    None => continue,
+                },
+                index => SectionIndex(index as usize),
+            };
+            let mut file = String::new();
+            if let Some(entry) = row.file(header) {
+                if let Some(directory) = entry.directory(header) {
+                    let directory = dwarf
+                        .attr_string(&unit, directory)
+                        .map_err(invalid)?;
+                    file.push_str(&directory.to_string_lossy());
+                }
+                let name = dwarf
+                    .attr_string(&unit, entry.path_name())
+                    .map_err(invalid)?;
+                let name = name.to_string_lossy();
+                if name.starts_with('/') || file.is_empty() {
+                    file = name.into_owned();
+                } else {
+                    file.push('/');
+                    file.push_str(&name);
+                }
+            }
+            let column = match row.column() {
+                ColumnType::LeftEdge => 0,
+                ColumnType::Column(column) => column.get(),
+            };
+            rows.push((
+                section,
+                address & ((1 << SECTION_SHIFT) - 1),
+                LineRow { address: 0, file, line: line.get(), column },
+            ));
+        }
+    }
+    Ok(rows)
+}
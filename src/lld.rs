@@ -0,0 +1,326 @@
+use std::{fs, path::Path};
+
+use crate::SbpfLinkerError;
+
+/// A command line of `ld.lld` rewritten into one of `sbpf-linker`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LldArgs {
+    pub args: Vec<String>,
+    /// Options dropped because they mean nothing for an SBPF program, e.g.
+    /// `-z relro` or `--as-needed`.
+    pub ignored: Vec<String>,
+}
+
+/// What becomes of an `ld.lld` option.
+#[derive(Clone, Copy)]
+enum Mapping {
+    /// Passed on as this `sbpf-linker` option, with its value if it takes
+    /// one.
+    To(&'static str),
+    Ignore,
+    Library,
+    VersionScript,
+    Keyword,
+}
+
+/// Options without a value, by their names without dashes.
+const FLAGS: &[(&[&str], Mapping)] = &[
+    (&["gc-sections"], Mapping::To("--gc-sections")),
+    (&["s", "strip-all"], Mapping::To("--strip-all")),
+    (&["S", "strip-debug"], Mapping::To("--strip-debug")),
+    (
+        &["allow-multiple-definition"],
+        Mapping::To("--allow-multiple-definition"),
+    ),
+    (&["fatal-warnings"], Mapping::To("-Werror")),
+    (&["undefined-version"], Mapping::To("--allow-undefined-exports")),
+    (&["no-fatal-warnings"], Mapping::To("-Wno-error")),
+    (&["v", "version"], Mapping::To("--version")),
+    (&["help"], Mapping::To("--help")),
+    (
+        &[
+            "shared",
+            "pie",
+            "no-pie",
+            "static",
+            "Bstatic",
+            "Bdynamic",
+            "Bsymbolic",
+            "Bsymbolic-functions",
+            "as-needed",
+            "no-as-needed",
+            "whole-archive",
+            "no-whole-archive",
+            "start-group",
+            "end-group",
+            "eh-frame-hdr",
+            "no-eh-frame-hdr",
+            "nostdlib",
+            "nostartfiles",
+            "nodefaultlibs",
+            "no-undefined",
+            "no-undefined-version",
+            "build-id",
+            "no-dynamic-linker",
+            "x",
+            "discard-all",
+            "X",
+            "discard-locals",
+            "E",
+            "export-dynamic",
+            "no-gc-sections",
+            "print-gc-sections",
+            "relax",
+            "no-relax",
+            "demangle",
+            "no-demangle",
+            "color-diagnostics",
+            "no-color-diagnostics",
+            "no-rosegment",
+            "allow-shlib-undefined",
+            "no-allow-shlib-undefined",
+            "warn-common",
+            "no-warn-mismatch",
+            "lto-O0",
+            "lto-O1",
+            "lto-O2",
+            "lto-O3",
+        ],
+        Mapping::Ignore,
+    ),
+];
+
+/// Options with a value, by their names without dashes.
+const VALUED: &[(&[&str], Mapping)] = &[
+    (&["o", "output"], Mapping::To("-o")),
+    (&["L", "library-path"], Mapping::To("-L")),
+    (&["e", "entry"], Mapping::To("--entry")),
+    (&["T", "script"], Mapping::To("-T")),
+    (&["defsym"], Mapping::To("--defsym")),
+    (&["y", "trace-symbol"], Mapping::To("--trace-symbol")),
+    (&["export-dynamic-symbol"], Mapping::To("--export")),
+    (&["O"], Mapping::To("-O")),
+    (&["icf"], Mapping::To("--icf")),
+    (&["symbol-ordering-file"], Mapping::To("--symbol-ordering-file")),
+    (&["section-start"], Mapping::To("--section-start")),
+    (&["flavor"], Mapping::To("--flavor")),
+    (&["l", "library"], Mapping::Library),
+    (&["version-script"], Mapping::VersionScript),
+    (&["z"], Mapping::Keyword),
+    (
+        &[
+            "soname",
+            "h",
+            "m",
+            "rpath",
+            "R",
+            "hash-style",
+            "threads",
+            "pack-dyn-relocs",
+            "sysroot",
+            "plugin",
+            "plugin-opt",
+            "sort-section",
+            "error-limit",
+            "Map",
+            "dynamic-linker",
+        ],
+        Mapping::Ignore,
+    ),
+];
+
+/// Whether `args`, starting with the program's name, are an `ld.lld`
+/// command line: they pick a linker flavor, as rustc does when it runs the
+/// linker, or the program was invoked as `ld.lld`.
+pub fn is_lld_invocation(args: &[String]) -> bool {
+    let invoked_as = args
+        .first()
+        .and_then(|program| Path::new(program).file_name())
+        .is_some_and(|name| name == "ld.lld");
+    invoked_as
+        || args
+            .iter()
+            .any(|arg| matches!(arg.as_str(), "-flavor" | "--flavor"))
+}
+
+/// Rewrites the `ld.lld` command line `args`, starting with the program's
+/// name, into one `sbpf-linker` parses. Options with an `sbpf-linker`
+/// equivalent are mapped to it, `-l` libraries are looked up in the `-L`
+/// directories and the globals of a `--version-script` are exported, as lld
+/// does. Response files must have been expanded, see
+/// [`expand_response_files`](crate::response::expand_response_files).
+/// Options that don't apply
+/// to an SBPF program are dropped; any other argument is kept as is, so
+/// unknown options are still errors.
+pub fn translate_args(args: &[String]) -> Result<LldArgs, SbpfLinkerError> {
+    let invalid = SbpfLinkerError::InvalidLldArgument;
+    let mut translated = LldArgs::default();
+    let mut args = args.iter().cloned();
+    translated.args.extend(args.next());
+    let mut search_dirs = Vec::new();
+    let mut libraries = Vec::new();
+
+    while let Some(arg) = args.next() {
+        let Some((mapping, value)) = option(&arg) else {
+            translated.args.push(arg);
+            continue;
+        };
+        let value =
+            match value {
+                Some(Value::Inline(value)) => Some(value),
+                Some(Value::Next) => Some(args.next().ok_or_else(|| {
+                    invalid(format!("`{arg}` expects a value"))
+                })?),
+                None => None,
+            };
+        match (mapping, value) {
+            (Mapping::To(to), value) => {
+                if to == "-L" {
+                    search_dirs.extend(value.clone());
+                }
+                translated.args.push(to.to_owned());
+                translated.args.extend(value);
+            }
+            (Mapping::Ignore, value) => {
+                translated.ignored.push(match value {
+                    Some(value) => format!("{arg} {value}"),
+                    None => arg,
+                });
+            }
+            (Mapping::Library, Some(name)) => libraries.push(name),
+            (Mapping::VersionScript, Some(path)) => {
+                let script = fs::read_to_string(&path).map_err(|e| {
+                    invalid(format!("can't read `{path}`: {e}"))
+                })?;
+                let globals = parse_version_script(&script);
+                if !globals.is_empty() {
+                    translated.args.push("--export".to_owned());
+                    translated.args.push(globals.join(","));
+                }
+            }
+            (Mapping::Keyword, Some(keyword)) => {
+                if keyword == "muldefs" {
+                    translated
+                        .args
+                        .push("--allow-multiple-definition".to_owned());
+                } else {
+                    translated.ignored.push(format!("-z {keyword}"));
+                }
+            }
+            (_, None) => {}
+        }
+    }
+
+    // like lld, `-l` searches every `-L` directory, wherever it's given
+    for name in libraries {
+        let file = match name.strip_prefix(':') {
+            Some(file) => file.to_owned(),
+            None => format!("lib{name}.a"),
+        };
+        let path = search_dirs
+            .iter()
+            .map(|dir| Path::new(dir).join(&file))
+            .find(|path| path.is_file())
+            .ok_or_else(|| {
+                invalid(format!("unable to find library -l{name}"))
+            })?;
+        translated.args.push(path.display().to_string());
+    }
+    Ok(translated)
+}
+
+/// Where an option's value is.
+enum Value {
+    Inline(String),
+    Next,
+}
+
+/// The mapping of the option `arg`, if it's one of lld's, and where its
+/// value is if it takes one. Like lld, long options take one dash or two
+/// and their value after `=` or as the next argument, and single letter
+/// ones may have their value attached, e.g. `-Lpath`.
+fn option(arg: &str) -> Option<(Mapping, Option<Value>)> {
+    let name = arg.strip_prefix("--").or_else(|| arg.strip_prefix('-'))?;
+    let find = |table: &[(&[&str], Mapping)], name: &str| {
+        table
+            .iter()
+            .find(|(names, _)| names.contains(&name))
+            .map(|(_, mapping)| *mapping)
+    };
+    let (name, inline) = match name.split_once('=') {
+        Some((name, value)) => (name, Some(value)),
+        None => (name, None),
+    };
+    if let Some(mapping) = find(VALUED, name) {
+        let value = match inline {
+            Some(value) => Value::Inline(value.to_owned()),
+            None => Value::Next,
+        };
+        return Some((mapping, Some(value)));
+    }
+    if let Some(mapping) = find(FLAGS, name) {
+        // e.g. `--build-id=sha1`, whose value doesn't matter here
+        return Some((mapping, None));
+    }
+    // a single letter option with its value attached, e.g. `-lfoo`
+    if !arg.starts_with("--") {
+        let split = name.chars().next()?.len_utf8();
+        let (letter, value) = arg[1..].split_at(split);
+        if let Some(mapping) = find(VALUED, letter) {
+            return Some((mapping, Some(Value::Inline(value.to_owned()))));
+        }
+    }
+    None
+}
+
+/// The global symbols a GNU version script lists, e.g. those of
+/// `{ global: entrypoint; local: *; };`, which is how rustc tells the
+/// linker what a `cdylib` exports. Patterns are left out.
+pub fn parse_version_script(script: &str) -> Vec<String> {
+    // without comments
+    let mut text = String::new();
+    let mut rest = script;
+    while let Some(start) = rest.find("/*") {
+        text.push_str(&rest[..start]);
+        rest = rest[start..]
+            .find("*/")
+            .map_or("", |end| &rest[start + end + 2..]);
+    }
+    text.push_str(rest);
+    let text: String = text
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let text = text.replace('}', " } ");
+    let mut globals = Vec::new();
+    let mut in_global = false;
+    for token in text
+        .split(|c: char| c == ';' || c == '{' || c.is_whitespace())
+        .filter(|token| !token.is_empty())
+    {
+        // the end of a version node
+        if token == "}" {
+            in_global = false;
+            continue;
+        }
+        let (label, symbol) = match token.split_once(':') {
+            Some((label, symbol)) => (Some(label), symbol),
+            None => (None, token),
+        };
+        match label {
+            Some("global") => in_global = true,
+            Some(_) => in_global = false,
+            None => {}
+        }
+        if in_global
+            && !symbol.is_empty()
+            && !symbol.contains(['*', '?', '[', '"'])
+            && symbol != "extern"
+        {
+            globals.push(symbol.to_owned());
+        }
+    }
+    globals
+}
@@ -0,0 +1,192 @@
+use std::{fmt, str::FromStr};
+
+use sbpf_assembler::astnode::ASTNode;
+
+use crate::{
+    LinkOptions, SbpfLinkerError, byteparser::parse_bytecode, link_program,
+    stack::STACK_FRAME_SIZE, version::SbpfVersion,
+};
+
+/// Largest program account the loaders accept, the runtime's
+/// `MAX_PERMITTED_DATA_LENGTH`.
+pub const MAX_PROGRAM_SIZE: usize = 10 * 1024 * 1024;
+
+/// Heap every transaction gets unless it requests more, the runtime's
+/// `MIN_HEAP_FRAME_BYTES`.
+pub const MIN_HEAP_SIZE: u64 = 32 * 1024;
+/// Largest heap a transaction can request, `MAX_HEAP_FRAME_BYTES`.
+pub const MAX_HEAP_SIZE: u64 = 256 * 1024;
+/// Heap sizes are requested in multiples of this.
+pub const HEAP_SIZE_GRANULARITY: u64 = 1024;
+
+/// Solana's on-chain program loaders.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LoaderVersion {
+    /// The original, deprecated `BPFLoader1111111111111111111111111111111111`.
+    V1,
+    /// `BPFLoader2111111111111111111111111111111111`.
+    V2,
+    /// The upgradeable loader.
+    V3,
+    V4,
+}
+
+impl LoaderVersion {
+    pub const ALL: [LoaderVersion; 4] =
+        [Self::V1, Self::V2, Self::V3, Self::V4];
+
+    /// Newest SBPF version the loader executes. The legacy loaders predate
+    /// SBPF versioning and only run v0 programs.
+    pub fn max_sbpf_version(self) -> SbpfVersion {
+        match self {
+            Self::V1 | Self::V2 => SbpfVersion::V0,
+            Self::V3 | Self::V4 => SbpfVersion::V3,
+        }
+    }
+}
+
+impl fmt::Display for LoaderVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = match self {
+            Self::V1 => 1,
+            Self::V2 => 2,
+            Self::V3 => 3,
+            Self::V4 => 4,
+        };
+        write!(f, "loader-v{n}")
+    }
+}
+
+impl FromStr for LoaderVersion {
+    type Err = SbpfLinkerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let n = s.strip_prefix("loader-").unwrap_or(s);
+        Ok(match n.strip_prefix('v').unwrap_or(n) {
+            "1" => Self::V1,
+            "2" => Self::V2,
+            "3" => Self::V3,
+            "4" => Self::V4,
+            _ => {
+                return Err(SbpfLinkerError::InvalidLoaderVersion(
+                    s.to_owned(),
+                ));
+            }
+        })
+    }
+}
+
+/// Checks whether the program linked from `source` would load on `loader`,
+/// returning every reason it wouldn't rather than stopping at the first.
+pub fn validate_for_loader(
+    source: &[u8],
+    loader: LoaderVersion,
+) -> Result<(), Vec<SbpfLinkerError>> {
+    let parse_result = parse_bytecode(source).map_err(|error| vec![error])?;
+    let mut violations =
+        instruction_violations(parse_result.code_section.get_nodes(), loader);
+
+    match link_program(source) {
+        Ok(program) => {
+            violations.extend(size_violation(program.len(), loader))
+        }
+        Err(error) => violations.push(error),
+    }
+
+    if violations.is_empty() { Ok(()) } else { Err(violations) }
+}
+
+/// The reasons the instructions `nodes` won't run on `loader`.
+pub(crate) fn instruction_violations(
+    nodes: &[ASTNode],
+    loader: LoaderVersion,
+) -> Vec<SbpfLinkerError> {
+    let mut violations = Vec::new();
+
+    // the program will be deployed at the lowest version that can run it
+    let required = SbpfVersion::required_by_nodes(nodes);
+    if required > loader.max_sbpf_version() {
+        violations.push(SbpfLinkerError::LoaderViolation {
+            loader,
+            reason: format!(
+                "the program requires SBPF {required}, but the loader runs at most {}",
+                loader.max_sbpf_version()
+            ),
+        });
+    }
+    for node in nodes {
+        if let ASTNode::Instruction { instruction, offset } = node
+            && required.deprecates(instruction.opcode)
+        {
+            violations.push(SbpfLinkerError::DeprecatedOpcode {
+                opcode: instruction.opcode,
+                offset: *offset,
+            });
+        }
+    }
+
+    violations
+}
+
+/// Checks the stack and heap sizes `options` ask for against what the
+/// runtime grants. Neither ends up in the program, which no loader reads
+/// them from: the stack size bounds the frames `--check-stack` accepts,
+/// and the heap has to be requested by each transaction, which the link
+/// warns about with a
+/// [`HeapRequest`](crate::diagnostic::DiagnosticKind::HeapRequest)
+/// diagnostic.
+pub(crate) fn check_memory_sizes(
+    options: &LinkOptions,
+) -> Result<(), SbpfLinkerError> {
+    let invalid = |option: &'static str, size: u64, detail: String| {
+        SbpfLinkerError::InvalidMemorySize { option, size, detail }
+    };
+    if let Some(size) = options.stack_size
+        && !(1..=STACK_FRAME_SIZE).contains(&size)
+    {
+        return Err(invalid(
+            "stack-size",
+            size,
+            format!(
+                "the loaders give every call a frame of {STACK_FRAME_SIZE} \
+                 bytes"
+            ),
+        ));
+    }
+    if let Some(size) = options.heap_size {
+        if !(MIN_HEAP_SIZE..=MAX_HEAP_SIZE).contains(&size) {
+            return Err(invalid(
+                "heap-size",
+                size,
+                format!(
+                    "the runtime grants a heap of {MIN_HEAP_SIZE} to \
+                     {MAX_HEAP_SIZE} bytes"
+                ),
+            ));
+        }
+        if size % HEAP_SIZE_GRANULARITY != 0 {
+            return Err(invalid(
+                "heap-size",
+                size,
+                format!(
+                    "heaps are requested in multiples of \
+                     {HEAP_SIZE_GRANULARITY} bytes"
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Why a program of `size` bytes won't load on `loader`, if it won't.
+pub(crate) fn size_violation(
+    size: usize,
+    loader: LoaderVersion,
+) -> Option<SbpfLinkerError> {
+    (size > MAX_PROGRAM_SIZE).then(|| SbpfLinkerError::LoaderViolation {
+        loader,
+        reason: format!(
+            "the program is {size} bytes, more than the {MAX_PROGRAM_SIZE} bytes a program account can hold"
+        ),
+    })
+}
@@ -0,0 +1,185 @@
+use std::fmt::Write as _;
+
+use object::{File, Object as _, ObjectSection as _};
+use sbpf_assembler::astnode::ASTNode;
+use sbpf_common::opcode::Opcode;
+
+use crate::{
+    LinkOptions, SbpfLinkerError,
+    analysis::decode,
+    byteparser::{DEFAULT_ENTRY, section_data},
+    inspect::function_sizes,
+};
+
+/// What a [`MapSymbol`] is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MapSymbolKind {
+    Function,
+    Rodata,
+}
+
+/// A function or rodata symbol of a linked program, where it ended up.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct MapSymbol {
+    pub name: String,
+    pub kind: MapSymbolKind,
+    /// Virtual address in the program.
+    pub address: u64,
+    pub size: u64,
+    /// Index of the input object the symbol came from, `None` if that isn't
+    /// known.
+    pub input: Option<usize>,
+    /// The symbol's name in the inputs, before renaming.
+    pub(crate) symbol: String,
+}
+
+/// A section of a linked program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct MapSection {
+    pub name: String,
+    pub address: u64,
+    pub size: u64,
+}
+
+/// The layout of a linked program, as reported by a linker map file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct LinkMap {
+    /// `.text` and `.rodata`, if the program has them.
+    pub sections: Vec<MapSection>,
+    /// Every function and rodata symbol, in address order.
+    pub symbols: Vec<MapSymbol>,
+}
+
+impl LinkMap {
+    /// Renders the map the way GNU ld's `-Map` lists output sections: each
+    /// section with its address and size, then the symbols in it, along
+    /// with the input they came from, named by `inputs`.
+    pub fn render(&self, inputs: &[String]) -> String {
+        let mut map = String::from("Linker map\n\n");
+        for section in &self.sections {
+            let _ = writeln!(
+                map,
+                "{:<16} {:#018x} {:#10x}",
+                section.name, section.address, section.size
+            );
+            let end = section.address + section.size;
+            for symbol in self.symbols.iter().filter(|symbol| {
+                (section.address..end).contains(&symbol.address)
+            }) {
+                let input = symbol
+                    .input
+                    .and_then(|input| inputs.get(input))
+                    .map_or("", String::as_str);
+                let _ = writeln!(
+                    map,
+                    "{:<16} {:#018x} {:#10x} {:<32} {input}",
+                    "", symbol.address, symbol.size, symbol.name
+                );
+            }
+            map.push('\n');
+        }
+        map
+    }
+}
+
+/// The map of `program`, linked from `source` with `options`. `rodata` are
+/// the nodes of the program's .rodata; functions are found in `source` and
+/// moved past what the prologue and epilogue insert. The entry function
+/// starts with the prologue.
+pub(crate) fn link_map(
+    source: &[u8],
+    rodata: &[ASTNode],
+    program: &[u8],
+    options: &LinkOptions,
+) -> Result<LinkMap, SbpfLinkerError> {
+    let linked = File::parse(program)?;
+    let mut map = LinkMap::default();
+    for name in [".text", ".rodata"] {
+        if let Some(section) = linked.section_by_name(name) {
+            map.sections.push(MapSection {
+                name: name.to_owned(),
+                address: section.address(),
+                size: section.size(),
+            });
+        }
+    }
+    let address_of = |name: &str| {
+        map.sections
+            .iter()
+            .find(|section| section.name == name)
+            .map_or(0, |section| section.address)
+    };
+    let (text_address, rodata_address) =
+        (address_of(".text"), address_of(".rodata"));
+
+    let prologue = options.prologue.as_ref().map_or(0, |p| p.size());
+    let epilogue = options.epilogue.as_ref().map_or(0, |e| e.size());
+    let obj = File::parse(source)?;
+    let exits: Vec<u64> = match obj.section_by_name(".text") {
+        Some(text) if epilogue > 0 => decode(&section_data(&text)?)
+            .iter()
+            .filter(|instruction| instruction.is(Opcode::Exit))
+            .map(|instruction| instruction.offset)
+            .collect(),
+        _ => Vec::new(),
+    };
+    let functions = function_sizes(source)?;
+    // the prologue goes before the entry function, or the start of .text
+    let entry = options.entry.as_deref().unwrap_or(DEFAULT_ENTRY);
+    let entry_offset = functions
+        .iter()
+        .find(|function| function.name == entry)
+        .map_or(0, |function| function.offset);
+    // an epilogue goes before every exit, so labels on an exit land on it
+    let spliced = |offset: u64| {
+        let before = exits.partition_point(|&exit| exit < offset) as u64;
+        let prologue = if offset > entry_offset { prologue } else { 0 };
+        prologue + offset + before * epilogue
+    };
+
+    let renamed = |symbol: &str| {
+        options
+            .renames
+            .iter()
+            .find(|rename| rename.from == symbol)
+            .map_or(symbol, |rename| rename.to.as_str())
+            .to_owned()
+    };
+    for function in functions {
+        let start = spliced(function.offset);
+        map.symbols.push(MapSymbol {
+            name: renamed(&function.name),
+            kind: MapSymbolKind::Function,
+            address: text_address + start,
+            size: spliced(function.offset + function.size) - start,
+            input: None,
+            symbol: function.name,
+        });
+    }
+    for node in rodata {
+        let ASTNode::ROData { rodata, offset } = node else { continue };
+        // labels synthesized into the middle of a symbol hold nothing
+        let size = rodata.get_size();
+        if size == 0 {
+            continue;
+        }
+        let symbol = options
+            .renames
+            .iter()
+            .find(|rename| rename.to == rodata.name)
+            .map_or(rodata.name.as_str(), |rename| rename.from.as_str());
+        map.symbols.push(MapSymbol {
+            name: rodata.name.clone(),
+            kind: MapSymbolKind::Rodata,
+            address: rodata_address + offset,
+            size,
+            input: None,
+            symbol: symbol.to_owned(),
+        });
+    }
+    map.symbols.sort_by_key(|symbol| symbol.address);
+    Ok(map)
+}
@@ -0,0 +1,552 @@
+use std::collections::{HashMap, HashSet};
+
+use object::write::{
+    Object as OutputObject, Relocation as OutputRelocation, SectionId,
+    Symbol as OutputSymbol, SymbolId, SymbolSection,
+};
+use object::{
+    Architecture, BinaryFormat, Endianness, File, FileFlags, Object as _,
+    ObjectSection as _, ObjectSymbol as _, RelocationFlags, RelocationTarget,
+    Section, SectionFlags, SectionIndex, SectionKind, SymbolFlags,
+    SymbolIndex, SymbolKind, SymbolScope,
+};
+
+use crate::byteparser::{
+    R_BPF_64_ABS64, R_BPF_64_RELATIVE, align_section, data_sections,
+    section_data,
+};
+use crate::diagnostic::{self, Diagnostic, DiagnosticKind, Location};
+use crate::script::{Placement, is_placeable};
+use crate::version::SbpfVersion;
+use crate::{LinkOptions, SbpfLinkerError, timings};
+
+/// 32-bit absolute address, as emitted for `.long symbol` in data sections.
+/// Missing from `object::elf`.
+pub(crate) const R_BPF_64_ABS32: u32 = 3;
+
+/// Merges several upstream BPF objects into one, as if they had been
+/// compiled as a single unit. Global symbols are resolved across the inputs:
+/// a strong definition overrides weak ones, and references to a symbol
+/// another input defines bind to that definition. Local symbols whose name
+/// is already taken get a `.<input>` suffix so they stay distinct.
+///
+/// Only sections that make it into the program are carried over, so debug
+/// info and BTF are dropped.
+pub(crate) fn merge_objects(
+    sources: &[&[u8]],
+    options: &LinkOptions,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Result<Vec<u8>, SbpfLinkerError> {
+    merge_tracked(sources, options, diagnostics, &mut HashMap::new())
+}
+
+/// [`merge_objects`], also recording the input each symbol defined in the
+/// merged object comes from, by its name in there, into `origins`.
+pub(crate) fn merge_tracked(
+    sources: &[&[u8]],
+    options: &LinkOptions,
+    diagnostics: &mut Vec<Diagnostic>,
+    origins: &mut HashMap<String, usize>,
+) -> Result<Vec<u8>, SbpfLinkerError> {
+    let _stage = timings::stage("merge");
+    tracing::debug!("merging {} object(s)", sources.len());
+    let objects = sources
+        .iter()
+        .map(|source| File::parse(*source))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut output = OutputObject::new(
+        BinaryFormat::Elf,
+        Architecture::Bpf,
+        Endianness::Little,
+    );
+    // the code of every input is read in the same encoding, which v2
+    // changes
+    let versions: Vec<_> =
+        objects.iter().map(SbpfVersion::of_object).collect();
+    let version = versions.iter().copied().max().unwrap_or_default();
+    if let Some(&first) = versions.first()
+        && let Some(&other) = versions.iter().find(|other| {
+            (**other >= SbpfVersion::V2) != (first >= SbpfVersion::V2)
+        })
+    {
+        return Err(SbpfLinkerError::MixedSbpfVersions { first, other });
+    }
+    output.flags = FileFlags::Elf {
+        os_abi: 0,
+        abi_version: 0,
+        e_flags: version.e_flags(),
+    };
+    // the input sections the program is made of, with the output section
+    // each goes to, in placement order. The sort is stable, so sections of
+    // the same rank stay in input order.
+    let mut carried = Vec::new();
+    for (input, obj) in objects.iter().enumerate() {
+        for section in carried_sections(obj, options)? {
+            carried.push((input, section));
+        }
+    }
+    let order = symbol_order(&objects, &carried, &options.symbol_ordering);
+    let mut carried: Vec<_> = carried.into_iter().zip(order).collect();
+    carried.sort_by_key(|((_, carried), order)| {
+        (carried.rank.0, *order, carried.rank.1)
+    });
+    let carried: Vec<_> =
+        carried.into_iter().map(|(carried, _)| carried).collect();
+    // an output section holds data if any of its inputs does
+    let mut kinds: HashMap<&str, SectionKind> = HashMap::new();
+    for (_, Carried { section, name, .. }) in &carried {
+        let kind = kinds.entry(name).or_insert(section.kind());
+        if *kind == SectionKind::UninitializedData {
+            *kind = section.kind();
+        }
+    }
+
+    // where each input section landed: its output section and its offset in
+    // there. Sections of the same name are concatenated in input order, as
+    // GNU ld does.
+    let mut sections: HashMap<&str, SectionId> = HashMap::new();
+    let mut placements: Vec<HashMap<SectionIndex, (SectionId, u64)>> =
+        vec![HashMap::new(); objects.len()];
+    for (input, Carried { section, name, .. }) in &carried {
+        let kind = kinds[name.as_str()];
+        let id = *sections.entry(name).or_insert_with(|| {
+            let id =
+                output.add_section(Vec::new(), name.as_bytes().to_vec(), kind);
+            // the contents are written out uncompressed
+            if let SectionFlags::Elf { sh_flags } = section.flags() {
+                output.section_mut(id).flags = SectionFlags::Elf {
+                    sh_flags: sh_flags
+                        & !u64::from(object::elf::SHF_COMPRESSED),
+                };
+            }
+            if let Some(script) = &options.linker_script {
+                output.section_mut(id).append_data(&[], script.align(name));
+            }
+            id
+        });
+        // rejects corrupt alignments before they reach the writer
+        align_section(0, section)?;
+        let align = section.align().max(1);
+        let base = if kind == SectionKind::UninitializedData {
+            output.section_mut(id).append_bss(section.size(), align)
+        } else if section.kind() == SectionKind::UninitializedData {
+            // .bss placed among data takes up zeroed space in it
+            output
+                .section_mut(id)
+                .append_data(&vec![0; section.size() as usize], align)
+        } else if objects[*input].is_little_endian() {
+            output.section_mut(id).append_data(&section_data(section)?, align)
+        } else {
+            let data = little_endian(section)?;
+            output.section_mut(id).append_data(&data, align)
+        };
+        placements[*input].insert(section.index(), (id, base));
+    }
+
+    // the definition each global name binds to: the first strong one, or
+    // the first weak one if there is no strong one
+    let mut definitions: HashMap<&str, (usize, SymbolIndex, bool)> =
+        HashMap::new();
+    let mut order = Vec::new();
+    for (input, obj) in objects.iter().enumerate() {
+        for symbol in obj.symbols() {
+            let Ok(name) = symbol.name() else { continue };
+            if !symbol.is_global()
+                || symbol.is_undefined()
+                || name.is_empty()
+                || symbol.section_index().is_some_and(|index| {
+                    !placements[input].contains_key(&index)
+                })
+            {
+                continue;
+            }
+            let weak = symbol.is_weak();
+            match definitions.get(name) {
+                None => {
+                    order.push(name);
+                    definitions.insert(name, (input, symbol.index(), weak));
+                }
+                Some(&(_, _, true)) if !weak => {
+                    definitions.insert(name, (input, symbol.index(), weak));
+                }
+                Some(_) if weak => {}
+                Some(_) => {
+                    if !options.allow_multiple_definition {
+                        return Err(SbpfLinkerError::MultipleDefinition(
+                            name.to_owned(),
+                        ));
+                    }
+                    diagnostic::warn(
+                        diagnostics,
+                        &options.warnings,
+                        DiagnosticKind::MultipleDefinition,
+                        Location::default(),
+                        format!(
+                            "multiple definition of `{name}`, ignoring the one in input {}",
+                            input + 1
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    let mut globals = HashMap::new();
+    let mut taken: HashSet<String> = HashSet::new();
+    for name in order {
+        let (input, index, _) = definitions[name];
+        let symbol = objects[input].symbol_by_index(index)?;
+        let id = output.add_symbol(output_symbol(
+            name.to_owned(),
+            &symbol,
+            &placements[input],
+        ));
+        globals.insert(name, id);
+        taken.insert(name.to_owned());
+        origins.insert(name.to_owned(), input);
+    }
+
+    // every input symbol, by index, mapped to the output symbol it became
+    let mut symbol_maps: Vec<HashMap<SymbolIndex, SymbolId>> = Vec::new();
+    let mut undefined = HashMap::new();
+    for (input, obj) in objects.iter().enumerate() {
+        let placed = &placements[input];
+        let mut symbols = HashMap::new();
+        for symbol in obj.symbols() {
+            let name = symbol.name().unwrap_or_default();
+            let placement = symbol
+                .section_index()
+                .and_then(|index| placed.get(&index).copied());
+            let id = match symbol.kind() {
+                SymbolKind::File => continue,
+                SymbolKind::Section => match placement {
+                    Some((section, _)) => output.section_symbol(section),
+                    None => continue,
+                },
+                _ if symbol.is_global() && globals.contains_key(name) => {
+                    globals[name]
+                }
+                _ if symbol.is_undefined()
+                    || (symbol.section_index().is_some()
+                        && placement.is_none()) =>
+                {
+                    *undefined.entry(name.to_owned()).or_insert_with(|| {
+                        output.add_symbol(OutputSymbol {
+                            name: name.as_bytes().to_vec(),
+                            value: 0,
+                            size: 0,
+                            kind: SymbolKind::Unknown,
+                            scope: SymbolScope::Unknown,
+                            weak: false,
+                            section: SymbolSection::Undefined,
+                            flags: SymbolFlags::None,
+                        })
+                    })
+                }
+                _ => {
+                    let mut unique = name.to_owned();
+                    if !name.is_empty() {
+                        let mut n = input + 1;
+                        while !taken.insert(unique.clone()) {
+                            unique = format!("{name}.{n}");
+                            n += 1;
+                        }
+                    }
+                    if placement.is_some() {
+                        origins.insert(unique.clone(), input);
+                    }
+                    output.add_symbol(output_symbol(unique, &symbol, placed))
+                }
+            };
+            symbols.insert(symbol.index(), id);
+        }
+        symbol_maps.push(symbols);
+    }
+
+    for (input, obj) in objects.iter().enumerate() {
+        let placed = &placements[input];
+        for section in obj.sections() {
+            let Some(&(holder, holder_base)) = placed.get(&section.index())
+            else {
+                continue;
+            };
+            for (offset, rel) in section.relocations() {
+                let RelocationTarget::Symbol(index) = rel.target() else {
+                    continue;
+                };
+                let Some(&symbol) = symbol_maps[input].get(&index) else {
+                    continue;
+                };
+                let offset = holder_base + offset;
+                // the implicit addend of a relocation against a section
+                // symbol is relative to this input's part of the section
+                let target = obj.symbol_by_index(index)?;
+                if target.kind() == SymbolKind::Section
+                    && let Some(&(_, base)) = target
+                        .section_index()
+                        .and_then(|index| placed.get(&index))
+                    && base > 0
+                    && let RelocationFlags::Elf { r_type } = rel.flags()
+                {
+                    rebase_addend(
+                        output.section_mut(holder).data_mut(),
+                        offset,
+                        r_type,
+                        base,
+                    );
+                }
+                output.add_relocation(
+                    holder,
+                    OutputRelocation {
+                        offset,
+                        symbol,
+                        addend: 0,
+                        flags: rel.flags(),
+                    },
+                )?;
+            }
+        }
+    }
+
+    Ok(output.write()?)
+}
+
+/// An input section carried into the merged object.
+struct Carried<'data, 'file> {
+    section: Section<'data, 'file>,
+    /// The output section it goes to.
+    name: String,
+    /// Its place in the [`LinkOptions::linker_script`], sections of lower
+    /// rank going first.
+    rank: (usize, usize),
+}
+
+/// Where each of the `carried` sections of `objects` goes by `ordering`:
+/// the position of the first symbol it defines in there, or past every
+/// position if it defines none. Symbols of `ordering` none of `objects`
+/// defines are logged as a warning, like lld does.
+fn symbol_order(
+    objects: &[File],
+    carried: &[(usize, Carried)],
+    ordering: &[String],
+) -> Vec<usize> {
+    if ordering.is_empty() {
+        return vec![0; carried.len()];
+    }
+    let mut positions: HashMap<&str, usize> = HashMap::new();
+    for (position, name) in ordering.iter().enumerate() {
+        positions.entry(name.as_str()).or_insert(position);
+    }
+    let mut found = HashSet::new();
+    let mut sections: HashMap<(usize, SectionIndex), usize> = HashMap::new();
+    for (input, obj) in objects.iter().enumerate() {
+        for symbol in obj.symbols() {
+            let (Ok(name), Some(section)) =
+                (symbol.name(), symbol.section_index())
+            else {
+                continue;
+            };
+            if let Some(&position) = positions.get(name) {
+                found.insert(name.to_owned());
+                let order =
+                    sections.entry((input, section)).or_insert(position);
+                *order = (*order).min(position);
+            }
+        }
+    }
+    for name in ordering {
+        if !found.contains(name) {
+            tracing::warn!(
+                "`{name}`, in the symbol ordering, isn't defined by any input"
+            );
+        }
+    }
+    carried
+        .iter()
+        .map(|(input, carried)| {
+            sections
+                .get(&(*input, carried.section.index()))
+                .copied()
+                .unwrap_or(ordering.len())
+        })
+        .collect()
+}
+
+/// The sections of `obj` that make it into the program. Without a
+/// [`LinkOptions::linker_script`], that's .text, with the `.text.*`
+/// sections after it, and the data sections under their own names.
+fn carried_sections<'data, 'file>(
+    obj: &'file File<'data>,
+    options: &LinkOptions,
+) -> Result<Vec<Carried<'data, 'file>>, SbpfLinkerError> {
+    let Some(script) = &options.linker_script else {
+        // the .text.* sections of -ffunction-sections too, after .text
+        let text = obj.section_by_name(".text").into_iter().chain(
+            obj.sections().filter(|section| {
+                section.kind() == SectionKind::Text
+                    && section
+                        .name()
+                        .is_ok_and(|name| name.starts_with(".text."))
+            }),
+        );
+        let text = text.map(|section| Carried {
+            section,
+            name: String::from(".text"),
+            rank: (0, 0),
+        });
+        return Ok(text
+            .chain(data_sections(obj).into_iter().map(|section| {
+                let name = section.name().unwrap_or_default().to_owned();
+                Carried { section, name, rank: (0, 0) }
+            }))
+            .collect());
+    };
+    let mut carried = Vec::new();
+    for section in obj.sections() {
+        if !matches!(
+            section.kind(),
+            SectionKind::Text
+                | SectionKind::Data
+                | SectionKind::ReadOnlyData
+                | SectionKind::ReadOnlyDataWithRel
+                | SectionKind::ReadOnlyString
+                | SectionKind::UninitializedData
+        ) {
+            continue;
+        }
+        let name = section.name()?;
+        let (name, rank) = match script.place(name) {
+            Placement::Output { name, rank } => (name, rank),
+            Placement::Discard => continue,
+            Placement::Orphan if is_placeable(name) => {
+                (name, (script.sections.len(), 0))
+            }
+            Placement::Orphan => continue,
+        };
+        if !is_placeable(name) {
+            return Err(SbpfLinkerError::UnplaceableOutputSection(
+                name.to_owned(),
+            ));
+        }
+        carried.push(Carried { section, name: name.to_owned(), rank });
+    }
+    Ok(carried)
+}
+
+/// `symbol` as defined in the merged object under `name`.
+fn output_symbol(
+    name: String,
+    symbol: &object::Symbol,
+    placed: &HashMap<SectionIndex, (SectionId, u64)>,
+) -> OutputSymbol {
+    let definition = symbol
+        .section_index()
+        .and_then(|index| placed.get(&index))
+        .map(|&(section, base)| (section, base + symbol.address()));
+    defined_symbol(name, symbol, definition)
+}
+
+/// `symbol` under `name`, defined at the given section and address of the
+/// output, or absolute if there is none.
+pub(crate) fn defined_symbol(
+    name: String,
+    symbol: &object::Symbol,
+    definition: Option<(SectionId, u64)>,
+) -> OutputSymbol {
+    let (section, value) = match definition {
+        Some((section, value)) => (SymbolSection::Section(section), value),
+        None => (SymbolSection::Absolute, symbol.address()),
+    };
+    OutputSymbol {
+        name: name.into_bytes(),
+        value,
+        size: symbol.size(),
+        kind: symbol.kind(),
+        scope: if symbol.is_global() {
+            SymbolScope::Linkage
+        } else {
+            SymbolScope::Compilation
+        },
+        weak: symbol.is_weak(),
+        section,
+        // keeps the type of e.g. the local labels of .text, which the
+        // writer can't derive
+        flags: match symbol.flags() {
+            SymbolFlags::Elf { st_info, st_other } => {
+                SymbolFlags::Elf { st_info, st_other }
+            }
+            _ => SymbolFlags::None,
+        },
+    }
+}
+
+/// The contents of `section`, of a big-endian object, in the little-endian
+/// order of the merged one, as far as it's known: every instruction of
+/// code, and the addresses relocations store in data. The rest of the data
+/// has no types to go by and is kept as it is.
+fn little_endian(section: &Section) -> Result<Vec<u8>, SbpfLinkerError> {
+    let mut data = section_data(section)?.into_owned();
+    if section.kind() == SectionKind::Text {
+        for instruction in data.chunks_exact_mut(8) {
+            // the registers are in the other nibbles, and the offset and
+            // the immediate the other way around
+            instruction[1] = instruction[1].rotate_left(4);
+            instruction[2..4].reverse();
+            instruction[4..8].reverse();
+        }
+        return Ok(data);
+    }
+    for (offset, rel) in section.relocations() {
+        let size = match rel.flags() {
+            RelocationFlags::Elf {
+                r_type: R_BPF_64_ABS64 | R_BPF_64_RELATIVE,
+            } => 8,
+            RelocationFlags::Elf { r_type: R_BPF_64_ABS32 } => 4,
+            _ => continue,
+        };
+        if let Some(slot) =
+            data.get_mut(offset as usize..offset as usize + size)
+        {
+            slot.reverse();
+        }
+    }
+    // strings read the same either way
+    let strings = matches!(
+        section.flags(),
+        SectionFlags::Elf { sh_flags }
+            if sh_flags & u64::from(object::elf::SHF_STRINGS) != 0
+    );
+    if !strings && data.iter().any(|&byte| byte != 0) {
+        tracing::warn!(
+            "{} is from a big-endian object; its addresses are converted, \
+             but other values are kept in big-endian order",
+            section.name().unwrap_or_default()
+        );
+    }
+    Ok(data)
+}
+
+/// Moves the implicit addend of the relocation at `offset` forward by `base`
+/// bytes.
+fn rebase_addend(data: &mut [u8], offset: u64, r_type: u32, base: u64) {
+    let at = offset as usize;
+    match r_type {
+        // lddw: the low half of the address in the first immediate
+        object::elf::R_BPF_64_64 => add_le::<4>(data, at + 4, base),
+        // call: the target in instructions, relative to the symbol
+        object::elf::R_BPF_64_32 => add_le::<4>(data, at + 4, base / 8),
+        R_BPF_64_ABS64 | R_BPF_64_RELATIVE => add_le::<8>(data, at, base),
+        R_BPF_64_ABS32 => add_le::<4>(data, at, base),
+        _ => {}
+    }
+}
+
+/// Adds `value` to the `N`-byte little-endian integer at `at`, wrapping.
+fn add_le<const N: usize>(data: &mut [u8], at: usize, value: u64) {
+    let Some(bytes) = data.get_mut(at..at + N) else { return };
+    let mut buffer = [0; 8];
+    buffer[..N].copy_from_slice(bytes);
+    let sum = u64::from_le_bytes(buffer).wrapping_add(value);
+    bytes.copy_from_slice(&sum.to_le_bytes()[..N]);
+}
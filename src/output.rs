@@ -0,0 +1,76 @@
+use std::{
+    fs::{self, File},
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// The output path that stands for stdout, as in `-o -`.
+pub const STDOUT: &str = "-";
+
+/// Distinguishes the temporary files of concurrent writes.
+static TEMP_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes `contents` to `path` through a temporary file next to it, renamed
+/// over `path` once complete, so that an interrupted link leaves the
+/// previous file or the new one but never a truncated one. An existing file
+/// at `path` is replaced, not written through: a symlink there is replaced
+/// by the file rather than changing its target.
+pub fn write_atomic(
+    path: &Path,
+    contents: impl AsRef<[u8]>,
+) -> io::Result<()> {
+    if path.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::IsADirectory,
+            format!("{} is a directory", path.display()),
+        ));
+    }
+    let temp = TempPath::next_to(path);
+    let mut file = File::create(temp.path())?;
+    file.write_all(contents.as_ref())?;
+    file.sync_all()?;
+    temp.persist(path)
+}
+
+/// A temporary file next to a destination, in the same directory so that
+/// it can be renamed to it. Hidden and removed when dropped, unless
+/// [persisted](TempPath::persist), so that failed links don't leave
+/// intermediate files behind.
+#[derive(Debug)]
+pub struct TempPath {
+    path: PathBuf,
+}
+
+impl TempPath {
+    /// A path for a temporary file that will become `destination`. Nothing
+    /// is created.
+    pub fn next_to(destination: &Path) -> Self {
+        let name = destination
+            .file_name()
+            .map_or("output".into(), |name| name.to_string_lossy());
+        let path = destination.with_file_name(format!(
+            ".{name}.{}-{}.tmp",
+            process::id(),
+            TEMP_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Renames the file to `destination`, replacing what's there.
+    pub fn persist(self, destination: &Path) -> io::Result<()> {
+        fs::rename(&self.path, destination)
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        // already renamed, or never created by a failed write
+        let _ = fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,227 @@
+use sbpf_assembler::{
+    astnode::ASTNode,
+    dynsym::RelocationType,
+    lexer::{ImmediateValue, Token},
+    parser::ParseResult,
+};
+use sbpf_common::opcode::Opcode;
+
+use crate::{disasm, inspect::RodataInfo};
+
+/// A program as it's assembled, before it's emitted: its functions,
+/// instructions, rodata and the relocations the loader applies, for tools
+/// built on the linker, e.g. analyses or disassemblers. Offsets are into
+/// the program's .text and .rodata, which the loader maps at addresses of
+/// its own.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct ParsedProgram {
+    /// In offset order, each spanning to the next one or the end of .text.
+    /// Code before the first is named `.text`.
+    pub functions: Vec<ParsedFunction>,
+    /// In offset order.
+    pub instructions: Vec<ParsedInstruction>,
+    /// In offset order. Symbols merged with
+    /// [`LinkOptions::dedup_rodata`](crate::LinkOptions::dedup_rodata)
+    /// share an offset, and only the first holds the bytes.
+    pub rodata: Vec<RodataInfo>,
+    /// Those of .text, then those of .rodata, in offset order.
+    pub relocations: Vec<ParsedRelocation>,
+    /// The exported functions, by name and offset.
+    pub exports: Vec<(String, u64)>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ParsedFunction {
+    pub name: String,
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// One instruction of .text, in the SBPF v0 encoding.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct ParsedInstruction {
+    pub offset: u64,
+    pub opcode: Opcode,
+    /// In assembly order, e.g. the destination register first.
+    pub operands: Vec<Operand>,
+    /// 16 bytes for an `lddw`, 8 for the rest.
+    pub size: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Operand {
+    Register(u8),
+    /// A constant, a memory displacement, or the target of a branch or a
+    /// call within .text, in instructions from the next one.
+    Immediate(i64),
+    /// A function, a rodata symbol or a syscall, by name.
+    Symbol(String),
+}
+
+/// A relocation the loader applies.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ParsedRelocation {
+    /// Offset of the instruction in .text, or of the pointer in .rodata.
+    pub offset: u64,
+    pub kind: ParsedRelocationKind,
+    /// What it refers to: the symbol whose address is loaded, or the
+    /// syscall called. Empty for a pointer.
+    pub symbol: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ParsedRelocationKind {
+    /// An `lddw` of an address, which the loader rebases.
+    Address,
+    /// A pointer stored in .rodata, which the loader rebases.
+    Pointer,
+    /// A `call` of a syscall, which the loader resolves by its name.
+    Syscall,
+}
+
+impl ParsedProgram {
+    pub(crate) fn new(parse_result: &ParseResult) -> Self {
+        let code = parse_result.code_section.get_nodes();
+        let mut instructions = Vec::new();
+        let mut starts = Vec::new();
+        for node in code {
+            match node {
+                ASTNode::Instruction { instruction, offset } => {
+                    instructions.push(ParsedInstruction {
+                        offset: *offset,
+                        opcode: instruction.opcode,
+                        operands: instruction
+                            .operands
+                            .iter()
+                            .map(operand)
+                            .collect(),
+                        size: instruction.get_size(),
+                    });
+                }
+                // labels of branch targets and taken addresses aren't
+                // functions
+                ASTNode::Label { label, offset }
+                    if !label.name.starts_with(".text+") =>
+                {
+                    starts.push((*offset, label.name.clone()));
+                }
+                _ => {}
+            }
+        }
+        instructions.sort_by_key(|instruction| instruction.offset);
+        let text_size =
+            instructions.last().map_or(0, |last| last.offset + last.size);
+        // aliases share a start; keep the first name
+        starts.sort_by_key(|(offset, _)| *offset);
+        starts.dedup_by_key(|(offset, _)| *offset);
+        if text_size > 0 && starts.first().is_none_or(|(at, _)| *at > 0) {
+            starts.insert(0, (0, ".text".to_owned()));
+        }
+        let functions = starts
+            .iter()
+            .enumerate()
+            .map(|(i, (offset, name))| {
+                let end =
+                    starts.get(i + 1).map_or(text_size, |(next, _)| *next);
+                ParsedFunction {
+                    name: name.clone(),
+                    offset: *offset,
+                    size: end.saturating_sub(*offset),
+                }
+            })
+            .collect();
+
+        let mut rodata: Vec<RodataInfo> = parse_result
+            .data_section
+            .get_nodes()
+            .iter()
+            .filter_map(|node| match node {
+                // alignment padding
+                ASTNode::ROData { rodata, .. }
+                    if rodata.name.starts_with(".pad.") =>
+                {
+                    None
+                }
+                ASTNode::ROData { rodata, offset } => Some(RodataInfo {
+                    name: rodata.name.clone(),
+                    offset: *offset,
+                    bytes: match rodata.args.get(1) {
+                        Some(Token::VectorLiteral(values, _)) => values
+                            .iter()
+                            .map(|value| immediate(value) as u8)
+                            .collect(),
+                        Some(Token::StringLiteral(text, _)) => {
+                            text.as_bytes().to_vec()
+                        }
+                        _ => Vec::new(),
+                    },
+                }),
+                _ => None,
+            })
+            .collect();
+        rodata.sort_by_key(|symbol| symbol.offset);
+
+        let mut relocations: Vec<ParsedRelocation> = parse_result
+            .relocation_data
+            .get_rel_dyns()
+            .into_iter()
+            .map(|(offset, kind, symbol)| match kind {
+                // pointers are relocated past .text, as if .rodata
+                // followed it directly
+                RelocationType::RSbf64Relative if offset >= text_size => {
+                    ParsedRelocation {
+                        offset: offset - text_size,
+                        kind: ParsedRelocationKind::Pointer,
+                        symbol,
+                    }
+                }
+                RelocationType::RSbf64Relative => ParsedRelocation {
+                    offset,
+                    kind: ParsedRelocationKind::Address,
+                    symbol,
+                },
+                RelocationType::RSbfSyscall => ParsedRelocation {
+                    offset,
+                    kind: ParsedRelocationKind::Syscall,
+                    symbol,
+                },
+            })
+            .collect();
+        relocations.sort_by_key(|relocation| {
+            (
+                relocation.kind == ParsedRelocationKind::Pointer,
+                relocation.offset,
+            )
+        });
+
+        Self {
+            functions,
+            instructions,
+            rodata,
+            relocations,
+            exports: parse_result.dynamic_symbols.get_entry_points(),
+        }
+    }
+}
+
+fn operand(token: &Token) -> Operand {
+    match token {
+        Token::Register(register, _) => Operand::Register(*register),
+        Token::ImmediateValue(value, _) => {
+            Operand::Immediate(immediate(value))
+        }
+        other => Operand::Symbol(disasm::render_operand(other)),
+    }
+}
+
+fn immediate(value: &ImmediateValue) -> i64 {
+    match value {
+        ImmediateValue::Int(value) | ImmediateValue::Addr(value) => *value,
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::SbpfLinkerError;
+
+/// A symbol renamed at link time, as with objcopy's `--redefine-sym`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SymbolRename {
+    pub from: String,
+    pub to: String,
+}
+
+/// Parses a rename map: one `old_name new_name` pair per line. Blank lines
+/// and everything after a `#` are ignored.
+pub fn parse_rename_map(
+    map: &str,
+) -> Result<Vec<SymbolRename>, SbpfLinkerError> {
+    map.lines()
+        .enumerate()
+        .filter_map(|(index, line)| {
+            let content = line.split('#').next().unwrap_or_default().trim();
+            (!content.is_empty()).then_some((index + 1, line, content))
+        })
+        .map(|(line, text, content)| {
+            match *content.split_whitespace().collect::<Vec<_>>() {
+                [from, to] => Ok(SymbolRename {
+                    from: from.to_owned(),
+                    to: to.to_owned(),
+                }),
+                _ => Err(SbpfLinkerError::InvalidRenameMap {
+                    line,
+                    content: text.to_owned(),
+                }),
+            }
+        })
+        .collect()
+}
+
+/// The renames that apply to an object, keyed by old name.
+#[derive(Debug, Default)]
+pub(crate) struct Renames(HashMap<String, String>);
+
+impl Renames {
+    /// Checks `renames` against the symbol names of the object. A symbol may
+    /// only be renamed once, and never to a name that is still in use after
+    /// renaming, so swapping two names is fine. Renames of symbols the object
+    /// doesn't have are ignored.
+    pub fn new<'a>(
+        renames: &[SymbolRename],
+        names: impl IntoIterator<Item = &'a str>,
+    ) -> Result<Self, SbpfLinkerError> {
+        let names: HashSet<_> = names.into_iter().collect();
+        let collision =
+            |rename: &SymbolRename| SbpfLinkerError::RenameCollision {
+                from: rename.from.clone(),
+                to: rename.to.clone(),
+            };
+
+        let mut map = HashMap::new();
+        for rename in renames {
+            if !names.contains(rename.from.as_str()) {
+                continue;
+            }
+            if map.insert(rename.from.clone(), rename.to.clone()).is_some() {
+                return Err(collision(rename));
+            }
+        }
+
+        let mut taken: HashSet<&str> = names
+            .iter()
+            .copied()
+            .filter(|name| !map.contains_key(*name))
+            .collect();
+        for rename in renames {
+            if map.contains_key(&rename.from) && !taken.insert(&rename.to) {
+                return Err(collision(rename));
+            }
+        }
+
+        Ok(Self(map))
+    }
+
+    /// The name `name` has in the program.
+    pub fn get<'a>(&'a self, name: &'a str) -> &'a str {
+        self.0.get(name).map_or(name, String::as_str)
+    }
+}
@@ -0,0 +1,74 @@
+use std::{fs, path::Path};
+
+use crate::SbpfLinkerError;
+
+/// How deep response files may include each other, which stops one that
+/// includes itself.
+const MAX_DEPTH: usize = 32;
+
+/// `args` with each `@file` argument replaced by the arguments in `file`,
+/// as GNU ld and lld read them: arguments are separated by whitespace,
+/// which single or double quotes keep together, a backslash escapes the
+/// character after it, and `@file` arguments in `file` are expanded too.
+/// Cargo passes the arguments of long links this way.
+pub fn expand_response_files(
+    args: impl IntoIterator<Item = String>,
+) -> Result<Vec<String>, SbpfLinkerError> {
+    let mut expanded = Vec::new();
+    for arg in args {
+        expand(arg, 0, &mut expanded)?;
+    }
+    Ok(expanded)
+}
+
+fn expand(
+    arg: String,
+    depth: usize,
+    expanded: &mut Vec<String>,
+) -> Result<(), SbpfLinkerError> {
+    let Some(path) = arg.strip_prefix('@').filter(|path| !path.is_empty())
+    else {
+        expanded.push(arg);
+        return Ok(());
+    };
+    let error = |detail: String| SbpfLinkerError::InvalidResponseFile {
+        path: Path::new(path).to_path_buf(),
+        detail,
+    };
+    if depth == MAX_DEPTH {
+        return Err(error(format!(
+            "response files nested more than {MAX_DEPTH} deep"
+        )));
+    }
+    let contents =
+        fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+    for arg in split(&contents) {
+        expand(arg, depth + 1, expanded)?;
+    }
+    Ok(())
+}
+
+/// The arguments in the contents of a response file.
+fn split(contents: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    // `None` between arguments, so that `""` is an empty argument
+    let mut current: Option<String> = None;
+    let mut quote = None;
+    let mut chars = contents.chars();
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            ('\\', _) => {
+                current.get_or_insert_default().extend(chars.next());
+            }
+            ('"' | '\'', None) => {
+                quote = Some(c);
+                current.get_or_insert_default();
+            }
+            (c, Some(open)) if c == open => quote = None,
+            (c, None) if c.is_whitespace() => args.extend(current.take()),
+            (c, _) => current.get_or_insert_default().push(c),
+        }
+    }
+    args.extend(current);
+    args
+}
@@ -0,0 +1,341 @@
+use crate::{SbpfLinkerError, byteparser::is_data_section, defsym};
+
+/// Name of the output section whose inputs are dropped.
+pub const DISCARD: &str = "/DISCARD/";
+
+/// A linker script: the part of GNU ld's script language that says which
+/// input sections go to which output section, in what order and with what
+/// alignment. The program only has two regions, so output sections become
+/// part of .text if they're named `.text` and part of .rodata if they're
+/// data sections (`.rodata*`, `.data*` or `.bss*`), in that order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LinkerScript {
+    pub sections: Vec<OutputSection>,
+}
+
+/// An output section description of a [`LinkerScript`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutputSection {
+    /// The output section's name, or [`DISCARD`].
+    pub name: String,
+    /// Alignment from `ALIGN(n)`, on top of that of its inputs.
+    pub align: Option<u64>,
+    /// The section name patterns of each input section description. Inputs
+    /// are placed in the order of the first description that matches them.
+    pub inputs: Vec<Vec<String>>,
+}
+
+/// Where a [`LinkerScript`] places an input section.
+pub(crate) enum Placement<'a> {
+    /// Into the output section with this name, at its index in the script
+    /// and that of the matching input section description.
+    Output {
+        name: &'a str,
+        rank: (usize, usize),
+    },
+    Discard,
+    /// Matched by nothing. Orphans keep their name and go after everything
+    /// the script places.
+    Orphan,
+}
+
+impl LinkerScript {
+    pub(crate) fn place(&self, name: &str) -> Placement<'_> {
+        for (at, section) in self.sections.iter().enumerate() {
+            let Some(input) = section.inputs.iter().position(|patterns| {
+                patterns.iter().any(|pattern| glob_matches(pattern, name))
+            }) else {
+                continue;
+            };
+            if section.name == DISCARD {
+                return Placement::Discard;
+            }
+            return Placement::Output {
+                name: &section.name,
+                rank: (at, input),
+            };
+        }
+        Placement::Orphan
+    }
+
+    /// Alignment the script gives the output section `name`.
+    pub(crate) fn align(&self, name: &str) -> u64 {
+        self.sections
+            .iter()
+            .filter(|section| section.name == name)
+            .filter_map(|section| section.align)
+            .max()
+            .unwrap_or(1)
+    }
+}
+
+/// Parses a linker script. `SECTIONS` may hold output section descriptions
+/// of the form `name : [ALIGN(n)] { *(pattern ...) ... }`, with `KEEP(...)`
+/// around input section descriptions allowed, and assignments, which are
+/// ignored since the program's layout is fixed. `PHDRS` and `MEMORY` are
+/// skipped, as are the program headers and regions output sections are
+/// assigned to, so scripts written for the platform tools' `bpf.ld` parse as
+/// they are.
+pub fn parse_linker_script(
+    script: &str,
+) -> Result<LinkerScript, SbpfLinkerError> {
+    let tokens = tokenize(script)?;
+    let mut parser = Parser { tokens: &tokens, at: 0 };
+    let mut sections = Vec::new();
+    while let Some(command) = parser.next() {
+        match command {
+            "SECTIONS" => {
+                parser.expect("{")?;
+                while !parser.eat("}") {
+                    if let Some(section) = parser.statement()? {
+                        sections.push(section);
+                    }
+                }
+            }
+            "PHDRS" | "MEMORY" => {
+                parser.expect("{")?;
+                parser.skip_past("}")?;
+            }
+            "OUTPUT_FORMAT" | "OUTPUT_ARCH" => {
+                parser.expect("(")?;
+                parser.skip_past(")")?;
+            }
+            _ => return Err(parser.error(format!("unsupported `{command}`"))),
+        }
+    }
+    Ok(LinkerScript { sections })
+}
+
+struct Parser<'a> {
+    tokens: &'a [(usize, &'a str)],
+    at: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.at).map(|&(_, token)| token)
+    }
+
+    fn next(&mut self) -> Option<&'a str> {
+        let token = self.peek()?;
+        self.at += 1;
+        Some(token)
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        let found = self.peek() == Some(token);
+        self.at += usize::from(found);
+        found
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), SbpfLinkerError> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected `{token}`")))
+        }
+    }
+
+    /// Skips everything up to and including `end`, minding nested braces
+    /// and parentheses.
+    fn skip_past(&mut self, end: &str) -> Result<(), SbpfLinkerError> {
+        let mut depth = 0usize;
+        loop {
+            let Some(token) = self.next() else {
+                return Err(self.error(format!("expected `{end}`")));
+            };
+            match token {
+                _ if token == end && depth == 0 => return Ok(()),
+                "{" | "(" => depth += 1,
+                "}" | ")" => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+    }
+
+    /// An error at the current token, or at the end of the script.
+    fn error(&self, detail: String) -> SbpfLinkerError {
+        let line = self
+            .tokens
+            .get(self.at.min(self.tokens.len().saturating_sub(1)))
+            .map_or(1, |&(line, _)| line);
+        SbpfLinkerError::InvalidLinkerScript { line, detail }
+    }
+
+    /// A statement of `SECTIONS`: an output section description, or `None`
+    /// for an assignment.
+    fn statement(&mut self) -> Result<Option<OutputSection>, SbpfLinkerError> {
+        let Some(name) = self.next() else {
+            return Err(self.error(String::from("expected `}`")));
+        };
+        if self.eat("=") {
+            self.skip_past(";")?;
+            return Ok(None);
+        }
+        if is_punctuation(name) {
+            return Err(self.error(format!("unexpected `{name}`")));
+        }
+        self.expect(":")?;
+        let align = if self.eat("ALIGN") {
+            self.expect("(")?;
+            let align = self
+                .next()
+                .and_then(defsym::parse_constant)
+                .and_then(|align| u64::try_from(align).ok())
+                .filter(|align| align.is_power_of_two())
+                .ok_or_else(|| {
+                    self.error(String::from("`ALIGN` takes a power of two"))
+                })?;
+            self.expect(")")?;
+            Some(align)
+        } else {
+            None
+        };
+        self.expect("{")?;
+        let mut inputs = Vec::new();
+        while !self.eat("}") {
+            if self.eat(";") {
+                continue;
+            }
+            // assignments, e.g. `. = ALIGN(8);`
+            if self
+                .tokens
+                .get(self.at + 1)
+                .is_some_and(|&(_, token)| token == "=")
+            {
+                self.skip_past(";")?;
+                continue;
+            }
+            let keep = self.eat("KEEP");
+            if keep {
+                self.expect("(")?;
+            }
+            inputs.push(self.input_description()?);
+            if keep {
+                self.expect(")")?;
+            }
+        }
+        // program headers and memory regions the section is assigned to,
+        // and its fill pattern
+        loop {
+            if self.eat(":")
+                || self.eat("=")
+                || self.peek().is_some_and(|token| token.starts_with('>'))
+            {
+                self.next();
+            } else {
+                break;
+            }
+        }
+        Ok(Some(OutputSection { name: name.to_owned(), align, inputs }))
+    }
+
+    /// The section patterns of an input section description, `*(...)`.
+    fn input_description(&mut self) -> Result<Vec<String>, SbpfLinkerError> {
+        match self.next() {
+            // the inputs are merged before the script applies, so the file
+            // they came from is gone
+            Some("*") => {}
+            Some(file) if !is_punctuation(file) => {
+                return Err(self.error(format!(
+                    "file pattern `{file}` (only `*` is supported)"
+                )));
+            }
+            _ => {
+                return Err(self.error(String::from(
+                    "expected an input section description",
+                )));
+            }
+        }
+        self.expect("(")?;
+        let mut patterns = Vec::new();
+        while !self.eat(")") {
+            match self.next() {
+                Some(pattern) if !is_punctuation(pattern) => {
+                    patterns.push(pattern.to_owned());
+                }
+                _ => return Err(self.error(String::from("expected `)`"))),
+            }
+        }
+        Ok(patterns)
+    }
+}
+
+/// Characters that are tokens of their own.
+const PUNCTUATION: &str = "{}():;=";
+
+fn is_punctuation(token: &str) -> bool {
+    token.len() == 1 && PUNCTUATION.contains(token)
+}
+
+/// Splits `script` into words and punctuation, along with the line each
+/// is on. Comments are `/* ... */`.
+fn tokenize(script: &str) -> Result<Vec<(usize, &str)>, SbpfLinkerError> {
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut rest = script;
+    while let Some(c) = rest.chars().next() {
+        if c == '\n' {
+            line += 1;
+        }
+        if c.is_whitespace() || c == ',' {
+            rest = &rest[c.len_utf8()..];
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            let end = comment.find("*/").ok_or(
+                SbpfLinkerError::InvalidLinkerScript {
+                    line,
+                    detail: String::from("unterminated comment"),
+                },
+            )?;
+            line += comment[..end].matches('\n').count();
+            rest = &comment[end + 2..];
+        } else if PUNCTUATION.contains(c) {
+            tokens.push((line, &rest[..1]));
+            rest = &rest[1..];
+        } else {
+            let end = rest
+                .find(|c: char| {
+                    c.is_whitespace() || c == ',' || PUNCTUATION.contains(c)
+                })
+                .unwrap_or(rest.len());
+            tokens.push((line, &rest[..end]));
+            rest = &rest[end..];
+        }
+    }
+    Ok(tokens)
+}
+
+/// Whether `name` matches the glob `pattern`, with `*` for any run of
+/// characters and `?` for one.
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let (pattern, name) = (pattern.as_bytes(), name.as_bytes());
+    let (mut p, mut n) = (0, 0);
+    // where the last `*` was, and the part of the name it swallowed
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Whether the script may place inputs into the output section `name`.
+pub(crate) fn is_placeable(name: &str) -> bool {
+    name == ".text" || is_data_section(name)
+}
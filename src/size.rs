@@ -0,0 +1,234 @@
+use std::fmt::Write as _;
+
+use object::{File, Object as _, ObjectSection as _};
+
+use crate::{
+    SbpfLinkerError,
+    map::{LinkMap, MapSymbolKind},
+};
+
+/// Where a linked program's bytes go: each of its sections, and each of its
+/// functions.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SizeReport {
+    /// Size of the whole `.so`.
+    pub total: u64,
+    /// Every section, in file order, and what the headers and padding
+    /// between them take as `(headers)`.
+    pub sections: Vec<SizeEntry>,
+    /// Every function of .text by demangled name, largest first.
+    pub functions: Vec<SizeEntry>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SizeEntry {
+    pub name: String,
+    pub size: u64,
+}
+
+/// Name of the entry for the bytes no section holds.
+const HEADERS: &str = "(headers)";
+
+/// The size report of `program`, with its functions taken from `map`.
+pub fn size_report(
+    program: &[u8],
+    map: &LinkMap,
+) -> Result<SizeReport, SbpfLinkerError> {
+    let linked = File::parse(program)?;
+    let total = program.len() as u64;
+    let mut sections: Vec<SizeEntry> = linked
+        .sections()
+        .filter_map(|section| {
+            let name = section.name().ok().filter(|name| !name.is_empty())?;
+            let (_, size) = section.file_range()?;
+            Some(SizeEntry { name: name.to_owned(), size })
+        })
+        .collect();
+    let in_sections: u64 = sections.iter().map(|section| section.size).sum();
+    sections.push(SizeEntry {
+        name: HEADERS.to_owned(),
+        size: total.saturating_sub(in_sections),
+    });
+
+    let mut functions: Vec<SizeEntry> = Vec::new();
+    for symbol in &map.symbols {
+        if symbol.kind != MapSymbolKind::Function {
+            continue;
+        }
+        // hashes aside, instances of a generic can demangle alike
+        let name = demangle(&symbol.name);
+        match functions.iter_mut().find(|function| function.name == name) {
+            Some(function) => function.size += symbol.size,
+            None => functions.push(SizeEntry { name, size: symbol.size }),
+        }
+    }
+    functions.sort_by(|a, b| b.size.cmp(&a.size).then(a.name.cmp(&b.name)));
+
+    Ok(SizeReport { total, sections, functions })
+}
+
+/// `name` demangled if it's a mangled Rust symbol, without the hash.
+pub fn demangle(name: &str) -> String {
+    match rustc_demangle::try_demangle(name) {
+        Ok(demangled) => format!("{demangled:#}"),
+        Err(_) => name.to_owned(),
+    }
+}
+
+impl SizeReport {
+    /// The report as JSON, with the change of every size since `previous`
+    /// when given. Entries only `previous` has are listed with size 0.
+    pub fn to_json(&self, previous: Option<&Self>) -> serde_json::Value {
+        let entries = |current: &[SizeEntry], before: Option<&[SizeEntry]>| {
+            diff(current, before)
+                .into_iter()
+                .map(|(name, size, delta)| {
+                    let mut entry =
+                        serde_json::json!({ "name": name, "size": size });
+                    if let Some(delta) = delta {
+                        entry["delta"] = delta.into();
+                    }
+                    entry
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut report = serde_json::json!({
+            "total": self.total,
+            "sections": entries(
+                &self.sections,
+                previous.map(|previous| previous.sections.as_slice()),
+            ),
+            "functions": entries(
+                &self.functions,
+                previous.map(|previous| previous.functions.as_slice()),
+            ),
+        });
+        if let Some(previous) = previous {
+            report["total_delta"] =
+                (self.total as i64 - previous.total as i64).into();
+        }
+        report
+    }
+
+    /// Reads back a report written by [`SizeReport::to_json`].
+    pub fn from_json(json: &str) -> Result<Self, SbpfLinkerError> {
+        let invalid = |detail: &str| {
+            SbpfLinkerError::InvalidSizeReport(detail.to_owned())
+        };
+        let report: serde_json::Value = serde_json::from_str(json)
+            .map_err(|e| SbpfLinkerError::InvalidSizeReport(e.to_string()))?;
+        let entries = |key: &str| -> Result<Vec<SizeEntry>, SbpfLinkerError> {
+            report[key]
+                .as_array()
+                .ok_or_else(|| invalid(&format!("no `{key}` list")))?
+                .iter()
+                .map(|entry| {
+                    Ok(SizeEntry {
+                        name: entry["name"]
+                            .as_str()
+                            .ok_or_else(|| invalid("entry without a name"))?
+                            .to_owned(),
+                        size: entry["size"]
+                            .as_u64()
+                            .ok_or_else(|| invalid("entry without a size"))?,
+                    })
+                })
+                .collect()
+        };
+        Ok(Self {
+            total: report["total"]
+                .as_u64()
+                .ok_or_else(|| invalid("no `total`"))?,
+            sections: entries("sections")?,
+            functions: entries("functions")?,
+        })
+    }
+
+    /// The report as a table of sections and one of functions, with the
+    /// change since `previous` next to each size when given.
+    pub fn render(&self, previous: Option<&Self>) -> String {
+        let mut report = String::new();
+        let mut table =
+            |title: &str,
+             current: &[SizeEntry],
+             before: Option<&[SizeEntry]>| {
+                let _ = writeln!(report, "{title}");
+                for (name, size, delta) in diff(current, before) {
+                    match delta {
+                        Some(delta) => {
+                            let _ = writeln!(
+                                report,
+                                "{size:>8} {delta:>+8}  {name}"
+                            );
+                        }
+                        None => {
+                            let _ = writeln!(
+                                report,
+                                "{size:>8} {:>6.2}%  {name}",
+                                size as f64 * 100.0 / self.total as f64
+                            );
+                        }
+                    }
+                }
+                report.push('\n');
+            };
+        table(
+            "Sections",
+            &self.sections,
+            previous.map(|previous| previous.sections.as_slice()),
+        );
+        table(
+            "Functions",
+            &self.functions,
+            previous.map(|previous| previous.functions.as_slice()),
+        );
+        match previous {
+            Some(previous) => {
+                let delta = self.total as i64 - previous.total as i64;
+                let _ =
+                    writeln!(report, "{:>8} {delta:>+8}  total", self.total);
+            }
+            None => {
+                let _ = writeln!(report, "{:>8} total", self.total);
+            }
+        }
+        report
+    }
+}
+
+/// `current` as `(name, size, delta)`, with the delta against `before`
+/// when given, followed by what only `before` has.
+fn diff<'a>(
+    current: &'a [SizeEntry],
+    before: Option<&'a [SizeEntry]>,
+) -> Vec<(&'a str, u64, Option<i64>)> {
+    let Some(before) = before else {
+        return current
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.size, None))
+            .collect();
+    };
+    let size_before = |name: &str| {
+        before
+            .iter()
+            .find(|entry| entry.name == name)
+            .map_or(0, |entry| entry.size)
+    };
+    let mut rows: Vec<_> = current
+        .iter()
+        .map(|entry| {
+            let delta = entry.size as i64 - size_before(&entry.name) as i64;
+            (entry.name.as_str(), entry.size, Some(delta))
+        })
+        .collect();
+    rows.extend(
+        before
+            .iter()
+            .filter(|entry| {
+                !current.iter().any(|current| current.name == entry.name)
+            })
+            .map(|entry| (entry.name.as_str(), 0, Some(-(entry.size as i64)))),
+    );
+    rows
+}
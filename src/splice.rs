@@ -0,0 +1,307 @@
+use std::{collections::HashMap, str::FromStr};
+
+use sbpf_assembler::astnode::{ASTNode, Label};
+use sbpf_assembler::instruction::Instruction;
+use sbpf_assembler::lexer::{ImmediateValue, Token};
+use sbpf_assembler::{parse_tokens, tokenize};
+use sbpf_common::opcode::Opcode;
+
+use crate::SbpfLinkerError;
+
+/// A fixed instruction sequence spliced into the program, given either as
+/// sbpf assembly (instructions separated by newlines or `;`) or as the hex
+/// encoding of its bytecode.
+#[derive(Clone, Debug)]
+pub struct InstructionSequence {
+    instructions: Vec<Instruction>,
+}
+
+impl InstructionSequence {
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    /// Size of the sequence's bytecode.
+    pub fn size(&self) -> u64 {
+        self.instructions
+            .iter()
+            .map(|instruction| instruction.get_size())
+            .sum()
+    }
+
+    fn from_hex(s: &str) -> Result<Self, SbpfLinkerError> {
+        let invalid =
+            |detail: String| SbpfLinkerError::InvalidInstructionSequence {
+                sequence: s.to_owned(),
+                detail,
+            };
+
+        let digits: Vec<u8> =
+            s.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+        let bytes = digits
+            .chunks(2)
+            .map(|pair| {
+                std::str::from_utf8(pair)
+                    .ok()
+                    .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                    .ok_or_else(|| invalid("odd number of hex digits".into()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut instructions = Vec::new();
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let node_len = match Opcode::from_u8(bytes[offset]) {
+                Some(Opcode::Lddw) => 16,
+                Some(_) => 8,
+                None => {
+                    return Err(invalid(format!(
+                        "unknown opcode {:#04x} at byte {offset}",
+                        bytes[offset]
+                    )));
+                }
+            };
+            let node =
+                bytes.get(offset..offset + node_len).ok_or_else(|| {
+                    invalid(format!("truncated instruction at byte {offset}"))
+                })?;
+            instructions.push(
+                Instruction::from_bytes(node)
+                    .map_err(|error| invalid(error.to_string()))?,
+            );
+            offset += node_len;
+        }
+
+        Ok(Self { instructions })
+    }
+
+    fn from_asm(s: &str) -> Result<Self, SbpfLinkerError> {
+        let invalid =
+            |errors: Vec<_>| SbpfLinkerError::InvalidInstructionSequence {
+                sequence: s.to_owned(),
+                detail: format!("{errors:?}"),
+            };
+
+        let tokens = tokenize(&s.replace(';', "\n")).map_err(invalid)?;
+        let parse_result = parse_tokens(&tokens).map_err(invalid)?;
+        let instructions = parse_result
+            .code_section
+            .get_nodes()
+            .iter()
+            .filter_map(|node| match node {
+                ASTNode::Instruction { instruction, .. } => {
+                    Some(instruction.clone())
+                }
+                _ => None,
+            })
+            .collect();
+
+        Ok(Self { instructions })
+    }
+}
+
+impl FromStr for InstructionSequence {
+    type Err = SbpfLinkerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let sequence = if !s.trim().is_empty()
+            && s.chars().all(|c| c.is_ascii_hexdigit() || c.is_whitespace())
+        {
+            Self::from_hex(s)?
+        } else {
+            Self::from_asm(s)?
+        };
+
+        if sequence.instructions.is_empty() {
+            return Err(SbpfLinkerError::InvalidInstructionSequence {
+                sequence: s.to_owned(),
+                detail: "no instructions".into(),
+            });
+        }
+        Ok(sequence)
+    }
+}
+
+/// An instruction's place in a rewritten .text.
+pub(crate) struct Placement {
+    pub instruction: Instruction,
+    /// Offset of the instruction in the input .text, if it was carried over
+    /// from there. Relative branches of such instructions are re-resolved
+    /// against the new layout; those of inserted instructions are kept as is.
+    pub origin: Option<u64>,
+    /// Branches that targeted this input .text offset land here instead.
+    pub lands: Option<u64>,
+}
+
+impl Placement {
+    fn carried(instruction: Instruction, offset: u64) -> Self {
+        Self { instruction, origin: Some(offset), lands: Some(offset) }
+    }
+
+    fn inserted(instruction: Instruction) -> Self {
+        Self { instruction, origin: None, lands: None }
+    }
+}
+
+/// Lays `placements` out back to back, rewriting the relative jump and call
+/// offsets of carried-over instructions so they still reach their targets.
+/// Each of `labels`, given with its input .text offset, moves to where
+/// branches to that offset land. Returns the new nodes and the resulting
+/// .text size.
+pub(crate) fn relayout(
+    placements: Vec<Placement>,
+    labels: Vec<(Label, u64)>,
+) -> Result<(Vec<ASTNode>, u64), SbpfLinkerError> {
+    let mut offsets = Vec::with_capacity(placements.len());
+    let mut landings = HashMap::new();
+    let mut offset = 0;
+    for placement in &placements {
+        offsets.push(offset);
+        if let Some(lands) = placement.lands {
+            landings.entry(lands).or_insert(offset);
+        }
+        offset += placement.instruction.get_size();
+    }
+    let text_size = offset;
+
+    let mut nodes = placements
+        .into_iter()
+        .zip(offsets)
+        .map(|(mut placement, offset)| {
+            if let Some(origin) = placement.origin {
+                retarget(
+                    &mut placement.instruction,
+                    origin,
+                    offset,
+                    &landings,
+                )?;
+            }
+            Ok(ASTNode::Instruction {
+                instruction: placement.instruction,
+                offset,
+            })
+        })
+        .collect::<Result<Vec<_>, SbpfLinkerError>>()?;
+    for (label, origin) in labels {
+        let &offset = landings.get(&origin).ok_or(
+            SbpfLinkerError::UnresolvedBranchTarget {
+                offset: origin,
+                target: origin,
+            },
+        )?;
+        nodes.push(ASTNode::Label { label, offset });
+    }
+
+    Ok((nodes, text_size))
+}
+
+fn retarget(
+    instruction: &mut Instruction,
+    origin: u64,
+    offset: u64,
+    landings: &HashMap<u64, u64>,
+) -> Result<(), SbpfLinkerError> {
+    if !instruction.is_jump() && instruction.opcode != Opcode::Call {
+        return Ok(());
+    }
+    // calls to syscalls and to relocated symbols carry an identifier instead
+    let Some(Token::ImmediateValue(ImmediateValue::Int(relative), span)) =
+        instruction.operands.last().cloned()
+    else {
+        return Ok(());
+    };
+
+    let target = origin.wrapping_add_signed((relative + 1) * 8);
+    let &new_target = landings.get(&target).ok_or(
+        SbpfLinkerError::UnresolvedBranchTarget { offset: origin, target },
+    )?;
+    let relative = (new_target as i64 - offset as i64) / 8 - 1;
+
+    let in_range = if instruction.is_jump() {
+        i16::try_from(relative).is_ok()
+    } else {
+        i32::try_from(relative).is_ok()
+    };
+    if !in_range {
+        return Err(SbpfLinkerError::BranchOutOfRange { offset: origin });
+    }
+
+    let last_idx = instruction.operands.len() - 1;
+    instruction.operands[last_idx] =
+        Token::ImmediateValue(ImmediateValue::Int(relative), span);
+    Ok(())
+}
+
+/// Inserts `prologue` where execution enters the program: right before the
+/// function labeled `entry`, or at the start of .text without one. The entry
+/// label moves onto the prologue, while branches back to the entry function
+/// skip it. `epilogue` goes before every `exit`, and branches to an `exit`
+/// run it first. Returns the new .text size.
+pub(crate) fn splice_prologue_epilogue(
+    nodes: &mut Vec<ASTNode>,
+    entry: Option<&str>,
+    prologue: Option<&InstructionSequence>,
+    epilogue: Option<&InstructionSequence>,
+) -> Result<u64, SbpfLinkerError> {
+    let entry_origin = nodes
+        .iter()
+        .find_map(|node| match node {
+            ASTNode::Label { label, offset }
+                if Some(label.name.as_str()) == entry =>
+            {
+                Some(*offset)
+            }
+            _ => None,
+        })
+        .unwrap_or(0);
+    let prologue_size = prologue.map_or(0, InstructionSequence::size);
+    let mut prologue = prologue.map(|prologue| &prologue.instructions);
+    let mut placements = Vec::new();
+
+    let mut labels = Vec::new();
+    for node in nodes.drain(..) {
+        let (instruction, offset) = match node {
+            ASTNode::Instruction { instruction, offset } => {
+                (instruction, offset)
+            }
+            ASTNode::Label { label, offset } => {
+                labels.push((label, offset));
+                continue;
+            }
+            _ => continue,
+        };
+        if offset == entry_origin
+            && let Some(prologue) = prologue.take()
+        {
+            placements
+                .extend(prologue.iter().cloned().map(Placement::inserted));
+        }
+        match epilogue {
+            Some(epilogue) if instruction.opcode == Opcode::Exit => {
+                let mut spliced = epilogue.instructions.iter().cloned();
+                let first = spliced.next().map(|instruction| Placement {
+                    lands: Some(offset),
+                    ..Placement::inserted(instruction)
+                });
+                placements.extend(first);
+                placements.extend(spliced.map(Placement::inserted));
+                placements.push(Placement {
+                    lands: None,
+                    ..Placement::carried(instruction, offset)
+                });
+            }
+            _ => placements.push(Placement::carried(instruction, offset)),
+        }
+    }
+
+    let (mut relaid, text_size) = relayout(placements, labels)?;
+    for node in &mut relaid {
+        if let ASTNode::Label { label, offset } = node
+            && Some(label.name.as_str()) == entry
+        {
+            *offset -= prologue_size;
+        }
+    }
+    *nodes = relaid;
+    Ok(text_size)
+}
@@ -0,0 +1,207 @@
+use std::collections::{HashMap, HashSet};
+
+use object::File;
+use sbpf_common::opcode::Opcode;
+
+use crate::{
+    SbpfLinkerError,
+    analysis::{Callee, RawInstruction, Text},
+};
+
+/// Bytes of stack each function call gets on SBPF, below `r10`.
+pub const STACK_FRAME_SIZE: u64 = 4096;
+
+/// Most nested calls the VM allows before aborting the program.
+pub const MAX_CALL_DEPTH: u32 = 64;
+
+/// Frame pointer register.
+const R10: u8 = 10;
+
+/// The stack one function of .text uses.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct StackUsage {
+    pub name: String,
+    pub offset: u64,
+    /// Bytes of its frame the function accesses, through `r10` or pointers
+    /// derived from it.
+    pub frame_size: u64,
+    /// Longest chain of nested calls the function makes, 0 for a leaf.
+    /// `None` if it can recurse, when there is no bound.
+    pub call_depth: Option<u32>,
+    /// Functions it calls, by name.
+    pub calls: Vec<String>,
+}
+
+impl StackUsage {
+    /// Whether the function accesses more stack than its frame holds.
+    pub fn overflows(&self) -> bool {
+        self.frame_size > STACK_FRAME_SIZE
+    }
+}
+
+/// Estimates the stack usage of every function in the .text of `source`,
+/// in address order, from the instructions alone. Frame sizes cover the
+/// accesses through `r10` and through registers set to `r10` plus a
+/// constant; pointers passed between functions aren't followed. Calls to
+/// syscalls don't count towards the call depth.
+pub fn stack_usage(source: &[u8]) -> Result<Vec<StackUsage>, SbpfLinkerError> {
+    let obj = File::parse(source)?;
+    let Some(text) = Text::parse(&obj)? else {
+        return Ok(Vec::new());
+    };
+
+    let mut frames = vec![0; text.functions.len()];
+    let mut callees: Vec<Vec<usize>> = vec![Vec::new(); text.functions.len()];
+    // registers holding `r10` plus the value, per function
+    let mut pointers: HashMap<u8, i64> = HashMap::new();
+    let mut previous = None;
+    for instruction in &text.instructions {
+        let RawInstruction { opcode, dst, src, off, imm, .. } = *instruction;
+        let Some(function) = text.function_at(instruction.offset) else {
+            continue;
+        };
+        if previous != Some(function) {
+            pointers.clear();
+            previous = Some(function);
+        }
+        let below = |base: u8, pointers: &HashMap<u8, i64>| -> Option<u64> {
+            let base = if base == R10 { 0 } else { *pointers.get(&base)? };
+            u64::try_from(-(base + off)).ok()
+        };
+
+        match Opcode::from_u8(opcode) {
+            Some(
+                Opcode::Ldxb | Opcode::Ldxh | Opcode::Ldxw | Opcode::Ldxdw,
+            ) => {
+                if let Some(size) = below(src, &pointers) {
+                    frames[function] = frames[function].max(size);
+                }
+                pointers.remove(&dst);
+            }
+            Some(
+                Opcode::Stb
+                | Opcode::Sth
+                | Opcode::Stw
+                | Opcode::Stdw
+                | Opcode::Stxb
+                | Opcode::Stxh
+                | Opcode::Stxw
+                | Opcode::Stxdw,
+            ) => {
+                if let Some(size) = below(dst, &pointers) {
+                    frames[function] = frames[function].max(size);
+                }
+            }
+            Some(Opcode::Mov64Reg) => {
+                match (src == R10).then_some(0).or(pointers.get(&src).copied())
+                {
+                    Some(base) => pointers.insert(dst, base),
+                    None => pointers.remove(&dst),
+                };
+            }
+            Some(Opcode::Add64Imm | Opcode::Sub64Imm)
+                if pointers.contains_key(&dst) =>
+            {
+                let delta =
+                    if instruction.is(Opcode::Add64Imm) { imm } else { -imm };
+                if let Some(base) = pointers.get_mut(&dst) {
+                    *base += delta;
+                    if let Ok(size) = u64::try_from(-*base) {
+                        frames[function] = frames[function].max(size);
+                    }
+                }
+            }
+            Some(Opcode::Call) => {
+                if let Callee::Function(callee) = text.callee(instruction)
+                    && !callees[function].contains(&callee)
+                {
+                    callees[function].push(callee);
+                }
+                // the callee may clobber the argument and return registers
+                for register in 0..=5 {
+                    pointers.remove(&register);
+                }
+            }
+            Some(
+                Opcode::Ja
+                | Opcode::Exit
+                | Opcode::Callx
+                | Opcode::JeqImm
+                | Opcode::JgtImm
+                | Opcode::JgeImm
+                | Opcode::JltImm
+                | Opcode::JleImm
+                | Opcode::JsetImm
+                | Opcode::JneImm
+                | Opcode::JsgtImm
+                | Opcode::JsgeImm
+                | Opcode::JsltImm
+                | Opcode::JsleImm
+                | Opcode::JeqReg
+                | Opcode::JgtReg
+                | Opcode::JgeReg
+                | Opcode::JltReg
+                | Opcode::JleReg
+                | Opcode::JsetReg
+                | Opcode::JneReg
+                | Opcode::JsgtReg
+                | Opcode::JsgeReg
+                | Opcode::JsltReg
+                | Opcode::JsleReg,
+            ) => {}
+            // everything else writes its destination register
+            _ => {
+                pointers.remove(&dst);
+            }
+        }
+    }
+
+    let mut depths = HashMap::new();
+    Ok(text
+        .functions
+        .iter()
+        .enumerate()
+        .map(|(function, (offset, name))| StackUsage {
+            name: name.clone(),
+            offset: *offset,
+            frame_size: frames[function],
+            call_depth: call_depth(
+                function,
+                &callees,
+                &mut depths,
+                &mut HashSet::new(),
+            ),
+            calls: callees[function]
+                .iter()
+                .map(|&callee| text.functions[callee].1.clone())
+                .collect(),
+        })
+        .collect())
+}
+
+/// Longest call chain below `function`, `None` if it reaches a cycle.
+fn call_depth(
+    function: usize,
+    callees: &[Vec<usize>],
+    depths: &mut HashMap<usize, Option<u32>>,
+    active: &mut HashSet<usize>,
+) -> Option<u32> {
+    if let Some(depth) = depths.get(&function) {
+        return *depth;
+    }
+    if !active.insert(function) {
+        return None;
+    }
+    let mut depth = Some(0);
+    for &callee in &callees[function] {
+        let below = call_depth(callee, callees, depths, active);
+        depth = match (depth, below) {
+            (Some(depth), Some(below)) => Some(depth.max(below + 1)),
+            _ => None,
+        };
+    }
+    active.remove(&function);
+    depths.insert(function, depth);
+    depth
+}
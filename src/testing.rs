@@ -0,0 +1,163 @@
+//! Upstream BPF objects for the tests to link, built with `object::write`
+//! the way LLVM lays them out.
+
+use object::write::{
+    Object as OutputObject, Relocation as OutputRelocation, SectionId,
+    Symbol as OutputSymbol, SymbolId, SymbolSection,
+};
+use object::{
+    Architecture, BinaryFormat, Endianness, RelocationFlags, SectionFlags,
+    SectionKind, SymbolFlags, SymbolKind, SymbolScope,
+};
+
+pub(crate) const EXIT: [u8; 8] = [0x95, 0, 0, 0, 0, 0, 0, 0];
+
+/// `lddw r<dst>, imm`.
+pub(crate) fn lddw(dst: u8, imm: u64) -> [u8; 16] {
+    let mut bytes = [0; 16];
+    bytes[0] = 0x18;
+    bytes[1] = dst;
+    bytes[4..8].copy_from_slice(&(imm as u32).to_le_bytes());
+    bytes[12..16].copy_from_slice(&((imm >> 32) as u32).to_le_bytes());
+    bytes
+}
+
+/// `mov64 r<dst>, imm`.
+pub(crate) fn mov64(dst: u8, imm: i32) -> [u8; 8] {
+    let mut bytes = [0xb7, dst, 0, 0, 0, 0, 0, 0];
+    bytes[4..].copy_from_slice(&imm.to_le_bytes());
+    bytes
+}
+
+/// `call -1`, as LLVM emits a call for a relocation to resolve.
+pub(crate) fn call() -> [u8; 8] {
+    let mut bytes = [0x85, 0x10, 0, 0, 0, 0, 0, 0];
+    bytes[4..].copy_from_slice(&(-1i32).to_le_bytes());
+    bytes
+}
+
+/// A relocatable little-endian BPF object under construction.
+pub(crate) struct TestObject {
+    object: OutputObject<'static>,
+    /// The code sections, whose symbols are functions.
+    code: Vec<SectionId>,
+}
+
+impl TestObject {
+    pub(crate) fn new() -> Self {
+        Self {
+            object: OutputObject::new(
+                BinaryFormat::Elf,
+                Architecture::Bpf,
+                Endianness::Little,
+            ),
+            code: Vec::new(),
+        }
+    }
+
+    /// Adds the section `name` holding `data`, 8-byte aligned.
+    pub(crate) fn section(
+        &mut self,
+        name: &str,
+        kind: SectionKind,
+        data: &[u8],
+    ) -> SectionId {
+        let id = self.object.add_section(
+            Vec::new(),
+            name.as_bytes().to_vec(),
+            kind,
+        );
+        self.object.section_mut(id).set_data(data.to_vec(), 8);
+        if kind == SectionKind::Text {
+            self.code.push(id);
+        }
+        id
+    }
+
+    /// Sets the ELF `sh_flags` of `section`.
+    pub(crate) fn flags(&mut self, section: SectionId, sh_flags: u64) {
+        self.object.section_mut(section).flags =
+            SectionFlags::Elf { sh_flags };
+    }
+
+    /// Adds the global symbol `name`, a function in a code section and an
+    /// object in any other, at `value` in `section`.
+    pub(crate) fn symbol(
+        &mut self,
+        name: &str,
+        section: SectionId,
+        value: u64,
+        size: u64,
+    ) -> SymbolId {
+        let kind = if self.code.contains(&section) {
+            SymbolKind::Text
+        } else {
+            SymbolKind::Data
+        };
+        self.object.add_symbol(OutputSymbol {
+            name: name.as_bytes().to_vec(),
+            value,
+            size,
+            kind,
+            scope: SymbolScope::Linkage,
+            weak: false,
+            section: SymbolSection::Section(section),
+            flags: SymbolFlags::None,
+        })
+    }
+
+    /// The symbol of `section` itself, which LLVM relocates against to
+    /// refer to what no symbol names.
+    pub(crate) fn section_symbol(&mut self, section: SectionId) -> SymbolId {
+        self.object.section_symbol(section)
+    }
+
+    /// Adds an undefined symbol `name`, e.g. a syscall.
+    pub(crate) fn undefined(&mut self, name: &str) -> SymbolId {
+        self.object.add_symbol(OutputSymbol {
+            name: name.as_bytes().to_vec(),
+            value: 0,
+            size: 0,
+            kind: SymbolKind::Unknown,
+            scope: SymbolScope::Unknown,
+            weak: false,
+            section: SymbolSection::Undefined,
+            flags: SymbolFlags::None,
+        })
+    }
+
+    /// Adds a relocation of type `r_type` at `offset` in `section`. As in
+    /// the objects LLVM emits, its addend is implicit, in the bytes it
+    /// applies to.
+    pub(crate) fn relocation(
+        &mut self,
+        section: SectionId,
+        offset: u64,
+        symbol: SymbolId,
+        r_type: u32,
+    ) {
+        self.object
+            .add_relocation(
+                section,
+                OutputRelocation {
+                    offset,
+                    symbol,
+                    addend: 0,
+                    flags: RelocationFlags::Elf { r_type },
+                },
+            )
+            .expect("the relocation has no explicit addend");
+    }
+
+    pub(crate) fn write(self) -> Vec<u8> {
+        self.object.write().expect("the object is well-formed")
+    }
+}
+
+/// An object whose `entrypoint` is `text`, holding no data.
+pub(crate) fn program(text: &[u8]) -> Vec<u8> {
+    let mut object = TestObject::new();
+    let text_id = object.section(".text", SectionKind::Text, text);
+    object.symbol("entrypoint", text_id, 0, text.len() as u64);
+    object.write()
+}
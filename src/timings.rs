@@ -0,0 +1,139 @@
+use std::{
+    cell::RefCell,
+    fmt, fs,
+    time::{Duration, Instant},
+};
+
+thread_local! {
+    /// The stages timed on this thread, while [`collect_timings`] runs.
+    static STAGES: RefCell<Option<Vec<StageTiming>>> =
+        const { RefCell::new(None) };
+}
+
+/// How long each stage of a link took, and how much memory the process had
+/// used by its end.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Timings {
+    /// In the order they ran. A stage that ran more than once, e.g. the
+    /// parse of each object, appears as often.
+    pub stages: Vec<StageTiming>,
+    /// Wall time of the whole link, including what's in no stage.
+    pub total: Duration,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StageTiming {
+    /// `cache`, `llvm`, `merge`, `gc`, `parse`, `relocations`, `emit`,
+    /// `verify` or `write`.
+    pub name: &'static str,
+    pub wall: Duration,
+    /// Peak resident memory of the process by the end of the stage, in
+    /// bytes, where the OS reports it (Linux). The stage that raises it is
+    /// the one that needed the memory.
+    pub peak_memory: Option<u64>,
+}
+
+/// Runs `f`, timing the stages of the links it does on this thread.
+pub fn collect_timings<T>(f: impl FnOnce() -> T) -> (T, Timings) {
+    let previous = STAGES.with(|stages| stages.replace(Some(Vec::new())));
+    let started = now();
+    let result = f();
+    let total = elapsed(started);
+    let stages =
+        STAGES.with(|stages| stages.replace(previous)).unwrap_or_default();
+    (result, Timings { stages, total })
+}
+
+/// Times the stage `name` until the returned guard is dropped, if
+/// [`collect_timings`] is collecting on this thread.
+pub fn stage(name: &'static str) -> Stage {
+    Stage { name, started: now() }
+}
+
+/// The current time, if the platform has a clock to read it from:
+/// `wasm32-unknown-unknown` has none, and times everything as taking no
+/// time rather than panicking.
+fn now() -> Option<Instant> {
+    if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+        None
+    } else {
+        Some(Instant::now())
+    }
+}
+
+fn elapsed(since: Option<Instant>) -> Duration {
+    since.map_or(Duration::ZERO, |since| since.elapsed())
+}
+
+/// A stage being timed, see [`stage`].
+#[must_use = "the stage ends when this is dropped"]
+pub struct Stage {
+    name: &'static str,
+    started: Option<Instant>,
+}
+
+impl Drop for Stage {
+    fn drop(&mut self) {
+        let wall = elapsed(self.started);
+        STAGES.with(|stages| {
+            if let Some(stages) = stages.borrow_mut().as_mut() {
+                stages.push(StageTiming {
+                    name: self.name,
+                    wall,
+                    peak_memory: peak_memory(),
+                });
+            }
+        });
+    }
+}
+
+/// The process's peak resident memory so far, in bytes.
+fn peak_memory() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find_map(|line| line.strip_prefix("VmHWM:"))?;
+    let kib: u64 = line.trim().strip_suffix("kB")?.trim().parse().ok()?;
+    Some(kib * 1024)
+}
+
+impl Timings {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "stages": self
+                .stages
+                .iter()
+                .map(|stage| serde_json::json!({
+                    "name": stage.name,
+                    "wall_ms": stage.wall.as_secs_f64() * 1000.0,
+                    "peak_memory": stage.peak_memory,
+                }))
+                .collect::<Vec<_>>(),
+            "total_ms": self.total.as_secs_f64() * 1000.0,
+        })
+    }
+}
+
+/// A table of the stages, with the wall time in milliseconds and the peak
+/// memory in MiB.
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<12} {:>10} {:>10}", "stage", "wall ms", "peak MiB")?;
+        for stage in &self.stages {
+            let peak = stage.peak_memory.map_or("-".to_owned(), |peak| {
+                format!("{:.1}", peak as f64 / (1024.0 * 1024.0))
+            });
+            writeln!(
+                f,
+                "{:<12} {:>10.3} {peak:>10}",
+                stage.name,
+                stage.wall.as_secs_f64() * 1000.0
+            )?;
+        }
+        write!(
+            f,
+            "{:<12} {:>10.3}",
+            "total",
+            self.total.as_secs_f64() * 1000.0
+        )
+    }
+}
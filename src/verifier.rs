@@ -0,0 +1,145 @@
+use std::collections::HashSet;
+
+use object::{Object as _, ObjectSection as _};
+use sbpf_common::opcode::Opcode;
+
+use crate::{
+    SbpfLinkerError,
+    analysis::{RawInstruction, decode},
+    version::SbpfVersion,
+};
+
+/// Frame pointer register, read-only for programs.
+const R10: u8 = 10;
+
+/// Runs the checks the loader's verifier makes over the .text of the
+/// emitted `program`, to run on `version`, failing with
+/// [`SbpfLinkerError::VerifierError`] at the first instruction it would
+/// reject.
+pub(crate) fn verify(
+    program: &[u8],
+    version: SbpfVersion,
+) -> Result<(), SbpfLinkerError> {
+    let obj = object::File::parse(program)?;
+    let Some(text) = obj.section_by_name(".text") else {
+        return Ok(());
+    };
+    let data = text.data()?;
+    let reject = |offset: u64, reason: String| {
+        Err(SbpfLinkerError::VerifierError { offset, reason })
+    };
+    if data.len() % 8 != 0 {
+        return reject(
+            data.len() as u64 / 8 * 8,
+            format!(
+                "the program is {} bytes, not whole instructions",
+                data.len()
+            ),
+        );
+    }
+
+    let instructions = decode(data);
+    let starts: HashSet<u64> =
+        instructions.iter().map(|instruction| instruction.offset).collect();
+    for instruction in &instructions {
+        if let Err(reason) = check(instruction, data, &starts, version) {
+            return reject(instruction.offset, reason);
+        }
+    }
+    // a call may end the program too, to a function that doesn't return
+    // such as `abort`
+    let ends = [Opcode::Exit, Opcode::Ja, Opcode::Call, Opcode::Callx];
+    match instructions.last() {
+        Some(last) if !ends.iter().any(|&opcode| last.is(opcode)) => reject(
+            last.offset,
+            String::from(
+                "execution can run past the last instruction, which isn't an exit, jump or call",
+            ),
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Why the verifier rejects `instruction`, if it does.
+fn check(
+    instruction: &RawInstruction,
+    data: &[u8],
+    starts: &HashSet<u64>,
+    version: SbpfVersion,
+) -> Result<(), String> {
+    use Opcode::*;
+
+    let RawInstruction { offset, dst, src, imm, .. } = *instruction;
+    let Some(opcode) = Opcode::from_u8(instruction.opcode) else {
+        return Err(format!("unknown opcode {:#04x}", instruction.opcode));
+    };
+    if dst > R10 {
+        return Err(format!("invalid destination register r{dst}"));
+    }
+    if src > R10 {
+        return Err(format!("invalid source register r{src}"));
+    }
+
+    let class = instruction.opcode & 0x07;
+    // loads and ALU operations write their destination; stores only
+    // address through it. From v1, with dynamic stack frames, functions
+    // move the frame pointer themselves with an immediate `add64`.
+    let writes_dst = matches!(class, 0x00 | 0x01 | 0x04 | 0x07);
+    let moves_frame = version >= SbpfVersion::V1 && opcode == Add64Imm;
+    if writes_dst && dst == R10 && !moves_frame {
+        return Err(String::from("writes to the read-only frame pointer r10"));
+    }
+
+    match opcode {
+        Lddw => {
+            let second = data
+                .get(offset as usize + 8..offset as usize + 16)
+                .ok_or_else(|| {
+                String::from("lddw is missing its second half")
+            })?;
+            if second[..4].iter().any(|&byte| byte != 0) {
+                return Err(String::from(
+                    "the second half of lddw must only hold the high immediate",
+                ));
+            }
+        }
+        Div32Imm | Div64Imm | Mod32Imm | Mod64Imm | Udiv32Imm | Udiv64Imm
+        | Urem32Imm | Urem64Imm | Sdiv32Imm | Sdiv64Imm | Srem32Imm
+        | Srem64Imm
+            if imm == 0 =>
+        {
+            return Err(format!("`{opcode}` by an immediate zero"));
+        }
+        Lsh32Imm | Rsh32Imm | Arsh32Imm if !(0..32).contains(&imm) => {
+            return Err(format!("`{opcode}` by {imm}, more than 31 bits"));
+        }
+        Lsh64Imm | Rsh64Imm | Arsh64Imm if !(0..64).contains(&imm) => {
+            return Err(format!("`{opcode}` by {imm}, more than 63 bits"));
+        }
+        Le | Be if !matches!(imm, 16 | 32 | 64) => {
+            return Err(format!("`{opcode}` to an invalid width of {imm}"));
+        }
+        // pc-relative calls within the program; an immediate of -1 is a
+        // syscall the loader relocates
+        Call if src == 1 && imm != -1 => {
+            let target = offset
+                .checked_add_signed(imm.saturating_add(1).saturating_mul(8));
+            if !target.is_some_and(|target| starts.contains(&target)) {
+                return Err(format!(
+                    "call to {imm:+} instructions, outside the program or into an instruction"
+                ));
+            }
+        }
+        _ if class == 0x05 && !matches!(opcode, Call | Callx | Exit) => {
+            let target = instruction.jump_target();
+            if !starts.contains(&target) {
+                return Err(format!(
+                    "jump to {:+} instructions, outside the program or into an instruction",
+                    instruction.off
+                ));
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
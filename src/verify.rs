@@ -0,0 +1,72 @@
+use object::{Object as _, ObjectSection as _};
+use sbpf_assembler::{
+    astnode::ASTNode, instruction::Instruction, lexer::Token,
+};
+use sbpf_common::opcode::Opcode;
+
+use crate::{SbpfLinkerError, disasm::render_operand};
+
+/// Decodes the .text of the emitted `program` again and checks that it
+/// holds exactly the instructions in `expected`, the code nodes the program
+/// was assembled from.
+pub(crate) fn round_trip(
+    program: &[u8],
+    expected: &[ASTNode],
+) -> Result<(), SbpfLinkerError> {
+    let obj = object::File::parse(program)?;
+    let text = match obj.section_by_name(".text") {
+        Some(text) => text.data()?,
+        None => &[],
+    };
+
+    let mut end = 0;
+    for node in expected {
+        let ASTNode::Instruction { instruction, offset } = node else {
+            continue;
+        };
+        let at = *offset as usize;
+        let size = instruction.get_size() as usize;
+        end = end.max(at + size);
+        let bytes = text.get(at..at + size);
+        // syscalls are relocated by the loader, so only the opcode is known
+        if instruction.opcode == Opcode::Call
+            && matches!(
+                instruction.operands.last(),
+                Some(Token::Identifier(..))
+            )
+            && bytes
+                .is_some_and(|bytes| bytes[0] == Opcode::Call.to_bytecode())
+        {
+            continue;
+        }
+        let found = match bytes.map(Instruction::from_bytes) {
+            Some(Ok(found)) => render(&found),
+            Some(Err(error)) => format!("<{error}>"),
+            None => String::from("<end of .text>"),
+        };
+        let expected = render(instruction);
+        if found != expected {
+            return Err(SbpfLinkerError::RoundTripMismatch {
+                offset: *offset,
+                expected,
+                found,
+            });
+        }
+    }
+    if text.len() > end {
+        return Err(SbpfLinkerError::RoundTripMismatch {
+            offset: end as u64,
+            expected: String::from("<end of .text>"),
+            found: format!("{} more bytes", text.len() - end),
+        });
+    }
+    Ok(())
+}
+
+/// `instruction` with every operand in its canonical form, so that e.g. a
+/// resolved address compares equal to the constant it was encoded as.
+fn render(instruction: &Instruction) -> String {
+    let operands: Vec<_> =
+        instruction.operands.iter().map(render_operand).collect();
+    format!("{} {}", instruction.opcode, operands.join(", "))
+}
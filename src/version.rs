@@ -0,0 +1,329 @@
+use std::{fmt, str::FromStr};
+
+use object::{FileFlags, Object as _, ObjectSection as _};
+use sbpf_assembler::{astnode::ASTNode, lexer::Token, syscall::SYSCALLS};
+use sbpf_common::opcode::Opcode;
+
+use crate::SbpfLinkerError;
+
+/// The loads and stores, which v2 moves to classes of their own, with their
+/// opcodes from v2.
+const MOVED_MEMORY: [(Opcode, u8); 12] = [
+    (Opcode::Ldxb, 0x2c),
+    (Opcode::Ldxh, 0x3c),
+    (Opcode::Ldxw, 0x8c),
+    (Opcode::Ldxdw, 0x9c),
+    (Opcode::Stb, 0x27),
+    (Opcode::Sth, 0x37),
+    (Opcode::Stw, 0x87),
+    (Opcode::Stdw, 0x97),
+    (Opcode::Stxb, 0x2f),
+    (Opcode::Stxh, 0x3f),
+    (Opcode::Stxw, 0x8f),
+    (Opcode::Stxdw, 0x9f),
+];
+
+/// The multiplications, divisions and remainders v2 drops, with the PQR
+/// instructions that compute the same.
+const PQR: [(Opcode, Opcode); 12] = [
+    (Opcode::Mul32Imm, Opcode::Lmul32Imm),
+    (Opcode::Mul32Reg, Opcode::Lmul32Reg),
+    (Opcode::Mul64Imm, Opcode::Lmul64Imm),
+    (Opcode::Mul64Reg, Opcode::Lmul64Reg),
+    (Opcode::Div32Imm, Opcode::Udiv32Imm),
+    (Opcode::Div32Reg, Opcode::Udiv32Reg),
+    (Opcode::Div64Imm, Opcode::Udiv64Imm),
+    (Opcode::Div64Reg, Opcode::Udiv64Reg),
+    (Opcode::Mod32Imm, Opcode::Urem32Imm),
+    (Opcode::Mod32Reg, Opcode::Urem32Reg),
+    (Opcode::Mod64Imm, Opcode::Urem64Imm),
+    (Opcode::Mod64Reg, Opcode::Urem64Reg),
+];
+
+/// SBPF instruction set version. The loader reads it from the ELF header's
+/// `e_flags`.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+pub enum SbpfVersion {
+    #[default]
+    V0,
+    V1,
+    V2,
+    V3,
+}
+
+impl SbpfVersion {
+    pub const ALL: [SbpfVersion; 4] = [Self::V0, Self::V1, Self::V2, Self::V3];
+
+    pub fn from_e_flags(e_flags: u32) -> Option<Self> {
+        Self::ALL.get(e_flags as usize).copied()
+    }
+
+    pub fn e_flags(self) -> u32 {
+        self as u32
+    }
+
+    /// The version the object `obj` is built for, by its `e_flags`. Upstream
+    /// BPF objects have none, which is v0.
+    pub(crate) fn of_object(obj: &object::File) -> Self {
+        match obj.flags() {
+            FileFlags::Elf { e_flags, .. } => {
+                Self::from_e_flags(e_flags).unwrap_or_default()
+            }
+            _ => Self::V0,
+        }
+    }
+
+    /// Lowest version whose instruction set contains `opcode`.
+    pub fn required_by(opcode: Opcode) -> Self {
+        match opcode {
+            // the PQR instruction class and `hor64` were introduced in v2
+            Opcode::Lmul32Imm
+            | Opcode::Lmul32Reg
+            | Opcode::Lmul64Imm
+            | Opcode::Lmul64Reg
+            | Opcode::Uhmul64Imm
+            | Opcode::Uhmul64Reg
+            | Opcode::Shmul64Imm
+            | Opcode::Shmul64Reg
+            | Opcode::Udiv32Imm
+            | Opcode::Udiv32Reg
+            | Opcode::Udiv64Imm
+            | Opcode::Udiv64Reg
+            | Opcode::Urem32Imm
+            | Opcode::Urem32Reg
+            | Opcode::Urem64Imm
+            | Opcode::Urem64Reg
+            | Opcode::Sdiv32Imm
+            | Opcode::Sdiv32Reg
+            | Opcode::Sdiv64Imm
+            | Opcode::Sdiv64Reg
+            | Opcode::Srem32Imm
+            | Opcode::Srem32Reg
+            | Opcode::Srem64Imm
+            | Opcode::Srem64Reg
+            | Opcode::Hor64Imm => Self::V2,
+            _ => Self::V0,
+        }
+    }
+
+    /// Version from which `opcode` is deprecated, if any.
+    pub fn deprecating(opcode: Opcode) -> Option<Self> {
+        match opcode {
+            // v2 replaces lddw with a two-instruction load, the old
+            // multiplication and division class with PQR, and drops neg and
+            // le
+            Opcode::Lddw
+            | Opcode::Mul32Imm
+            | Opcode::Mul32Reg
+            | Opcode::Mul64Imm
+            | Opcode::Mul64Reg
+            | Opcode::Div32Imm
+            | Opcode::Div32Reg
+            | Opcode::Div64Imm
+            | Opcode::Div64Reg
+            | Opcode::Mod32Imm
+            | Opcode::Mod32Reg
+            | Opcode::Mod64Imm
+            | Opcode::Mod64Reg
+            | Opcode::Neg32
+            | Opcode::Neg64
+            | Opcode::Le => Some(Self::V2),
+            _ => None,
+        }
+    }
+
+    pub fn deprecates(self, opcode: Opcode) -> bool {
+        Self::deprecating(opcode).is_some_and(|since| since <= self)
+    }
+
+    /// Lowest version able to execute every instruction in `nodes`.
+    pub fn required_by_nodes(nodes: &[ASTNode]) -> Self {
+        nodes
+            .iter()
+            .filter_map(|node| match node {
+                ASTNode::Instruction { instruction, .. } => {
+                    Some(Self::required_by(instruction.opcode))
+                }
+                _ => None,
+            })
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+impl fmt::Display for SbpfVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v{}", self.e_flags())
+    }
+}
+
+impl FromStr for SbpfVersion {
+    type Err = SbpfLinkerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.strip_prefix('v').unwrap_or(s) {
+            "0" => Self::V0,
+            "1" => Self::V1,
+            "2" => Self::V2,
+            "3" => Self::V3,
+            _ => {
+                return Err(SbpfLinkerError::InvalidSbpfVersion(s.to_owned()));
+            }
+        })
+    }
+}
+
+/// Rewrites `bytes`, the first 8 bytes of an instruction encoded for
+/// `version`, in the v0 encoding the parser reads: the loads and stores
+/// back in their v0 classes, and the register of a `callx` in the
+/// destination field, rather than in the source field as from v2, or in
+/// the immediate as upstream LLVM has it.
+pub(crate) fn decode_as_v0(bytes: &mut [u8], version: SbpfVersion) {
+    if version >= SbpfVersion::V2
+        && let Some((opcode, _)) =
+            MOVED_MEMORY.iter().find(|(_, moved)| *moved == bytes[0])
+    {
+        bytes[0] = opcode.to_bytecode();
+        return;
+    }
+    if bytes[0] != Opcode::Callx.to_bytecode() || bytes[1] & 0x0f != 0 {
+        return;
+    }
+    if version >= SbpfVersion::V2 {
+        bytes[1] >>= 4;
+    } else if bytes[4] <= 10 && bytes[5..8] == [0; 3] {
+        bytes[1] = bytes[4];
+        bytes[4] = 0;
+    }
+}
+
+/// The calls to known syscalls among `nodes`, by their offset into .text
+/// and the hash of the syscall's name, which are static from v3.
+pub(crate) fn static_syscalls(nodes: &[ASTNode]) -> Vec<(u64, u32)> {
+    nodes
+        .iter()
+        .filter_map(|node| match node {
+            ASTNode::Instruction { instruction, offset }
+                if instruction.opcode == Opcode::Call =>
+            {
+                let Some(Token::Identifier(name, _)) =
+                    instruction.operands.last()
+                else {
+                    return None;
+                };
+                SYSCALLS
+                    .entries()
+                    .find(|(_, syscall)| **syscall == name)
+                    .map(|(hash, _)| (*offset, *hash))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Re-encodes the .text of `program`, emitted in the v0 encoding from code
+/// built for `source`, for `target`.
+///
+/// From v2, the loads and stores move to classes of their own and a
+/// `callx` names its register in the source field. From v3, the known
+/// `syscalls`, by offset into .text and the hash of their name, are called
+/// by that hash rather than relocated by the loader. Code built for an
+/// earlier version than v2 also has the instructions v2 drops replaced by
+/// ones that compute the same: `lddw` by a `mov32` and an `hor64` of the
+/// halves of its immediate, multiplication, division and remainder by
+/// their PQR forms, `neg` by a `sub` from 0 and the `sub` of an immediate,
+/// whose operands v2 swaps, by the `add` of its negation. `le` has no such
+/// replacement and fails with [`SbpfLinkerError::UnencodableInstruction`],
+/// as does the 64-bit `sub` of `i32::MIN`.
+pub(crate) fn encode(
+    program: &mut [u8],
+    source: SbpfVersion,
+    target: SbpfVersion,
+    syscalls: &[(u64, u32)],
+) -> Result<(), SbpfLinkerError> {
+    if target < SbpfVersion::V2 {
+        return Ok(());
+    }
+    let Some((start, size)) = object::File::parse(&*program)?
+        .section_by_name(".text")
+        .and_then(|text| text.file_range())
+    else {
+        return Ok(());
+    };
+    let text = &mut program[start as usize..(start + size) as usize];
+    let unencodable = |opcode, offset| {
+        Err(SbpfLinkerError::UnencodableInstruction {
+            opcode,
+            offset,
+            version: target,
+        })
+    };
+
+    let mut at = 0;
+    while at + 8 <= text.len() {
+        let offset = at as u64;
+        let Some(opcode) = Opcode::from_u8(text[at]) else {
+            at += 8;
+            continue;
+        };
+        let instruction = &mut text[at..];
+        at += if opcode == Opcode::Lddw { 16 } else { 8 };
+        let imm = i32::from_le_bytes([
+            instruction[4],
+            instruction[5],
+            instruction[6],
+            instruction[7],
+        ]);
+        if source < SbpfVersion::V2 {
+            let replacement = match opcode {
+                Opcode::Lddw if instruction.len() >= 16 => {
+                    instruction[0] = Opcode::Mov32Imm.to_bytecode();
+                    instruction[8] = Opcode::Hor64Imm.to_bytecode();
+                    instruction[9] = instruction[1];
+                    continue;
+                }
+                Opcode::Neg32 => Some((Opcode::Sub32Imm, 0)),
+                Opcode::Neg64 => Some((Opcode::Sub64Imm, 0)),
+                Opcode::Sub32Imm => {
+                    Some((Opcode::Add32Imm, imm.wrapping_neg()))
+                }
+                Opcode::Sub64Imm => match imm.checked_neg() {
+                    Some(negated) => Some((Opcode::Add64Imm, negated)),
+                    None => return unencodable(opcode, offset),
+                },
+                Opcode::Le => return unencodable(opcode, offset),
+                _ => None,
+            };
+            if let Some((opcode, imm)) = replacement {
+                instruction[0] = opcode.to_bytecode();
+                instruction[4..8].copy_from_slice(&imm.to_le_bytes());
+            }
+            if let Some((_, pqr)) =
+                PQR.iter().find(|(dropped, _)| *dropped == opcode)
+            {
+                instruction[0] = pqr.to_bytecode();
+            }
+        }
+        if let Some((_, moved)) =
+            MOVED_MEMORY.iter().find(|(memory, _)| *memory == opcode)
+        {
+            instruction[0] = *moved;
+        } else if opcode == Opcode::Callx {
+            instruction[1] = (instruction[1] & 0x0f) << 4;
+        }
+    }
+
+    if target >= SbpfVersion::V3 {
+        for &(offset, hash) in syscalls {
+            if let Some(call) =
+                text.get_mut(offset as usize..offset as usize + 8)
+            {
+                call[1] = 0;
+                call[4..8].copy_from_slice(&hash.to_le_bytes());
+            }
+        }
+    }
+    Ok(())
+}